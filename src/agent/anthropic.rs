@@ -1,11 +1,17 @@
-use super::Agent;
-use crate::types::{AgentConfig, StreamEvent};
+use super::{send_with_retry, sse_events, Agent, AgentCapabilities, ToolBox, ToolCall};
+use crate::types::{
+    AgentConfig, AgentRequest, ChatMessage, ModelInfo, StreamEvent, TokenUsage, ToolDefinition,
+};
 use anyhow::Result;
 use async_trait::async_trait;
+use futures::StreamExt;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 
+/// The Messages API requires `max_tokens`; used when the request doesn't set one
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
 /// Direct Anthropic Claude API integration
 pub struct AnthropicAgent {
     config: AgentConfig,
@@ -15,31 +21,454 @@ pub struct AnthropicAgent {
 #[derive(Debug, Serialize)]
 struct AnthropicRequest {
     model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
     messages: Vec<Message>,
     stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     temperature: Option<f32>,
-    max_tokens: Option<u32>,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
     top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop_sequences: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<ThinkingConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Tool>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 struct Message {
     role: String,
-    content: String,
+    content: MessageContent,
+}
+
+impl Message {
+    /// Assistant tool calls become tool_use blocks and tool messages become
+    /// tool_result blocks in a user message; the API merges consecutive user turns.
+    fn from_chat(message: &ChatMessage) -> Self {
+        if message.role == "tool" {
+            return Self {
+                role: "user".to_string(),
+                content: MessageContent::Blocks(vec![RequestBlock::ToolResult {
+                    tool_use_id: message.tool_call_id.clone().unwrap_or_default(),
+                    content: message.content.clone(),
+                    is_error: false,
+                }]),
+            };
+        }
+        if message.tool_calls.is_empty() {
+            return Self {
+                role: message.role.clone(),
+                content: MessageContent::Text(message.content.clone()),
+            };
+        }
+
+        let text = (!message.content.is_empty()).then(|| RequestBlock::Text {
+            text: message.content.clone(),
+        });
+        let calls = message.tool_calls.iter().map(|call| RequestBlock::ToolUse {
+            id: call.id.clone(),
+            name: call.name.clone(),
+            // tool_use input must be an object
+            input: serde_json::from_str(&call.arguments)
+                .ok()
+                .filter(serde_json::Value::is_object)
+                .unwrap_or_else(|| serde_json::json!({})),
+        });
+        Self {
+            role: message.role.clone(),
+            content: MessageContent::Blocks(text.into_iter().chain(calls).collect()),
+        }
+    }
+}
+
+/// Plain text, or content blocks once tools are involved
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+enum MessageContent {
+    Text(String),
+    Blocks(Vec<RequestBlock>),
+}
+
+/// Content blocks sent back to the API. Thinking blocks must be returned
+/// unchanged (with their signature) alongside the tool_use blocks they precede.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RequestBlock {
+    Text {
+        text: String,
+    },
+    Thinking {
+        thinking: String,
+        signature: String,
+    },
+    RedactedThinking {
+        data: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Tool {
+    name: String,
+    description: String,
+    input_schema: serde_json::Value,
+}
+
+impl From<ToolDefinition> for Tool {
+    fn from(tool: ToolDefinition) -> Self {
+        Self {
+            name: tool.name,
+            description: tool.description,
+            input_schema: tool.input_schema,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ThinkingConfig {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    budget_tokens: u32,
+}
+
+/// Streaming events, see https://docs.anthropic.com/en/api/messages-streaming
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicStreamEvent {
+    MessageStart {
+        message: MessageStart,
+    },
+    ContentBlockStart {
+        content_block: ContentBlock,
+    },
+    ContentBlockDelta {
+        delta: ContentDelta,
+    },
+    ContentBlockStop,
+    MessageDelta {
+        delta: MessageDeltaBody,
+        #[serde(default)]
+        usage: Option<AnthropicUsage>,
+    },
+    MessageStop,
+    Ping,
+    Error {
+        error: AnthropicError,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageStart {
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text {
+        #[serde(default)]
+        text: String,
+    },
+    Thinking {
+        #[serde(default)]
+        thinking: String,
+        #[serde(default)]
+        signature: String,
+    },
+    RedactedThinking {
+        data: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentDelta {
+    TextDelta {
+        text: String,
+    },
+    ThinkingDelta {
+        thinking: String,
+    },
+    SignatureDelta {
+        signature: String,
+    },
+    /// Fragment of a tool_use block's JSON input
+    InputJsonDelta {
+        partial_json: String,
+    },
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Debug, Deserialize)]
-struct AnthropicStreamChunk {
+struct MessageDeltaBody {
+    #[serde(default)]
+    stop_reason: Option<String>,
+}
+
+/// Usage counters; `message_delta` only carries the fields that changed
+#[derive(Debug, Default, Deserialize)]
+struct AnthropicUsage {
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+    cache_creation_input_tokens: Option<u64>,
+    cache_read_input_tokens: Option<u64>,
+}
+
+impl AnthropicUsage {
+    fn apply(&self, usage: &mut TokenUsage) {
+        if let Some(v) = self.input_tokens {
+            usage.input_tokens = v;
+        }
+        if let Some(v) = self.output_tokens {
+            usage.output_tokens = v;
+        }
+        if let Some(v) = self.cache_creation_input_tokens {
+            usage.cache_creation_input_tokens = v;
+        }
+        if let Some(v) = self.cache_read_input_tokens {
+            usage.cache_read_input_tokens = v;
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicError {
     #[serde(rename = "type")]
-    chunk_type: String,
-    delta: Option<Delta>,
-    index: Option<u32>,
-    finish_reason: Option<String>,
+    kind: String,
+    message: String,
+}
+
+/// Non-streaming error body: `{"type": "error", "error": {...}}`
+#[derive(Debug, Deserialize)]
+struct AnthropicErrorResponse {
+    error: AnthropicError,
 }
 
+/// `GET /models` response
 #[derive(Debug, Deserialize)]
-struct Delta {
-    text: Option<String>,
+struct ModelList {
+    data: Vec<Model>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Model {
+    id: String,
+    #[serde(default)]
+    display_name: Option<String>,
+    #[serde(default)]
+    created_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// One streamed assistant turn
+#[derive(Debug, Default)]
+struct Turn {
+    blocks: Vec<RequestBlock>,
+    /// Input JSON of the tool_use block being streamed
+    partial_json: String,
+    usage: TokenUsage,
+    stop_reason: Option<String>,
+}
+
+impl Turn {
+    fn start_block(&mut self, block: ContentBlock) {
+        self.finish_block();
+        let block = match block {
+            ContentBlock::Text { text } => RequestBlock::Text { text },
+            ContentBlock::Thinking {
+                thinking,
+                signature,
+            } => RequestBlock::Thinking {
+                thinking,
+                signature,
+            },
+            ContentBlock::RedactedThinking { data } => RequestBlock::RedactedThinking { data },
+            ContentBlock::ToolUse { id, name } => RequestBlock::ToolUse {
+                id,
+                name,
+                input: serde_json::json!({}),
+            },
+            ContentBlock::Unknown => return,
+        };
+        self.blocks.push(block);
+    }
+
+    /// Blocks stream one at a time, so deltas apply to the last started block
+    fn apply(&mut self, delta: &ContentDelta) {
+        match (self.blocks.last_mut(), delta) {
+            (Some(RequestBlock::Text { text }), ContentDelta::TextDelta { text: t }) => {
+                text.push_str(t)
+            }
+            (
+                Some(RequestBlock::Thinking { thinking, .. }),
+                ContentDelta::ThinkingDelta { thinking: t },
+            ) => thinking.push_str(t),
+            (
+                Some(RequestBlock::Thinking { signature, .. }),
+                ContentDelta::SignatureDelta { signature: s },
+            ) => signature.push_str(s),
+            (Some(RequestBlock::ToolUse { .. }), ContentDelta::InputJsonDelta { partial_json }) => {
+                self.partial_json.push_str(partial_json)
+            }
+            _ => {}
+        }
+    }
+
+    /// Decode the accumulated tool input once its block is complete
+    fn finish_block(&mut self) {
+        let json = std::mem::take(&mut self.partial_json);
+        if let Some(RequestBlock::ToolUse { input, .. }) = self.blocks.last_mut() {
+            if !json.trim().is_empty() {
+                // Keep malformed input as a string so the tool reports the error to the model
+                *input = serde_json::from_str(&json).unwrap_or(serde_json::Value::String(json));
+            }
+        }
+    }
+
+    fn tool_calls(&self) -> Vec<ToolCall> {
+        self.blocks
+            .iter()
+            .filter_map(|block| match block {
+                RequestBlock::ToolUse { id, name, input } => Some(ToolCall {
+                    id: id.clone(),
+                    name: name.clone(),
+                    input: input.clone(),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// `stop_reason` in OpenAI's finish_reason terms
+    fn finish_reason(&self) -> Option<String> {
+        let reason = match self.stop_reason.as_deref()? {
+            "max_tokens" => "length",
+            "tool_use" => "tool_calls",
+            "refusal" => "content_filter",
+            _ => "stop",
+        };
+        Some(reason.to_string())
+    }
+}
+
+/// Read a Messages API stream, forwarding text and thinking as they arrive
+async fn stream_turn(
+    response: reqwest::Response,
+    max_tokens: u32,
+    tx: &mpsc::Sender<StreamEvent>,
+) -> Result<Turn> {
+    let mut events = sse_events(response.bytes_stream());
+    let mut turn = Turn::default();
+    let mut stopped = false;
+
+    while let Some(event) = events.next().await {
+        let event = match event {
+            Ok(event) => event,
+            // Reported once, by the caller turning the error into `StreamEvent::Error`
+            Err(e) => return Err(anyhow::anyhow!("Stream error: {}", e)),
+        };
+
+        let event = match serde_json::from_str::<AnthropicStreamEvent>(&event.data) {
+            Ok(event) => event,
+            Err(e) => {
+                tracing::warn!("Skipping malformed Anthropic event: {}", e);
+                continue;
+            }
+        };
+
+        match event {
+            AnthropicStreamEvent::MessageStart { message } => {
+                if let Some(u) = message.usage {
+                    u.apply(&mut turn.usage);
+                }
+            }
+            // Blocks normally start empty, but forward any initial content
+            AnthropicStreamEvent::ContentBlockStart { content_block } => {
+                match &content_block {
+                    ContentBlock::Text { text } if !text.is_empty() => {
+                        let _ = tx
+                            .send(StreamEvent::Token {
+                                content: text.clone(),
+                            })
+                            .await;
+                    }
+                    ContentBlock::Thinking { thinking, .. } if !thinking.is_empty() => {
+                        let _ = tx
+                            .send(StreamEvent::Thinking {
+                                content: thinking.clone(),
+                            })
+                            .await;
+                    }
+                    _ => {}
+                }
+                turn.start_block(content_block);
+            }
+            AnthropicStreamEvent::ContentBlockDelta { delta } => {
+                turn.apply(&delta);
+                match delta {
+                    ContentDelta::TextDelta { text } => {
+                        let _ = tx.send(StreamEvent::Token { content: text }).await;
+                    }
+                    ContentDelta::ThinkingDelta { thinking } => {
+                        let _ = tx.send(StreamEvent::Thinking { content: thinking }).await;
+                    }
+                    _ => {}
+                }
+            }
+            AnthropicStreamEvent::ContentBlockStop => turn.finish_block(),
+            AnthropicStreamEvent::MessageDelta { delta, usage: u } => {
+                if let Some(u) = u {
+                    u.apply(&mut turn.usage);
+                }
+                if delta.stop_reason.as_deref() == Some("max_tokens") {
+                    tracing::warn!(
+                        "Anthropic response truncated at max_tokens ({})",
+                        max_tokens
+                    );
+                }
+                if delta.stop_reason.is_some() {
+                    turn.stop_reason = delta.stop_reason;
+                }
+            }
+            AnthropicStreamEvent::MessageStop => {
+                stopped = true;
+                break;
+            }
+            AnthropicStreamEvent::Error { error } => {
+                anyhow::bail!("Anthropic stream error: {}: {}", error.kind, error.message);
+            }
+            AnthropicStreamEvent::Ping | AnthropicStreamEvent::Unknown => {}
+        }
+    }
+
+    if !stopped {
+        anyhow::bail!("Anthropic stream ended before message_stop");
+    }
+    turn.finish_block();
+
+    Ok(turn)
 }
 
 impl AnthropicAgent {
@@ -51,9 +480,18 @@ impl AnthropicAgent {
     }
 
     async fn get_api_key(&self) -> Result<String> {
-        if let Some(api_key) = self.config.env.get("ANTHROPIC_API_KEY") {
+        if let Some(api_key) = self
+            .config
+            .env
+            .get("ANTHROPIC_API_KEY")
+            .filter(|k| !k.is_empty())
+        {
             return Ok(api_key.clone());
         }
+        // The server's key is only sent to the default endpoint
+        if let Some(base_url) = self.config.env.get("ANTHROPIC_BASE_URL") {
+            anyhow::bail!("No Anthropic API key configured for {}", base_url)
+        }
 
         if let Ok(api_key) = std::env::var("ANTHROPIC_API_KEY") {
             return Ok(api_key);
@@ -70,6 +508,15 @@ impl AnthropicAgent {
             .unwrap_or_else(|| "https://api.anthropic.com/v1".to_string())
     }
 
+    /// Extended thinking budget from `ANTHROPIC_THINKING_BUDGET`; unset or 0 disables thinking
+    fn thinking_budget(&self) -> Option<u32> {
+        self.config
+            .env
+            .get("ANTHROPIC_THINKING_BUDGET")
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|v| *v > 0)
+    }
+
     async fn get_model_name(&self) -> String {
         self.config
             .model
@@ -104,116 +551,183 @@ impl Agent for AnthropicAgent {
         }
     }
 
-    async fn run(&self, prompt: String, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
         let api_key = self.get_api_key().await?;
         let base_url = self.get_base_url().await;
-        let model_name = self.get_model_name().await;
 
-        // Build request - Anthropic uses different message format
-        let request = AnthropicRequest {
-            model: model_name,
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: prompt,
-            }],
-            stream: Some(true),
-            temperature: None,
-            max_tokens: None,
-            top_p: None,
-        };
-
-        // Send streaming request
+        // 1000 is the page size limit, well above the number of Claude models
         let response = self
             .client
-            .post(&format!("{}/messages", base_url))
+            .get(format!("{}/models?limit=1000", base_url))
             .header("x-api-key", api_key)
             .header("anthropic-version", "2023-06-01")
-            .header("Content-Type", "application/json")
-            .header("Accept", "text/event-stream")
-            .json(&request)
+            .timeout(std::time::Duration::from_secs(10))
             .send()
             .await?;
-
         if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(anyhow::anyhow!("Anthropic API error: {}", error_text));
-        }
-
-        // Process streaming response
-        use futures::StreamExt;
-        let mut stream = response.bytes_stream();
-
-        let mut full_response = String::new();
-
-        while let Some(chunk_result) = stream.next().await {
-            match chunk_result {
-                Ok(chunk) => {
-                    let chunk_str = String::from_utf8_lossy(&chunk);
-
-                    // Parse SSE format for Anthropic
-                    for line in chunk_str.lines() {
-                        if line.starts_with("data: ") {
-                            let data = &line[6..];
-
-                            if data == "[DONE]" {
-                                if !full_response.is_empty() {
-                                    let _ = tx
-                                        .send(StreamEvent::Token {
-                                            content: full_response.clone(),
-                                        })
-                                        .await;
-                                }
-                                let _ = tx
-                                    .send(StreamEvent::Done {
-                                        session_id: uuid::Uuid::new_v4(),
-                                    })
-                                    .await;
-                                return Ok(());
-                            }
-
-                            if let Ok(stream_data) =
-                                serde_json::from_str::<AnthropicStreamChunk>(data)
-                            {
-                                if stream_data.chunk_type == "content_block_delta" {
-                                    if let Some(delta) = stream_data.delta {
-                                        if let Some(text) = delta.text {
-                                            full_response.push_str(&text);
-                                            let _ =
-                                                tx.send(StreamEvent::Token { content: text }).await;
-                                        }
-                                    }
-                                }
-
-                                if stream_data.finish_reason.is_some() {
-                                    let _ = tx
-                                        .send(StreamEvent::Done {
-                                            session_id: uuid::Uuid::new_v4(),
-                                        })
-                                        .await;
-                                    return Ok(());
-                                }
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
+            anyhow::bail!("Anthropic API model list failed: {}", response.status());
+        }
+
+        let list: ModelList = response.json().await?;
+        Ok(list
+            .data
+            .into_iter()
+            .map(|model| ModelInfo {
+                id: model.id,
+                name: model.display_name,
+                created: model.created_at.map(|t| t.timestamp()),
+                owned_by: Some("anthropic".to_string()),
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    fn capabilities(&self) -> AgentCapabilities {
+        AgentCapabilities {
+            tools: true,
+            streaming: true,
+            multi_turn: true,
+            mcp: true,
+            ..Default::default()
+        }
+    }
+
+    async fn run(&self, prompt: String, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
+        self.execute(AgentRequest::new(prompt), tx).await
+    }
+
+    async fn execute(&self, request: AgentRequest, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
+        let api_key = self.get_api_key().await?;
+        let base_url = self.get_base_url().await;
+        let model_name = self.get_model_name().await;
+
+        // Build request - Anthropic takes the system prompt as a top-level field
+        // and only accepts user / assistant messages
+        let mut messages: Vec<Message> = request
+            .messages
+            .iter()
+            .filter(|m| matches!(m.role.as_str(), "user" | "assistant" | "tool"))
+            .map(Message::from_chat)
+            .collect();
+
+        // The toolbox's tools, followed by the caller's own
+        let toolbox = ToolBox::new(&self.config, request.working_dir.as_deref()).await;
+        let tools: Vec<Tool> = toolbox
+            .iter()
+            .flat_map(|t| t.definitions())
+            .chain(request.tools.iter().cloned())
+            .map(Tool::from)
+            .collect();
+
+        // Penalties, seed and response_format have no Messages API equivalent.
+        // With thinking enabled the default max_tokens leaves room for the budget.
+        let system = request.system;
+        let sampling = request.sampling;
+        let thinking = self.thinking_budget();
+        let max_tokens = sampling
+            .max_tokens
+            .unwrap_or(DEFAULT_MAX_TOKENS + thinking.unwrap_or(0));
+        let mut usage = TokenUsage::default();
+        let mut iterations = 0;
+
+        let finish_reason = loop {
+            let body = AnthropicRequest {
+                model: model_name.clone(),
+                system: system.clone(),
+                messages: messages.clone(),
+                stream: Some(true),
+                temperature: sampling.temperature,
+                max_tokens,
+                top_p: sampling.top_p,
+                stop_sequences: sampling.stop.clone(),
+                thinking: thinking.map(|budget_tokens| ThinkingConfig {
+                    kind: "enabled",
+                    budget_tokens,
+                }),
+                tools: tools.clone(),
+            };
+
+            // Send streaming request, retrying failures that happen before any output
+            let request = || {
+                self.client
+                    .post(format!("{}/messages", base_url))
+                    .header("x-api-key", &api_key)
+                    .header("anthropic-version", "2023-06-01")
+                    .header("Content-Type", "application/json")
+                    .header("Accept", "text/event-stream")
+                    .json(&body)
+            };
+            let response = send_with_retry(&self.config.retry, self.name(), request, &tx).await?;
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let error_text = response.text().await?;
+                return match serde_json::from_str::<AnthropicErrorResponse>(&error_text) {
+                    Ok(body) => Err(anyhow::anyhow!(
+                        "Anthropic API error ({}): {}: {}",
+                        status,
+                        body.error.kind,
+                        body.error.message
+                    )),
+                    Err(_) => Err(anyhow::anyhow!("Anthropic API error: {}", error_text)),
+                };
+            }
+
+            let turn = stream_turn(response, max_tokens, &tx).await?;
+            // Report the running total after every turn so budgets can stop a long tool loop
+            usage.add(&turn.usage);
+            let _ = tx.send(StreamEvent::Usage { usage }).await;
+
+            let calls = turn.tool_calls();
+            if calls.is_empty() {
+                break turn.finish_reason();
+            }
+            // Calls to the caller's own tools end the request; the caller sends the results back
+            let Some(toolbox) = toolbox.as_ref().filter(|t| t.handles(&calls)) else {
+                for call in calls {
                     let _ = tx
-                        .send(StreamEvent::Error {
-                            message: format!("Stream error: {}", e),
+                        .send(StreamEvent::ToolCallRequested {
+                            id: call.id,
+                            name: call.name,
+                            arguments: call.input.to_string(),
                         })
                         .await;
-                    return Err(anyhow::anyhow!("Stream error: {}", e));
                 }
+                break Some("tool_calls".to_string());
+            };
+
+            iterations += 1;
+            if iterations >= toolbox.max_iterations() {
+                anyhow::bail!(
+                    "Tool loop stopped after {} iterations",
+                    toolbox.max_iterations()
+                );
             }
-        }
 
-        if !full_response.is_empty() {
-            let _ = tx
-                .send(StreamEvent::Token {
-                    content: full_response,
-                })
-                .await;
+            // Every tool_use block must be answered in the next user message
+            messages.push(Message {
+                role: "assistant".to_string(),
+                content: MessageContent::Blocks(turn.blocks),
+            });
+            let mut results = Vec::with_capacity(calls.len());
+            for call in &calls {
+                let result = toolbox.call(call, &tx).await;
+                results.push(RequestBlock::ToolResult {
+                    tool_use_id: result.id,
+                    content: result.output,
+                    is_error: !result.ok,
+                });
+            }
+            messages.push(Message {
+                role: "user".to_string(),
+                content: MessageContent::Blocks(results),
+            });
+        };
+
+        if let Some(reason) = finish_reason {
+            let _ = tx.send(StreamEvent::Stop { reason }).await;
         }
+
         let _ = tx
             .send(StreamEvent::Done {
                 session_id: uuid::Uuid::new_v4(),
@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Weight of the newest sample in the latency moving average
+const LATENCY_ALPHA: f64 = 0.3;
+
+/// When a provider's circuit opens and how long it stays open
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures that open the circuit
+    pub failure_threshold: u32,
+    /// How long an open circuit rejects requests before letting one probe through
+    pub cooldown_ms: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cooldown_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Requests go through
+    Closed,
+    /// Requests are rejected until the cooldown ends
+    Open,
+    /// The cooldown ended; a single probe request decides whether to close
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum Circuit {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { probing: bool },
+}
+
+/// Circuit breaker, in-flight count and latency of one gateway provider
+///
+/// Shared by every `GatewayAgent` through `GATEWAY_MANAGER`, so the state
+/// outlives the per-request agents.
+#[derive(Debug)]
+pub struct ProviderHealth {
+    circuit: Mutex<Circuit>,
+    in_flight: AtomicUsize,
+    /// Moving average of the time to first output, in milliseconds
+    latency_ms: Mutex<Option<f64>>,
+}
+
+impl Default for ProviderHealth {
+    fn default() -> Self {
+        Self {
+            circuit: Mutex::new(Circuit::Closed { failures: 0 }),
+            in_flight: AtomicUsize::new(0),
+            latency_ms: Mutex::new(None),
+        }
+    }
+}
+
+impl ProviderHealth {
+    pub fn state(&self) -> CircuitState {
+        match *self.circuit.lock().unwrap() {
+            Circuit::Closed { .. } => CircuitState::Closed,
+            Circuit::Open { until } if Instant::now() < until => CircuitState::Open,
+            Circuit::Open { .. } | Circuit::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Whether a request would be let through right now
+    pub fn is_available(&self) -> bool {
+        match *self.circuit.lock().unwrap() {
+            Circuit::Closed { .. } => true,
+            Circuit::Open { until } => Instant::now() >= until,
+            Circuit::HalfOpen { probing } => !probing,
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency_ms
+            .lock()
+            .unwrap()
+            .map(|ms| Duration::from_secs_f64(ms / 1000.0))
+    }
+
+    /// Start a request, or `None` while the circuit is open or a probe is running
+    ///
+    /// The first request after the cooldown becomes the half-open probe.
+    pub fn acquire(self: &Arc<Self>) -> Option<InFlight> {
+        {
+            let mut circuit = self.circuit.lock().unwrap();
+            match *circuit {
+                Circuit::Closed { .. } => {}
+                Circuit::Open { until } if Instant::now() < until => return None,
+                Circuit::HalfOpen { probing: true } => return None,
+                Circuit::Open { .. } | Circuit::HalfOpen { .. } => {
+                    *circuit = Circuit::HalfOpen { probing: true };
+                }
+            }
+        }
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        Some(InFlight {
+            health: self.clone(),
+        })
+    }
+
+    /// Close the circuit and fold `latency` into the moving average
+    pub fn record_success(&self, latency: Duration) {
+        *self.circuit.lock().unwrap() = Circuit::Closed { failures: 0 };
+        let sample = latency.as_secs_f64() * 1000.0;
+        let mut latency_ms = self.latency_ms.lock().unwrap();
+        *latency_ms = Some(match *latency_ms {
+            Some(average) => average + LATENCY_ALPHA * (sample - average),
+            None => sample,
+        });
+    }
+
+    /// Count a failure; a failed probe or the threshold-th failure in a row opens the circuit
+    pub fn record_failure(&self, config: &CircuitBreakerConfig) {
+        let mut circuit = self.circuit.lock().unwrap();
+        let failures = match *circuit {
+            Circuit::Closed { failures } => failures + 1,
+            // Failures of requests started before the circuit opened change nothing
+            Circuit::Open { .. } => return,
+            Circuit::HalfOpen { .. } => config.failure_threshold,
+        };
+        *circuit = if failures >= config.failure_threshold.max(1) {
+            Circuit::Open {
+                until: Instant::now() + Duration::from_millis(config.cooldown_ms),
+            }
+        } else {
+            Circuit::Closed { failures }
+        };
+    }
+}
+
+/// A request counted in `ProviderHealth::in_flight` until dropped
+pub struct InFlight {
+    health: Arc<ProviderHealth>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        // A probe that ended without a verdict (e.g. the client went away)
+        // lets the next request probe instead
+        let mut circuit = self.health.circuit.lock().unwrap();
+        if let Circuit::HalfOpen { probing: true } = *circuit {
+            *circuit = Circuit::HalfOpen { probing: false };
+        }
+        drop(circuit);
+        self.health.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,291 @@
+use super::{AgentRegistry, GatewayConfig, GATEWAY_MANAGER};
+use crate::pricing::ModelPrice;
+use crate::types::ModelInfo;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 管理员配置的模型元信息
+///
+/// `models` 的键为目录中的模型 ID（`provider/model`），其中的字段覆盖 provider
+/// 返回的值，provider 没有列出的模型也会加入目录。别名和路由规则在同一个文件中，
+/// 见 `RoutingConfig`。
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CatalogConfig {
+    #[serde(default)]
+    pub models: HashMap<String, ModelMetadata>,
+}
+
+/// 单个模型的元信息，未设置的字段沿用 provider 返回的值
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ModelMetadata {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub owned_by: Option<String>,
+    #[serde(default)]
+    pub context_length: Option<u64>,
+    /// 美元 / 百万 token
+    #[serde(default)]
+    pub pricing: Option<ModelPrice>,
+    #[serde(default)]
+    pub input_modalities: Option<Vec<String>>,
+    #[serde(default)]
+    pub output_modalities: Option<Vec<String>>,
+}
+
+impl CatalogConfig {
+    /// 从 `OPENRUNNER_MODELS_FILE`（JSON）加载，未设置时为空
+    pub fn from_env() -> Self {
+        match std::env::var("OPENRUNNER_MODELS_FILE") {
+            Ok(path) => match std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|s| serde_json::from_str::<CatalogConfig>(&s).map_err(Into::into))
+            {
+                Ok(config) => {
+                    tracing::info!("Loaded {} models from {}", config.models.len(), path);
+                    config
+                }
+                Err(e) => {
+                    tracing::error!("Failed to load models file {}: {}", path, e);
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+/// 模型目录中的一项（`/v1/models` 的格式）
+#[derive(Debug, Clone, Serialize)]
+pub struct CatalogModel {
+    /// `provider/model` 或别名
+    pub id: String,
+    pub object: &'static str,
+    /// 发布时间（Unix 秒），未知时为 0
+    pub created: i64,
+    pub owned_by: String,
+    /// 提供该模型的 gateway provider
+    pub provider: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u64>,
+    /// 美元 / 百万 token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<ModelPrice>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub input_modalities: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub output_modalities: Vec<String>,
+    /// 别名指向的模型
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias_for: Option<String>,
+}
+
+impl CatalogModel {
+    fn new(provider: &str, model: ModelInfo) -> Self {
+        Self {
+            id: format!("{}/{}", provider, model.id),
+            object: "model",
+            created: model.created.unwrap_or_default(),
+            owned_by: model.owned_by.unwrap_or_else(|| provider.to_string()),
+            provider: provider.to_string(),
+            name: model.name,
+            context_length: model.context_length,
+            pricing: model.pricing,
+            input_modalities: model.input_modalities,
+            output_modalities: model.output_modalities,
+            alias_for: None,
+        }
+    }
+
+    fn apply(&mut self, metadata: &ModelMetadata) {
+        if let Some(name) = &metadata.name {
+            self.name = Some(name.clone());
+        }
+        if let Some(owned_by) = &metadata.owned_by {
+            self.owned_by = owned_by.clone();
+        }
+        if let Some(context_length) = metadata.context_length {
+            self.context_length = Some(context_length);
+        }
+        if let Some(pricing) = &metadata.pricing {
+            self.pricing = Some(pricing.clone());
+        }
+        if let Some(modalities) = &metadata.input_modalities {
+            self.input_modalities = modalities.clone();
+        }
+        if let Some(modalities) = &metadata.output_modalities {
+            self.output_modalities = modalities.clone();
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ProviderModels {
+    fetched_at: Instant,
+    models: Vec<ModelInfo>,
+}
+
+/// 模型目录
+///
+/// 按需拉取 `GATEWAY_MANAGER` 中每个 provider 的 `/models`，按 provider 缓存，
+/// 过期后在下一次读取时重新拉取；拉取失败时继续使用上一次的结果。
+/// 目录中的模型 ID 为 `provider/model`，并合并管理员配置的元信息和 gateway 的别名。
+#[derive(Clone)]
+pub struct ModelCatalog {
+    registry: AgentRegistry,
+    config: Arc<CatalogConfig>,
+    providers: Arc<DashMap<String, ProviderModels>>,
+    ttl: Duration,
+    timeout: Duration,
+    /// 同一时间只有一个请求在拉取
+    refreshing: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl ModelCatalog {
+    /// 缓存时间取 `OPENRUNNER_MODELS_TTL_SECS`（默认 600），
+    /// 元信息取 `OPENRUNNER_MODELS_FILE`
+    pub fn new(registry: AgentRegistry) -> Self {
+        let ttl = std::env::var("OPENRUNNER_MODELS_TTL_SECS")
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .unwrap_or(600);
+        Self {
+            registry,
+            config: Arc::new(CatalogConfig::from_env()),
+            providers: Arc::new(DashMap::new()),
+            ttl: Duration::from_secs(ttl),
+            timeout: Duration::from_secs(10),
+            refreshing: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    /// 替换元信息
+    pub fn with_config(mut self, config: CatalogConfig) -> Self {
+        self.config = Arc::new(config);
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// 替换创建 provider agent 用的注册表，缓存随之清空
+    pub fn with_registry(mut self, registry: AgentRegistry) -> Self {
+        self.registry = registry;
+        self.providers = Arc::new(DashMap::new());
+        self
+    }
+
+    /// 全部模型和别名（按 ID 排序）
+    pub async fn list(&self) -> Vec<CatalogModel> {
+        self.refresh_stale().await;
+
+        let mut models: Vec<CatalogModel> = self
+            .providers
+            .iter()
+            .flat_map(|entry| {
+                let provider = entry.key().clone();
+                entry
+                    .models
+                    .clone()
+                    .into_iter()
+                    .map(move |model| CatalogModel::new(&provider, model))
+            })
+            .collect();
+
+        for (id, metadata) in &self.config.models {
+            match models.iter_mut().find(|m| &m.id == id) {
+                Some(model) => model.apply(metadata),
+                None => {
+                    let (provider, model_id) = id.split_once('/').unwrap_or(("", id));
+                    let info = ModelInfo {
+                        id: model_id.to_string(),
+                        ..Default::default()
+                    };
+                    let mut model = CatalogModel::new(provider, info);
+                    model.id = id.clone();
+                    model.apply(metadata);
+                    models.push(model);
+                }
+            }
+        }
+
+        let aliases: Vec<CatalogModel> = GATEWAY_MANAGER
+            .list_aliases()
+            .into_iter()
+            .filter_map(|(alias, target)| {
+                let mut model = models.iter().find(|m| m.id == target)?.clone();
+                model.id = alias;
+                model.alias_for = Some(target);
+                Some(model)
+            })
+            .collect();
+        models.extend(aliases);
+
+        models.sort_by(|a, b| a.id.cmp(&b.id));
+        models
+    }
+
+    /// 按 ID 或别名查找模型
+    pub async fn get(&self, id: &str) -> Option<CatalogModel> {
+        self.list().await.into_iter().find(|m| m.id == id)
+    }
+
+    /// 重新拉取缓存过期或尚未拉取的 provider
+    async fn refresh_stale(&self) {
+        let _guard = self.refreshing.lock().await;
+
+        let providers: Vec<(String, GatewayConfig)> = GATEWAY_MANAGER
+            .list_providers()
+            .into_iter()
+            .filter_map(|name| GATEWAY_MANAGER.get_provider(&name).map(|c| (name, c)))
+            .collect();
+        self.providers
+            .retain(|name, _| providers.iter().any(|(n, _)| n == name));
+
+        let stale: Vec<&(String, GatewayConfig)> = providers
+            .iter()
+            .filter(|(name, _)| {
+                self.providers
+                    .get(name)
+                    .is_none_or(|cached| cached.fetched_at.elapsed() >= self.ttl)
+            })
+            .collect();
+        let fetches = stale.iter().map(|(_, config)| self.fetch(config));
+        let results = futures::future::join_all(fetches).await;
+
+        for ((name, _), result) in stale.into_iter().zip(results) {
+            let models = match result {
+                Ok(models) => models,
+                Err(e) => {
+                    tracing::warn!("Failed to list models of provider {}: {}", name, e);
+                    self.providers
+                        .get(name)
+                        .map(|cached| cached.models.clone())
+                        .unwrap_or_default()
+                }
+            };
+            self.providers.insert(
+                name.clone(),
+                ProviderModels {
+                    fetched_at: Instant::now(),
+                    models,
+                },
+            );
+        }
+    }
+
+    async fn fetch(&self, config: &GatewayConfig) -> anyhow::Result<Vec<ModelInfo>> {
+        let agent = self.registry.create(&config.agent_config())?;
+        match tokio::time::timeout(self.timeout, agent.list_models()).await {
+            Ok(result) => result,
+            Err(_) => anyhow::bail!("Timed out after {}s", self.timeout.as_secs()),
+        }
+    }
+}
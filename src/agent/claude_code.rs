@@ -1,7 +1,9 @@
-use super::Agent;
-use crate::types::{AgentConfig, StreamEvent};
+use super::{Agent, AgentCapabilities, SandboxCommand};
+use crate::types::{AgentConfig, StreamEvent, TokenUsage};
 use anyhow::Result;
 use async_trait::async_trait;
+use serde::Deserialize;
+use std::path::PathBuf;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::sync::mpsc;
 
@@ -10,10 +12,87 @@ pub struct ClaudeCodeAgent {
     config: AgentConfig,
 }
 
+/// `--output-format json` 的结果（stream-json 的最后一行格式相同）
+#[derive(Debug, Deserialize)]
+struct ClaudeResult {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    result: Option<String>,
+    #[serde(default)]
+    total_cost_usd: Option<f64>,
+    #[serde(default)]
+    usage: Option<TokenUsage>,
+}
+
+impl ClaudeResult {
+    fn parse(line: &str) -> Option<Self> {
+        serde_json::from_str::<Self>(line)
+            .ok()
+            .filter(|r| r.kind == "result")
+    }
+
+    fn usage(&self) -> TokenUsage {
+        TokenUsage {
+            cost_usd: self.total_cost_usd,
+            ..self.usage.unwrap_or_default()
+        }
+    }
+}
+
 impl ClaudeCodeAgent {
     pub fn new(config: AgentConfig) -> Self {
         Self { config }
     }
+
+    /// 根据配置的 MCP server 生成 `--mcp-config` 文件
+    /// 返回临时目录（文件为其中的 mcp.json）
+    fn create_mcp_config(&self) -> Result<Option<PathBuf>> {
+        if self.config.mcp_servers.is_empty() {
+            return Ok(None);
+        }
+
+        let servers: serde_json::Map<String, serde_json::Value> = self
+            .config
+            .mcp_servers
+            .iter()
+            .map(|server| {
+                let value = match server.url {
+                    Some(ref url) => serde_json::json!({
+                        "type": "http",
+                        "url": url,
+                        "headers": server.headers,
+                    }),
+                    None => serde_json::json!({
+                        "type": "stdio",
+                        "command": server.command,
+                        "args": server.args,
+                        "env": server.env,
+                    }),
+                };
+                (server.name.clone(), value)
+            })
+            .collect();
+        let config = serde_json::json!({ "mcpServers": servers });
+
+        let temp_dir = std::env::temp_dir().join(format!("claude-mcp-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&temp_dir)?;
+
+        // 配置中含有解析后的凭据，只允许当前用户读取
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        std::io::Write::write_all(
+            &mut options.open(temp_dir.join("mcp.json"))?,
+            serde_json::to_string_pretty(&config)?.as_bytes(),
+        )?;
+
+        Ok(Some(temp_dir))
+    }
 }
 
 #[async_trait]
@@ -22,6 +101,15 @@ impl Agent for ClaudeCodeAgent {
         "claude_code"
     }
 
+    fn capabilities(&self) -> AgentCapabilities {
+        AgentCapabilities {
+            tools: true,
+            streaming: true,
+            mcp: true,
+            ..Default::default()
+        }
+    }
+
     async fn health_check(&self) -> Result<()> {
         let output = tokio::process::Command::new("claude")
             .arg("--version")
@@ -35,7 +123,7 @@ impl Agent for ClaudeCodeAgent {
     }
 
     async fn run(&self, prompt: String, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
-        let mut cmd = tokio::process::Command::new("claude");
+        let mut cmd = SandboxCommand::new("claude", self.config.sandbox.as_ref());
 
         // -p/--print: 非交互模式，输出后退出
         // --dangerously-skip-permissions: 跳过权限检查（适合自动化）
@@ -53,14 +141,52 @@ impl Agent for ClaudeCodeAgent {
             cmd.env(k, v);
         }
 
+        // MCP server
+        let mcp_dir = self.create_mcp_config()?;
+        if let Some(ref dir) = mcp_dir {
+            cmd.arg("--mcp-config")
+                .arg(dir.join("mcp.json").to_string_lossy());
+            cmd.bind(dir);
+        }
+
         // 额外参数（如 --model, --output-format 等）
         for arg in &self.config.extra_args {
             cmd.arg(arg);
         }
 
+        // 未指定输出格式时使用 json，从结果中读取 token 用量和费用
+        let json_output = !self
+            .config
+            .extra_args
+            .iter()
+            .any(|a| a.starts_with("--output-format"));
+        if json_output {
+            cmd.arg("--output-format").arg("json");
+        }
+
         // prompt 作为位置参数
         cmd.arg(&prompt);
 
+        let result = self.run_command(cmd, json_output, tx).await;
+
+        if let Some(ref dir) = mcp_dir {
+            if let Err(e) = std::fs::remove_dir_all(dir) {
+                tracing::warn!("Failed to cleanup MCP config dir {:?}: {}", dir, e);
+            }
+        }
+        result
+    }
+}
+
+impl ClaudeCodeAgent {
+    /// `json_output` 为 true 时只输出结果文本，否则原样转发每一行
+    async fn run_command(
+        &self,
+        cmd: SandboxCommand,
+        json_output: bool,
+        tx: mpsc::Sender<StreamEvent>,
+    ) -> Result<()> {
+        let mut cmd = cmd.build()?;
         cmd.stdout(std::process::Stdio::piped());
         cmd.stderr(std::process::Stdio::piped());
 
@@ -71,22 +197,31 @@ impl Agent for ClaudeCodeAgent {
             .ok_or_else(|| anyhow::anyhow!("Failed to capture stdout"))?;
 
         let mut reader = BufReader::new(stdout).lines();
+        let mut usage = None;
 
         // 流式读取输出
         while let Some(line) = reader.next_line().await? {
-            if tx
-                .send(StreamEvent::Token {
-                    content: format!("{}\n", line),
-                })
-                .await
-                .is_err()
-            {
+            let content = match ClaudeResult::parse(&line) {
+                Some(result) => {
+                    usage = Some(result.usage());
+                    match result.result {
+                        Some(text) if json_output => format!("{}\n", text),
+                        _ => format!("{}\n", line),
+                    }
+                }
+                None => format!("{}\n", line),
+            };
+            if tx.send(StreamEvent::Token { content }).await.is_err() {
                 // 接收方已关闭，终止进程
                 child.kill().await?;
                 break;
             }
         }
 
+        if let Some(usage) = usage {
+            let _ = tx.send(StreamEvent::Usage { usage }).await;
+        }
+
         let status = child.wait().await?;
         if !status.success() {
             anyhow::bail!("claude exited with status: {}", status);
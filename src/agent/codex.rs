@@ -1,7 +1,8 @@
-use super::Agent;
-use crate::types::{AgentConfig, StreamEvent};
+use super::{Agent, AgentCapabilities, SandboxCommand};
+use crate::types::{AgentConfig, StreamEvent, TokenUsage};
 use anyhow::Result;
 use async_trait::async_trait;
+use serde::Deserialize;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::sync::mpsc;
 
@@ -19,12 +20,61 @@ impl CodexAgent {
     }
 }
 
+/// `codex exec --json` 输出的事件（只解析用到的字段）
+#[derive(Debug, Deserialize)]
+struct CodexEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    item: Option<CodexItem>,
+    #[serde(default)]
+    usage: Option<CodexUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CodexItem {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: Option<String>,
+}
+
+/// `turn.completed` 的用量，`input_tokens` 包含命中缓存的部分
+#[derive(Debug, Deserialize)]
+struct CodexUsage {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    cached_input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+}
+
+impl From<CodexUsage> for TokenUsage {
+    fn from(usage: CodexUsage) -> Self {
+        Self {
+            input_tokens: usage.input_tokens.saturating_sub(usage.cached_input_tokens),
+            output_tokens: usage.output_tokens,
+            cache_read_input_tokens: usage.cached_input_tokens,
+            ..Default::default()
+        }
+    }
+}
+
 #[async_trait]
 impl Agent for CodexAgent {
     fn name(&self) -> &str {
         "codex"
     }
 
+    fn capabilities(&self) -> AgentCapabilities {
+        AgentCapabilities {
+            tools: true,
+            streaming: true,
+            ..Default::default()
+        }
+    }
+
     async fn health_check(&self) -> Result<()> {
         let output = tokio::process::Command::new("codex")
             .arg("--version")
@@ -38,7 +88,7 @@ impl Agent for CodexAgent {
     }
 
     async fn run(&self, prompt: String, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
-        let mut cmd = tokio::process::Command::new("codex");
+        let mut cmd = SandboxCommand::new("codex", self.config.sandbox.as_ref());
 
         // 使用 exec 子命令进行非交互执行
         cmd.arg("exec");
@@ -51,6 +101,7 @@ impl Agent for CodexAgent {
         // 工作目录
         if let Some(ref dir) = self.config.working_dir {
             cmd.arg("-C").arg(dir);
+            cmd.current_dir(dir);
         }
 
         // 环境变量
@@ -63,9 +114,16 @@ impl Agent for CodexAgent {
             cmd.arg(arg);
         }
 
+        // 未指定 --json 时由这里打开，只输出 agent 消息并读取 token 用量
+        let json_output = !self.config.extra_args.iter().any(|a| a == "--json");
+        if json_output {
+            cmd.arg("--json");
+        }
+
         // prompt 作为最后一个位置参数
         cmd.arg(&prompt);
 
+        let mut cmd = cmd.build()?;
         cmd.stdout(std::process::Stdio::piped());
         cmd.stderr(std::process::Stdio::piped());
 
@@ -76,20 +134,40 @@ impl Agent for CodexAgent {
             .ok_or_else(|| anyhow::anyhow!("Failed to capture stdout"))?;
 
         let mut reader = BufReader::new(stdout).lines();
+        let mut usage: Option<TokenUsage> = None;
 
         while let Some(line) = reader.next_line().await? {
-            if tx
-                .send(StreamEvent::Token {
-                    content: format!("{}\n", line),
-                })
-                .await
-                .is_err()
-            {
+            let content = match serde_json::from_str::<CodexEvent>(&line) {
+                Ok(event) => {
+                    if let Some(u) = event.usage.filter(|_| event.kind == "turn.completed") {
+                        usage.get_or_insert_with(TokenUsage::default).add(&u.into());
+                    }
+                    if !json_output {
+                        format!("{}\n", line)
+                    } else {
+                        match event.item {
+                            Some(CodexItem {
+                                kind,
+                                text: Some(text),
+                            }) if event.kind == "item.completed" && kind == "agent_message" => {
+                                format!("{}\n", text)
+                            }
+                            _ => continue,
+                        }
+                    }
+                }
+                Err(_) => format!("{}\n", line),
+            };
+            if tx.send(StreamEvent::Token { content }).await.is_err() {
                 child.kill().await?;
                 break;
             }
         }
 
+        if let Some(usage) = usage {
+            let _ = tx.send(StreamEvent::Usage { usage }).await;
+        }
+
         let status = child.wait().await?;
         if !status.success() {
             // 读取 stderr 获取错误信息
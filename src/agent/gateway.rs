@@ -1,10 +1,31 @@
-use crate::agent::{create_agent, Agent};
-use crate::types::{AgentConfig, StreamEvent};
+use crate::agent::breaker::{CircuitBreakerConfig, ProviderHealth};
+use crate::agent::rate_limit::{estimate_tokens, RateLimitConfig, RateLimiter};
+use crate::agent::{create_agent, Agent, AgentCapabilities};
+use crate::types::{AgentConfig, AgentRequest, StreamEvent};
 use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
+/// Upstream APIs a registration can point at: the agent types `GatewayConfig::agent_config`
+/// knows the env names of
+pub const PROVIDER_TYPES: &[&str] = &["openai", "anthropic", "openrouter"];
+
+fn validate_provider_type(provider: &str) -> Result<(), String> {
+    if PROVIDER_TYPES.contains(&provider) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Unknown provider '{}': use one of {}",
+            provider,
+            PROVIDER_TYPES.join(", ")
+        ))
+    }
+}
+
 /// Gateway routing configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GatewayConfig {
@@ -14,105 +35,453 @@ pub struct GatewayConfig {
     pub base_url: Option<String>,
     pub fallback_providers: Vec<String>,
     pub load_balancing: Option<LoadBalancing>,
+    /// Fail over to the next provider even after output was streamed,
+    /// sending `StreamEvent::Restart` first; otherwise only before the first token
+    #[serde(default)]
+    pub restart_on_failure: bool,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Requests and tokens per minute allowed through this registration's key
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+impl GatewayConfig {
+    /// Check a registration made through `/api/providers`
+    pub fn validate(&self) -> Result<(), String> {
+        validate_provider_type(&self.provider)?;
+        if self.base_url.as_deref().is_some_and(|u| !u.is_empty()) && !self.has_own_key() {
+            return Err("A provider with a base_url needs its own api_key".to_string());
+        }
+        self.rate_limit
+            .as_ref()
+            .map_or(Ok(()), RateLimitConfig::validate)
+    }
+
+    /// Whether requests carry this registration's key rather than the server's
+    pub fn has_own_key(&self) -> bool {
+        self.api_key.as_deref().is_some_and(|k| !k.is_empty())
+    }
+
+    /// Agent config for the primary provider
+    ///
+    /// The API key and base URL go under the env names that provider reads,
+    /// and only when set, so the provider's own defaults apply otherwise.
+    /// A base URL always comes with its own key, empty if none is set, so the
+    /// server's key is never sent to it.
+    pub fn agent_config(&self) -> AgentConfig {
+        let prefix = match self.provider.as_str() {
+            "openai" => "OPENAI",
+            "anthropic" => "ANTHROPIC",
+            _ => "OPENROUTER",
+        };
+        let mut env = HashMap::new();
+        if let Some(api_key) = self.api_key.clone().filter(|k| !k.is_empty()) {
+            env.insert(format!("{}_API_KEY", prefix), api_key);
+        }
+        if let Some(base_url) = self.base_url.clone().filter(|u| !u.is_empty()) {
+            env.insert(format!("{}_BASE_URL", prefix), base_url);
+            env.entry(format!("{}_API_KEY", prefix)).or_default();
+        }
+        AgentConfig {
+            agent_type: self.provider.clone(),
+            model: self.model.clone(),
+            env,
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum LoadBalancing {
     RoundRobin,
     Random,
+    /// Fewest requests in flight, earlier providers first on a tie
     LeastLoaded,
+    /// Random, weighted by the inverse of each provider's average time to first output
+    LatencyWeighted,
+}
+
+/// Sends model names matching `pattern` to a registered provider
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteRule {
+    /// Glob on the model name; `*` matches any run of characters
+    pub pattern: String,
+    pub provider: String,
+}
+
+/// Model aliases and routing rules, from `OPENRUNNER_MODELS_FILE`
+///
+/// Aliases map a name to a `provider/model` ID. Rules are tried in order,
+/// before the built-in ones.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RoutingConfig {
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    #[serde(default)]
+    pub routes: Vec<RouteRule>,
+}
+
+impl RoutingConfig {
+    pub fn from_env() -> Self {
+        match std::env::var("OPENRUNNER_MODELS_FILE") {
+            Ok(path) => match std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|s| serde_json::from_str::<RoutingConfig>(&s).map_err(Into::into))
+            {
+                Ok(config) => {
+                    tracing::info!(
+                        "Loaded {} aliases and {} routes from {}",
+                        config.aliases.len(),
+                        config.routes.len(),
+                        path
+                    );
+                    config
+                }
+                Err(e) => {
+                    tracing::error!("Failed to load routes from {}: {}", path, e);
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+/// Routing rules registered by `init_default_providers`, after the configured ones
+const DEFAULT_ROUTES: &[(&str, &str)] = &[
+    ("gpt-*", "openai"),
+    ("chatgpt-*", "openai"),
+    ("o1*", "openai"),
+    ("o3*", "openai"),
+    ("o4*", "openai"),
+    ("claude-*", "anthropic"),
+    // OpenRouter takes `vendor/model` names for everything else
+    ("*", "openrouter"),
+];
+
+/// Glob match where `*` matches any run of characters, including none
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` at all
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// A model name resolved to a registered provider
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// Name the provider is registered under
+    pub name: String,
+    /// The provider's config, with `model` set to the upstream model name
+    pub config: GatewayConfig,
+}
+
+impl Route {
+    fn new(name: &str, mut config: GatewayConfig, model: &str) -> Self {
+        // An empty model name keeps the provider's default model
+        if !model.is_empty() {
+            config.model = Some(model.to_string());
+        }
+        Self {
+            name: name.to_string(),
+            config,
+        }
+    }
 }
 
 /// Gateway agent that routes to different LLM providers
+///
+/// A failed provider is retried on the next one only until the first token
+/// is forwarded, unless `restart_on_failure` is set. Providers whose circuit
+/// is open are skipped, as are those whose rate limit has no capacity left
+/// within its `max_wait_ms`.
 pub struct GatewayAgent {
     config: GatewayConfig,
+    /// Name the primary provider is registered under
+    name: String,
     providers: Vec<String>,
     current_provider_index: std::sync::atomic::AtomicUsize,
+    /// The caller's agent config, carried over to the provider agents
+    base: AgentConfig,
+    /// Where fallbacks, breakers and rate limiters are looked up
+    manager: GatewayManager,
+}
+
+/// How a single provider attempt failed
+struct AttemptError {
+    error: anyhow::Error,
+    /// Output had already been forwarded to the caller
+    streamed: bool,
+    /// Tokens the provider reported before failing
+    used_tokens: u64,
 }
 
 impl GatewayAgent {
     pub fn new(config: GatewayConfig) -> Self {
         let mut providers = vec![config.provider.clone()];
-        providers.extend(config.fallback_providers.iter().cloned());
+        for fallback in &config.fallback_providers {
+            if !providers.contains(fallback) {
+                providers.push(fallback.clone());
+            }
+        }
 
         Self {
+            name: config.provider.clone(),
             config,
             providers,
             current_provider_index: std::sync::atomic::AtomicUsize::new(0),
+            base: AgentConfig::default(),
+            manager: GATEWAY_MANAGER.clone(),
         }
     }
 
-    async fn get_next_provider(&self) -> String {
-        match self.config.load_balancing {
-            Some(LoadBalancing::RoundRobin) => {
+    /// Look providers up in `manager` (e.g. one user's registrations)
+    /// instead of `GATEWAY_MANAGER`
+    pub fn with_manager(mut self, manager: GatewayManager) -> Self {
+        self.manager = manager;
+        self
+    }
+
+    /// Track the primary provider's health under its registered name rather
+    /// than its type, so registrations of the same type don't share a breaker
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self.providers[0] = self.name.clone();
+        self
+    }
+
+    /// Pass the caller's env, tools, MCP servers and timeouts on to the
+    /// provider agents; API keys and base URLs in its env are ignored
+    pub fn with_base_config(mut self, base: AgentConfig) -> Self {
+        self.base = base;
+        self
+    }
+
+    /// Providers to try in order: the load-balanced pick first, then the
+    /// rest in configured order. Providers with an open circuit aren't picked.
+    fn attempt_order(&self) -> Vec<String> {
+        let health: Vec<Arc<ProviderHealth>> = self
+            .providers
+            .iter()
+            .map(|p| self.manager.provider_health(p))
+            .collect();
+        let available: Vec<usize> = (0..self.providers.len())
+            .filter(|&i| health[i].is_available())
+            .collect();
+
+        let first = match (&self.config.load_balancing, available.is_empty()) {
+            (_, true) | (None, _) => 0,
+            (Some(LoadBalancing::RoundRobin), _) => {
                 let idx = self
                     .current_provider_index
                     .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                self.providers[idx % self.providers.len()].clone()
+                available[idx % available.len()]
             }
-            Some(LoadBalancing::Random) => {
+            (Some(LoadBalancing::Random), _) => {
                 use rand::Rng;
-                let mut rng = rand::thread_rng();
-                let idx = rng.gen_range(0..self.providers.len());
-                self.providers[idx].clone()
+                available[rand::thread_rng().gen_range(0..available.len())]
             }
-            Some(LoadBalancing::LeastLoaded) => {
-                // For now, just use round robin as a simple load balancing
-                let idx = self
-                    .current_provider_index
-                    .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-                self.providers[idx % self.providers.len()].clone()
+            (Some(LoadBalancing::LeastLoaded), _) => available
+                .iter()
+                .copied()
+                .min_by_key(|&i| health[i].in_flight())
+                .unwrap_or_default(),
+            (Some(LoadBalancing::LatencyWeighted), _) => {
+                let latencies: Vec<Option<Duration>> =
+                    available.iter().map(|&i| health[i].latency()).collect();
+                available[pick_by_latency(&latencies)]
             }
-            None => self.providers[0].clone(),
+        };
+
+        let mut order = vec![self.providers[first].clone()];
+        order.extend(
+            self.providers
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != first)
+                .map(|(_, p)| p.clone()),
+        );
+        order
+    }
+
+    /// Breaker settings of a provider, from its registration when there is one
+    fn breaker_config(&self, provider: &str) -> CircuitBreakerConfig {
+        if provider == self.name {
+            return self.config.circuit_breaker;
         }
+        self.manager
+            .get_provider(provider)
+            .map(|config| config.circuit_breaker)
+            .unwrap_or_default()
     }
 
+    /// The configured credentials and model belong to the primary provider.
+    /// Fallbacks registered in the manager use their own config, others
+    /// only their environment.
     async fn create_provider_agent(&self, provider: &str) -> Result<Box<dyn Agent>> {
-        let agent_config = AgentConfig {
-            agent_type: provider.to_string(),
-            model: self.config.model.clone(),
-            env: std::collections::HashMap::from([
-                (
-                    "OPENROUTER_API_KEY".to_string(),
-                    self.config.api_key.clone().unwrap_or_default(),
-                ),
-                (
-                    "OPENROUTER_BASE_URL".to_string(),
-                    self.config.base_url.clone().unwrap_or_default(),
-                ),
-            ]),
-            ..Default::default()
+        let provider_config = if provider == self.name {
+            self.config.agent_config()
+        } else {
+            match self.manager.get_provider(provider) {
+                Some(config) => config.agent_config(),
+                None => AgentConfig {
+                    agent_type: provider.to_string(),
+                    model: self.config.model.clone(),
+                    ..Default::default()
+                },
+            }
         };
 
-        create_agent(&agent_config)
+        // The caller can't swap a provider's credentials or point it elsewhere
+        let mut env: HashMap<String, String> = self
+            .base
+            .env
+            .iter()
+            .filter(|(key, _)| !is_provider_key(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        env.extend(provider_config.env);
+        create_agent(&AgentConfig {
+            agent_type: provider_config.agent_type,
+            model: provider_config.model,
+            env,
+            ..self.base.clone()
+        })
     }
 
+    /// Run one provider, forwarding its events; returns the time to first
+    /// output and the tokens used
+    ///
+    /// Errors and `Done` are held back: a failed attempt may still be retried
+    /// elsewhere, and the caller reports the final outcome.
     async fn try_provider(
         &self,
         provider: &str,
-        prompt: String,
-        tx: mpsc::Sender<StreamEvent>,
-    ) -> Result<()> {
-        let agent = self.create_provider_agent(provider).await?;
+        request: AgentRequest,
+        tx: &mpsc::Sender<StreamEvent>,
+    ) -> std::result::Result<(Duration, u64), AttemptError> {
+        let agent = self
+            .create_provider_agent(provider)
+            .await
+            .map_err(|error| AttemptError {
+                error,
+                streamed: false,
+                used_tokens: 0,
+            })?;
 
         // Create a new channel for this attempt
         let (attempt_tx, mut attempt_rx) = mpsc::channel(100);
+        let started = Instant::now();
+        let mut first_output = None;
+        let mut error_event = None;
+        let mut done = None;
+        let mut used_tokens = 0;
 
         // Spawn the agent
-        let agent_handle = tokio::spawn(async move { agent.run(prompt, attempt_tx).await });
+        let agent_handle = tokio::spawn(async move { agent.execute(request, attempt_tx).await });
 
         // Forward events from attempt channel to main channel
         while let Some(event) = attempt_rx.recv().await {
+            match event {
+                StreamEvent::Error { message, .. } => {
+                    error_event = Some(message);
+                    continue;
+                }
+                StreamEvent::Done { .. } => {
+                    done = Some(event);
+                    continue;
+                }
+                StreamEvent::Token { .. }
+                | StreamEvent::Thinking { .. }
+                | StreamEvent::ToolCallStarted { .. }
+                | StreamEvent::ToolCallFinished { .. }
+                | StreamEvent::ToolCallRequested { .. } => {
+                    first_output.get_or_insert_with(|| started.elapsed());
+                }
+                // Usage is cumulative, the last report counts
+                StreamEvent::Usage { ref usage } => used_tokens = usage.total_tokens(),
+                StreamEvent::Stop { .. }
+                | StreamEvent::Attempt { .. }
+                | StreamEvent::Restart { .. } => {}
+            }
             if tx.send(event).await.is_err() {
                 // Main receiver disconnected
                 break;
             }
         }
 
-        // Wait for agent to complete
-        agent_handle.await??;
-        Ok(())
+        let streamed = first_output.is_some();
+        let result = match agent_handle.await {
+            Ok(Ok(())) => match error_event {
+                Some(message) => Err(anyhow::anyhow!(message)),
+                None => Ok(()),
+            },
+            Ok(Err(e)) => Err(e),
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(()) => {
+                if let Some(done) = done {
+                    let _ = tx.send(done).await;
+                }
+                Ok((
+                    first_output.unwrap_or_else(|| started.elapsed()),
+                    used_tokens,
+                ))
+            }
+            Err(error) => Err(AttemptError {
+                error,
+                streamed,
+                used_tokens,
+            }),
+        }
+    }
+}
+
+/// Env keys that hold a provider's API key or endpoint
+fn is_provider_key(key: &str) -> bool {
+    key.ends_with("_API_KEY") || key.ends_with("_BASE_URL")
+}
+
+/// Index of a provider picked at random with weight `1 / latency`
+///
+/// Providers without a measurement get the best weight so they get measured.
+fn pick_by_latency(latencies: &[Option<Duration>]) -> usize {
+    use rand::Rng;
+
+    let weight = |latency: Duration| 1.0 / latency.as_secs_f64().max(0.001);
+    let best = latencies
+        .iter()
+        .flatten()
+        .map(|&l| weight(l))
+        .fold(None, |best: Option<f64>, w| {
+            Some(best.map_or(w, |b| b.max(w)))
+        })
+        .unwrap_or(1.0);
+    let weights: Vec<f64> = latencies.iter().map(|l| l.map_or(best, weight)).collect();
+
+    let mut target = rand::thread_rng().gen_range(0.0..weights.iter().sum::<f64>());
+    for (i, w) in weights.iter().enumerate() {
+        if target < *w {
+            return i;
+        }
+        target -= w;
     }
+    weights.len() - 1
 }
 
 #[async_trait]
@@ -131,57 +500,275 @@ impl Agent for GatewayAgent {
         anyhow::bail!("No healthy providers available")
     }
 
+    fn capabilities(&self) -> AgentCapabilities {
+        AgentCapabilities {
+            streaming: true,
+            multi_turn: true,
+            ..Default::default()
+        }
+    }
+
     async fn run(&self, prompt: String, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
-        let provider = self.get_next_provider().await;
+        self.execute(AgentRequest::new(prompt), tx).await
+    }
 
-        match self
-            .try_provider(&provider, prompt.clone(), tx.clone())
-            .await
-        {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                // If this provider failed, try fallback providers
-                for fallback_provider in &self.config.fallback_providers {
-                    if let Ok(_) = self
-                        .try_provider(fallback_provider, prompt.clone(), tx.clone())
-                        .await
-                    {
-                        return Ok(());
-                    }
+    async fn execute(&self, request: AgentRequest, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
+        let reserved = estimate_tokens(&request);
+        let mut last_error = None;
+        for provider in self.attempt_order() {
+            let health = self.manager.provider_health(&provider);
+            let Some(_in_flight) = health.acquire() else {
+                tracing::debug!("Skipping provider {}: circuit open", provider);
+                continue;
+            };
+            // Running out of client-side capacity says nothing about the provider's health
+            let limiter = self.manager.rate_limiter(&provider);
+            if let Some(limiter) = &limiter {
+                if let Err(e) = limiter.acquire(&provider, reserved).await {
+                    tracing::debug!("Skipping provider {}: {}", provider, e);
+                    last_error = Some(e.into());
+                    continue;
+                }
+            }
+
+            let result = self.try_provider(&provider, request.clone(), &tx).await;
+            if let Some(limiter) = &limiter {
+                let used = match &result {
+                    Ok((_, used)) => *used,
+                    Err(failed) => failed.used_tokens,
+                };
+                limiter.settle(reserved, used);
+            }
+            let failed = match result {
+                Ok((latency, _)) => {
+                    health.record_success(latency);
+                    return Ok(());
+                }
+                Err(failed) => failed,
+            };
+            health.record_failure(&self.breaker_config(&provider));
+            tracing::warn!("Provider {} failed: {}", provider, failed.error);
+
+            if failed.streamed {
+                // The caller already has part of this provider's answer
+                if !self.config.restart_on_failure {
+                    return Err(failed.error);
                 }
-                Err(e)
+                let _ = tx
+                    .send(StreamEvent::Restart {
+                        provider: provider.clone(),
+                        reason: failed.error.to_string(),
+                    })
+                    .await;
             }
+            last_error = Some(failed.error);
         }
+        Err(last_error.unwrap_or_else(|| {
+            anyhow::anyhow!("No healthy providers available: all circuits are open")
+        }))
     }
 }
 
 /// Gateway manager for handling multiple providers
+///
+/// Clones share the same registrations. A scoped manager (see `scoped`)
+/// holds its own providers and falls back to its parent's.
+#[derive(Clone)]
 pub struct GatewayManager {
     providers: std::sync::Arc<dashmap::DashMap<String, GatewayConfig>>,
+    health: Arc<dashmap::DashMap<String, Arc<ProviderHealth>>>,
+    limiters: Arc<dashmap::DashMap<String, Arc<RateLimiter>>>,
+    aliases: Arc<dashmap::DashMap<String, String>>,
+    routes: Arc<RwLock<Vec<RouteRule>>>,
+    /// Consulted for providers not registered here
+    parent: Option<Arc<GatewayManager>>,
+}
+
+/// Only the provider names: configs carry API keys
+impl std::fmt::Debug for GatewayManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GatewayManager")
+            .field("providers", &self.list_providers())
+            .finish_non_exhaustive()
+    }
 }
 
 impl GatewayManager {
     pub fn new() -> Self {
         Self {
             providers: std::sync::Arc::new(dashmap::DashMap::new()),
+            health: Arc::new(dashmap::DashMap::new()),
+            limiters: Arc::new(dashmap::DashMap::new()),
+            aliases: Arc::new(dashmap::DashMap::new()),
+            routes: Arc::new(RwLock::new(Vec::new())),
+            parent: None,
+        }
+    }
+
+    /// An empty manager for one user's providers
+    ///
+    /// Providers registered here shadow this manager's ones of the same name;
+    /// other names, aliases and routing rules come from this manager.
+    pub fn scoped(&self) -> Self {
+        Self {
+            providers: Arc::new(dashmap::DashMap::new()),
+            health: Arc::new(dashmap::DashMap::new()),
+            limiters: Arc::new(dashmap::DashMap::new()),
+            aliases: self.aliases.clone(),
+            routes: self.routes.clone(),
+            parent: Some(Arc::new(self.clone())),
         }
     }
 
+    /// The manager `name` is registered in: this one, else the nearest parent
+    /// that has it, else the root
+    fn owner(&self, name: &str) -> &GatewayManager {
+        match &self.parent {
+            Some(parent) if !self.providers.contains_key(name) => parent.owner(name),
+            _ => self,
+        }
+    }
+
+    /// Register or replace a provider; its rate limiter keeps its levels
+    /// unless the limits changed
     pub fn register_provider(&self, name: String, config: GatewayConfig) {
+        match config.rate_limit {
+            Some(limits) => {
+                let unchanged = self
+                    .limiters
+                    .get(&name)
+                    .is_some_and(|limiter| *limiter.config() == limits);
+                if !unchanged {
+                    self.limiters
+                        .insert(name.clone(), Arc::new(RateLimiter::new(limits)));
+                }
+            }
+            None => {
+                self.limiters.remove(&name);
+            }
+        }
         self.providers.insert(name, config);
     }
 
+    /// Remove a provider registered here, with its breaker and rate limiter
+    pub fn remove_provider(&self, name: &str) -> bool {
+        self.health.remove(name);
+        self.limiters.remove(name);
+        self.providers.remove(name).is_some()
+    }
+
     pub fn get_provider(&self, name: &str) -> Option<GatewayConfig> {
+        self.owner(name)
+            .providers
+            .get(name)
+            .map(|entry| entry.clone())
+    }
+
+    /// A provider registered here rather than in a parent
+    pub fn get_own_provider(&self, name: &str) -> Option<GatewayConfig> {
         self.providers.get(name).map(|entry| entry.clone())
     }
 
-    pub fn list_providers(&self) -> Vec<String> {
+    /// Circuit breaker and load of a provider, created on first use
+    pub fn provider_health(&self, name: &str) -> Arc<ProviderHealth> {
+        self.owner(name)
+            .health
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Client-side rate limiter of a registered provider, if it has limits
+    pub fn rate_limiter(&self, name: &str) -> Option<Arc<RateLimiter>> {
+        self.owner(name)
+            .limiters
+            .get(name)
+            .map(|entry| entry.clone())
+    }
+
+    /// Providers registered here rather than in a parent
+    pub fn list_own_providers(&self) -> Vec<String> {
         self.providers
             .iter()
             .map(|entry| entry.key().clone())
             .collect()
     }
 
+    /// Providers visible here: own ones first, then the parent's not shadowed by them
+    pub fn list_providers(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .providers
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect();
+        if let Some(parent) = &self.parent {
+            for name in parent.list_providers() {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    /// Point `alias` at a `provider/model` ID
+    pub fn register_alias(&self, alias: String, target: String) {
+        self.aliases.insert(alias, target);
+    }
+
+    pub fn list_aliases(&self) -> Vec<(String, String)> {
+        self.aliases
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    /// Append a routing rule; rules are tried in the order they were added
+    pub fn add_route(&self, rule: RouteRule) {
+        self.routes.write().unwrap().push(rule);
+    }
+
+    pub fn list_routes(&self) -> Vec<RouteRule> {
+        self.routes.read().unwrap().clone()
+    }
+
+    /// Register configured aliases and routing rules
+    pub fn load_routing(&self, config: RoutingConfig) {
+        for (alias, target) in config.aliases {
+            self.register_alias(alias, target);
+        }
+        for rule in config.routes {
+            self.add_route(rule);
+        }
+    }
+
+    /// Resolve a model name to a registered provider and its upstream model
+    ///
+    /// Aliases are expanded first. A `provider/model` name goes to that
+    /// provider with the prefix stripped; otherwise the first rule matching
+    /// the name whose provider is registered wins, and the name is sent as is.
+    pub fn resolve(&self, model: &str) -> Result<Route> {
+        let model = self
+            .aliases
+            .get(model)
+            .map(|target| target.clone())
+            .unwrap_or_else(|| model.to_string());
+
+        if let Some((name, upstream)) = model.split_once('/') {
+            if let Some(config) = self.get_provider(name) {
+                return Ok(Route::new(name, config, upstream));
+            }
+        }
+
+        let routes = self.routes.read().unwrap();
+        for rule in routes.iter().filter(|r| glob_matches(&r.pattern, &model)) {
+            if let Some(config) = self.get_provider(&rule.provider) {
+                return Ok(Route::new(&rule.provider, config, &model));
+            }
+        }
+        anyhow::bail!("No provider is registered for model '{}'", model)
+    }
+
     pub async fn route_request(
         &self,
         target_provider: Option<&str>,
@@ -191,15 +778,16 @@ impl GatewayManager {
         let provider_name: String = match target_provider {
             Some(name) => name.to_string(),
             None => self
-                .providers
-                .iter()
+                .list_providers()
+                .into_iter()
                 .next()
-                .map(|entry| entry.key().clone())
                 .ok_or_else(|| anyhow::anyhow!("No providers registered"))?,
         };
 
         if let Some(config) = self.get_provider(&provider_name) {
-            let gateway_agent = GatewayAgent::new(config);
+            let gateway_agent = GatewayAgent::new(config)
+                .named(provider_name)
+                .with_manager(self.clone());
             gateway_agent.run(prompt, tx).await
         } else {
             anyhow::bail!("Provider {} not found", provider_name)
@@ -217,10 +805,14 @@ pub fn init_default_providers() {
     let openrouter_config = GatewayConfig {
         provider: "openrouter".to_string(),
         model: Some("openai/gpt-4".to_string()),
-        api_key: None, // Will be read from environment
-        base_url: Some("https://openrouter.ai/api/v1".to_string()),
+        // Key and endpoint come from the server's environment
+        api_key: None,
+        base_url: None,
         fallback_providers: vec!["openai".to_string(), "anthropic".to_string()],
         load_balancing: Some(LoadBalancing::RoundRobin),
+        restart_on_failure: false,
+        circuit_breaker: CircuitBreakerConfig::default(),
+        rate_limit: None,
     };
 
     // OpenAI provider
@@ -228,9 +820,12 @@ pub fn init_default_providers() {
         provider: "openai".to_string(),
         model: Some("gpt-4".to_string()),
         api_key: None,
-        base_url: Some("https://api.openai.com/v1".to_string()),
+        base_url: None,
         fallback_providers: vec!["openrouter".to_string()],
         load_balancing: Some(LoadBalancing::RoundRobin),
+        restart_on_failure: false,
+        circuit_breaker: CircuitBreakerConfig::default(),
+        rate_limit: None,
     };
 
     // Anthropic provider
@@ -238,12 +833,23 @@ pub fn init_default_providers() {
         provider: "anthropic".to_string(),
         model: Some("claude-3-sonnet-20240229".to_string()),
         api_key: None,
-        base_url: Some("https://api.anthropic.com/v1".to_string()),
+        base_url: None,
         fallback_providers: vec!["openai".to_string()],
         load_balancing: Some(LoadBalancing::RoundRobin),
+        restart_on_failure: false,
+        circuit_breaker: CircuitBreakerConfig::default(),
+        rate_limit: None,
     };
 
     GATEWAY_MANAGER.register_provider("openrouter".to_string(), openrouter_config);
     GATEWAY_MANAGER.register_provider("openai".to_string(), openai_config);
     GATEWAY_MANAGER.register_provider("anthropic".to_string(), anthropic_config);
+
+    GATEWAY_MANAGER.load_routing(RoutingConfig::from_env());
+    for (pattern, provider) in DEFAULT_ROUTES {
+        GATEWAY_MANAGER.add_route(RouteRule {
+            pattern: pattern.to_string(),
+            provider: provider.to_string(),
+        });
+    }
 }
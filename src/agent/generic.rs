@@ -0,0 +1,405 @@
+use super::{version_probe, Agent, AgentCapabilities, AgentInfo, AgentRegistry, SandboxCommand};
+use crate::types::{AgentConfig, StreamEvent};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+/// 声明式 CLI agent 定义（来自 TOML / YAML 配置文件）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentDefinition {
+    /// agent 类型名，即请求中的 `agent_type`
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// 可执行文件
+    pub binary: String,
+    /// 固定参数，放在所有参数之前
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// prompt 的传入方式
+    #[serde(default)]
+    pub prompt: PromptInput,
+    /// 传入模型的参数，如 `--model`；未设置时忽略 model
+    #[serde(default)]
+    pub model_flag: Option<String>,
+    /// 传入工作目录的参数，如 `--cwd`；无论是否设置，子进程都在工作目录中启动
+    #[serde(default)]
+    pub cwd_flag: Option<String>,
+    /// 额外环境变量，值中的 `${NAME}` 会替换为请求 env 中的同名变量
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// stdout 解析方式
+    #[serde(default)]
+    pub output: OutputFormat,
+    /// 健康检查命令，默认 `<binary> --version`
+    #[serde(default)]
+    pub health_check: Option<Vec<String>>,
+    /// 安装提示
+    #[serde(default)]
+    pub install: Option<String>,
+    /// 能力声明
+    #[serde(default)]
+    pub capabilities: AgentCapabilities,
+}
+
+/// prompt 传入方式
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum PromptInput {
+    /// 作为最后一个位置参数
+    #[default]
+    Arg,
+    /// 作为某个参数的值，如 `-p <prompt>`
+    Flag { flag: String },
+    /// 写入 stdin
+    Stdin,
+}
+
+/// stdout 输出格式
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// 每行原样作为文本输出
+    #[default]
+    Lines,
+    /// 每行一个 JSON 对象，按字段路径（如 `part.text`、`content.0.text`）提取
+    Jsonl {
+        /// 文本字段路径
+        text_path: String,
+        /// 错误信息字段路径
+        #[serde(default)]
+        error_path: Option<String>,
+        /// 事件类型字段路径
+        #[serde(default)]
+        type_path: Option<String>,
+        /// 只从这些类型的事件中提取文本，空表示全部
+        #[serde(default)]
+        text_types: Vec<String>,
+    },
+}
+
+/// 定义文件格式：`[[agents]]`（TOML）或 `agents:` 列表（YAML）
+#[derive(Debug, Deserialize)]
+struct DefinitionFile {
+    #[serde(default)]
+    agents: Vec<AgentDefinition>,
+}
+
+/// 加载 agent 定义文件并注册到 `registry`
+///
+/// 路径取 `OPENRUNNER_AGENTS_FILE`，未设置时使用当前目录下的 `agents.toml`（不存在则跳过）。
+/// 扩展名为 `.yaml` / `.yml` 时按 YAML 解析，否则按 TOML 解析。
+pub fn load_agent_definitions(registry: &AgentRegistry) -> Result<usize> {
+    let path = match std::env::var("OPENRUNNER_AGENTS_FILE") {
+        Ok(path) => path,
+        Err(_) if Path::new("agents.toml").exists() => "agents.toml".to_string(),
+        Err(_) => return Ok(0),
+    };
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path, e))?;
+    let definitions = parse_definitions(&path, &content)?;
+
+    let mut loaded = 0;
+    for def in definitions {
+        if registry.contains(&def.name) {
+            tracing::warn!(
+                "Agent definition '{}' conflicts with a registered agent, skipped",
+                def.name
+            );
+            continue;
+        }
+        register_definition(registry, def);
+        loaded += 1;
+    }
+    tracing::info!("Loaded {} agent definitions from {}", loaded, path);
+    Ok(loaded)
+}
+
+/// 把单个定义注册为 `GenericCliAgent`
+pub fn register_definition(registry: &AgentRegistry, def: AgentDefinition) {
+    let mut info = AgentInfo::new(
+        def.name.clone(),
+        def.description
+            .clone()
+            .unwrap_or_else(|| format!("{} CLI agent", def.name)),
+    )
+    .capabilities(def.capabilities);
+    if let Some(ref hint) = def.install {
+        info = info.install(hint.clone());
+    }
+    let probe = version_probe(
+        def.health_check
+            .clone()
+            .unwrap_or_else(|| vec![def.binary.clone(), "--version".to_string()]),
+        def.install.clone(),
+    );
+    registry.register_with_probe(
+        info,
+        move |config| Ok(Box::new(GenericCliAgent::new(def.clone(), config.clone()))),
+        probe,
+    );
+}
+
+fn parse_definitions(path: &str, content: &str) -> Result<Vec<AgentDefinition>> {
+    let file: DefinitionFile = if path.ends_with(".yaml") || path.ends_with(".yml") {
+        serde_yaml::from_str(content)?
+    } else {
+        toml::from_str(content)?
+    };
+    Ok(file.agents)
+}
+
+/// 按配置文件定义运行的 CLI agent
+pub struct GenericCliAgent {
+    definition: AgentDefinition,
+    config: AgentConfig,
+}
+
+impl GenericCliAgent {
+    pub fn new(definition: AgentDefinition, config: AgentConfig) -> Self {
+        Self { definition, config }
+    }
+
+    fn build_args(&self, prompt: &str) -> Vec<String> {
+        let def = &self.definition;
+        let mut args = def.args.clone();
+
+        if let (Some(flag), Some(model)) = (&def.model_flag, &self.config.model) {
+            args.push(flag.clone());
+            args.push(model.clone());
+        }
+        if let (Some(flag), Some(dir)) = (&def.cwd_flag, &self.config.working_dir) {
+            args.push(flag.clone());
+            args.push(dir.clone());
+        }
+        args.extend(self.config.extra_args.iter().cloned());
+
+        match &def.prompt {
+            PromptInput::Arg => args.push(prompt.to_string()),
+            PromptInput::Flag { flag } => {
+                args.push(flag.clone());
+                args.push(prompt.to_string());
+            }
+            PromptInput::Stdin => {}
+        }
+        args
+    }
+
+    /// 把定义中的 `${NAME}` 替换为请求 env 的值（不存在时为空）
+    fn expand_env(&self, value: &str) -> String {
+        let mut result = String::new();
+        let mut rest = value;
+        while let Some(start) = rest.find("${") {
+            result.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            match after.find('}') {
+                Some(end) => {
+                    let name = &after[..end];
+                    result.push_str(self.config.env.get(name).map(String::as_str).unwrap_or(""));
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    rest = &rest[start..];
+                    break;
+                }
+            }
+        }
+        result.push_str(rest);
+        result
+    }
+
+    /// 解析一行输出，返回 (文本, 错误)
+    fn parse_line(&self, line: &str) -> (Option<String>, Option<String>) {
+        match &self.definition.output {
+            OutputFormat::Lines => (Some(format!("{}\n", line)), None),
+            OutputFormat::Jsonl {
+                text_path,
+                error_path,
+                type_path,
+                text_types,
+            } => {
+                let Ok(event) = serde_json::from_str::<serde_json::Value>(line) else {
+                    tracing::debug!("{} non-JSON line: {}", self.definition.name, line);
+                    return (None, None);
+                };
+
+                let error = error_path
+                    .as_deref()
+                    .and_then(|p| lookup(&event, p))
+                    .map(value_to_string);
+                if error.is_some() {
+                    return (None, error);
+                }
+
+                if let Some(path) = type_path {
+                    let event_type = lookup(&event, path).and_then(|t| t.as_str());
+                    if !text_types.is_empty()
+                        && !event_type.is_some_and(|t| text_types.iter().any(|x| x == t))
+                    {
+                        return (None, None);
+                    }
+                }
+
+                let text = lookup(&event, text_path).map(value_to_string);
+                (text, None)
+            }
+        }
+    }
+}
+
+/// 按 `a.b.0.c` 形式的路径取 JSON 字段，数字段用于数组下标
+fn lookup<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.')
+        .filter(|s| !s.is_empty())
+        .try_fold(value, |v, key| match v {
+            serde_json::Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => v.get(key),
+        })
+        .filter(|v| !v.is_null())
+}
+
+fn value_to_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[async_trait]
+impl Agent for GenericCliAgent {
+    fn name(&self) -> &str {
+        &self.definition.name
+    }
+
+    fn capabilities(&self) -> AgentCapabilities {
+        self.definition.capabilities
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        let command = self
+            .definition
+            .health_check
+            .clone()
+            .unwrap_or_else(|| vec![self.definition.binary.clone(), "--version".to_string()]);
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("Empty health check command"))?;
+
+        let ok = tokio::process::Command::new(program)
+            .args(args)
+            .output()
+            .await
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+
+        if !ok {
+            match self.definition.install {
+                Some(ref hint) => anyhow::bail!(
+                    "{} CLI not available. Install: {}",
+                    self.definition.name,
+                    hint
+                ),
+                None => anyhow::bail!("{} CLI not available", self.definition.name),
+            }
+        }
+        Ok(())
+    }
+
+    async fn run(&self, prompt: String, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
+        let def = &self.definition;
+        let mut cmd = SandboxCommand::new(&def.binary, self.config.sandbox.as_ref());
+
+        for arg in self.build_args(&prompt) {
+            cmd.arg(arg);
+        }
+        if let Some(ref dir) = self.config.working_dir {
+            cmd.current_dir(dir);
+        }
+        for (k, v) in &self.config.env {
+            cmd.env(k, v);
+        }
+        for (k, v) in &def.env {
+            cmd.env(k, self.expand_env(v));
+        }
+
+        let env_keys: Vec<&String> = self.config.env.keys().chain(def.env.keys()).collect();
+        tracing::info!(
+            "{} run - binary: {}, env keys: {:?}",
+            def.name,
+            def.binary,
+            env_keys
+        );
+
+        let stdin_prompt = matches!(def.prompt, PromptInput::Stdin);
+        let mut cmd = cmd.build()?;
+        cmd.stdin(if stdin_prompt {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        });
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        let mut child = cmd.spawn()?;
+        if stdin_prompt {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(prompt.as_bytes()).await?;
+                // drop 关闭 stdin，CLI 才能读到 EOF
+            }
+        }
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Failed to capture stdout"))?;
+        // stderr 在单独的任务中读取，避免 CLI 写满 stderr 管道后阻塞，stdout 永远等不到 EOF
+        let stderr_task = child.stderr.take().map(|mut err| {
+            tokio::spawn(async move {
+                let mut stderr = String::new();
+                let _ = err.read_to_string(&mut stderr).await;
+                stderr
+            })
+        });
+        let mut reader = BufReader::new(stdout).lines();
+        let mut error: Option<String> = None;
+
+        while let Some(line) = reader.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (text, err) = self.parse_line(&line);
+            if let Some(err) = err {
+                error = Some(err);
+                continue;
+            }
+            if let Some(content) = text {
+                if tx.send(StreamEvent::Token { content }).await.is_err() {
+                    let _ = child.kill().await;
+                    break;
+                }
+            }
+        }
+
+        let status = child.wait().await?;
+        if let Some(err) = error {
+            anyhow::bail!("{} error: {}", def.name, err);
+        }
+        if !status.success() {
+            let stderr = match stderr_task {
+                Some(task) => task.await.unwrap_or_default(),
+                None => String::new(),
+            };
+            if stderr.trim().is_empty() {
+                anyhow::bail!("{} exited with status: {}", def.name, status);
+            }
+            anyhow::bail!("{} failed: {}", def.name, stderr.trim());
+        }
+
+        Ok(())
+    }
+}
@@ -1,13 +1,14 @@
-use super::Agent;
-use crate::types::StreamEvent;
+use super::{Agent, RateLimited};
+use crate::types::{AgentRequest, StreamEvent};
 use anyhow::Result;
 use tokio::sync::{mpsc, oneshot};
+use tokio::task::AbortHandle;
 use uuid::Uuid;
 
 /// Agent 消息类型
 pub enum AgentMessage {
     Run {
-        prompt: String,
+        request: Box<AgentRequest>,
         reply: oneshot::Sender<Result<()>>,
     },
     Cancel,
@@ -16,6 +17,7 @@ pub enum AgentMessage {
 /// Agent 句柄 - 用于与运行中的 agent 通信
 pub struct AgentHandle {
     tx: mpsc::Sender<AgentMessage>,
+    task: AbortHandle,
     pub session_id: Uuid,
 }
 
@@ -26,13 +28,13 @@ impl AgentHandle {
         let (tx, mut rx) = mpsc::channel::<AgentMessage>(32);
 
         let sid = session_id;
-        tokio::spawn(async move {
+        let task = tokio::spawn(async move {
             tracing::info!(session_id = %sid, agent = agent.name(), "Agent started");
 
             while let Some(msg) = rx.recv().await {
                 match msg {
-                    AgentMessage::Run { prompt, reply } => {
-                        let result = agent.run(prompt, stream_tx.clone()).await;
+                    AgentMessage::Run { request, reply } => {
+                        let result = agent.execute(*request, stream_tx.clone()).await;
 
                         // 发送完成或错误事件
                         match &result {
@@ -43,6 +45,9 @@ impl AgentHandle {
                                 let _ = stream_tx
                                     .send(StreamEvent::Error {
                                         message: e.to_string(),
+                                        retry_after_secs: e
+                                            .downcast_ref::<RateLimited>()
+                                            .map(RateLimited::retry_after_secs),
                                     })
                                     .await;
                             }
@@ -60,15 +65,24 @@ impl AgentHandle {
             tracing::info!(session_id = %sid, "Agent stopped");
         });
 
-        Self { tx, session_id }
+        Self {
+            tx,
+            task: task.abort_handle(),
+            session_id,
+        }
     }
 
     /// 执行 prompt
     pub async fn run(&self, prompt: String) -> Result<()> {
+        self.execute(AgentRequest::new(prompt)).await
+    }
+
+    /// 执行结构化请求
+    pub async fn execute(&self, request: AgentRequest) -> Result<()> {
         let (reply_tx, reply_rx) = oneshot::channel();
         self.tx
             .send(AgentMessage::Run {
-                prompt,
+                request: Box::new(request),
                 reply: reply_tx,
             })
             .await
@@ -76,6 +90,11 @@ impl AgentHandle {
         reply_rx.await?
     }
 
+    /// 用于立即终止 actor 的句柄：正在执行的请求被丢弃，agent 子进程随之结束
+    pub fn abort_handle(&self) -> AbortHandle {
+        self.task.clone()
+    }
+
     /// 取消执行
     pub async fn cancel(&self) -> Result<()> {
         self.tx
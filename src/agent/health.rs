@@ -0,0 +1,298 @@
+use super::{
+    AgentInfo, AgentRegistry, GatewayAgent, GatewayConfig, GatewayManager, GATEWAY_MANAGER,
+};
+use crate::agent::Agent;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 单个 agent / provider 的健康状态
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HealthStatus {
+    pub available: bool,
+    /// CLI 版本号（`--version` 输出的第一行）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub error: Option<String>,
+    /// 最近一次检查耗时
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// 最近一次检查时间，None 表示尚未检查
+    pub last_checked: Option<DateTime<Utc>>,
+    /// 最近一次检查成功的时间
+    pub last_success: Option<DateTime<Utc>>,
+}
+
+/// agent 健康状态（附带元信息）
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentHealth {
+    #[serde(flatten)]
+    pub info: AgentInfo,
+    #[serde(flatten)]
+    pub status: HealthStatus,
+}
+
+/// 后台健康检查
+///
+/// 按固定间隔并发检查注册表中的全部 agent、全局 gateway provider 和用户注册的 provider，
+/// 结果缓存在内存中，`/health/agents` 直接读取缓存，不会在请求中启动子进程。
+#[derive(Clone)]
+pub struct HealthMonitor {
+    registry: AgentRegistry,
+    agents: Arc<DashMap<String, HealthStatus>>,
+    providers: Arc<DashMap<String, HealthStatus>>,
+    /// 各用户的 gateway manager（按用户 ID），只检查用户自己注册的 provider
+    scopes: Arc<DashMap<String, GatewayManager>>,
+    /// 用户 provider 的状态，键为 (用户 ID, provider 名)
+    user_providers: Arc<DashMap<(String, String), HealthStatus>>,
+    interval: Duration,
+    timeout: Duration,
+    started: Arc<AtomicBool>,
+}
+
+impl HealthMonitor {
+    /// 检查间隔取 `OPENRUNNER_HEALTH_INTERVAL_SECS`（默认 60），
+    /// 单次检查超时取 `OPENRUNNER_HEALTH_TIMEOUT_SECS`（默认 10）
+    pub fn new(registry: AgentRegistry) -> Self {
+        let secs = |key: &str, default: u64| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(default)
+        };
+        Self {
+            registry,
+            agents: Arc::new(DashMap::new()),
+            providers: Arc::new(DashMap::new()),
+            scopes: Arc::new(DashMap::new()),
+            user_providers: Arc::new(DashMap::new()),
+            interval: Duration::from_secs(secs("OPENRUNNER_HEALTH_INTERVAL_SECS", 60)),
+            timeout: Duration::from_secs(secs("OPENRUNNER_HEALTH_TIMEOUT_SECS", 10)),
+            started: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// 同时检查这些用户 gateway manager 中注册的 provider（与 `ProviderStore` 共享）
+    pub fn with_scopes(mut self, scopes: Arc<DashMap<String, GatewayManager>>) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    /// 启动后台检查任务（重复调用无效；不在 tokio runtime 中时跳过）
+    pub fn start(&self) {
+        if tokio::runtime::Handle::try_current().is_err() {
+            tracing::warn!("No tokio runtime, agent health monitor not started");
+            return;
+        }
+        if self.started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(monitor.interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                monitor.refresh().await;
+            }
+        });
+    }
+
+    /// 立即检查一轮
+    pub async fn refresh(&self) {
+        let agents = self.registry.list();
+        let agent_checks = agents.iter().map(|info| {
+            let registry = self.registry.clone();
+            let agent_type = info.agent_type.clone();
+            async move { self.probe(registry.check(&agent_type)).await }
+        });
+
+        let providers: Vec<(String, GatewayConfig)> = GATEWAY_MANAGER
+            .list_providers()
+            .into_iter()
+            .filter_map(|name| GATEWAY_MANAGER.get_provider(&name).map(|c| (name, c)))
+            .collect();
+        let provider_checks = providers
+            .iter()
+            .map(|(name, config)| self.probe_provider(name, config));
+
+        let user_providers: Vec<((String, String), GatewayConfig)> = self
+            .scopes
+            .iter()
+            .flat_map(|scope| {
+                let user_id = scope.key().clone();
+                scope
+                    .list_own_providers()
+                    .into_iter()
+                    .filter_map(|name| scope.get_own_provider(&name).map(|c| (name, c)))
+                    .map(move |(name, config)| ((user_id.clone(), name), config))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let user_provider_checks = user_providers
+            .iter()
+            .map(|((_, name), config)| self.probe_user_provider(name, config));
+
+        let (agent_results, provider_results, user_provider_results) = tokio::join!(
+            futures::future::join_all(agent_checks),
+            futures::future::join_all(provider_checks),
+            futures::future::join_all(user_provider_checks)
+        );
+
+        for (info, result) in agents.iter().zip(agent_results) {
+            update(&self.agents, &info.agent_type, result);
+        }
+        for ((name, _), result) in providers.iter().zip(provider_results) {
+            update(&self.providers, name, result);
+        }
+        for ((key, _), result) in user_providers.iter().zip(user_provider_results) {
+            update(&self.user_providers, key, result);
+        }
+
+        // 清理已移除的 agent / provider
+        self.agents
+            .retain(|k, _| agents.iter().any(|i| &i.agent_type == k));
+        self.providers
+            .retain(|k, _| providers.iter().any(|(name, _)| name == k));
+        self.user_providers
+            .retain(|k, _| user_providers.iter().any(|(key, _)| key == k));
+    }
+
+    /// 只检查该 provider 本身，不走 fallback
+    async fn probe_provider(&self, name: &str, config: &GatewayConfig) -> ProbeResult {
+        let agent = GatewayAgent::new(GatewayConfig {
+            fallback_providers: vec![],
+            ..config.clone()
+        })
+        .named(name.to_string());
+        self.probe(async move { agent.health_check().await.map(|_| None) })
+            .await
+    }
+
+    /// A user's provider is only probed with its own key, never the server's
+    async fn probe_user_provider(&self, name: &str, config: &GatewayConfig) -> ProbeResult {
+        if !config.has_own_key() {
+            return ProbeResult {
+                result: Err(anyhow::anyhow!("Not checked: the provider has no API key")),
+                latency: Duration::ZERO,
+            };
+        }
+        self.probe_provider(name, config).await
+    }
+
+    async fn probe<F>(&self, check: F) -> ProbeResult
+    where
+        F: Future<Output = anyhow::Result<Option<String>>>,
+    {
+        let started = Instant::now();
+        let result = match tokio::time::timeout(self.timeout, check).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!(
+                "Health check timed out after {}s",
+                self.timeout.as_secs()
+            )),
+        };
+        ProbeResult {
+            result,
+            latency: started.elapsed(),
+        }
+    }
+
+    /// 全部 agent 的缓存状态（按注册顺序）
+    pub fn agents(&self) -> Vec<AgentHealth> {
+        self.registry
+            .list()
+            .into_iter()
+            .map(|info| {
+                let status = self
+                    .agents
+                    .get(&info.agent_type)
+                    .map(|s| s.clone())
+                    .unwrap_or_else(pending);
+                AgentHealth { info, status }
+            })
+            .collect()
+    }
+
+    /// 全部 gateway provider 的缓存状态
+    pub fn providers(&self) -> Vec<(String, HealthStatus)> {
+        let mut providers: Vec<(String, HealthStatus)> = GATEWAY_MANAGER
+            .list_providers()
+            .into_iter()
+            .map(|name| {
+                let status = self
+                    .providers
+                    .get(&name)
+                    .map(|s| s.clone())
+                    .unwrap_or_else(pending);
+                (name, status)
+            })
+            .collect();
+        providers.sort_by(|a, b| a.0.cmp(&b.0));
+        providers
+    }
+
+    /// 用户自己注册的 provider 的缓存状态（不含全局 provider）
+    pub fn user_providers(&self, user_id: &str) -> Vec<(String, HealthStatus)> {
+        let Some(scope) = self.scopes.get(user_id) else {
+            return vec![];
+        };
+        let mut providers: Vec<(String, HealthStatus)> = scope
+            .list_own_providers()
+            .into_iter()
+            .map(|name| {
+                let status = self
+                    .user_providers
+                    .get(&(user_id.to_string(), name.clone()))
+                    .map(|s| s.clone())
+                    .unwrap_or_else(pending);
+                (name, status)
+            })
+            .collect();
+        providers.sort_by(|a, b| a.0.cmp(&b.0));
+        providers
+    }
+}
+
+struct ProbeResult {
+    result: anyhow::Result<Option<String>>,
+    latency: Duration,
+}
+
+fn pending() -> HealthStatus {
+    HealthStatus {
+        error: Some("Health check pending".to_string()),
+        ..Default::default()
+    }
+}
+
+fn update<K>(cache: &DashMap<K, HealthStatus>, key: &K, probe: ProbeResult)
+where
+    K: Clone + Eq + std::hash::Hash,
+{
+    let now = Utc::now();
+    let mut status = cache.get(key).map(|s| s.clone()).unwrap_or_default();
+    status.last_checked = Some(now);
+    status.latency_ms = Some(probe.latency.as_millis() as u64);
+    match probe.result {
+        Ok(version) => {
+            status.available = true;
+            status.error = None;
+            status.last_success = Some(now);
+            if version.is_some() {
+                status.version = version;
+            }
+        }
+        Err(e) => {
+            status.available = false;
+            status.error = Some(e.to_string());
+        }
+    }
+    cache.insert(key.clone(), status);
+}
@@ -1,4 +1,4 @@
-use super::Agent;
+use super::{Agent, AgentCapabilities, SandboxCommand};
 use crate::types::{AgentConfig, StreamEvent};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -24,6 +24,14 @@ impl Agent for KimiCliAgent {
         "kimi_cli"
     }
 
+    fn capabilities(&self) -> AgentCapabilities {
+        AgentCapabilities {
+            tools: true,
+            streaming: true,
+            ..Default::default()
+        }
+    }
+
     async fn health_check(&self) -> Result<()> {
         let output = tokio::process::Command::new("kimi")
             .arg("--version")
@@ -37,7 +45,7 @@ impl Agent for KimiCliAgent {
     }
 
     async fn run(&self, prompt: String, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
-        let mut cmd = tokio::process::Command::new("kimi");
+        let mut cmd = SandboxCommand::new("kimi", self.config.sandbox.as_ref());
 
         let mut args: Vec<String> = Vec::new();
 
@@ -78,6 +86,7 @@ impl Agent for KimiCliAgent {
             cmd.env(k, v);
         }
 
+        let mut cmd = cmd.build()?;
         cmd.stdout(std::process::Stdio::piped());
         cmd.stderr(std::process::Stdio::piped());
 
@@ -0,0 +1,419 @@
+use super::{sse_events, SandboxCommand};
+use crate::types::{McpServerConfig, SandboxConfig};
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin};
+use tokio::sync::oneshot;
+
+const PROTOCOL_VERSION: &str = "2025-06-18";
+/// 单个请求（包括 initialize 和工具调用）的超时
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// MCP server 提供的工具
+#[derive(Debug, Clone)]
+pub struct McpTool {
+    pub name: String,
+    pub description: String,
+    pub input_schema: Value,
+}
+
+/// MCP 客户端
+///
+/// 支持 stdio（换行分隔的 JSON-RPC）和 Streamable HTTP 两种传输，只使用 tools 能力。
+/// stdio server 通过 `SandboxCommand` 启动，只带最小环境变量和配置中的 env；
+/// 客户端 drop 时子进程随之结束。
+pub struct McpClient {
+    name: String,
+    transport: Transport,
+    next_id: AtomicU64,
+}
+
+enum Transport {
+    Stdio(StdioTransport),
+    Http(HttpTransport),
+}
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Value>>>>;
+
+struct StdioTransport {
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    pending: Pending,
+    reader: tokio::task::JoinHandle<()>,
+    _child: Child,
+}
+
+impl Drop for StdioTransport {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+struct HttpTransport {
+    client: reqwest::Client,
+    url: String,
+    headers: HashMap<String, String>,
+    session_id: Mutex<Option<String>>,
+}
+
+impl McpClient {
+    /// 启动或连接 server 并完成 initialize 握手
+    pub async fn connect(
+        config: &McpServerConfig,
+        sandbox: Option<&SandboxConfig>,
+        working_dir: Option<&Path>,
+    ) -> Result<Self> {
+        let transport = match (&config.command, &config.url) {
+            (_, Some(url)) => Transport::Http(HttpTransport {
+                client: reqwest::Client::new(),
+                url: url.clone(),
+                headers: config.headers.clone(),
+                session_id: Mutex::new(None),
+            }),
+            (Some(command), None) => {
+                Transport::Stdio(spawn_stdio(config, command, sandbox, working_dir)?)
+            }
+            (None, None) => anyhow::bail!("MCP server '{}' has no command or url", config.name),
+        };
+
+        let client = Self {
+            name: config.name.clone(),
+            transport,
+            next_id: AtomicU64::new(1),
+        };
+        client
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {
+                        "name": "openrunner",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                }),
+            )
+            .await?;
+        client
+            .notify("notifications/initialized", json!({}))
+            .await?;
+        Ok(client)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// tools/list（跟随分页游标）
+    pub async fn list_tools(&self) -> Result<Vec<McpTool>> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.request("tools/list", params).await?;
+            for tool in result["tools"].as_array().into_iter().flatten() {
+                let Some(name) = tool["name"].as_str() else {
+                    continue;
+                };
+                tools.push(McpTool {
+                    name: name.to_string(),
+                    description: tool["description"].as_str().unwrap_or("").to_string(),
+                    input_schema: tool
+                        .get("inputSchema")
+                        .cloned()
+                        .unwrap_or_else(|| json!({ "type": "object" })),
+                });
+            }
+            cursor = result["nextCursor"].as_str().map(str::to_string);
+            if cursor.is_none() {
+                return Ok(tools);
+            }
+        }
+    }
+
+    /// tools/call，返回文本化的结果；`isError` 为 true 时返回错误
+    pub async fn call_tool(&self, name: &str, arguments: Value) -> Result<String> {
+        let arguments = match arguments {
+            Value::Object(_) => arguments,
+            Value::Null => json!({}),
+            other => anyhow::bail!("Tool arguments must be a JSON object, got: {}", other),
+        };
+        let result = self
+            .request(
+                "tools/call",
+                json!({ "name": name, "arguments": arguments }),
+            )
+            .await?;
+
+        let mut parts: Vec<String> = result["content"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|item| match item["type"].as_str() {
+                Some("text") => item["text"].as_str().unwrap_or("").to_string(),
+                Some("resource") => match item["resource"]["text"].as_str() {
+                    Some(text) => text.to_string(),
+                    None => format!("[resource: {}]", item["resource"]["uri"]),
+                },
+                Some("resource_link") => format!("[resource: {}]", item["uri"]),
+                Some(kind) => format!("[{}: {}]", kind, item["mimeType"]),
+                None => item.to_string(),
+            })
+            .collect();
+        if parts.is_empty() {
+            if let Some(structured) = result.get("structuredContent") {
+                parts.push(structured.to_string());
+            }
+        }
+        let output = parts.join("\n");
+
+        if result["isError"].as_bool().unwrap_or(false) {
+            anyhow::bail!("{}", output);
+        }
+        Ok(output)
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let message = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, async {
+            match &self.transport {
+                Transport::Stdio(stdio) => stdio.request(id, &message).await,
+                Transport::Http(http) => http.request(id, &message).await,
+            }
+        })
+        .await
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "MCP server '{}' timed out on {} after {}s",
+                self.name,
+                method,
+                REQUEST_TIMEOUT.as_secs()
+            )
+        })?
+        .with_context(|| format!("MCP server '{}' {} failed", self.name, method))?;
+
+        if let Some(error) = response.get("error") {
+            anyhow::bail!(
+                "MCP server '{}' {} error {}: {}",
+                self.name,
+                method,
+                error["code"],
+                error["message"].as_str().unwrap_or("")
+            );
+        }
+        Ok(response.get("result").cloned().unwrap_or(Value::Null))
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<()> {
+        let message = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        });
+        match &self.transport {
+            Transport::Stdio(stdio) => stdio.send(&message).await,
+            Transport::Http(http) => http.post(&message).await.map(|_| ()),
+        }
+    }
+}
+
+/// 启动 stdio server，后台任务读取 stdout 并按 id 分发响应
+fn spawn_stdio(
+    config: &McpServerConfig,
+    command: &str,
+    sandbox: Option<&SandboxConfig>,
+    working_dir: Option<&Path>,
+) -> Result<StdioTransport> {
+    // 不继承服务进程的环境变量
+    let mut cmd = SandboxCommand::new("env", sandbox);
+    cmd.arg("-i")
+        .arg(format!(
+            "PATH={}",
+            std::env::var("PATH").unwrap_or_default()
+        ))
+        .arg(format!(
+            "HOME={}",
+            std::env::var("HOME").unwrap_or_default()
+        ))
+        .arg("LANG=C.UTF-8");
+    for (k, v) in &config.env {
+        cmd.arg(format!("{}={}", k, v));
+    }
+    cmd.arg(command);
+    for arg in &config.args {
+        cmd.arg(arg);
+    }
+    if let Some(dir) = working_dir {
+        cmd.current_dir(dir);
+    }
+
+    let mut cmd = cmd.build()?;
+    cmd.stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to start MCP server '{}'", config.name))?;
+
+    let stdin = Arc::new(tokio::sync::Mutex::new(
+        child.stdin.take().context("Failed to capture stdin")?,
+    ));
+    let stdout = child.stdout.take().context("Failed to capture stdout")?;
+    let pending: Pending = Arc::default();
+
+    if let Some(stderr) = child.stderr.take() {
+        let name = config.name.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                tracing::debug!(server = %name, "MCP stderr: {}", line);
+            }
+        });
+    }
+
+    let reader = {
+        let stdin = stdin.clone();
+        let pending = pending.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                    continue;
+                };
+                let id = message.get("id").cloned();
+                match (id, message.get("method")) {
+                    // server 发来的请求：只支持 ping
+                    (Some(id), Some(method)) => {
+                        let reply = if method == "ping" {
+                            json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+                        } else {
+                            json!({
+                                "jsonrpc": "2.0",
+                                "id": id,
+                                "error": { "code": -32601, "message": "Method not found" },
+                            })
+                        };
+                        let mut stdin = stdin.lock().await;
+                        let _ = stdin.write_all(format!("{}\n", reply).as_bytes()).await;
+                        let _ = stdin.flush().await;
+                    }
+                    (Some(id), None) => {
+                        let sender = id
+                            .as_u64()
+                            .and_then(|id| pending.lock().unwrap().remove(&id));
+                        if let Some(sender) = sender {
+                            let _ = sender.send(message);
+                        }
+                    }
+                    // 通知忽略
+                    (None, _) => {}
+                }
+            }
+            // server 退出：丢弃等待中的请求，调用方收到连接关闭错误
+            pending.lock().unwrap().clear();
+        })
+    };
+
+    Ok(StdioTransport {
+        stdin,
+        pending,
+        reader,
+        _child: child,
+    })
+}
+
+impl StdioTransport {
+    async fn send(&self, message: &Value) -> Result<()> {
+        let mut stdin = self.stdin.lock().await;
+        stdin.write_all(format!("{}\n", message).as_bytes()).await?;
+        stdin.flush().await?;
+        Ok(())
+    }
+
+    async fn request(&self, id: u64, message: &Value) -> Result<Value> {
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        if let Err(e) = self.send(message).await {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        rx.await
+            .map_err(|_| anyhow::anyhow!("MCP server closed the connection"))
+    }
+}
+
+impl HttpTransport {
+    async fn post(&self, message: &Value) -> Result<reqwest::Response> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .header("Accept", "application/json, text/event-stream")
+            .header("MCP-Protocol-Version", PROTOCOL_VERSION)
+            .json(message);
+        for (k, v) in &self.headers {
+            request = request.header(k, v);
+        }
+        if let Some(ref session_id) = *self.session_id.lock().unwrap() {
+            request = request.header("Mcp-Session-Id", session_id);
+        }
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("HTTP {}: {}", status, body);
+        }
+        if let Some(session_id) = response
+            .headers()
+            .get("mcp-session-id")
+            .and_then(|v| v.to_str().ok())
+        {
+            *self.session_id.lock().unwrap() = Some(session_id.to_string());
+        }
+        Ok(response)
+    }
+
+    /// 响应可能是单个 JSON，也可能是 SSE 流（取 id 匹配的那条消息）
+    async fn request(&self, id: u64, message: &Value) -> Result<Value> {
+        let response = self.post(message).await?;
+        let is_sse = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/event-stream"));
+
+        if !is_sse {
+            return Ok(response.json().await?);
+        }
+
+        let mut events = sse_events(response.bytes_stream());
+        while let Some(event) = events.next().await {
+            let event = event?;
+            let Ok(message) = serde_json::from_str::<Value>(&event.data) else {
+                continue;
+            };
+            if message.get("method").is_none() && message["id"].as_u64() == Some(id) {
+                return Ok(message);
+            }
+        }
+        anyhow::bail!("Stream ended without a response")
+    }
+}
@@ -1,4 +1,4 @@
-use super::Agent;
+use super::{Agent, AgentCapabilities};
 use crate::types::{AgentConfig, StreamEvent};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -22,6 +22,13 @@ impl Agent for MockAgent {
         "mock"
     }
 
+    fn capabilities(&self) -> AgentCapabilities {
+        AgentCapabilities {
+            streaming: true,
+            ..Default::default()
+        }
+    }
+
     async fn health_check(&self) -> Result<()> {
         Ok(())
     }
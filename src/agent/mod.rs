@@ -1,77 +1,137 @@
 mod anthropic;
+mod breaker;
+mod catalog;
 mod claude_code;
 mod codex;
 mod gateway;
+mod generic;
 mod handle;
+mod health;
 mod kimi_cli;
+mod mcp;
 mod mock;
 mod openai;
 mod opencode;
 mod openrouter;
+mod rate_limit;
+mod registry;
+mod retry;
+mod sandbox;
+mod sse;
+mod tools;
 mod traits;
 
 pub use anthropic::AnthropicAgent;
+pub use breaker::{CircuitBreakerConfig, CircuitState, InFlight, ProviderHealth};
+pub use catalog::{CatalogConfig, CatalogModel, ModelCatalog, ModelMetadata};
 pub use claude_code::ClaudeCodeAgent;
 pub use codex::CodexAgent;
 pub use gateway::{
-    init_default_providers, GatewayAgent, GatewayConfig, GatewayManager, LoadBalancing,
-    GATEWAY_MANAGER,
+    init_default_providers, GatewayAgent, GatewayConfig, GatewayManager, LoadBalancing, Route,
+    RouteRule, RoutingConfig, GATEWAY_MANAGER, PROVIDER_TYPES,
+};
+pub use generic::{
+    load_agent_definitions, register_definition, AgentDefinition, GenericCliAgent, OutputFormat,
+    PromptInput,
 };
 pub use handle::AgentHandle;
+pub use health::{AgentHealth, HealthMonitor, HealthStatus};
 pub use kimi_cli::KimiCliAgent;
+pub use mcp::{McpClient, McpTool};
 pub use mock::MockAgent;
 pub use openai::OpenAIAgent;
 pub use opencode::OpenCodeAgent;
 pub use openrouter::OpenRouterAgent;
+pub use rate_limit::{estimate_tokens, RateLimitConfig, RateLimitStatus, RateLimited, RateLimiter};
+pub use registry::{
+    version_probe, AgentCapabilities, AgentFactory, AgentInfo, AgentRegistry, HealthProbe,
+    AGENT_REGISTRY,
+};
+pub(crate) use retry::send_with_retry;
+pub use sandbox::SandboxCommand;
+pub use sse::{sse_events, SseDecoder, SseEvent};
+pub(crate) use tools::read_text_file;
+pub use tools::{ToolBox, ToolCall, ToolResult};
 pub use traits::Agent;
 
 use crate::types::AgentConfig;
 use anyhow::Result;
 
-/// 根据配置创建对应的 Agent
+/// 根据配置创建对应的 Agent（使用全局注册表）
 pub fn create_agent(config: &AgentConfig) -> Result<Box<dyn Agent>> {
-    match config.agent_type.as_str() {
-        "claude_code" => Ok(Box::new(ClaudeCodeAgent::new(config.clone()))),
-        "codex" => Ok(Box::new(CodexAgent::new(config.clone()))),
-        "opencode" => Ok(Box::new(OpenCodeAgent::new(config.clone()))),
-        "kimi_cli" => Ok(Box::new(KimiCliAgent::new(config.clone()))),
-        "mock" => Ok(Box::new(MockAgent::new(config.clone()))),
+    AGENT_REGISTRY.create(config)
+}
 
-        // LLM Gateway providers
-        "openrouter" => Ok(Box::new(OpenRouterAgent::new(config.clone()))),
-        "openai" => Ok(Box::new(OpenAIAgent::new(config.clone()))),
-        "anthropic" => Ok(Box::new(AnthropicAgent::new(config.clone()))),
-        "gateway" => {
-            // For gateway agent, we need to create a GatewayAgent
-            let gateway_config = crate::agent::gateway::GatewayConfig {
-                provider: config
-                    .model
-                    .clone()
-                    .unwrap_or_else(|| "openrouter".to_string()),
-                model: config.model.clone(),
-                api_key: config
-                    .env
-                    .get("OPENROUTER_API_KEY")
-                    .cloned()
-                    .or_else(|| config.env.get("OPENAI_API_KEY").cloned())
-                    .or_else(|| config.env.get("ANTHROPIC_API_KEY").cloned()),
-                base_url: config
-                    .env
-                    .get("OPENROUTER_BASE_URL")
-                    .cloned()
-                    .or_else(|| config.env.get("OPENAI_BASE_URL").cloned())
-                    .or_else(|| config.env.get("ANTHROPIC_BASE_URL").cloned()),
-                fallback_providers: vec!["openai".to_string(), "anthropic".to_string()],
-                load_balancing: Some(crate::agent::gateway::LoadBalancing::RoundRobin),
-            };
-            Ok(Box::new(crate::agent::gateway::GatewayAgent::new(
-                gateway_config,
-            )))
-        }
+/// 注册内置 agent
+fn register_builtins(registry: &AgentRegistry) {
+    let cli_probe = |binary: &str, install: &str| {
+        version_probe(
+            vec![binary.to_string(), "--version".to_string()],
+            Some(install.to_string()),
+        )
+    };
 
-        // UI-supported agents (placeholders for now)
-        "droid" | "augment" | "amp" => Ok(Box::new(MockAgent::new(config.clone()))),
+    registry.register(
+        AgentInfo::new("mock", "Mock agent for testing (no API key required)"),
+        |config| Ok(Box::new(MockAgent::new(config.clone()))),
+    );
+    registry.register_with_probe(
+        AgentInfo::new("claude_code", "Claude Code CLI agent")
+            .install("npm install -g @anthropic-ai/claude-code"),
+        |config| Ok(Box::new(ClaudeCodeAgent::new(config.clone()))),
+        cli_probe("claude", "npm install -g @anthropic-ai/claude-code"),
+    );
+    registry.register_with_probe(
+        AgentInfo::new("codex", "OpenAI Codex CLI agent").install("npm install -g @openai/codex"),
+        |config| Ok(Box::new(CodexAgent::new(config.clone()))),
+        cli_probe("codex", "npm install -g @openai/codex"),
+    );
+    registry.register_with_probe(
+        AgentInfo::new("opencode", "OpenCode CLI agent")
+            .install("go install github.com/opencode-ai/opencode@latest"),
+        |config| Ok(Box::new(OpenCodeAgent::new(config.clone()))),
+        cli_probe(
+            "opencode",
+            "go install github.com/opencode-ai/opencode@latest",
+        ),
+    );
+    registry.register_with_probe(
+        AgentInfo::new("kimi_cli", "Kimi CLI agent")
+            .install("https://moonshotai.github.io/kimi-cli/en/guides/getting-started.html"),
+        |config| Ok(Box::new(KimiCliAgent::new(config.clone()))),
+        cli_probe(
+            "kimi",
+            "https://moonshotai.github.io/kimi-cli/en/guides/getting-started.html",
+        ),
+    );
 
-        _ => anyhow::bail!("Unknown agent type: {}", config.agent_type),
-    }
+    // LLM Gateway providers
+    registry.register(AgentInfo::new("openrouter", "OpenRouter API"), |config| {
+        Ok(Box::new(OpenRouterAgent::new(config.clone())))
+    });
+    registry.register(
+        AgentInfo::new("openai", "OpenAI-compatible chat completions API"),
+        |config| Ok(Box::new(OpenAIAgent::new(config.clone()))),
+    );
+    registry.register(
+        AgentInfo::new("anthropic", "Anthropic Messages API"),
+        |config| Ok(Box::new(AnthropicAgent::new(config.clone()))),
+    );
+    // gateway 按模型名路由到已注册的 provider
+    registry.register(
+        AgentInfo::new("gateway", "LLM gateway with provider fallback"),
+        |config| {
+            let manager = config
+                .gateway
+                .clone()
+                .unwrap_or_else(|| GATEWAY_MANAGER.clone());
+            let route = manager.resolve(config.model.as_deref().unwrap_or_default())?;
+            Ok(Box::new(
+                GatewayAgent::new(route.config)
+                    .named(route.name)
+                    .with_manager(manager)
+                    .with_base_config(config.clone()),
+            ))
+        },
+    );
 }
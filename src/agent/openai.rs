@@ -1,7 +1,10 @@
-use super::Agent;
-use crate::types::{AgentConfig, StreamEvent};
+use super::{send_with_retry, sse_events, Agent, AgentCapabilities, ToolBox, ToolCall, ToolResult};
+use crate::types::{
+    AgentConfig, AgentRequest, ChatMessage, ModelInfo, StreamEvent, TokenUsage, ToolDefinition,
+};
 use anyhow::Result;
 use async_trait::async_trait;
+use futures::StreamExt;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
@@ -22,17 +25,170 @@ struct OpenAIRequest {
     top_p: Option<f32>,
     frequency_penalty: Option<f32>,
     presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<FunctionTool>,
+    stream_options: StreamOptions,
 }
 
-#[derive(Debug, Serialize)]
-struct Message {
+/// Asks for a final chunk carrying the token usage of the request
+#[derive(Debug, Clone, Serialize)]
+pub(super) struct StreamOptions {
+    include_usage: bool,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            include_usage: true,
+        }
+    }
+}
+
+/// Chat Completions message, shared with the OpenRouter agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct Message {
     role: String,
-    content: String,
+    /// Omitted on assistant messages that only carry tool calls
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<MessageToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+impl Message {
+    pub(super) fn new(role: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: Some(content.into()),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    /// `role: "tool"` message answering one tool call
+    pub(super) fn tool(result: ToolResult) -> Self {
+        Self {
+            role: "tool".to_string(),
+            content: Some(result.output),
+            tool_calls: Vec::new(),
+            tool_call_id: Some(result.id),
+        }
+    }
+}
+
+impl From<&ChatMessage> for Message {
+    fn from(message: &ChatMessage) -> Self {
+        let has_content = !message.content.is_empty() || message.tool_calls.is_empty();
+        Self {
+            role: message.role.clone(),
+            content: has_content.then(|| message.content.clone()),
+            tool_calls: message
+                .tool_calls
+                .iter()
+                .map(|call| MessageToolCall {
+                    id: call.id.clone(),
+                    kind: "function".to_string(),
+                    function: FunctionCall {
+                        name: call.name.clone(),
+                        arguments: call.arguments.clone(),
+                    },
+                })
+                .collect(),
+            tool_call_id: message.tool_call_id.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct MessageToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    function: FunctionCall,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FunctionCall {
+    name: String,
+    /// JSON-encoded arguments, as generated by the model
+    arguments: String,
+}
+
+/// Entry of the `tools` request field
+#[derive(Debug, Clone, Serialize)]
+pub(super) struct FunctionTool {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: FunctionSpec,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct FunctionSpec {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+impl From<ToolDefinition> for FunctionTool {
+    fn from(tool: ToolDefinition) -> Self {
+        Self {
+            kind: "function",
+            function: FunctionSpec {
+                name: tool.name,
+                description: tool.description,
+                parameters: tool.input_schema,
+            },
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct OpenAIStreamChunk {
+    #[serde(default)]
     choices: Vec<StreamChoice>,
+    usage: Option<ChatUsage>,
+}
+
+/// `usage` of the final chunk; `prompt_tokens` includes cached tokens
+#[derive(Debug, Deserialize)]
+struct ChatUsage {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+    prompt_tokens_details: Option<PromptTokensDetails>,
+    /// Reported by OpenRouter, in USD
+    cost: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PromptTokensDetails {
+    #[serde(default)]
+    cached_tokens: u64,
+}
+
+impl From<ChatUsage> for TokenUsage {
+    fn from(usage: ChatUsage) -> Self {
+        let cached = usage
+            .prompt_tokens_details
+            .map(|d| d.cached_tokens)
+            .unwrap_or(0);
+        Self {
+            input_tokens: usage.prompt_tokens.saturating_sub(cached),
+            output_tokens: usage.completion_tokens,
+            cache_read_input_tokens: cached,
+            cost_usd: usage.cost,
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -44,6 +200,179 @@ struct StreamChoice {
 #[derive(Debug, Deserialize)]
 struct Delta {
     content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCallDelta>,
+}
+
+/// Tool calls arrive in fragments keyed by `index`; only the first one carries id and name
+#[derive(Debug, Deserialize)]
+struct ToolCallDelta {
+    #[serde(default)]
+    index: usize,
+    id: Option<String>,
+    function: Option<FunctionDelta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+/// `GET /models` response
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    data: Vec<Model>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Model {
+    id: String,
+    #[serde(default)]
+    created: Option<i64>,
+    #[serde(default)]
+    owned_by: Option<String>,
+}
+
+/// One streamed assistant turn
+#[derive(Debug, Default)]
+pub(super) struct ChatTurn {
+    text: String,
+    tool_calls: Vec<MessageToolCall>,
+    pub(super) usage: TokenUsage,
+    pub(super) finish_reason: Option<String>,
+}
+
+impl ChatTurn {
+    /// Tool calls requested by the model, with arguments decoded
+    pub(super) fn tool_calls(&self) -> Vec<ToolCall> {
+        self.tool_calls
+            .iter()
+            .map(|call| {
+                let arguments = call.function.arguments.trim();
+                let input = if arguments.is_empty() {
+                    serde_json::json!({})
+                } else {
+                    // Hand malformed arguments to the tool as-is so the model sees the error
+                    serde_json::from_str(arguments)
+                        .unwrap_or_else(|_| serde_json::Value::String(arguments.to_string()))
+                };
+                ToolCall {
+                    id: call.id.clone(),
+                    name: call.function.name.clone(),
+                    input,
+                }
+            })
+            .collect()
+    }
+
+    /// Hand the tool calls back to the caller, whose own tools they target
+    pub(super) async fn request_tool_calls(&self, tx: &mpsc::Sender<StreamEvent>) {
+        for call in &self.tool_calls {
+            let _ = tx
+                .send(StreamEvent::ToolCallRequested {
+                    id: call.id.clone(),
+                    name: call.function.name.clone(),
+                    arguments: call.function.arguments.clone(),
+                })
+                .await;
+        }
+    }
+
+    /// The assistant message to append to the conversation
+    pub(super) fn into_message(self) -> Message {
+        Message {
+            role: "assistant".to_string(),
+            content: (!self.text.is_empty()).then_some(self.text),
+            tool_calls: self.tool_calls,
+            tool_call_id: None,
+        }
+    }
+
+    fn apply(&mut self, delta: ToolCallDelta) {
+        while self.tool_calls.len() <= delta.index {
+            self.tool_calls.push(MessageToolCall {
+                id: String::new(),
+                kind: "function".to_string(),
+                function: FunctionCall {
+                    name: String::new(),
+                    arguments: String::new(),
+                },
+            });
+        }
+        let call = &mut self.tool_calls[delta.index];
+        if let Some(id) = delta.id {
+            call.id = id;
+        }
+        if let Some(function) = delta.function {
+            if let Some(name) = function.name {
+                call.function.name.push_str(&name);
+            }
+            if let Some(arguments) = function.arguments {
+                call.function.arguments.push_str(&arguments);
+            }
+        }
+    }
+}
+
+/// Tools offered to the model: the toolbox's (when the request has a working
+/// directory) followed by the caller's own
+pub(super) fn function_tools(
+    toolbox: Option<&ToolBox>,
+    request: &AgentRequest,
+) -> Vec<FunctionTool> {
+    toolbox
+        .iter()
+        .flat_map(|t| t.definitions())
+        .chain(request.tools.iter().cloned())
+        .map(FunctionTool::from)
+        .collect()
+}
+
+/// Read a Chat Completions stream, forwarding text and collecting tool calls
+///
+/// Reads until `[DONE]`: with `include_usage` the usage chunk follows the one
+/// carrying `finish_reason`.
+pub(super) async fn stream_turn(
+    response: reqwest::Response,
+    tx: &mpsc::Sender<StreamEvent>,
+) -> Result<ChatTurn> {
+    let mut events = sse_events(response.bytes_stream());
+    let mut turn = ChatTurn::default();
+
+    while let Some(event) = events.next().await {
+        let event = match event {
+            Ok(event) => event,
+            // Reported once, by the caller turning the error into `StreamEvent::Error`
+            Err(e) => return Err(anyhow::anyhow!("Stream error: {}", e)),
+        };
+
+        if event.data == "[DONE]" {
+            break;
+        }
+
+        if let Ok(stream_data) = serde_json::from_str::<OpenAIStreamChunk>(&event.data) {
+            if let Some(usage) = stream_data.usage {
+                turn.usage = usage.into();
+            }
+            for choice in stream_data.choices {
+                if choice.finish_reason.is_some() {
+                    turn.finish_reason = choice.finish_reason;
+                }
+                if let Some(delta) = choice.delta {
+                    if let Some(content) = delta.content {
+                        turn.text.push_str(&content);
+                        let _ = tx.send(StreamEvent::Token { content }).await;
+                    }
+                    for call in delta.tool_calls {
+                        turn.apply(call);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(turn)
 }
 
 impl OpenAIAgent {
@@ -55,9 +384,18 @@ impl OpenAIAgent {
     }
 
     async fn get_api_key(&self) -> Result<String> {
-        if let Some(api_key) = self.config.env.get("OPENAI_API_KEY") {
+        if let Some(api_key) = self
+            .config
+            .env
+            .get("OPENAI_API_KEY")
+            .filter(|k| !k.is_empty())
+        {
             return Ok(api_key.clone());
         }
+        // The server's key is only sent to the default endpoint
+        if let Some(base_url) = self.config.env.get("OPENAI_BASE_URL") {
+            anyhow::bail!("No OpenAI API key configured for {}", base_url)
+        }
 
         if let Ok(api_key) = std::env::var("OPENAI_API_KEY") {
             return Ok(api_key);
@@ -107,114 +445,133 @@ impl Agent for OpenAIAgent {
         }
     }
 
-    async fn run(&self, prompt: String, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
         let api_key = self.get_api_key().await?;
         let base_url = self.get_base_url().await;
-        let model_name = self.get_model_name().await;
-
-        // Build request
-        let request = OpenAIRequest {
-            model: model_name,
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: prompt,
-            }],
-            stream: Some(true),
-            temperature: None,
-            max_tokens: None,
-            top_p: None,
-            frequency_penalty: None,
-            presence_penalty: None,
-        };
 
-        // Send streaming request
         let response = self
             .client
-            .post(&format!("{}/chat/completions", base_url))
+            .get(format!("{}/models", base_url))
             .header("Authorization", format!("Bearer {}", api_key))
-            .header("Content-Type", "application/json")
-            .header("Accept", "text/event-stream")
-            .json(&request)
+            .timeout(std::time::Duration::from_secs(10))
             .send()
             .await?;
-
         if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(anyhow::anyhow!("OpenAI API error: {}", error_text));
-        }
-
-        // Process streaming response
-        use futures::StreamExt;
-        let mut stream = response.bytes_stream();
-
-        let mut full_response = String::new();
-
-        while let Some(chunk_result) = stream.next().await {
-            match chunk_result {
-                Ok(chunk) => {
-                    let chunk_str = String::from_utf8_lossy(&chunk);
-
-                    // Parse SSE format
-                    for line in chunk_str.lines() {
-                        if line.starts_with("data: ") {
-                            let data = &line[6..];
-
-                            if data == "[DONE]" {
-                                if !full_response.is_empty() {
-                                    let _ = tx
-                                        .send(StreamEvent::Token {
-                                            content: full_response.clone(),
-                                        })
-                                        .await;
-                                }
-                                let _ = tx
-                                    .send(StreamEvent::Done {
-                                        session_id: uuid::Uuid::new_v4(),
-                                    })
-                                    .await;
-                                return Ok(());
-                            }
-
-                            if let Ok(stream_data) = serde_json::from_str::<OpenAIStreamChunk>(data)
-                            {
-                                for choice in stream_data.choices {
-                                    if let Some(delta) = choice.delta {
-                                        if let Some(content) = delta.content {
-                                            full_response.push_str(&content);
-                                            let _ = tx.send(StreamEvent::Token { content }).await;
-                                        }
-                                    }
-
-                                    if choice.finish_reason.is_some() {
-                                        let _ = tx
-                                            .send(StreamEvent::Done {
-                                                session_id: uuid::Uuid::new_v4(),
-                                            })
-                                            .await;
-                                        return Ok(());
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    let _ = tx
-                        .send(StreamEvent::Error {
-                            message: format!("Stream error: {}", e),
-                        })
-                        .await;
-                    return Err(anyhow::anyhow!("Stream error: {}", e));
-                }
-            }
+            anyhow::bail!("OpenAI API model list failed: {}", response.status());
         }
 
-        if !full_response.is_empty() {
-            let _ = tx
-                .send(StreamEvent::Token {
-                    content: full_response,
-                })
-                .await;
+        let list: ModelList = response.json().await?;
+        Ok(list
+            .data
+            .into_iter()
+            .map(|model| ModelInfo {
+                id: model.id,
+                created: model.created,
+                owned_by: model.owned_by,
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    fn capabilities(&self) -> AgentCapabilities {
+        AgentCapabilities {
+            tools: true,
+            streaming: true,
+            multi_turn: true,
+            mcp: true,
+            ..Default::default()
+        }
+    }
+
+    async fn run(&self, prompt: String, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
+        self.execute(AgentRequest::new(prompt), tx).await
+    }
+
+    async fn execute(&self, request: AgentRequest, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
+        let api_key = self.get_api_key().await?;
+        let base_url = self.get_base_url().await;
+        let model_name = self.get_model_name().await;
+
+        // Build request: system prompt goes first as a system message
+        let mut messages: Vec<Message> = request
+            .system
+            .iter()
+            .map(|system| Message::new("system", system.clone()))
+            .collect();
+        messages.extend(request.messages.iter().map(Message::from));
+
+        let toolbox = ToolBox::new(&self.config, request.working_dir.as_deref()).await;
+        let tools = function_tools(toolbox.as_ref(), &request);
+        let sampling = request.sampling;
+        let mut usage = TokenUsage::default();
+        let mut iterations = 0;
+
+        let finish_reason = loop {
+            let body = OpenAIRequest {
+                model: model_name.clone(),
+                messages: messages.clone(),
+                stream: Some(true),
+                temperature: sampling.temperature,
+                max_tokens: sampling.max_tokens,
+                top_p: sampling.top_p,
+                frequency_penalty: sampling.frequency_penalty,
+                presence_penalty: sampling.presence_penalty,
+                stop: sampling.stop.clone(),
+                seed: sampling.seed,
+                response_format: sampling.response_format.clone(),
+                tools: tools.clone(),
+                stream_options: StreamOptions::default(),
+            };
+
+            // Send streaming request, retrying failures that happen before any output
+            let request = || {
+                self.client
+                    .post(format!("{}/chat/completions", base_url))
+                    .header("Authorization", format!("Bearer {}", api_key))
+                    .header("Content-Type", "application/json")
+                    .header("Accept", "text/event-stream")
+                    .json(&body)
+            };
+            let response = send_with_retry(&self.config.retry, self.name(), request, &tx).await?;
+
+            if !response.status().is_success() {
+                let error_text = response.text().await?;
+                return Err(anyhow::anyhow!("OpenAI API error: {}", error_text));
+            }
+
+            let turn = stream_turn(response, &tx).await?;
+            // Report the running total after every turn so budgets can stop a long tool loop
+            usage.add(&turn.usage);
+            if !turn.usage.is_empty() {
+                let _ = tx.send(StreamEvent::Usage { usage }).await;
+            }
+
+            let calls = turn.tool_calls();
+            if calls.is_empty() {
+                break turn.finish_reason;
+            }
+            // Calls to the caller's own tools end the request; the caller sends the results back
+            let Some(toolbox) = toolbox.as_ref().filter(|t| t.handles(&calls)) else {
+                turn.request_tool_calls(&tx).await;
+                break Some("tool_calls".to_string());
+            };
+
+            iterations += 1;
+            if iterations >= toolbox.max_iterations() {
+                anyhow::bail!(
+                    "Tool loop stopped after {} iterations",
+                    toolbox.max_iterations()
+                );
+            }
+
+            messages.push(turn.into_message());
+            for call in &calls {
+                messages.push(Message::tool(toolbox.call(call, &tx).await));
+            }
+        };
+
+        if let Some(reason) = finish_reason {
+            let _ = tx.send(StreamEvent::Stop { reason }).await;
         }
         let _ = tx
             .send(StreamEvent::Done {
@@ -1,5 +1,6 @@
-use super::Agent;
-use crate::types::{AgentConfig, StreamEvent};
+use super::{Agent, AgentCapabilities, SandboxCommand};
+use crate::redact::{redact_args, Redactor};
+use crate::types::{AgentConfig, StreamEvent, TokenUsage};
 use anyhow::Result;
 use async_trait::async_trait;
 use std::path::PathBuf;
@@ -54,14 +55,15 @@ impl OpenCodeAgent {
         args
     }
 
-    /// 根据环境变量动态生成 opencode 配置
-    /// 返回 (临时配置目录路径, 需要使用的 model 名称)
-    fn create_dynamic_config(&self) -> Result<Option<(PathBuf, String)>> {
+    /// 根据环境变量和 MCP server 配置动态生成 opencode 配置
+    /// 返回 (临时配置目录路径, 需要使用的 model 名称)；未配置 provider 时 model 为 None
+    fn create_dynamic_config(&self) -> Result<Option<(PathBuf, Option<String>)>> {
         let base_url = self.config.env.get("OPENCODE_BASE_URL");
         let api_key = self.config.env.get("OPENCODE_API_KEY");
+        let has_provider = base_url.is_some() || api_key.is_some();
 
-        // 如果没有提供动态配置相关的环境变量，返回 None
-        if base_url.is_none() && api_key.is_none() {
+        // 如果既没有 provider 相关的环境变量也没有 MCP server，返回 None
+        if !has_provider && self.config.mcp_servers.is_empty() {
             return Ok(None);
         }
 
@@ -98,9 +100,11 @@ impl OpenCodeAgent {
         std::fs::create_dir_all(&config_dir)?;
 
         // 生成 opencode.json 配置
-        let config = serde_json::json!({
+        let mut config = serde_json::json!({
             "$schema": "https://opencode.ai/config.json",
-            "provider": {
+        });
+        if has_provider {
+            config["provider"] = serde_json::json!({
                 provider_name: {
                     "npm": "@ai-sdk/openai-compatible",
                     "options": {
@@ -113,8 +117,11 @@ impl OpenCodeAgent {
                         }
                     }
                 }
-            }
-        });
+            });
+        }
+        if !self.config.mcp_servers.is_empty() {
+            config["mcp"] = self.mcp_config();
+        }
 
         let config_path = config_dir.join("opencode.json");
         std::fs::write(&config_path, serde_json::to_string_pretty(&config)?)?;
@@ -122,10 +129,41 @@ impl OpenCodeAgent {
         tracing::info!("Created dynamic opencode config at: {:?}", config_path);
 
         // 返回配置目录和完整的 model 名称 (provider/model)
-        let full_model = format!("{}/{}", provider_name, model_name);
+        let full_model = has_provider.then(|| format!("{}/{}", provider_name, model_name));
         Ok(Some((temp_dir, full_model)))
     }
 
+    /// opencode.json 的 `mcp` 段：stdio server 为 local，HTTP server 为 remote
+    fn mcp_config(&self) -> serde_json::Value {
+        let servers: serde_json::Map<String, serde_json::Value> = self
+            .config
+            .mcp_servers
+            .iter()
+            .map(|server| {
+                let value = match server.url {
+                    Some(ref url) => serde_json::json!({
+                        "type": "remote",
+                        "url": url,
+                        "headers": server.headers,
+                        "enabled": true,
+                    }),
+                    None => {
+                        let command: Vec<&String> =
+                            server.command.iter().chain(server.args.iter()).collect();
+                        serde_json::json!({
+                            "type": "local",
+                            "command": command,
+                            "environment": server.env,
+                            "enabled": true,
+                        })
+                    }
+                };
+                (server.name.clone(), value)
+            })
+            .collect();
+        serde_json::Value::Object(servers)
+    }
+
     /// 清理临时配置目录
     fn cleanup_config(&self, config_dir: &PathBuf) {
         if let Err(e) = std::fs::remove_dir_all(config_dir) {
@@ -140,6 +178,15 @@ impl Agent for OpenCodeAgent {
         "opencode"
     }
 
+    fn capabilities(&self) -> AgentCapabilities {
+        AgentCapabilities {
+            tools: true,
+            streaming: true,
+            mcp: true,
+            ..Default::default()
+        }
+    }
+
     async fn health_check(&self) -> Result<()> {
         let output = tokio::process::Command::new("opencode")
             .arg("--version")
@@ -158,7 +205,7 @@ impl Agent for OpenCodeAgent {
             "OpenCode run - env vars: {:?}, model: {:?}, extra_args: {:?}",
             self.config.env.keys().collect::<Vec<_>>(),
             self.config.model,
-            self.config.extra_args
+            redact_args(&self.config.extra_args)
         );
 
         // 尝试创建动态配置
@@ -168,7 +215,7 @@ impl Agent for OpenCodeAgent {
         // 如果有动态配置，使用配置中的 model 名称
         let effective_model = dynamic_config
             .as_ref()
-            .map(|(_, model)| model.clone())
+            .and_then(|(_, model)| model.clone())
             .or_else(|| self.config.model.clone());
 
         // 构建命令行参数
@@ -212,7 +259,7 @@ expect {{
             cmd = opencode_cmd
         );
 
-        let mut cmd = tokio::process::Command::new("expect");
+        let mut cmd = SandboxCommand::new("expect", self.config.sandbox.as_ref());
         cmd.arg("-c");
         cmd.arg(&expect_script);
 
@@ -223,7 +270,8 @@ expect {{
 
         // 如果有动态配置，设置 XDG_CONFIG_HOME 环境变量
         if let Some(ref dir) = temp_dir {
-            cmd.env("XDG_CONFIG_HOME", dir);
+            cmd.env("XDG_CONFIG_HOME", dir.to_string_lossy());
+            cmd.bind(dir);
             tracing::info!("Using dynamic config from: {:?}", dir);
         }
 
@@ -234,6 +282,7 @@ expect {{
             }
         }
 
+        let mut cmd = cmd.build()?;
         cmd.stdin(std::process::Stdio::null());
         cmd.stdout(std::process::Stdio::piped());
         cmd.stderr(std::process::Stdio::piped());
@@ -247,9 +296,13 @@ expect {{
 
         let mut reader = BufReader::new(stdout).lines();
         let mut result: Result<()> = Ok(());
+        // 每一步结束后上报累计用量
+        let mut usage: Option<TokenUsage> = None;
+        // 日志中过滤 env 里的凭据和常见 token
+        let redactor = Redactor::from_env(&self.config.env);
 
         while let Some(line) = reader.next_line().await? {
-            tracing::debug!("opencode output line: {}", line);
+            tracing::debug!("opencode output line: {}", redactor.redact(&line).0);
 
             // 跳过 expect 的 spawn 行
             if line.starts_with("spawn ") {
@@ -302,7 +355,7 @@ expect {{
                                 })
                                 .or_else(|| event.get("message").and_then(|m| m.as_str()))
                                 .unwrap_or("Unknown error");
-                            tracing::error!("opencode error: {}", error_msg);
+                            tracing::error!("opencode error: {}", redactor.redact(error_msg).0);
                             result = Err(anyhow::anyhow!("opencode error: {}", error_msg));
                             // 发送错误消息给前端
                             let _ = tx
@@ -312,8 +365,16 @@ expect {{
                                 .await;
                             break;
                         }
-                        // 忽略 step_start, step_finish 等内部事件
-                        "step_start" | "step_finish" | "tool_start" | "tool_finish" => {
+                        "step_finish" => {
+                            // 每一步的用量: {"part":{"tokens":{"input":..,"output":..,"reasoning":..,"cache":{"read":..,"write":..}},"cost":..}}
+                            if let Some(part) = event.get("part") {
+                                let total = usage.get_or_insert_with(TokenUsage::default);
+                                total.add(&step_usage(part));
+                                let _ = tx.send(StreamEvent::Usage { usage: *total }).await;
+                            }
+                        }
+                        // 忽略 step_start 等内部事件
+                        "step_start" | "tool_start" | "tool_finish" => {
                             // 这些是内部事件，不需要发送给前端
                             continue;
                         }
@@ -325,7 +386,7 @@ expect {{
                 }
             } else if !line.trim().is_empty() && !line.contains("OPENCODE_TIMEOUT") {
                 // 非 JSON 行，可能是错误信息
-                tracing::debug!("opencode non-JSON line: {}", line);
+                tracing::debug!("opencode non-JSON line: {}", redactor.redact(&line).0);
                 // 只发送看起来像错误的内容
                 if line.contains("Error") || line.contains("error") {
                     let _ = tx
@@ -362,3 +423,16 @@ expect {{
         Ok(())
     }
 }
+
+/// 解析 step_finish 事件中的 token 用量，推理 token 计入输出
+fn step_usage(part: &serde_json::Value) -> TokenUsage {
+    let tokens = &part["tokens"];
+    let count = |v: &serde_json::Value| v.as_u64().unwrap_or(0);
+    TokenUsage {
+        input_tokens: count(&tokens["input"]),
+        output_tokens: count(&tokens["output"]) + count(&tokens["reasoning"]),
+        cache_creation_input_tokens: count(&tokens["cache"]["write"]),
+        cache_read_input_tokens: count(&tokens["cache"]["read"]),
+        cost_usd: part["cost"].as_f64(),
+    }
+}
@@ -1,5 +1,7 @@
-use super::Agent;
-use crate::types::{AgentConfig, StreamEvent};
+use super::openai::{function_tools, stream_turn, FunctionTool, Message, StreamOptions};
+use super::{send_with_retry, Agent, AgentCapabilities, ToolBox};
+use crate::pricing::ModelPrice;
+use crate::types::{AgentConfig, AgentRequest, ModelInfo, StreamEvent, TokenUsage};
 use anyhow::Result;
 use async_trait::async_trait;
 use reqwest::Client;
@@ -19,12 +21,21 @@ struct OpenRouterRequest {
     stream: Option<bool>,
     temperature: Option<f32>,
     max_tokens: Option<u32>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Message {
-    role: String,
-    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<FunctionTool>,
+    stream_options: StreamOptions,
 }
 
 #[derive(Debug, Deserialize)]
@@ -46,20 +57,62 @@ struct Usage {
     total_tokens: u32,
 }
 
+/// `GET /models` response
 #[derive(Debug, Deserialize)]
-struct OpenRouterStreamChunk {
-    choices: Vec<StreamChoice>,
+struct ModelList {
+    data: Vec<Model>,
 }
 
 #[derive(Debug, Deserialize)]
-struct StreamChoice {
-    delta: Option<Delta>,
-    finish_reason: Option<String>,
+struct Model {
+    id: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    created: Option<i64>,
+    #[serde(default)]
+    context_length: Option<u64>,
+    #[serde(default)]
+    pricing: Option<ModelPricing>,
+    #[serde(default)]
+    architecture: Option<Architecture>,
 }
 
+/// Prices are USD per token, as decimal strings
 #[derive(Debug, Deserialize)]
-struct Delta {
-    content: Option<String>,
+struct ModelPricing {
+    prompt: String,
+    completion: String,
+    #[serde(default)]
+    input_cache_read: Option<String>,
+    #[serde(default)]
+    input_cache_write: Option<String>,
+}
+
+impl ModelPricing {
+    fn per_million(price: &str) -> Option<f64> {
+        price.parse::<f64>().ok().map(|p| p * 1_000_000.0)
+    }
+
+    fn to_price(&self) -> Option<ModelPrice> {
+        Some(ModelPrice {
+            input: Self::per_million(&self.prompt)?,
+            output: Self::per_million(&self.completion)?,
+            cache_write: self
+                .input_cache_write
+                .as_deref()
+                .and_then(Self::per_million),
+            cache_read: self.input_cache_read.as_deref().and_then(Self::per_million),
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Architecture {
+    #[serde(default)]
+    input_modalities: Vec<String>,
+    #[serde(default)]
+    output_modalities: Vec<String>,
 }
 
 impl OpenRouterAgent {
@@ -72,9 +125,18 @@ impl OpenRouterAgent {
 
     async fn get_api_key(&self) -> Result<String> {
         // Try to get API key from config env, then from environment variables
-        if let Some(api_key) = self.config.env.get("OPENROUTER_API_KEY") {
+        if let Some(api_key) = self
+            .config
+            .env
+            .get("OPENROUTER_API_KEY")
+            .filter(|k| !k.is_empty())
+        {
             return Ok(api_key.clone());
         }
+        // The server's keys are only sent to the default endpoint
+        if let Some(base_url) = self.config.env.get("OPENROUTER_BASE_URL") {
+            anyhow::bail!("No OpenRouter API key configured for {}", base_url)
+        }
 
         if let Ok(api_key) = std::env::var("OPENROUTER_API_KEY") {
             return Ok(api_key);
@@ -137,116 +199,145 @@ impl Agent for OpenRouterAgent {
         }
     }
 
-    async fn run(&self, prompt: String, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
-        let api_key = self.get_api_key().await?;
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
         let base_url = self.get_base_url().await;
-        let model_name = self.get_model_name().await;
 
-        // Build request
-        let request = OpenRouterRequest {
-            model: model_name,
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: prompt,
-            }],
-            stream: Some(true),
-            temperature: None,
-            max_tokens: None,
-        };
-
-        // Send streaming request
-        let response = self
+        // The model list is public; send the key when there is one
+        let mut request = self
             .client
-            .post(&format!("{}/chat/completions", base_url))
-            .header("Authorization", format!("Bearer {}", api_key))
+            .get(format!("{}/models", base_url))
             .header("HTTP-Referer", "https://github.com/openrunner")
             .header("X-Title", "OpenRunner")
-            .header("Content-Type", "application/json")
-            .header("Accept", "text/event-stream")
-            .json(&request)
-            .send()
-            .await?;
-
+            .timeout(std::time::Duration::from_secs(10));
+        if let Ok(api_key) = self.get_api_key().await {
+            request = request.header("Authorization", format!("Bearer {}", api_key));
+        }
+        let response = request.send().await?;
         if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(anyhow::anyhow!("OpenRouter API error: {}", error_text));
+            anyhow::bail!("OpenRouter API model list failed: {}", response.status());
         }
 
-        // Process streaming response
-        use futures::StreamExt;
-        let mut stream = response.bytes_stream();
-
-        let mut full_response = String::new();
-
-        while let Some(chunk_result) = stream.next().await {
-            match chunk_result {
-                Ok(chunk) => {
-                    let chunk_str = String::from_utf8_lossy(&chunk);
-
-                    // Parse SSE format
-                    for line in chunk_str.lines() {
-                        if line.starts_with("data: ") {
-                            let data = &line[6..];
-
-                            if data == "[DONE]" {
-                                // Send final completion token
-                                if !full_response.is_empty() {
-                                    let _ = tx
-                                        .send(StreamEvent::Token {
-                                            content: full_response.clone(),
-                                        })
-                                        .await;
-                                }
-                                let _ = tx
-                                    .send(StreamEvent::Done {
-                                        session_id: uuid::Uuid::new_v4(),
-                                    })
-                                    .await;
-                                return Ok(());
-                            }
-
-                            if let Ok(stream_data) =
-                                serde_json::from_str::<OpenRouterStreamChunk>(data)
-                            {
-                                for choice in stream_data.choices {
-                                    if let Some(delta) = choice.delta {
-                                        if let Some(content) = delta.content {
-                                            full_response.push_str(&content);
-                                            let _ = tx.send(StreamEvent::Token { content }).await;
-                                        }
-                                    }
-
-                                    if choice.finish_reason.is_some() {
-                                        let _ = tx
-                                            .send(StreamEvent::Done {
-                                                session_id: uuid::Uuid::new_v4(),
-                                            })
-                                            .await;
-                                        return Ok(());
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    let _ = tx
-                        .send(StreamEvent::Error {
-                            message: format!("Stream error: {}", e),
-                        })
-                        .await;
-                    return Err(anyhow::anyhow!("Stream error: {}", e));
+        let list: ModelList = response.json().await?;
+        Ok(list
+            .data
+            .into_iter()
+            .map(|model| {
+                let architecture = model.architecture.unwrap_or_default();
+                ModelInfo {
+                    owned_by: model.id.split_once('/').map(|(owner, _)| owner.to_string()),
+                    id: model.id,
+                    name: model.name,
+                    created: model.created,
+                    context_length: model.context_length,
+                    pricing: model.pricing.as_ref().and_then(ModelPricing::to_price),
+                    input_modalities: architecture.input_modalities,
+                    output_modalities: architecture.output_modalities,
                 }
-            }
+            })
+            .collect())
+    }
+
+    fn capabilities(&self) -> AgentCapabilities {
+        AgentCapabilities {
+            tools: true,
+            streaming: true,
+            multi_turn: true,
+            mcp: true,
+            ..Default::default()
         }
+    }
+
+    async fn run(&self, prompt: String, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
+        self.execute(AgentRequest::new(prompt), tx).await
+    }
+
+    async fn execute(&self, request: AgentRequest, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
+        let api_key = self.get_api_key().await?;
+        let base_url = self.get_base_url().await;
+        let model_name = self.get_model_name().await;
+
+        // Build request: system prompt goes first as a system message
+        let mut messages: Vec<Message> = request
+            .system
+            .iter()
+            .map(|system| Message::new("system", system.clone()))
+            .collect();
+        messages.extend(request.messages.iter().map(Message::from));
+
+        let toolbox = ToolBox::new(&self.config, request.working_dir.as_deref()).await;
+        let tools = function_tools(toolbox.as_ref(), &request);
+        let sampling = request.sampling;
+        let mut usage = TokenUsage::default();
+        let mut iterations = 0;
+
+        let finish_reason = loop {
+            let body = OpenRouterRequest {
+                model: model_name.clone(),
+                messages: messages.clone(),
+                stream: Some(true),
+                temperature: sampling.temperature,
+                max_tokens: sampling.max_tokens,
+                top_p: sampling.top_p,
+                frequency_penalty: sampling.frequency_penalty,
+                presence_penalty: sampling.presence_penalty,
+                stop: sampling.stop.clone(),
+                seed: sampling.seed,
+                response_format: sampling.response_format.clone(),
+                tools: tools.clone(),
+                stream_options: StreamOptions::default(),
+            };
+
+            // Send streaming request, retrying failures that happen before any output
+            let request = || {
+                self.client
+                    .post(format!("{}/chat/completions", base_url))
+                    .header("Authorization", format!("Bearer {}", api_key))
+                    .header("HTTP-Referer", "https://github.com/openrunner")
+                    .header("X-Title", "OpenRunner")
+                    .header("Content-Type", "application/json")
+                    .header("Accept", "text/event-stream")
+                    .json(&body)
+            };
+            let response = send_with_retry(&self.config.retry, self.name(), request, &tx).await?;
+
+            if !response.status().is_success() {
+                let error_text = response.text().await?;
+                return Err(anyhow::anyhow!("OpenRouter API error: {}", error_text));
+            }
+
+            let turn = stream_turn(response, &tx).await?;
+            // Report the running total after every turn so budgets can stop a long tool loop
+            usage.add(&turn.usage);
+            if !turn.usage.is_empty() {
+                let _ = tx.send(StreamEvent::Usage { usage }).await;
+            }
+
+            let calls = turn.tool_calls();
+            if calls.is_empty() {
+                break turn.finish_reason;
+            }
+            // Calls to the caller's own tools end the request; the caller sends the results back
+            let Some(toolbox) = toolbox.as_ref().filter(|t| t.handles(&calls)) else {
+                turn.request_tool_calls(&tx).await;
+                break Some("tool_calls".to_string());
+            };
+
+            iterations += 1;
+            if iterations >= toolbox.max_iterations() {
+                anyhow::bail!(
+                    "Tool loop stopped after {} iterations",
+                    toolbox.max_iterations()
+                );
+            }
+
+            messages.push(turn.into_message());
+            for call in &calls {
+                messages.push(Message::tool(toolbox.call(call, &tx).await));
+            }
+        };
 
-        // Send completion even if stream ended unexpectedly
-        if !full_response.is_empty() {
-            let _ = tx
-                .send(StreamEvent::Token {
-                    content: full_response,
-                })
-                .await;
+        if let Some(reason) = finish_reason {
+            let _ = tx.send(StreamEvent::Stop { reason }).await;
         }
         let _ = tx
             .send(StreamEvent::Done {
@@ -0,0 +1,262 @@
+use crate::types::AgentRequest;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Client-side limits of a provider key, enforced before requests go upstream
+///
+/// A limit left out is not enforced; zero is rejected by `validate`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+    /// Prompt plus completion tokens; requests reserve an estimate up front
+    #[serde(default)]
+    pub tokens_per_minute: Option<u64>,
+    /// How long a request may queue for capacity before it's rejected
+    #[serde(default = "default_max_wait_ms")]
+    pub max_wait_ms: u64,
+}
+
+fn default_max_wait_ms() -> u64 {
+    10_000
+}
+
+impl RateLimitConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.requests_per_minute == Some(0) {
+            return Err("rate_limit.requests_per_minute must be at least 1".to_string());
+        }
+        if self.tokens_per_minute == Some(0) {
+            return Err("rate_limit.tokens_per_minute must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: None,
+            tokens_per_minute: None,
+            max_wait_ms: default_max_wait_ms(),
+        }
+    }
+}
+
+/// Current bucket levels of a provider, as shown in `/api/providers`
+#[derive(Debug, Clone, Serialize)]
+pub struct RateLimitStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requests_per_minute: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requests_available: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens_per_minute: Option<u64>,
+    /// Negative while requests used more tokens than they reserved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens_available: Option<i64>,
+    /// Requests waiting for capacity
+    pub queued: usize,
+}
+
+/// A request that couldn't get capacity within `max_wait_ms`
+#[derive(Debug, thiserror::Error)]
+#[error("rate_limited: provider '{provider}' is over its client-side rate limit, retry after {}s", self.retry_after_secs())]
+pub struct RateLimited {
+    pub provider: String,
+    pub retry_after: Duration,
+}
+
+impl RateLimited {
+    /// The wait in whole seconds, as sent in `Retry-After`
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs_f64().ceil().max(1.0) as u64
+    }
+}
+
+/// Rough token count of a request: prompt characters / 4 plus the completion limit
+pub fn estimate_tokens(request: &AgentRequest) -> u64 {
+    let chars: usize = request.system.as_deref().map_or(0, str::len)
+        + request
+            .messages
+            .iter()
+            .map(|m| {
+                m.content.len()
+                    + m.tool_calls
+                        .iter()
+                        .map(|c| c.arguments.len())
+                        .sum::<usize>()
+            })
+            .sum::<usize>();
+    (chars as u64).div_ceil(4) + request.sampling.max_tokens.unwrap_or(0) as u64
+}
+
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    per_sec: f64,
+}
+
+impl Bucket {
+    fn per_minute(limit: f64) -> Self {
+        Self {
+            capacity: limit,
+            available: limit,
+            per_sec: limit / 60.0,
+        }
+    }
+
+    /// Time until `amount` is available; amounts over the capacity wait for a full bucket
+    fn wait(&self, amount: f64) -> Duration {
+        let missing = amount.min(self.capacity) - self.available;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing / self.per_sec)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+    refilled: Instant,
+}
+
+impl Buckets {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = (now - self.refilled).as_secs_f64();
+        self.refilled = now;
+        for bucket in self.requests.iter_mut().chain(self.tokens.iter_mut()) {
+            bucket.available = (bucket.available + elapsed * bucket.per_sec).min(bucket.capacity);
+        }
+    }
+
+    /// Take one request and `tokens`, or return how long until both are available
+    fn take(&mut self, tokens: u64) -> Option<Duration> {
+        self.refill();
+        let wait = [
+            self.requests.as_ref().map(|b| b.wait(1.0)),
+            self.tokens.as_ref().map(|b| b.wait(tokens as f64)),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .unwrap_or_default();
+        if !wait.is_zero() {
+            return Some(wait);
+        }
+        if let Some(bucket) = &mut self.requests {
+            bucket.available -= 1.0;
+        }
+        if let Some(bucket) = &mut self.tokens {
+            bucket.available -= (tokens as f64).min(bucket.capacity);
+        }
+        None
+    }
+}
+
+/// Token buckets of one registered provider, shared by every request through it
+///
+/// Waiting requests are served in arrival order. Token usage is reserved from
+/// an estimate and corrected with `settle` once the provider reports usage;
+/// going over the estimate leaves the bucket in debt for later requests.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<Buckets>,
+    queue: tokio::sync::Mutex<()>,
+    queued: AtomicUsize,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            buckets: Mutex::new(Buckets {
+                // A bucket that never refills would wait forever: zero means no limit
+                requests: config
+                    .requests_per_minute
+                    .filter(|&limit| limit > 0)
+                    .map(|limit| Bucket::per_minute(limit as f64)),
+                tokens: config
+                    .tokens_per_minute
+                    .filter(|&limit| limit > 0)
+                    .map(|limit| Bucket::per_minute(limit as f64)),
+                refilled: Instant::now(),
+            }),
+            config,
+            queue: tokio::sync::Mutex::new(()),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    /// Wait until a request reserving `tokens` fits, for at most `max_wait_ms`
+    ///
+    /// Fails right away when the wait is known to run past the deadline.
+    pub async fn acquire(&self, provider: &str, tokens: u64) -> Result<(), RateLimited> {
+        let max_wait = Duration::from_millis(self.config.max_wait_ms);
+        let deadline = tokio::time::Instant::now() + max_wait;
+        let rejected = |retry_after| RateLimited {
+            provider: provider.to_string(),
+            retry_after,
+        };
+
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        let _queued = Queued(&self.queued);
+        let _turn = tokio::time::timeout_at(deadline, self.queue.lock())
+            .await
+            .map_err(|_| rejected(max_wait))?;
+        loop {
+            let Some(wait) = self.buckets.lock().unwrap().take(tokens) else {
+                return Ok(());
+            };
+            if tokio::time::Instant::now() + wait > deadline {
+                return Err(rejected(wait));
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Correct a reservation of `reserved` tokens to what the request actually used
+    pub fn settle(&self, reserved: u64, used: u64) {
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.refill();
+        if let Some(bucket) = &mut buckets.tokens {
+            let reserved = (reserved as f64).min(bucket.capacity);
+            bucket.available = (bucket.available + reserved - used as f64).min(bucket.capacity);
+        }
+    }
+
+    pub fn status(&self) -> RateLimitStatus {
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.refill();
+        RateLimitStatus {
+            requests_per_minute: self.config.requests_per_minute,
+            requests_available: buckets
+                .requests
+                .as_ref()
+                .map(|b| b.available.max(0.0).floor() as u32),
+            tokens_per_minute: self.config.tokens_per_minute,
+            tokens_available: buckets.tokens.as_ref().map(|b| b.available.floor() as i64),
+            queued: self.queued.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Counts a request in `RateLimiter::queued` until dropped
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,244 @@
+use super::Agent;
+use crate::types::AgentConfig;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+
+/// Agent 能力声明（供客户端决定展示哪些功能）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentCapabilities {
+    /// 会读写文件、执行命令
+    #[serde(default)]
+    pub tools: bool,
+    /// 增量输出
+    #[serde(default)]
+    pub streaming: bool,
+    /// 支持多轮对话（会话续接）
+    #[serde(default)]
+    pub multi_turn: bool,
+    /// 支持附件
+    #[serde(default)]
+    pub attachments: bool,
+    /// 使用配置的 MCP server
+    #[serde(default)]
+    pub mcp: bool,
+}
+
+/// Agent 元信息
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentInfo {
+    #[serde(rename = "type")]
+    pub agent_type: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub install: Option<String>,
+    pub capabilities: AgentCapabilities,
+}
+
+impl AgentInfo {
+    pub fn new(agent_type: impl Into<String>, description: impl Into<String>) -> Self {
+        Self {
+            agent_type: agent_type.into(),
+            description: description.into(),
+            install: None,
+            capabilities: AgentCapabilities::default(),
+        }
+    }
+
+    pub fn install(mut self, hint: impl Into<String>) -> Self {
+        self.install = Some(hint.into());
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: AgentCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+}
+
+/// 根据配置创建 agent 实例
+pub type AgentFactory = Arc<dyn Fn(&AgentConfig) -> Result<Box<dyn Agent>> + Send + Sync>;
+
+/// 健康检查，成功时可返回版本号
+pub type HealthProbe = Arc<dyn Fn() -> BoxFuture<'static, Result<Option<String>>> + Send + Sync>;
+
+struct AgentEntry {
+    info: AgentInfo,
+    factory: AgentFactory,
+    probe: Option<HealthProbe>,
+}
+
+/// Agent 注册表
+///
+/// 保存每种 agent 的工厂、元信息和健康检查，`create_agent`、`/agents`、
+/// `/health/agents` 都从这里读取。克隆共享同一份数据，可以在运行时注册自定义 agent。
+#[derive(Clone, Default)]
+pub struct AgentRegistry {
+    entries: Arc<RwLock<Vec<Arc<AgentEntry>>>>,
+}
+
+impl AgentRegistry {
+    /// 空注册表
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 包含全部内置 agent 的注册表
+    pub fn with_builtins() -> Self {
+        let registry = Self::new();
+        super::register_builtins(&registry);
+        registry
+    }
+
+    /// 注册 agent（同名覆盖），健康检查使用默认配置创建实例后调用 `Agent::health_check`
+    pub fn register<F>(&self, info: AgentInfo, factory: F)
+    where
+        F: Fn(&AgentConfig) -> Result<Box<dyn Agent>> + Send + Sync + 'static,
+    {
+        self.insert(AgentEntry {
+            info,
+            factory: Arc::new(factory),
+            probe: None,
+        });
+    }
+
+    /// 注册 agent 并指定健康检查
+    pub fn register_with_probe<F, P>(&self, info: AgentInfo, factory: F, probe: P)
+    where
+        F: Fn(&AgentConfig) -> Result<Box<dyn Agent>> + Send + Sync + 'static,
+        P: Fn() -> BoxFuture<'static, Result<Option<String>>> + Send + Sync + 'static,
+    {
+        self.insert(AgentEntry {
+            info,
+            factory: Arc::new(factory),
+            probe: Some(Arc::new(probe)),
+        });
+    }
+
+    fn insert(&self, mut entry: AgentEntry) {
+        // 未显式声明能力时以 `Agent::capabilities` 为准
+        if entry.info.capabilities == AgentCapabilities::default() {
+            let config = AgentConfig {
+                agent_type: entry.info.agent_type.clone(),
+                ..Default::default()
+            };
+            if let Ok(agent) = (entry.factory)(&config) {
+                entry.info.capabilities = agent.capabilities();
+            }
+        }
+
+        let mut entries = self.entries.write().unwrap();
+        let entry = Arc::new(entry);
+        match entries
+            .iter()
+            .position(|e| e.info.agent_type == entry.info.agent_type)
+        {
+            Some(idx) => entries[idx] = entry,
+            None => entries.push(entry),
+        }
+    }
+
+    /// 移除 agent
+    pub fn unregister(&self, agent_type: &str) -> bool {
+        let mut entries = self.entries.write().unwrap();
+        let before = entries.len();
+        entries.retain(|e| e.info.agent_type != agent_type);
+        entries.len() != before
+    }
+
+    fn get(&self, agent_type: &str) -> Option<Arc<AgentEntry>> {
+        self.entries
+            .read()
+            .unwrap()
+            .iter()
+            .find(|e| e.info.agent_type == agent_type)
+            .cloned()
+    }
+
+    pub fn contains(&self, agent_type: &str) -> bool {
+        self.get(agent_type).is_some()
+    }
+
+    /// 获取 agent 元信息
+    pub fn info(&self, agent_type: &str) -> Option<AgentInfo> {
+        self.get(agent_type).map(|e| e.info.clone())
+    }
+
+    /// 按注册顺序列出全部 agent
+    pub fn list(&self) -> Vec<AgentInfo> {
+        self.entries
+            .read()
+            .unwrap()
+            .iter()
+            .map(|e| e.info.clone())
+            .collect()
+    }
+
+    /// 根据配置创建 agent
+    pub fn create(&self, config: &AgentConfig) -> Result<Box<dyn Agent>> {
+        match self.get(&config.agent_type) {
+            Some(entry) => (entry.factory)(config),
+            None => anyhow::bail!("Unknown agent type: {}", config.agent_type),
+        }
+    }
+
+    /// 检查单个 agent 是否可用，返回版本号（如果能获取）
+    pub async fn check(&self, agent_type: &str) -> Result<Option<String>> {
+        let entry = self
+            .get(agent_type)
+            .ok_or_else(|| anyhow::anyhow!("Unknown agent type: {}", agent_type))?;
+        if let Some(ref probe) = entry.probe {
+            return probe().await;
+        }
+        let config = AgentConfig {
+            agent_type: agent_type.to_string(),
+            ..Default::default()
+        };
+        (entry.factory)(&config)?.health_check().await?;
+        Ok(None)
+    }
+}
+
+/// 执行版本命令（如 `claude --version`）的健康检查，stdout 第一行作为版本号
+pub fn version_probe(
+    command: Vec<String>,
+    install: Option<String>,
+) -> impl Fn() -> BoxFuture<'static, Result<Option<String>>> + Send + Sync + 'static {
+    move || {
+        let command = command.clone();
+        let install = install.clone();
+        Box::pin(async move {
+            let (program, args) = command
+                .split_first()
+                .ok_or_else(|| anyhow::anyhow!("Empty health check command"))?;
+            let not_available = |reason: String| match install {
+                Some(ref hint) => {
+                    anyhow::anyhow!("{} not available ({}). Install: {}", program, reason, hint)
+                }
+                None => anyhow::anyhow!("{} not available ({})", program, reason),
+            };
+
+            let output = tokio::process::Command::new(program)
+                .args(args)
+                .kill_on_drop(true)
+                .output()
+                .await
+                .map_err(|e| not_available(e.to_string()))?;
+            if !output.status.success() {
+                return Err(not_available(output.status.to_string()));
+            }
+
+            let version = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(str::trim)
+                .find(|l| !l.is_empty())
+                .map(str::to_string);
+            Ok(version)
+        })
+    }
+}
+
+/// 全局 agent 注册表（包含内置 agent，启动时再加载配置文件中的定义）
+pub static AGENT_REGISTRY: Lazy<AgentRegistry> = Lazy::new(AgentRegistry::with_builtins);
@@ -0,0 +1,201 @@
+use crate::types::{ProviderAttempt, RetryConfig, StreamEvent};
+use anyhow::Result;
+use reqwest::header::HeaderMap;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+impl RetryConfig {
+    /// 从环境变量读取，`OPENRUNNER_RETRY_<AGENT>_<KEY>` 优先于 `OPENRUNNER_RETRY_<KEY>`
+    ///
+    /// - `MAX_RETRIES`：最多重试次数
+    /// - `BASE_DELAY_MS`：第一次重试前的基础等待
+    /// - `MAX_DELAY_MS`：单次等待上限
+    pub fn from_env(agent_type: &str) -> Self {
+        let get = |key: &str| {
+            std::env::var(format!(
+                "OPENRUNNER_RETRY_{}_{}",
+                agent_type.to_ascii_uppercase(),
+                key
+            ))
+            .or_else(|_| std::env::var(format!("OPENRUNNER_RETRY_{}", key)))
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+        };
+
+        let mut config = Self::default();
+        if let Some(n) = get("MAX_RETRIES") {
+            config.max_retries = n as u32;
+        }
+        if let Some(ms) = get("BASE_DELAY_MS") {
+            config.base_delay_ms = ms;
+        }
+        if let Some(ms) = get("MAX_DELAY_MS") {
+            config.max_delay_ms = ms;
+        }
+        config
+    }
+
+    /// 第 `retry` 次重试前的等待，超过上限时为 None
+    ///
+    /// provider 给出的时间优先；否则在指数退避值的一半到全值之间随机取，
+    /// 避免同时失败的请求一起重试。
+    fn delay(&self, retry: u32, hint: Option<Duration>) -> Option<Duration> {
+        let max = Duration::from_millis(self.max_delay_ms);
+        if let Some(hint) = hint {
+            return (hint <= max).then_some(hint);
+        }
+        let backoff = self
+            .base_delay_ms
+            .saturating_mul(1 << retry.saturating_sub(1).min(20));
+        let cap = Duration::from_millis(backoff).min(max);
+        Some(cap / 2 + cap.mul_f64(rand::random::<f64>() / 2.0))
+    }
+}
+
+/// 再次请求可能成功的状态码；provider 的 `x-should-retry` 优先
+fn retryable(status: StatusCode, headers: &HeaderMap) -> bool {
+    match headers.get("x-should-retry").and_then(|v| v.to_str().ok()) {
+        Some("true") => true,
+        Some("false") => false,
+        // 529 是 Anthropic 的 overloaded_error
+        _ => matches!(
+            status.as_u16(),
+            408 | 409 | 429 | 500 | 502 | 503 | 504 | 529
+        ),
+    }
+}
+
+/// provider 在响应头中要求的等待时间，取最长的一个
+///
+/// 支持 `retry-after-ms`、`retry-after`（秒数或 HTTP 日期），以及配额用完
+/// （`x-ratelimit-remaining-*` 为 0）时对应的 `x-ratelimit-reset-*`。
+fn retry_hint(headers: &HeaderMap) -> Option<Duration> {
+    let get = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+    };
+    let mut hints = Vec::new();
+
+    if let Some(ms) = get("retry-after-ms").and_then(|v| v.parse::<f64>().ok()) {
+        hints.push(Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+    }
+    if let Some(value) = get("retry-after") {
+        if let Ok(secs) = value.parse::<f64>() {
+            hints.push(Duration::from_secs_f64(secs.max(0.0)));
+        } else if let Ok(date) = chrono::DateTime::parse_from_rfc2822(value) {
+            let wait = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+            hints.push(wait.to_std().unwrap_or_default());
+        }
+    }
+    // OpenAI 分 requests / tokens 两组，OpenRouter 只有一组
+    for suffix in ["-requests", "-tokens", ""] {
+        if get(&format!("x-ratelimit-remaining{}", suffix)) == Some("0") {
+            if let Some(reset) = get(&format!("x-ratelimit-reset{}", suffix)).and_then(parse_reset)
+            {
+                hints.push(reset);
+            }
+        }
+    }
+    hints.into_iter().max()
+}
+
+/// 解析重置时间：`6m0s` / `20ms` 这样的时长、秒数，或 Unix 毫秒时间戳
+fn parse_reset(value: &str) -> Option<Duration> {
+    if let Ok(n) = value.parse::<f64>() {
+        // 秒数不会大到这个量级
+        if n > 1e12 {
+            let now = chrono::Utc::now().timestamp_millis() as f64;
+            return Some(Duration::from_millis((n - now).max(0.0) as u64));
+        }
+        return Some(Duration::from_secs_f64(n.max(0.0)));
+    }
+
+    let mut total = 0.0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let end = rest.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+        let n: f64 = rest[..end].parse().ok()?;
+        rest = &rest[end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        total += n * match &rest[..unit_end] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        rest = &rest[unit_end..];
+    }
+    Some(Duration::from_secs_f64(total))
+}
+
+/// 发送请求，失败且可以重试时等待后重发
+///
+/// 只覆盖拿到响应头之前：流式响应开始后的错误不会重试，已输出的 token 不会重复。
+/// 每次请求以 `StreamEvent::Attempt` 上报。重试用完或不可重试时返回最后一个响应，
+/// 由调用方按 provider 的格式解析错误。
+pub(crate) async fn send_with_retry(
+    config: &RetryConfig,
+    provider: &str,
+    request: impl Fn() -> RequestBuilder,
+    tx: &mpsc::Sender<StreamEvent>,
+) -> Result<Response> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let started = Instant::now();
+        let result = request().send().await;
+
+        let (status, error, retry, hint) = match &result {
+            Ok(response) if response.status().is_success() => {
+                (Some(response.status()), None, false, None)
+            }
+            Ok(response) => (
+                Some(response.status()),
+                Some(response.status().to_string()),
+                retryable(response.status(), response.headers()),
+                retry_hint(response.headers()),
+            ),
+            Err(e) => (
+                None,
+                Some(e.to_string()),
+                e.is_connect() || e.is_timeout(),
+                None,
+            ),
+        };
+        let delay = if retry && attempt <= config.max_retries {
+            config.delay(attempt, hint)
+        } else {
+            None
+        };
+
+        let _ = tx
+            .send(StreamEvent::Attempt {
+                attempt: ProviderAttempt {
+                    provider: provider.to_string(),
+                    attempt,
+                    status: status.map(|s| s.as_u16()),
+                    error: error.clone(),
+                    retry_after_ms: delay.map(|d| d.as_millis() as u64),
+                    duration_ms: started.elapsed().as_millis() as u64,
+                },
+            })
+            .await;
+
+        let Some(delay) = delay else {
+            return Ok(result?);
+        };
+        tracing::warn!(
+            "{} request failed ({}), retrying in {:?}",
+            provider,
+            error.unwrap_or_default(),
+            delay
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
@@ -0,0 +1,314 @@
+use crate::types::{SandboxBackend, SandboxConfig};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// 沙箱内只读挂载的系统目录（不存在的会被跳过）
+const SYSTEM_RO_DIRS: &[&str] = &["/usr", "/bin", "/sbin", "/lib", "/lib64", "/etc", "/opt"];
+
+/// CLI agent 子进程命令构建器
+///
+/// 用法与 `tokio::process::Command` 一致。配置了沙箱时，`build()` 会用 bubblewrap
+/// 或 podman 包装实际命令：工作目录读写挂载，系统目录只读挂载，其余文件系统不可见。
+pub struct SandboxCommand {
+    program: String,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    current_dir: Option<PathBuf>,
+    binds: Vec<PathBuf>,
+    sandbox: Option<SandboxConfig>,
+}
+
+impl SandboxCommand {
+    pub fn new(program: &str, sandbox: Option<&SandboxConfig>) -> Self {
+        Self {
+            program: program.to_string(),
+            args: Vec::new(),
+            envs: Vec::new(),
+            current_dir: None,
+            binds: Vec::new(),
+            sandbox: sandbox.cloned(),
+        }
+    }
+
+    pub fn arg(&mut self, arg: impl Into<String>) -> &mut Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn env(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// 设置工作目录（沙箱内同时以读写方式挂载）
+    pub fn current_dir(&mut self, dir: impl AsRef<Path>) -> &mut Self {
+        self.current_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// 额外读写挂载一个路径（如临时配置目录）
+    pub fn bind(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.binds.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// 生成最终要执行的命令
+    pub fn build(self) -> Result<Command> {
+        let backend = self.sandbox.as_ref().map(|s| s.backend).unwrap_or_default();
+
+        let mut cmd = match backend {
+            SandboxBackend::None => {
+                let mut cmd = Command::new(&self.program);
+                cmd.args(&self.args);
+                cmd
+            }
+            SandboxBackend::Bubblewrap => {
+                let mut cmd = Command::new("bwrap");
+                cmd.args(self.bwrap_args());
+                cmd.arg("--").arg(&self.program).args(&self.args);
+                cmd
+            }
+            SandboxBackend::Podman => {
+                let mut cmd = Command::new("podman");
+                cmd.args(self.podman_args()?);
+                cmd.arg(&self.program).args(&self.args);
+                cmd
+            }
+        };
+
+        for (k, v) in &self.envs {
+            cmd.env(k, v);
+        }
+        // 执行被中止（如 Run 取消）时结束子进程
+        cmd.kill_on_drop(true);
+        if let Some(ref dir) = self.current_dir {
+            cmd.current_dir(dir);
+        }
+
+        // podman 通过 cgroups 限制资源，其余情况使用 rlimit
+        if let Some(ref sandbox) = self.sandbox {
+            if backend != SandboxBackend::Podman {
+                apply_rlimits(&mut cmd, sandbox);
+            }
+        }
+
+        Ok(cmd)
+    }
+
+    /// 需要读写挂载的路径：工作目录 + 额外挂载 + 配置中的 rw_binds
+    fn rw_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.current_dir.iter().cloned().collect();
+        paths.extend(self.binds.iter().cloned());
+        if let Some(ref sandbox) = self.sandbox {
+            paths.extend(sandbox.rw_binds.iter().map(PathBuf::from));
+        }
+        paths
+    }
+
+    fn bwrap_args(&self) -> Vec<String> {
+        let sandbox = self.sandbox.clone().unwrap_or_default();
+        let mut args: Vec<String> = ["--die-with-parent", "--new-session", "--unshare-all"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        if sandbox.network {
+            args.push("--share-net".to_string());
+        }
+
+        for dir in SYSTEM_RO_DIRS {
+            args.extend([
+                "--ro-bind-try".to_string(),
+                dir.to_string(),
+                dir.to_string(),
+            ]);
+        }
+        args.extend(
+            ["--proc", "/proc", "--dev", "/dev", "--tmpfs", "/tmp"]
+                .iter()
+                .map(|s| s.to_string()),
+        );
+        for path in &sandbox.ro_binds {
+            args.extend(["--ro-bind-try".to_string(), path.clone(), path.clone()]);
+        }
+        for path in self.rw_paths() {
+            let path = path.to_string_lossy().to_string();
+            args.extend(["--bind".to_string(), path.clone(), path]);
+        }
+        if let Some(ref dir) = self.current_dir {
+            args.push("--chdir".to_string());
+            args.push(dir.to_string_lossy().to_string());
+        }
+        args
+    }
+
+    fn podman_args(&self) -> Result<Vec<String>> {
+        let sandbox = self.sandbox.clone().unwrap_or_default();
+        let image = sandbox
+            .image
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("podman sandbox requires an image"))?;
+
+        let mut args: Vec<String> = ["run", "--rm", "-i", "--init", "--userns=keep-id"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        if !sandbox.network {
+            args.push("--network=none".to_string());
+        }
+        if let Some(mb) = sandbox.memory_mb {
+            args.push(format!("--memory={}m", mb));
+        }
+        if let Some(pids) = sandbox.max_pids {
+            args.push(format!("--pids-limit={}", pids));
+        }
+        if let Some(secs) = sandbox.cpu_secs {
+            args.push(format!("--ulimit=cpu={}:{}", secs, secs));
+        }
+        for path in &sandbox.ro_binds {
+            args.push(format!("--volume={}:{}:ro", path, path));
+        }
+        for path in self.rw_paths() {
+            let path = path.to_string_lossy();
+            args.push(format!("--volume={}:{}:rw", path, path));
+        }
+        if let Some(ref dir) = self.current_dir {
+            args.push(format!("--workdir={}", dir.to_string_lossy()));
+        }
+        // 只写 key，值由 podman 从自身环境中继承，避免出现在进程参数里
+        for (k, _) in &self.envs {
+            args.push(format!("--env={}", k));
+        }
+        args.push(image);
+        Ok(args)
+    }
+}
+
+/// 在子进程 exec 前设置 rlimit
+///
+/// 内存限制使用 RLIMIT_DATA（node 会预留大量虚拟地址，RLIMIT_AS 容易误杀）；
+/// RLIMIT_NPROC 按用户计数，应给服务自身的进程留出余量。
+#[cfg(unix)]
+fn apply_rlimits(cmd: &mut Command, sandbox: &SandboxConfig) {
+    let mut limits = Vec::new();
+    if let Some(mb) = sandbox.memory_mb {
+        limits.push((libc::RLIMIT_DATA, mb.saturating_mul(1024 * 1024)));
+    }
+    if let Some(secs) = sandbox.cpu_secs {
+        limits.push((libc::RLIMIT_CPU, secs));
+    }
+    if let Some(pids) = sandbox.max_pids {
+        limits.push((libc::RLIMIT_NPROC, pids));
+    }
+    if limits.is_empty() {
+        return;
+    }
+
+    // SAFETY: 闭包只调用 async-signal-safe 的 setrlimit，不分配内存
+    unsafe {
+        cmd.pre_exec(move || {
+            for (resource, value) in &limits {
+                let rlim = libc::rlimit {
+                    rlim_cur: *value as libc::rlim_t,
+                    rlim_max: *value as libc::rlim_t,
+                };
+                if libc::setrlimit(*resource, &rlim) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn apply_rlimits(_cmd: &mut Command, sandbox: &SandboxConfig) {
+    if sandbox.memory_mb.is_some() || sandbox.cpu_secs.is_some() || sandbox.max_pids.is_some() {
+        tracing::warn!("Sandbox resource limits are only supported on unix");
+    }
+}
+
+impl SandboxConfig {
+    /// 从环境变量读取某个 agent 类型的沙箱配置
+    ///
+    /// 每个设置先查 `OPENRUNNER_SANDBOX_<AGENT_TYPE>_<KEY>`，再查
+    /// `OPENRUNNER_SANDBOX_<KEY>`，例如 `OPENRUNNER_SANDBOX_CODEX_BACKEND=bubblewrap`。
+    /// 未启用隔离、没有资源限制也没有配置 workspace root 时返回 None。
+    pub fn from_env(agent_type: &str) -> Option<Self> {
+        let get = |key: &str| {
+            let agent_key = format!(
+                "OPENRUNNER_SANDBOX_{}_{}",
+                agent_type.to_ascii_uppercase(),
+                key
+            );
+            std::env::var(agent_key)
+                .or_else(|_| std::env::var(format!("OPENRUNNER_SANDBOX_{}", key)))
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+        };
+        let get_u64 = |key: &str| get(key).and_then(|v| v.trim().parse::<u64>().ok());
+        let get_paths = |key: &str| {
+            get(key)
+                .map(|v| {
+                    std::env::split_paths(&v)
+                        .map(|p| p.to_string_lossy().to_string())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let backend = match get("BACKEND").as_deref().map(str::trim) {
+            Some("bubblewrap") | Some("bwrap") => SandboxBackend::Bubblewrap,
+            Some("podman") => SandboxBackend::Podman,
+            Some("none") | None => SandboxBackend::None,
+            Some(other) => {
+                tracing::warn!("Unknown sandbox backend '{}', sandbox disabled", other);
+                SandboxBackend::None
+            }
+        };
+
+        let config = Self {
+            backend,
+            network: get("NETWORK")
+                .map(|v| !matches!(v.trim(), "0" | "false" | "off" | "no"))
+                .unwrap_or(true),
+            memory_mb: get_u64("MEMORY_MB"),
+            cpu_secs: get_u64("CPU_SECS"),
+            max_pids: get_u64("PIDS"),
+            ro_binds: get_paths("RO_BINDS"),
+            rw_binds: get_paths("RW_BINDS"),
+            image: get("IMAGE"),
+            workspace_root: get("WORKSPACE_ROOT"),
+        };
+
+        let has_limits =
+            config.memory_mb.is_some() || config.cpu_secs.is_some() || config.max_pids.is_some();
+        if config.backend == SandboxBackend::None && !has_limits && config.workspace_root.is_none()
+        {
+            return None;
+        }
+        Some(config)
+    }
+
+    /// 客户端指定的工作目录能否挂载进沙箱
+    ///
+    /// 沙箱会读写挂载工作目录，启用隔离时只允许 `workspace_root` 之内已存在的目录，
+    /// 未配置 `workspace_root` 时一律拒绝；只有资源限制时不做检查。
+    pub fn allows_working_dir(&self, dir: &str) -> bool {
+        self.backend == SandboxBackend::None || self.in_workspace(dir)
+    }
+
+    /// `dir` 是否为 `workspace_root` 之内已存在的目录（按真实路径比较）
+    pub fn in_workspace(&self, dir: &str) -> bool {
+        let Some(ref root) = self.workspace_root else {
+            return false;
+        };
+        match (
+            Path::new(root).canonicalize(),
+            Path::new(dir).canonicalize(),
+        ) {
+            (Ok(root), Ok(dir)) => dir.starts_with(root),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use futures::stream::{BoxStream, Stream, StreamExt};
+use std::collections::VecDeque;
+
+/// 一条 SSE 事件
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// `event:` 字段，未设置时为 None（规范中的默认类型 `message`）
+    pub event: Option<String>,
+    /// 多行 `data:` 以 `\n` 连接
+    pub data: String,
+    /// 最近一次 `id:` 字段（跨事件保留）
+    pub id: Option<String>,
+    pub retry: Option<u64>,
+}
+
+/// 增量 SSE 解码器
+///
+/// 按 [HTML 规范](https://html.spec.whatwg.org/multipage/server-sent-events.html#event-stream-interpretation)
+/// 解析 `text/event-stream`：网络分片按字节缓冲，只在完整的一行上做 UTF-8 解码，
+/// 因此事件或多字节字符被拆到两个分片时不会丢失或损坏。支持 `\n`、`\r\n`、`\r` 换行、
+/// 注释行、多行 `data:` 以及 `id:` / `retry:` 字段。
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buf: Vec<u8>,
+    started: bool,
+    event: Option<String>,
+    data: String,
+    has_data: bool,
+    last_id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 写入一个网络分片，返回其中已完整的事件
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buf.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        let mut start = 0;
+        let mut i = 0;
+
+        while i < self.buf.len() {
+            let end = match self.buf[i] {
+                b'\n' => i + 1,
+                // `\r` 在末尾时可能是被拆开的 `\r\n`，等下一个分片
+                b'\r' if i + 1 == self.buf.len() => break,
+                b'\r' if self.buf[i + 1] == b'\n' => i + 2,
+                b'\r' => i + 1,
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let line = String::from_utf8_lossy(&self.buf[start..i]).into_owned();
+            if let Some(event) = self.process_line(&line) {
+                events.push(event);
+            }
+            start = end;
+            i = end;
+        }
+
+        self.buf.drain(..start);
+        events
+    }
+
+    /// 流结束：处理缓冲中剩余的行
+    ///
+    /// 规范要求丢弃没有以空行结尾的事件，但不少服务端在最后一个事件后直接断开，
+    /// 这里仍然分发已收到 `data:` 的事件。
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if !self.buf.is_empty() {
+            let mut rest = std::mem::take(&mut self.buf);
+            if rest.last() == Some(&b'\r') {
+                rest.pop();
+            }
+            let line = String::from_utf8_lossy(&rest).into_owned();
+            if let Some(event) = self.process_line(&line) {
+                return Some(event);
+            }
+        }
+        self.dispatch()
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        // 流开头的 BOM 忽略
+        let line = if self.started {
+            line
+        } else {
+            self.started = true;
+            line.strip_prefix('\u{feff}').unwrap_or(line)
+        };
+
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => {
+                if self.has_data {
+                    self.data.push('\n');
+                }
+                self.data.push_str(value);
+                self.has_data = true;
+            }
+            "id" if !value.contains('\0') => self.last_id = Some(value.to_string()),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                self.retry = value.parse().ok();
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if !std::mem::take(&mut self.has_data) {
+            return None;
+        }
+        Some(SseEvent {
+            event,
+            data: std::mem::take(&mut self.data),
+            id: self.last_id.clone(),
+            retry: self.retry,
+        })
+    }
+}
+
+/// 把字节流（如 `reqwest::Response::bytes_stream()`）解码为 SSE 事件流
+///
+/// 底层流出错时先产出该错误，随后结束。
+pub fn sse_events<S, B, E>(stream: S) -> BoxStream<'static, Result<SseEvent, E>>
+where
+    S: Stream<Item = Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]> + Send + 'static,
+    E: Send + 'static,
+{
+    let state = (stream.boxed(), SseDecoder::new(), VecDeque::new(), false);
+    futures::stream::unfold(
+        state,
+        |(mut stream, mut decoder, mut pending, mut done)| async move {
+            loop {
+                if let Some(event) = pending.pop_front() {
+                    return Some((Ok(event), (stream, decoder, pending, done)));
+                }
+                if done {
+                    return None;
+                }
+                match stream.next().await {
+                    Some(Ok(chunk)) => pending.extend(decoder.push(chunk.as_ref())),
+                    Some(Err(e)) => {
+                        return Some((Err(e), (stream, decoder, pending, true)));
+                    }
+                    None => {
+                        pending.extend(decoder.finish());
+                        done = true;
+                    }
+                }
+            }
+        },
+    )
+    .boxed()
+}
@@ -0,0 +1,547 @@
+use super::mcp::{McpClient, McpTool};
+use super::SandboxCommand;
+use crate::types::{
+    AgentConfig, SandboxBackend, SandboxConfig, StreamEvent, ToolDefinition, ToolsConfig,
+};
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+
+/// read_file 返回的最大字节数
+const MAX_READ_BYTES: usize = 256 * 1024;
+/// run_shell 保留的最大输出字节数（stdout、stderr 各自计算）
+const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+/// list_files 最多返回的条目数
+const MAX_LIST_ENTRIES: usize = 1000;
+
+/// 模型发起的一次工具调用
+#[derive(Debug, Clone)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub input: Value,
+}
+
+/// 工具调用结果（失败时 output 为错误信息，回传给模型）
+#[derive(Debug, Clone)]
+pub struct ToolResult {
+    pub id: String,
+    pub output: String,
+    pub ok: bool,
+}
+
+impl ToolsConfig {
+    /// 从环境变量读取，`OPENRUNNER_TOOLS_<AGENT>_<KEY>` 优先于 `OPENRUNNER_TOOLS_<KEY>`
+    ///
+    /// - `ALLOW`：逗号分隔的工具名，`none` 表示禁用全部工具；未设置时在该 agent
+    ///   配置了隔离沙箱（bubblewrap / podman）的情况下额外允许 `run_shell`
+    /// - `MAX_ITERATIONS`：最多请求模型的轮数
+    /// - `SHELL_TIMEOUT_SECS`：单条命令超时
+    pub fn from_env(agent_type: &str) -> Self {
+        let get = |key: &str| {
+            std::env::var(format!(
+                "OPENRUNNER_TOOLS_{}_{}",
+                agent_type.to_ascii_uppercase(),
+                key
+            ))
+            .or_else(|_| std::env::var(format!("OPENRUNNER_TOOLS_{}", key)))
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+        };
+
+        let mut config = Self::default();
+        if let Some(allow) = get("ALLOW") {
+            config.allow = allow
+                .split(',')
+                .map(str::trim)
+                .filter(|t| !t.is_empty() && *t != "none")
+                .map(str::to_string)
+                .collect();
+        } else if SandboxConfig::from_env(agent_type)
+            .is_some_and(|sandbox| sandbox.backend != SandboxBackend::None)
+        {
+            config.allow.push("run_shell".to_string());
+        }
+        if let Some(n) = get("MAX_ITERATIONS").and_then(|v| v.trim().parse().ok()) {
+            config.max_iterations = n;
+        }
+        if let Some(n) = get("SHELL_TIMEOUT_SECS").and_then(|v| v.trim().parse().ok()) {
+            config.shell_timeout_secs = n;
+        }
+        config
+    }
+}
+
+/// HTTP 模型 agent 可用的工具：内置工具和 MCP server 提供的工具
+///
+/// 内置工具的路径都限制在工作目录内（包括经过符号链接的路径）；命令通过 `SandboxCommand`
+/// 在工作目录中执行，只带最小环境变量，不会拿到服务进程或 agent 配置中的凭据。
+/// MCP 工具以 `mcp__<server>__<tool>` 的名称提供给模型。
+pub struct ToolBox {
+    builtin: Option<BuiltinTools>,
+    mcp: Vec<McpClient>,
+    /// (提供给模型的名称, 所属 client 下标, 工具)
+    mcp_tools: Vec<(String, usize, McpTool)>,
+    max_iterations: u32,
+}
+
+impl ToolBox {
+    /// 连接配置中的 MCP server；没有任何可用工具时返回 None
+    ///
+    /// 内置工具需要存在的工作目录。连接失败的 MCP server 记录警告后跳过。
+    pub async fn new(config: &AgentConfig, working_dir: Option<&str>) -> Option<Self> {
+        if config.tools.max_iterations == 0 {
+            return None;
+        }
+        let root = working_dir
+            .and_then(|dir| std::fs::canonicalize(dir).ok())
+            .filter(|root| root.is_dir());
+        let builtin = root
+            .clone()
+            .filter(|_| !config.tools.allow.is_empty())
+            .map(|root| BuiltinTools {
+                root,
+                config: config.tools.clone(),
+                sandbox: config.sandbox.clone(),
+            });
+
+        let connections = futures::future::join_all(config.mcp_servers.iter().map(|server| {
+            let root = root.clone();
+            async move {
+                let client =
+                    McpClient::connect(server, config.sandbox.as_ref(), root.as_deref()).await?;
+                let tools = client.list_tools().await?;
+                anyhow::Ok((client, tools))
+            }
+        }))
+        .await;
+
+        let mut mcp = Vec::new();
+        let mut mcp_tools = Vec::new();
+        for (server, connection) in config.mcp_servers.iter().zip(connections) {
+            match connection {
+                Ok((client, tools)) => {
+                    for tool in tools {
+                        let name = mcp_tool_name(client.name(), &tool.name);
+                        mcp_tools.push((name, mcp.len(), tool));
+                    }
+                    mcp.push(client);
+                }
+                Err(e) => {
+                    tracing::warn!("MCP server '{}' unavailable: {:#}", server.name, e);
+                }
+            }
+        }
+
+        if builtin.is_none() && mcp_tools.is_empty() {
+            return None;
+        }
+        Some(Self {
+            builtin,
+            mcp,
+            mcp_tools,
+            max_iterations: config.tools.max_iterations,
+        })
+    }
+
+    pub fn max_iterations(&self) -> u32 {
+        self.max_iterations
+    }
+
+    /// 提供给模型的工具定义
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        let mut definitions = self
+            .builtin
+            .as_ref()
+            .map(|b| b.definitions())
+            .unwrap_or_default();
+        definitions.extend(self.mcp_tools.iter().map(|(name, _, tool)| ToolDefinition {
+            name: name.clone(),
+            description: tool.description.clone(),
+            input_schema: tool.input_schema.clone(),
+        }));
+        definitions
+    }
+
+    /// 是否提供全部被调用的工具；否则这些调用交给调用方执行
+    pub fn handles(&self, calls: &[ToolCall]) -> bool {
+        let definitions = self.definitions();
+        calls
+            .iter()
+            .all(|call| definitions.iter().any(|d| d.name == call.name))
+    }
+
+    /// 执行一次工具调用，并发送 ToolCallStarted / ToolCallFinished 事件
+    pub async fn call(&self, call: &ToolCall, tx: &mpsc::Sender<StreamEvent>) -> ToolResult {
+        let _ = tx
+            .send(StreamEvent::ToolCallStarted {
+                id: call.id.clone(),
+                name: call.name.clone(),
+                input: call.input.clone(),
+            })
+            .await;
+
+        let (output, ok) = match self.execute(&call.name, &call.input).await {
+            Ok(output) => (output, true),
+            Err(e) => (format!("Error: {:#}", e), false),
+        };
+
+        let _ = tx
+            .send(StreamEvent::ToolCallFinished {
+                id: call.id.clone(),
+                output: output.clone(),
+                ok,
+            })
+            .await;
+
+        ToolResult {
+            id: call.id.clone(),
+            output,
+            ok,
+        }
+    }
+
+    async fn execute(&self, name: &str, input: &Value) -> Result<String> {
+        if let Some((_, index, tool)) = self.mcp_tools.iter().find(|(n, _, _)| n == name) {
+            return self.mcp[*index].call_tool(&tool.name, input.clone()).await;
+        }
+        match &self.builtin {
+            Some(builtin) => builtin.execute(name, input).await,
+            None => anyhow::bail!("Tool '{}' is not available", name),
+        }
+    }
+}
+
+/// `mcp__<server>__<tool>`，只保留模型 API 接受的字符，最长 64 个
+fn mcp_tool_name(server: &str, tool: &str) -> String {
+    format!("mcp__{}__{}", server, tool)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(64)
+        .collect()
+}
+
+/// 工作目录内的内置工具
+struct BuiltinTools {
+    root: PathBuf,
+    config: ToolsConfig,
+    sandbox: Option<SandboxConfig>,
+}
+
+impl BuiltinTools {
+    /// 允许的工具定义
+    pub fn definitions(&self) -> Vec<ToolDefinition> {
+        let all = [
+            ToolDefinition {
+                name: "read_file".to_string(),
+                description: "Read a UTF-8 text file in the working directory.".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string", "description": "Path relative to the working directory" }
+                    },
+                    "required": ["path"]
+                }),
+            },
+            ToolDefinition {
+                name: "write_file".to_string(),
+                description: "Create or overwrite a file in the working directory. Parent directories are created as needed.".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string", "description": "Path relative to the working directory" },
+                        "content": { "type": "string", "description": "Full file content" }
+                    },
+                    "required": ["path", "content"]
+                }),
+            },
+            ToolDefinition {
+                name: "list_files".to_string(),
+                description: "List files in a directory of the working directory. Directories end with '/'.".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string", "description": "Directory relative to the working directory, defaults to '.'" },
+                        "recursive": { "type": "boolean", "description": "List subdirectories recursively" }
+                    }
+                }),
+            },
+            ToolDefinition {
+                name: "run_shell".to_string(),
+                description: "Run a shell command (sh -c) in the working directory and return its exit code, stdout and stderr.".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "properties": {
+                        "command": { "type": "string", "description": "Command to run" }
+                    },
+                    "required": ["command"]
+                }),
+            },
+        ];
+        all.into_iter()
+            .filter(|t| self.config.allow.contains(&t.name))
+            .collect()
+    }
+
+    async fn execute(&self, name: &str, input: &Value) -> Result<String> {
+        if !self.config.allow.iter().any(|t| t == name) {
+            anyhow::bail!("Tool '{}' is not available", name);
+        }
+        let str_arg = |key: &str| {
+            input
+                .get(key)
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow::anyhow!("Missing string argument '{}'", key))
+        };
+
+        match name {
+            "read_file" => self.read_file(str_arg("path")?).await,
+            "write_file" => self.write_file(str_arg("path")?, str_arg("content")?).await,
+            "list_files" => {
+                let path = input.get("path").and_then(Value::as_str).unwrap_or(".");
+                let recursive = input
+                    .get("recursive")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false);
+                self.list_files(path, recursive).await
+            }
+            "run_shell" => self.run_shell(str_arg("command")?).await,
+            _ => anyhow::bail!("Unknown tool '{}'", name),
+        }
+    }
+
+    async fn read_file(&self, path: &str) -> Result<String> {
+        read_text_file(&self.root, path).await
+    }
+
+    async fn write_file(&self, path: &str, content: &str) -> Result<String> {
+        let path = self.resolve(path)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, content)
+            .await
+            .with_context(|| format!("Failed to write {}", self.display(&path)))?;
+        Ok(format!(
+            "Wrote {} bytes to {}",
+            content.len(),
+            self.display(&path)
+        ))
+    }
+
+    async fn list_files(&self, path: &str, recursive: bool) -> Result<String> {
+        let dir = self.resolve(path)?;
+        let root = self.root.clone();
+        let entries =
+            tokio::task::spawn_blocking(move || list_dir(&root, &dir, recursive)).await??;
+        if entries.is_empty() {
+            return Ok("(empty)".to_string());
+        }
+        let mut output = entries.join("\n");
+        if entries.len() >= MAX_LIST_ENTRIES {
+            output.push_str(&format!("\n[truncated at {} entries]", MAX_LIST_ENTRIES));
+        }
+        Ok(output)
+    }
+
+    async fn run_shell(&self, command: &str) -> Result<String> {
+        // 只传最小环境变量
+        let mut cmd = SandboxCommand::new("env", self.sandbox.as_ref());
+        cmd.arg("-i")
+            .arg(format!(
+                "PATH={}",
+                std::env::var("PATH").unwrap_or_default()
+            ))
+            .arg(format!("HOME={}", self.root.display()))
+            .arg("LANG=C.UTF-8")
+            .arg("sh")
+            .arg("-c")
+            .arg(command)
+            .current_dir(&self.root);
+
+        let mut cmd = cmd.build()?;
+        cmd.stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true);
+        let mut child = cmd.spawn()?;
+        let mut stdout = child.stdout.take();
+        let mut stderr = child.stderr.take();
+
+        let timeout = Duration::from_secs(self.config.shell_timeout_secs);
+        let run = async {
+            let (out, err) = tokio::join!(read_capped(&mut stdout), read_capped(&mut stderr));
+            let status = child.wait().await?;
+            anyhow::Ok((status, out, err))
+        };
+        let (status, out, err) = match tokio::time::timeout(timeout, run).await {
+            Ok(result) => result?,
+            Err(_) => anyhow::bail!("Command timed out after {}s", timeout.as_secs()),
+        };
+
+        let mut output = match status.code() {
+            Some(code) => format!("exit code: {}", code),
+            None => format!("terminated: {}", status),
+        };
+        if !out.is_empty() {
+            output.push_str("\nstdout:\n");
+            output.push_str(&out);
+        }
+        if !err.is_empty() {
+            output.push_str("\nstderr:\n");
+            output.push_str(&err);
+        }
+        Ok(output)
+    }
+
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        resolve_path(&self.root, path)
+    }
+
+    fn display(&self, path: &Path) -> String {
+        display_path(&self.root, path)
+    }
+}
+
+/// 读取 `root` 内的文本文件，超过上限的部分截断
+///
+/// `root` 需为规范化后的绝对路径。
+pub(crate) async fn read_text_file(root: &Path, path: &str) -> Result<String> {
+    let path = resolve_path(root, path)?;
+    let bytes = tokio::fs::read(&path)
+        .await
+        .with_context(|| format!("Failed to read {}", display_path(root, &path)))?;
+    let truncated = bytes.len() > MAX_READ_BYTES;
+    let mut content =
+        String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_READ_BYTES)]).into_owned();
+    if truncated {
+        content.push_str(&format!(
+            "\n[truncated: showing {} of {} bytes]",
+            MAX_READ_BYTES,
+            bytes.len()
+        ));
+    }
+    Ok(content)
+}
+
+/// 把模型给出的路径解析为 `root` 内的绝对路径
+fn resolve_path(root: &Path, path: &str) -> Result<PathBuf> {
+    let path = Path::new(path);
+    let joined = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        root.join(path)
+    };
+
+    // 先按字面规范化 `.` / `..`
+    let mut normalized = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            other => normalized.push(other),
+        }
+    }
+    if !normalized.starts_with(root) {
+        anyhow::bail!("Path {} is outside the working directory", path.display());
+    }
+
+    // 再逐级检查已存在的部分，防止通过符号链接跳出：链接必须指向工作目录内已存在的路径，
+    // 悬空链接也拒绝（写入时会在链接指向的位置创建文件）
+    let outside = || anyhow::anyhow!("Path {} is outside the working directory", path.display());
+    let mut current = root.to_path_buf();
+    for component in normalized.strip_prefix(root)?.components() {
+        current.push(component);
+        match std::fs::symlink_metadata(&current) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                let real = std::fs::canonicalize(&current).map_err(|_| outside())?;
+                if !real.starts_with(root) {
+                    return Err(outside());
+                }
+            }
+            Ok(_) => {}
+            // 不存在的部分之后会新建
+            Err(_) => break,
+        }
+    }
+    Ok(normalized)
+}
+
+fn display_path(root: &Path, path: &Path) -> String {
+    match path.strip_prefix(root) {
+        Ok(rel) if rel.as_os_str().is_empty() => ".".to_string(),
+        Ok(rel) => rel.display().to_string(),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+fn list_dir(root: &Path, dir: &Path, recursive: bool) -> Result<Vec<String>> {
+    let mut entries = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut children: Vec<_> = std::fs::read_dir(&dir)
+            .with_context(|| format!("Failed to list {}", dir.display()))?
+            .filter_map(|e| e.ok())
+            .collect();
+        children.sort_by_key(|e| e.file_name());
+
+        for entry in children {
+            if entries.len() >= MAX_LIST_ENTRIES {
+                return Ok(entries);
+            }
+            let path = entry.path();
+            let rel = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .display()
+                .to_string();
+            // 不跟随符号链接
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            if is_dir {
+                entries.push(format!("{}/", rel));
+                if recursive && entry.file_name() != ".git" {
+                    pending.push(path);
+                }
+            } else {
+                entries.push(rel);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// 读取全部输出，只保留前 MAX_OUTPUT_BYTES 字节
+async fn read_capped<R: tokio::io::AsyncRead + Unpin>(reader: &mut Option<R>) -> String {
+    let Some(reader) = reader.as_mut() else {
+        return String::new();
+    };
+    let mut kept = Vec::new();
+    let mut total = 0usize;
+    let mut buf = [0u8; 8192];
+    loop {
+        match reader.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                total += n;
+                let room = MAX_OUTPUT_BYTES.saturating_sub(kept.len());
+                kept.extend_from_slice(&buf[..n.min(room)]);
+            }
+        }
+    }
+    let mut output = String::from_utf8_lossy(&kept).into_owned();
+    if total > kept.len() {
+        output.push_str(&format!("\n[truncated: {} of {} bytes]", kept.len(), total));
+    }
+    output
+}
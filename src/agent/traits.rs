@@ -1,19 +1,38 @@
-use crate::types::StreamEvent;
+use super::AgentCapabilities;
+use crate::types::{AgentRequest, ModelInfo, StreamEvent};
 use anyhow::Result;
 use async_trait::async_trait;
 use tokio::sync::mpsc;
 
 /// Agent trait - 所有 agent 实现的核心接口
+///
+/// 只支持字符串 prompt 的 agent 实现 `run` 即可；需要消息历史、system prompt、
+/// 采样参数等结构化输入的 agent 覆盖 `execute`，并让 `run` 转调 `execute`。
 #[async_trait]
 pub trait Agent: Send + Sync + 'static {
     /// 执行 agent，流式输出到 tx
     async fn run(&self, prompt: String, tx: mpsc::Sender<StreamEvent>) -> Result<()>;
 
+    /// 执行结构化请求，默认拼接为 prompt 后调用 `run`
+    async fn execute(&self, request: AgentRequest, tx: mpsc::Sender<StreamEvent>) -> Result<()> {
+        self.run(request.to_prompt(), tx).await
+    }
+
     /// Agent 名称
     fn name(&self) -> &str;
 
+    /// 支持的能力
+    fn capabilities(&self) -> AgentCapabilities {
+        AgentCapabilities::default()
+    }
+
     /// 检查 agent 是否可用（CLI 是否安装等）
     async fn health_check(&self) -> Result<()> {
         Ok(())
     }
+
+    /// 列出 provider 提供的模型（HTTP 模型 agent 实现）
+    async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        anyhow::bail!("{} does not list models", self.name())
+    }
 }
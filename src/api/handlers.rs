@@ -6,21 +6,26 @@ use axum::{
         IntoResponse,
     },
 };
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::Deserialize;
 use std::convert::Infallible;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 
-use crate::agent::{
-    create_agent, Agent, AgentHandle, ClaudeCodeAgent, CodexAgent, MockAgent, OpenCodeAgent,
-};
+use crate::agent::AgentHandle;
 use crate::auth::{
-    self, create_token, verify_token, AuthError, LoginRequest, LoginResponse, RegisterRequest,
-    RegisterResponse, TOKEN_EXPIRY_SECS,
+    self, create_token, verify_token, AuthError, Claims, LoginRequest, LoginResponse,
+    RegisterRequest, RegisterResponse, TOKEN_EXPIRY_SECS,
 };
+use crate::budget::{Budget, BudgetError, BudgetScope};
+use crate::redact::{redact_args, Redactor};
+use crate::run::RunSummary;
+use crate::secrets::{self, secret_scope, SecretInfo, SecretNotFound};
+use crate::storage::{AgentDefault, UsageGroupBy};
 use crate::types::{
-    AgentConfig, ChatRequest, ChatResponse, CreateProjectRequest, CreateRunRequest,
-    CreateRunResponse, ErrorResponse, Project, SessionPayload, SessionsResponse,
+    AgentConfig, AgentRequest, ChatRequest, ChatResponse, CreateProjectRequest, CreateRunRequest,
+    CreateRunResponse, ErrorResponse, McpServerConfig, Project, RetryConfig, SamplingParams,
+    SandboxConfig, SessionPayload, SessionsResponse, SetMcpServersRequest, ToolsConfig,
 };
 
 use super::AppState;
@@ -91,58 +96,30 @@ pub async fn health() -> impl IntoResponse {
     Json(serde_json::json!({ "ok": true }))
 }
 
-/// GET /health/agents
-pub async fn health_agents() -> impl IntoResponse {
-    let config = AgentConfig::default();
-
-    let claude = ClaudeCodeAgent::new(config.clone());
-    let codex = CodexAgent::new(config.clone());
-    let opencode = OpenCodeAgent::new(config.clone());
-    let mock = MockAgent::new(config.clone());
-
-    let (claude_ok, codex_ok, opencode_ok, mock_ok) = tokio::join!(
-        claude.health_check(),
-        codex.health_check(),
-        opencode.health_check(),
-        mock.health_check()
-    );
-
-    Json(serde_json::json!({
-        "agents": {
-            "mock": {
-                "available": mock_ok.is_ok(),
-                "error": mock_ok.err().map(|e| e.to_string()),
-                "description": "Mock agent for testing (no API key required)"
-            },
-            "claude_code": {
-                "available": claude_ok.is_ok(),
-                "error": claude_ok.err().map(|e| e.to_string()),
-                "install": "npm install -g @anthropic-ai/claude-code"
-            },
-            "codex": {
-                "available": codex_ok.is_ok(),
-                "error": codex_ok.err().map(|e| e.to_string()),
-                "install": "npm install -g @openai/codex"
-            },
-            "opencode": {
-                "available": opencode_ok.is_ok(),
-                "error": opencode_ok.err().map(|e| e.to_string()),
-                "install": "go install github.com/opencode-ai/opencode@latest"
-            }
-        }
-    }))
+/// GET /health/agents - 返回后台健康检查的缓存结果
+pub async fn health_agents(State(state): State<AppState>) -> impl IntoResponse {
+    let agents: serde_json::Map<String, serde_json::Value> = state
+        .health
+        .agents()
+        .into_iter()
+        .map(|h| {
+            let key = h.info.agent_type.clone();
+            (key, serde_json::to_value(h).unwrap_or_default())
+        })
+        .collect();
+    let providers: serde_json::Map<String, serde_json::Value> = state
+        .health
+        .providers()
+        .into_iter()
+        .map(|(name, status)| (name, serde_json::to_value(status).unwrap_or_default()))
+        .collect();
+
+    Json(serde_json::json!({ "agents": agents, "providers": providers }))
 }
 
 /// GET /agents
-pub async fn list_agents() -> impl IntoResponse {
-    Json(serde_json::json!({
-        "agents": [
-            {"type": "mock", "description": "Mock agent for testing (no API key required)"},
-            {"type": "claude_code", "description": "Claude Code CLI agent"},
-            {"type": "codex", "description": "OpenAI Codex CLI agent"},
-            {"type": "opencode", "description": "OpenCode CLI agent"}
-        ]
-    }))
+pub async fn list_agents(State(state): State<AppState>) -> impl IntoResponse {
+    Json(serde_json::json!({ "agents": state.registry.list() }))
 }
 
 // ============ Run Handlers ============
@@ -153,8 +130,24 @@ pub async fn create_run(
     headers: axum::http::HeaderMap,
     Json(req): Json<CreateRunRequest>,
 ) -> Result<Json<CreateRunResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let user_id = auth_user_from_headers(&headers).unwrap_or_else(|_| "anonymous".to_string());
+    let claims = auth_claims_from_headers(&headers).ok();
+    let user_id = claims
+        .as_ref()
+        .map(|c| c.sub.clone())
+        .unwrap_or_else(|| "anonymous".to_string());
+    let roles = claims.map(|c| c.roles).unwrap_or_default();
+
+    let run_id = start_run(&state, &user_id, &roles, req).await?;
+    Ok(Json(CreateRunResponse { run_id }))
+}
 
+/// 按请求构建 agent 配置并启动 Run（`POST /api/runs` 和 MCP `create_run` 共用）
+pub async fn start_run(
+    state: &AppState,
+    user_id: &str,
+    roles: &[String],
+    req: CreateRunRequest,
+) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
     let (agent_type, model, env, extra_args) = normalize_run_metadata(&req);
 
     // Debug: log received metadata
@@ -163,64 +156,194 @@ pub async fn create_run(
         agent_type,
         model,
         env.as_ref().map(|e| e.keys().collect::<Vec<_>>()),
-        extra_args,
+        extra_args.as_deref().map(redact_args),
         req.metadata.project_id
     );
 
     // Determine working directory: use project path if project_id is provided
-    let working_dir = if let Some(project_id) = req.metadata.project_id.as_ref() {
-        match state.db.get_project(&user_id, project_id).await {
-            Ok(Some(project)) => {
-                tracing::info!("Using project directory: {}", project.path);
-                Some(project.path)
-            }
-            Ok(None) => {
-                tracing::warn!("Project {} not found, using default cwd", project_id);
-                req.metadata.cwd.clone()
-            }
-            Err(e) => {
-                tracing::error!("Failed to get project: {}", e);
-                req.metadata.cwd.clone()
+    let (working_dir, project_mcp_servers, project_id) =
+        if let Some(project_id) = req.metadata.project_id.as_ref() {
+            match state.db.get_project(user_id, project_id).await {
+                Ok(Some(project)) => {
+                    tracing::info!("Using project directory: {}", project.path);
+                    (Some(project.path), project.mcp_servers, Some(project.id))
+                }
+                Ok(None) => {
+                    tracing::warn!("Project {} not found, using default cwd", project_id);
+                    (req.metadata.cwd.clone(), vec![], None)
+                }
+                Err(e) => {
+                    tracing::error!("Failed to get project: {}", e);
+                    (req.metadata.cwd.clone(), vec![], None)
+                }
             }
+        } else {
+            (req.metadata.cwd.clone(), vec![], None)
+        };
+
+    // 构建 AgentConfig (默认使用 mock agent 便于测试)
+    let effective_agent_type = agent_type.clone().unwrap_or_else(|| "mock".to_string());
+
+    // 附件只有元信息，声明支持附件的 agent 才能处理，其他 agent 不能静默忽略
+    if !req.input.attachments.is_empty() {
+        reject_attachments(state, &effective_agent_type)?;
+    }
+
+    // 该 agent 类型的默认配置（采样参数、MCP server）
+    let defaults = match state.db.get_agent_defaults(user_id).await {
+        Ok(defaults) => defaults
+            .into_iter()
+            .find(|d| d.agent_type == effective_agent_type),
+        Err(e) => {
+            tracing::warn!("Failed to load agent defaults: {}", e);
+            None
         }
-    } else {
-        req.metadata.cwd.clone()
     };
+    let (default_sampling, default_mcp_servers) = defaults
+        .map(|d| (d.sampling, d.mcp_servers))
+        .unwrap_or_default();
+
+    // 沙箱会读写挂载工作目录，客户端指定的目录必须在 workspace root 之内
+    let sandbox = SandboxConfig::from_env(&effective_agent_type);
+    if let (Some(sandbox), Some(dir)) = (&sandbox, working_dir.as_deref()) {
+        if project_id.is_none() && !sandbox.allows_working_dir(dir) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: format!(
+                        "working directory '{}' is outside the sandbox workspace",
+                        dir
+                    ),
+                }),
+            ));
+        }
+    }
 
-    let run_id = state
-        .run_manager
-        .create_run(&user_id, req.session_id.clone(), &req.input.text);
+    // 内置文件工具读写工作目录，客户端指定的目录只有在 workspace root 之内时才开放
+    let mut tools = ToolsConfig::from_env(&effective_agent_type);
+    let trusted_dir = project_id.is_some()
+        || working_dir
+            .as_deref()
+            .is_some_and(|dir| sandbox.as_ref().is_some_and(|s| s.in_workspace(dir)));
+    if !trusted_dir {
+        tools.allow.clear();
+    }
 
-    // 构建 AgentConfig (默认使用 mock agent 便于测试)
     let config = AgentConfig {
-        agent_type: agent_type.clone().unwrap_or_else(|| "mock".to_string()),
+        sandbox,
+        tools,
+        retry: RetryConfig::from_env(&effective_agent_type),
+        gateway: Some(state.providers.scope(user_id)),
+        agent_type: effective_agent_type,
         working_dir,
         model: model.clone(),
         env: env.clone().unwrap_or_default(),
         extra_args: extra_args.clone().unwrap_or_default(),
+        // 项目中的同名 server 覆盖 agent 默认配置
+        mcp_servers: McpServerConfig::merge(&default_mcp_servers, &project_mcp_servers),
         ..Default::default()
     };
 
+    state.policy.check(&config, roles).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    })?;
+
+    let limit = state
+        .budgets
+        .check(user_id, project_id.as_deref())
+        .await
+        .map_err(budget_error)?;
+
+    // 引用的密钥不存在时在创建 Run 之前拒绝
+    state
+        .run_manager
+        .check_secrets(user_id, &config)
+        .await
+        .map_err(start_error)?;
+
+    let run_id = state
+        .run_manager
+        .create_run(user_id, req.session_id.clone(), &req.input.text);
+    state.run_manager.store().set_limit(&run_id, limit);
+    if let Some(project_id) = project_id {
+        state.run_manager.store().set_project(&run_id, project_id);
+    }
+
     if let Some(session_id) = req.session_id.as_ref() {
+        // 明文凭据不写入 session，改为密钥引用
+        let session_env = match env {
+            Some(ref env) => {
+                let scope = secret_scope("session", session_id);
+                externalize_env(state, user_id, &scope, env).await.ok()
+            }
+            None => None,
+        };
         let _ = state
             .db
             .upsert_session(
-                &user_id, session_id, None, agent_type, model, env, extra_args, None, None, None,
+                user_id,
+                session_id,
+                None,
+                agent_type,
+                model,
+                session_env,
+                extra_args,
+                None,
+                None,
+                None,
             )
             .await;
     }
 
+    // 采样参数：请求中未设置的字段取该 agent 类型的默认配置
+    let sampling = req
+        .metadata
+        .sampling
+        .clone()
+        .unwrap_or_default()
+        .or(&default_sampling);
+
     // 启动 Run
-    if let Err(e) = state.run_manager.start_run(&run_id, config).await {
-        return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
-        ));
-    }
+    let mut request = AgentRequest::new(req.input.text.clone());
+    request.attachments = req.input.attachments.clone();
+    request.working_dir = config.working_dir.clone();
+    request.sampling = sampling;
+    state
+        .run_manager
+        .start_request(&run_id, config, request)
+        .await
+        .map_err(start_error)?;
 
-    Ok(Json(CreateRunResponse { run_id }))
+    Ok(run_id)
+}
+
+/// GET /api/runs/:run_id - 获取 Run 信息
+pub async fn get_run(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Path(run_id): Path<String>,
+) -> Result<Json<RunSummary>, (StatusCode, Json<ErrorResponse>)> {
+    let user_id = auth_user_from_headers(&headers).unwrap_or_else(|_| "anonymous".to_string());
+
+    let run = state
+        .run_manager
+        .get_run(&run_id)
+        .filter(|r| r.user_id == user_id)
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: format!("Run not found: {}", run_id),
+                }),
+            )
+        })?;
+
+    Ok(Json(RunSummary::from(&run)))
 }
 
 #[derive(Debug, Deserialize)]
@@ -249,6 +372,7 @@ pub async fn list_sessions(
             }),
         )
     })?;
+
     Ok(Json(SessionsResponse { sessions }))
 }
 
@@ -284,6 +408,8 @@ pub async fn save_sessions(
     }
 
     for (idx, s) in req.sessions.iter().enumerate() {
+        let scope = secret_scope("session", &s.id);
+        let env = externalize_env(&state, &user_id, &scope, &s.env).await?;
         state
             .db
             .upsert_session(
@@ -292,7 +418,7 @@ pub async fn save_sessions(
                 Some(s.title.clone()),
                 Some(s.agent_type.clone()),
                 s.model.clone(),
-                Some(s.env.clone()),
+                Some(env),
                 Some(s.extra_args.clone()),
                 Some(s.hidden),
                 Some(idx as i32),
@@ -350,6 +476,8 @@ pub async fn get_agent_defaults(
                 "model": d.model,
                 "env": d.env,
                 "extra_args": d.extra_args,
+                "sampling": d.sampling,
+                "mcp_servers": d.mcp_servers,
             }),
         );
     }
@@ -366,6 +494,10 @@ pub struct SetAgentDefaultRequest {
     pub env: std::collections::HashMap<String, String>,
     #[serde(default)]
     pub extra_args: Vec<String>,
+    #[serde(default)]
+    pub sampling: SamplingParams,
+    #[serde(default)]
+    pub mcp_servers: Vec<McpServerConfig>,
 }
 
 /// POST /api/agent-defaults - 设置某个 agent 类型的默认配置
@@ -374,7 +506,7 @@ pub async fn set_agent_default(
     headers: axum::http::HeaderMap,
     Json(req): Json<SetAgentDefaultRequest>,
 ) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    let user_id = auth_user_from_headers(&headers).map_err(|_| {
+    let claims = auth_claims_from_headers(&headers).map_err(|_| {
         (
             StatusCode::UNAUTHORIZED,
             Json(ErrorResponse {
@@ -382,15 +514,38 @@ pub async fn set_agent_default(
             }),
         )
     })?;
+    let user_id = claims.sub;
+
+    // Run 时还会再检查一次，这里提前拒绝策略不允许的 MCP server
+    let mcp_check = AgentConfig {
+        agent_type: req.agent_type.clone(),
+        mcp_servers: req.mcp_servers.clone(),
+        ..Default::default()
+    };
+    state.policy.check(&mcp_check, &claims.roles).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    })?;
 
+    let scope = secret_scope("defaults", &req.agent_type);
+    let env = externalize_env(&state, &user_id, &scope, &req.env).await?;
+    let mcp_servers = externalize_mcp_servers(&state, &user_id, &scope, &req.mcp_servers).await?;
     state
         .db
         .set_agent_default(
             &user_id,
-            &req.agent_type,
-            req.model,
-            req.env,
-            req.extra_args,
+            &AgentDefault {
+                agent_type: req.agent_type,
+                model: req.model,
+                env,
+                extra_args: req.extra_args,
+                sampling: req.sampling,
+                mcp_servers,
+            },
         )
         .await
         .map_err(|e| {
@@ -405,6 +560,536 @@ pub async fn set_agent_default(
     Ok(Json(serde_json::json!({ "ok": true })))
 }
 
+/// 把 env 中的明文凭据移入密钥库，返回改为 `${secret:NAME}` 引用后的 env
+async fn externalize_env(
+    state: &AppState,
+    user_id: &str,
+    scope: &str,
+    env: &std::collections::HashMap<String, String>,
+) -> Result<std::collections::HashMap<String, String>, (StatusCode, Json<ErrorResponse>)> {
+    state
+        .secrets
+        .externalize_env(user_id, scope, env)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: e.to_string(),
+                }),
+            )
+        })
+}
+
+/// 校验 MCP server 配置，并把 env / headers 中的明文凭据移入密钥库
+///
+/// 密钥名以所属对象的 `owner` 为前缀，如 `project.<id>.mcp.<name>.<KEY>`。
+async fn externalize_mcp_servers(
+    state: &AppState,
+    user_id: &str,
+    owner: &str,
+    servers: &[McpServerConfig],
+) -> Result<Vec<McpServerConfig>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |error: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }));
+    let mut result: Vec<McpServerConfig> = Vec::with_capacity(servers.len());
+    for server in servers {
+        server.validate().map_err(bad_request)?;
+        if result.iter().any(|s| s.name == server.name) {
+            return Err(bad_request(format!(
+                "Duplicate MCP server name '{}'",
+                server.name
+            )));
+        }
+        let scope = format!("{}.mcp.{}", owner, server.name);
+        let mut server = server.clone();
+        server.env = externalize_env(state, user_id, &scope, &server.env).await?;
+        server.headers = externalize_env(state, user_id, &scope, &server.headers).await?;
+        result.push(server);
+    }
+    Ok(result)
+}
+
+// ============ Secrets ============
+
+#[derive(Debug, Deserialize)]
+pub struct SetSecretRequest {
+    pub name: String,
+    pub value: String,
+}
+
+/// GET /api/secrets - 列出密钥（只返回名称和末 4 位）
+pub async fn list_secrets(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let user_id = auth_user_from_headers(&headers).map_err(|_| {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "invalid_token".to_string(),
+            }),
+        )
+    })?;
+
+    let secrets = state.secrets.list(&user_id).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    })?;
+
+    Ok(Json(serde_json::json!({ "secrets": secrets })))
+}
+
+/// POST /api/secrets - 创建或更新密钥（只写）
+pub async fn set_secret(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<SetSecretRequest>,
+) -> Result<Json<SecretInfo>, (StatusCode, Json<ErrorResponse>)> {
+    let user_id = auth_user_from_headers(&headers).map_err(|_| {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "invalid_token".to_string(),
+            }),
+        )
+    })?;
+
+    if !secrets::is_valid_name(&req.name) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Secret name can only contain letters, numbers, '_', '.' and '-'"
+                    .to_string(),
+            }),
+        ));
+    }
+
+    let info = state
+        .secrets
+        .set(&user_id, &req.name, &req.value)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: e.to_string(),
+                }),
+            )
+        })?;
+
+    Ok(Json(info))
+}
+
+/// DELETE /api/secrets/:name - 删除密钥
+pub async fn delete_secret(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Path(name): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let user_id = auth_user_from_headers(&headers).map_err(|_| {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "invalid_token".to_string(),
+            }),
+        )
+    })?;
+
+    let deleted = state.secrets.delete(&user_id, &name).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    })?;
+
+    if !deleted {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Secret not found".to_string(),
+            }),
+        ));
+    }
+
+    Ok(Json(serde_json::json!({ "ok": true })))
+}
+
+// ============ API Keys ============
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+}
+
+/// GET /api/api-keys - 列出 API key（不含 key 本身）
+pub async fn list_api_keys(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let user_id = auth_user_from_headers(&headers).map_err(|_| {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "invalid_token".to_string(),
+            }),
+        )
+    })?;
+
+    let keys = state.db.list_api_keys(&user_id).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    })?;
+
+    Ok(Json(serde_json::json!({ "api_keys": keys })))
+}
+
+/// POST /api/api-keys - 创建 API key，key 只在响应中返回这一次
+///
+/// key 继承创建者当前的角色，用于 MCP server 等无法登录的调用方。
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<CreateApiKeyRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let claims = auth_claims_from_headers(&headers).map_err(|_| {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "invalid_token".to_string(),
+            }),
+        )
+    })?;
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "API key name is required".to_string(),
+            }),
+        ));
+    }
+
+    let key = auth::generate_api_key();
+    let info = state
+        .db
+        .create_api_key(
+            &claims.sub,
+            name,
+            &auth::hash_api_key(&key),
+            &auth::api_key::display_prefix(&key),
+            &claims.roles,
+        )
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: e.to_string(),
+                }),
+            )
+        })?;
+
+    let mut value = serde_json::to_value(info).unwrap_or_default();
+    value["key"] = serde_json::Value::String(key);
+    Ok(Json(value))
+}
+
+/// DELETE /api/api-keys/:id - 吊销 API key
+pub async fn delete_api_key(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let user_id = auth_user_from_headers(&headers).map_err(|_| {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "invalid_token".to_string(),
+            }),
+        )
+    })?;
+
+    let deleted = state.db.delete_api_key(&user_id, &id).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    })?;
+
+    if !deleted {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "API key not found".to_string(),
+            }),
+        ));
+    }
+
+    Ok(Json(serde_json::json!({ "ok": true })))
+}
+
+/// GET /api/usage query params
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    #[serde(default)]
+    pub group_by: UsageGroupBy,
+    /// 起始时间（含），RFC 3339 或 YYYY-MM-DD
+    pub from: Option<String>,
+    /// 结束时间（不含），格式同 from
+    pub to: Option<String>,
+}
+
+/// 解析 RFC 3339 时间或日期（按 UTC 零点）
+fn parse_usage_time(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(value) {
+        return Some(t.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|t| t.and_utc())
+}
+
+/// GET /api/usage - 按用户 / 项目 / agent / 模型汇总 token 用量和费用
+///
+/// admin 角色可以看到所有用户的用量，其他用户只能看到自己的。
+pub async fn get_usage(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let claims = auth_claims_from_headers(&headers).map_err(|_| {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "invalid_token".to_string(),
+            }),
+        )
+    })?;
+
+    let parse = |name: &str, value: &Option<String>| match value {
+        Some(value) => parse_usage_time(value).map(Some).ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: format!("Invalid '{}': expected RFC 3339 or YYYY-MM-DD", name),
+                }),
+            )
+        }),
+        None => Ok(None),
+    };
+    let from = parse("from", &query.from)?;
+    let to = parse("to", &query.to)?;
+
+    let user_filter = (!is_admin(&claims)).then_some(claims.sub.as_str());
+    let usage = state
+        .db
+        .usage_summary(user_filter, query.group_by, from, to)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: e.to_string(),
+                }),
+            )
+        })?;
+
+    Ok(Json(serde_json::json!({
+        "group_by": query.group_by,
+        "from": from,
+        "to": to,
+        "usage": usage,
+    })))
+}
+
+/// 预算用完时的响应：token 预算返回 429，费用预算返回 402
+/// 启动 Run 的错误，引用的密钥不存在时返回 400
+fn start_error(e: anyhow::Error) -> (StatusCode, Json<ErrorResponse>) {
+    let status = if e.is::<SecretNotFound>() {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    (
+        status,
+        Json(ErrorResponse {
+            error: e.to_string(),
+        }),
+    )
+}
+
+pub(crate) fn budget_error(e: BudgetError) -> (StatusCode, Json<ErrorResponse>) {
+    let status = match e {
+        BudgetError::Tokens { .. } => StatusCode::TOO_MANY_REQUESTS,
+        BudgetError::Cost { .. } => StatusCode::PAYMENT_REQUIRED,
+        BudgetError::Storage(ref e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: e.to_string(),
+                }),
+            )
+        }
+    };
+    (
+        status,
+        Json(ErrorResponse {
+            error: format!("budget_exceeded: {}", e),
+        }),
+    )
+}
+
+/// GET /api/budgets - 列出预算及当前周期的用量
+///
+/// admin 可以看到全部预算；其他用户只能看到自己和自己项目的预算（不含 webhook 地址）。
+pub async fn list_budgets(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let claims = auth_claims_from_headers(&headers).map_err(|_| {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "invalid_token".to_string(),
+            }),
+        )
+    })?;
+    let internal = |e: anyhow::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    };
+
+    let budgets = if is_admin(&claims) {
+        state.db.list_budgets().await.map_err(internal)?
+    } else {
+        let projects: Vec<String> = state
+            .db
+            .list_projects(&claims.sub)
+            .await
+            .map_err(internal)?
+            .into_iter()
+            .map(|p| p.id)
+            .collect();
+        let mut budgets: Vec<Budget> = state
+            .db
+            .list_budgets()
+            .await
+            .map_err(internal)?
+            .into_iter()
+            .filter(|b| match b.scope {
+                BudgetScope::User => b.scope_id == claims.sub,
+                BudgetScope::Project => projects.contains(&b.scope_id),
+            })
+            .collect();
+        for budget in &mut budgets {
+            budget.webhook_url = None;
+        }
+        budgets
+    };
+
+    let now = chrono::Utc::now();
+    let mut items = Vec::with_capacity(budgets.len());
+    for budget in budgets {
+        let (used_tokens, used_cost_usd) = state
+            .db
+            .usage_total(budget.scope, &budget.scope_id, budget.period.start(now))
+            .await
+            .map_err(internal)?;
+        let mut item = serde_json::to_value(&budget).unwrap_or_default();
+        item["used_tokens"] = used_tokens.into();
+        item["used_cost_usd"] = used_cost_usd.into();
+        items.push(item);
+    }
+
+    Ok(Json(serde_json::json!({ "budgets": items })))
+}
+
+/// POST /api/budgets - 创建或替换预算（同一范围和周期只有一条，仅 admin）
+pub async fn set_budget(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Json(budget): Json<Budget>,
+) -> Result<Json<Budget>, (StatusCode, Json<ErrorResponse>)> {
+    require_admin(&headers)?;
+
+    let invalid = |error: &str| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: error.to_string(),
+            }),
+        )
+    };
+    if budget.scope_id.is_empty() {
+        return Err(invalid("scope_id is required"));
+    }
+    if budget.max_tokens.is_none()
+        && budget.max_cost_usd.is_none()
+        && budget.max_run_tokens.is_none()
+        && budget.max_run_cost_usd.is_none()
+    {
+        return Err(invalid("budget must set at least one limit"));
+    }
+    if budget.warn_ratio.is_some_and(|r| !(r > 0.0 && r <= 1.0)) {
+        return Err(invalid("warn_ratio must be in (0, 1]"));
+    }
+
+    let budget = state.db.upsert_budget(&budget).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    })?;
+    Ok(Json(budget))
+}
+
+/// DELETE /api/budgets/:id - 删除预算（仅 admin）
+pub async fn delete_budget(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    require_admin(&headers)?;
+
+    let deleted = state.db.delete_budget(&id).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    })?;
+    if !deleted {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Budget not found".to_string(),
+            }),
+        ));
+    }
+
+    Ok(Json(serde_json::json!({ "ok": true })))
+}
+
 /// SSE query params
 #[derive(Debug, Deserialize)]
 pub struct EventsQuery {
@@ -466,11 +1151,13 @@ pub async fn run_events(
         return Ok(Sse::new(stream));
     }
 
-    // 如果失败，返回错误
-    if run.status == RunStatus::Failed {
-        let events = vec![RunEvent::RunFailed(RunFailed {
-            error: run.error.unwrap_or_else(|| "Unknown error".to_string()),
-        })];
+    // 如果失败或已取消，返回错误
+    if matches!(run.status, RunStatus::Failed | RunStatus::Cancelled) {
+        let error = match run.status {
+            RunStatus::Cancelled => "Run cancelled".to_string(),
+            _ => run.error.unwrap_or_else(|| "Unknown error".to_string()),
+        };
+        let events = vec![RunEvent::RunFailed(RunFailed { error })];
         let stream: std::pin::Pin<
             Box<dyn futures::stream::Stream<Item = Result<Event, Infallible>> + Send>,
         > = Box::pin(futures::stream::iter(events).map(run_event_to_sse));
@@ -527,22 +1214,77 @@ pub async fn run_events(
     Ok(Sse::new(stream))
 }
 
+/// 请求带有附件而 agent 不支持时返回 400（未知 agent 留给创建时报错）
+fn reject_attachments(
+    state: &AppState,
+    agent_type: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    match state.registry.info(agent_type) {
+        Some(info) if !info.capabilities.attachments => Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Agent '{}' does not support attachments", agent_type),
+            }),
+        )),
+        _ => Ok(()),
+    }
+}
+
 // ============ Chat Handler (Fallback) ============
 
 /// POST /api/chat - 非流式聊天（降级方案）
 pub async fn chat(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
     Json(req): Json<ChatRequest>,
 ) -> Result<Json<ChatResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let claims = auth_claims_from_headers(&headers).ok();
+    let user_id = claims
+        .as_ref()
+        .map(|c| c.sub.clone())
+        .unwrap_or_else(|| "anonymous".to_string());
+    let roles = claims.map(|c| c.roles).unwrap_or_default();
+    let agent_type = req.agent_type.unwrap_or_else(|| "claude_code".to_string());
+    if !req.attachments.is_empty() {
+        reject_attachments(&state, &agent_type)?;
+    }
     let config = AgentConfig {
-        agent_type: req.agent_type.unwrap_or_else(|| "claude_code".to_string()),
+        sandbox: SandboxConfig::from_env(&agent_type),
+        tools: ToolsConfig::from_env(&agent_type),
+        retry: RetryConfig::from_env(&agent_type),
+        gateway: Some(state.providers.scope(&user_id)),
+        agent_type,
         model: req.model,
         env: req.env.unwrap_or_default(),
         extra_args: req.extra_args.unwrap_or_default(),
         ..Default::default()
     };
 
-    let agent = create_agent(&config).map_err(|e| {
+    state.policy.check(&config, &roles).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    })?;
+
+    let mut config = config;
+    let original_env = config.env.clone();
+    config.env = state
+        .secrets
+        .resolve_env(&user_id, &config.env)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: e.to_string(),
+                }),
+            )
+        })?;
+
+    let agent = state.registry.create(&config).map_err(|e| {
         (
             StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
@@ -559,15 +1301,26 @@ pub async fn chat(
     let run_handle = tokio::spawn(async move { handle.run(prompt).await });
 
     // 收集输出
+    let redactor = Redactor::from_resolved_env(&original_env, &config.env);
     let mut output = String::new();
     while let Some(event) = rx.recv().await {
         match event {
             crate::types::StreamEvent::Token { content } => output.push_str(&content),
+            crate::types::StreamEvent::Restart { .. } => output.clear(),
+            crate::types::StreamEvent::Thinking { .. }
+            | crate::types::StreamEvent::Usage { .. }
+            | crate::types::StreamEvent::ToolCallStarted { .. }
+            | crate::types::StreamEvent::ToolCallFinished { .. }
+            | crate::types::StreamEvent::ToolCallRequested { .. }
+            | crate::types::StreamEvent::Attempt { .. }
+            | crate::types::StreamEvent::Stop { .. } => {}
             crate::types::StreamEvent::Done { .. } => break,
-            crate::types::StreamEvent::Error { message } => {
+            crate::types::StreamEvent::Error { message, .. } => {
                 return Err((
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ErrorResponse { error: message }),
+                    Json(ErrorResponse {
+                        error: redactor.redact(&message).0,
+                    }),
                 ));
             }
         }
@@ -577,19 +1330,14 @@ pub async fn chat(
 
     Ok(Json(ChatResponse {
         role: "assistant".to_string(),
-        content: output,
+        content: redactor.redact(&output).0,
         timestamp: chrono::Utc::now().to_rfc3339(),
     }))
 }
 
 // 需要引入 StreamExt
 use futures::StreamExt;
-fn get_user_id_from_token(token: &str) -> Result<String, AuthError> {
-    let claims = verify_token(token)?;
-    Ok(claims.sub)
-}
-
-fn auth_user_from_headers(headers: &axum::http::HeaderMap) -> Result<String, AuthError> {
+fn auth_claims_from_headers(headers: &axum::http::HeaderMap) -> Result<Claims, AuthError> {
     let auth = headers
         .get(axum::http::header::AUTHORIZATION)
         .and_then(|v| v.to_str().ok())
@@ -598,7 +1346,38 @@ fn auth_user_from_headers(headers: &axum::http::HeaderMap) -> Result<String, Aut
     if token.is_empty() {
         return Err(AuthError::MissingToken);
     }
-    get_user_id_from_token(token)
+    verify_token(token)
+}
+
+fn is_admin(claims: &Claims) -> bool {
+    claims.roles.iter().any(|r| r == "admin")
+}
+
+/// 要求 admin 角色，未登录返回 401，非 admin 返回 403
+fn require_admin(
+    headers: &axum::http::HeaderMap,
+) -> Result<Claims, (StatusCode, Json<ErrorResponse>)> {
+    let claims = auth_claims_from_headers(headers).map_err(|_| {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "invalid_token".to_string(),
+            }),
+        )
+    })?;
+    if !is_admin(&claims) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse {
+                error: "admin role required".to_string(),
+            }),
+        ));
+    }
+    Ok(claims)
+}
+
+fn auth_user_from_headers(headers: &axum::http::HeaderMap) -> Result<String, AuthError> {
+    auth_claims_from_headers(headers).map(|c| c.sub)
 }
 
 // ============ Project Handlers ============
@@ -683,11 +1462,13 @@ pub async fn create_project(
     })?;
 
     let project_id = uuid::Uuid::new_v4().to_string();
+    let scope = secret_scope("project", &project_id);
+    let mcp_servers = externalize_mcp_servers(&state, &user_id, &scope, &req.mcp_servers).await?;
     let now = chrono::Utc::now().to_rfc3339();
 
     state
         .db
-        .create_project(&user_id, &project_id, name, &project_path)
+        .create_project(&user_id, &project_id, name, &project_path, &mcp_servers)
         .await
         .map_err(|e| {
             (
@@ -702,11 +1483,65 @@ pub async fn create_project(
         id: project_id,
         name: name.to_string(),
         path: project_path,
+        mcp_servers,
         created_at: now.clone(),
         updated_at: now,
     }))
 }
 
+/// PUT /api/projects/:id/mcp-servers - Replace a project's MCP servers
+pub async fn set_project_mcp_servers(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Path(project_id): Path<String>,
+    Json(req): Json<SetMcpServersRequest>,
+) -> Result<Json<Project>, (StatusCode, Json<ErrorResponse>)> {
+    let user_id = auth_user_from_headers(&headers).map_err(|_| {
+        (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "invalid_token".to_string(),
+            }),
+        )
+    })?;
+
+    let internal_error = |e: anyhow::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    };
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Project not found".to_string(),
+            }),
+        )
+    };
+
+    let scope = secret_scope("project", &project_id);
+    let mcp_servers = externalize_mcp_servers(&state, &user_id, &scope, &req.mcp_servers).await?;
+    let updated = state
+        .db
+        .set_project_mcp_servers(&user_id, &project_id, &mcp_servers)
+        .await
+        .map_err(internal_error)?;
+    if !updated {
+        return Err(not_found());
+    }
+
+    let project = state
+        .db
+        .get_project(&user_id, &project_id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(not_found)?;
+    Ok(Json(project))
+}
+
 /// DELETE /api/projects/:id - Delete a project
 pub async fn delete_project(
     State(state): State<AppState>,
@@ -0,0 +1,410 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+use super::handlers::start_run;
+use super::AppState;
+use crate::agent::read_text_file;
+use crate::auth::{hash_api_key, is_api_key, verify_token};
+use crate::run::{Run, RunSummary};
+use crate::types::{CreateRunRequest, ErrorResponse, RunInput, RunMetadata};
+
+/// 支持的协议版本，客户端请求的版本不在其中时使用第一个
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// `wait_run` 默认和最长等待时间（秒）
+const DEFAULT_WAIT_SECS: u64 = 60;
+const MAX_WAIT_SECS: u64 = 600;
+
+/// MCP 调用者
+#[derive(Debug, Clone)]
+pub struct McpCaller {
+    pub user_id: String,
+    pub roles: Vec<String>,
+}
+
+/// 按 API key 识别调用者，也接受登录 token
+pub async fn authenticate(state: &AppState, token: &str) -> Option<McpCaller> {
+    if is_api_key(token) {
+        let owner = match state.db.find_api_key_owner(&hash_api_key(token)).await {
+            Ok(owner) => owner?,
+            Err(e) => {
+                tracing::error!("Failed to look up API key: {}", e);
+                return None;
+            }
+        };
+        return Some(McpCaller {
+            user_id: owner.user_id,
+            roles: owner.roles,
+        });
+    }
+    verify_token(token).ok().map(|claims| McpCaller {
+        user_id: claims.sub,
+        roles: claims.roles,
+    })
+}
+
+/// MCP server：以工具形式提供 Run 和项目操作
+///
+/// 传输无关，stdio 和 Streamable HTTP 都把收到的 JSON-RPC 消息交给 `handle`。
+pub struct McpServer {
+    state: AppState,
+    caller: McpCaller,
+}
+
+impl McpServer {
+    pub fn new(state: AppState, caller: McpCaller) -> Self {
+        Self { state, caller }
+    }
+
+    /// 处理一条 JSON-RPC 消息，通知和客户端发来的响应没有返回值
+    pub async fn handle(&self, message: Value) -> Option<Value> {
+        let method = message.get("method").and_then(Value::as_str)?;
+        let id = message.get("id").cloned()?;
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = match method {
+            "initialize" => Ok(initialize_result(&params)),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": tool_definitions() })),
+            "tools/call" => self.call_tool(&params).await,
+            _ => Err((-32601, format!("Method not found: {}", method))),
+        };
+
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => error_response(id, code, &message),
+        })
+    }
+
+    async fn call_tool(&self, params: &Value) -> Result<Value, (i64, String)> {
+        let name = params
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or((-32602, "Missing tool name".to_string()))?;
+        let args = params
+            .get("arguments")
+            .cloned()
+            .unwrap_or_else(|| json!({}));
+
+        let result = match name {
+            "create_run" => self.create_run(args).await,
+            "get_run" => self.get_run(args),
+            "wait_run" => self.wait_run(args).await,
+            "cancel_run" => self.cancel_run(args),
+            "list_projects" => self.list_projects().await,
+            "read_project_file" => self.read_project_file(args).await,
+            _ => return Err((-32602, format!("Unknown tool: {}", name))),
+        };
+
+        // 工具执行失败作为结果返回，让调用方的模型看到错误
+        Ok(match result {
+            Ok(Value::String(text)) => json!({
+                "content": [{ "type": "text", "text": text }],
+                "isError": false,
+            }),
+            Ok(value) => json!({
+                "content": [{
+                    "type": "text",
+                    "text": serde_json::to_string_pretty(&value).unwrap_or_default(),
+                }],
+                "structuredContent": value,
+                "isError": false,
+            }),
+            Err(e) => json!({
+                "content": [{ "type": "text", "text": e.to_string() }],
+                "isError": true,
+            }),
+        })
+    }
+
+    async fn create_run(&self, args: Value) -> Result<Value> {
+        let args: CreateRunArgs = parse_args(args)?;
+        let req = CreateRunRequest {
+            input: RunInput {
+                text: args.prompt,
+                attachments: vec![],
+            },
+            session_id: args.session_id,
+            metadata: RunMetadata {
+                agent_type: args.agent_type,
+                model: args.model,
+                project_id: args.project_id,
+                ..Default::default()
+            },
+        };
+
+        let run_id = start_run(&self.state, &self.caller.user_id, &self.caller.roles, req)
+            .await
+            .map_err(|(_, Json(e))| anyhow::anyhow!(e.error))?;
+        Ok(json!({ "run_id": run_id }))
+    }
+
+    fn get_run(&self, args: Value) -> Result<Value> {
+        let args: RunArgs = parse_args(args)?;
+        let run = self.own_run(self.state.run_manager.get_run(&args.run_id), &args.run_id)?;
+        Ok(serde_json::to_value(RunSummary::from(&run))?)
+    }
+
+    async fn wait_run(&self, args: Value) -> Result<Value> {
+        let args: WaitRunArgs = parse_args(args)?;
+        // 先确认归属，避免等待别人的 Run
+        self.own_run(self.state.run_manager.get_run(&args.run_id), &args.run_id)?;
+
+        let timeout = args
+            .timeout_secs
+            .unwrap_or(DEFAULT_WAIT_SECS)
+            .min(MAX_WAIT_SECS);
+        let run = self.own_run(
+            self.state
+                .run_manager
+                .wait_run(&args.run_id, Duration::from_secs(timeout))
+                .await,
+            &args.run_id,
+        )?;
+        Ok(serde_json::to_value(RunSummary::from(&run))?)
+    }
+
+    fn cancel_run(&self, args: Value) -> Result<Value> {
+        let args: RunArgs = parse_args(args)?;
+        self.own_run(self.state.run_manager.get_run(&args.run_id), &args.run_id)?;
+
+        let cancelled = self.state.run_manager.cancel_run(&args.run_id);
+        let run = self.own_run(self.state.run_manager.get_run(&args.run_id), &args.run_id)?;
+        Ok(json!({ "cancelled": cancelled, "status": run.status }))
+    }
+
+    async fn list_projects(&self) -> Result<Value> {
+        let projects = self.state.db.list_projects(&self.caller.user_id).await?;
+        Ok(json!({ "projects": projects }))
+    }
+
+    async fn read_project_file(&self, args: Value) -> Result<Value> {
+        let args: ReadProjectFileArgs = parse_args(args)?;
+        let project = self
+            .state
+            .db
+            .get_project(&self.caller.user_id, &args.project_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Project not found: {}", args.project_id))?;
+
+        let root = std::fs::canonicalize(&project.path)?;
+        Ok(Value::String(read_text_file(&root, &args.path).await?))
+    }
+
+    /// 只允许访问调用者自己的 Run
+    fn own_run(&self, run: Option<Run>, run_id: &str) -> Result<Run> {
+        run.filter(|r| r.user_id == self.caller.user_id)
+            .ok_or_else(|| anyhow::anyhow!("Run not found: {}", run_id))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateRunArgs {
+    prompt: String,
+    #[serde(default)]
+    agent_type: Option<String>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    project_id: Option<String>,
+    #[serde(default)]
+    session_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RunArgs {
+    run_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WaitRunArgs {
+    run_id: String,
+    #[serde(default)]
+    timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadProjectFileArgs {
+    project_id: String,
+    path: String,
+}
+
+fn parse_args<T: serde::de::DeserializeOwned>(args: Value) -> Result<T> {
+    serde_json::from_value(args).map_err(|e| anyhow::anyhow!("Invalid arguments: {}", e))
+}
+
+fn initialize_result(params: &Value) -> Value {
+    let requested = params.get("protocolVersion").and_then(Value::as_str);
+    let version = PROTOCOL_VERSIONS
+        .iter()
+        .find(|v| Some(**v) == requested)
+        .unwrap_or(&PROTOCOL_VERSIONS[0]);
+    json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": {} },
+        "serverInfo": {
+            "name": "openrunner",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "instructions": "Delegate tasks to OpenRunner agents: create_run starts a run, wait_run blocks until it finishes.",
+    })
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+fn tool_definitions() -> Vec<Value> {
+    let run_id = json!({ "type": "string", "description": "Run id returned by create_run" });
+    vec![
+        json!({
+            "name": "create_run",
+            "description": "Start an agent run on OpenRunner and return its run_id. The run continues in the background; use wait_run to get the result.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "prompt": { "type": "string", "description": "Task for the agent" },
+                    "agent_type": { "type": "string", "description": "Agent to run, e.g. claude, codex, opencode, openai, anthropic" },
+                    "model": { "type": "string", "description": "Model override" },
+                    "project_id": { "type": "string", "description": "Run inside this project's directory (see list_projects)" },
+                    "session_id": { "type": "string", "description": "Session to record the run in" }
+                },
+                "required": ["prompt"]
+            }
+        }),
+        json!({
+            "name": "get_run",
+            "description": "Get the current status and output of a run.",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "run_id": run_id },
+                "required": ["run_id"]
+            }
+        }),
+        json!({
+            "name": "wait_run",
+            "description": "Wait until a run completes, fails or is cancelled, then return its status and output. Returns the current state if the timeout is reached first.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "run_id": run_id,
+                    "timeout_secs": {
+                        "type": "integer",
+                        "description": format!("Seconds to wait (default {}, max {})", DEFAULT_WAIT_SECS, MAX_WAIT_SECS)
+                    }
+                },
+                "required": ["run_id"]
+            }
+        }),
+        json!({
+            "name": "cancel_run",
+            "description": "Cancel a pending or running run.",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "run_id": run_id },
+                "required": ["run_id"]
+            }
+        }),
+        json!({
+            "name": "list_projects",
+            "description": "List the caller's OpenRunner projects.",
+            "inputSchema": { "type": "object", "properties": {} }
+        }),
+        json!({
+            "name": "read_project_file",
+            "description": "Read a text file inside a project directory.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "project_id": { "type": "string" },
+                    "path": { "type": "string", "description": "Path relative to the project directory" }
+                },
+                "required": ["project_id", "path"]
+            }
+        }),
+    ]
+}
+
+// ============ Transports ============
+
+/// POST /mcp - Streamable HTTP 传输
+///
+/// 每个请求直接返回 JSON 响应，不建立会话，也不使用 SSE 流。
+pub async fn mcp_post(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Json(message): Json<Value>,
+) -> Response {
+    let token = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or("");
+    let Some(caller) = authenticate(&state, token).await else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "invalid_api_key".to_string(),
+            }),
+        )
+            .into_response();
+    };
+
+    match McpServer::new(state, caller).handle(message).await {
+        Some(response) => Json(response).into_response(),
+        None => StatusCode::ACCEPTED.into_response(),
+    }
+}
+
+/// GET /mcp - 不提供服务端主动推送的 SSE 流
+pub async fn mcp_get() -> StatusCode {
+    StatusCode::METHOD_NOT_ALLOWED
+}
+
+/// stdio 传输：从 stdin 读取换行分隔的 JSON-RPC 消息，响应写到 stdout
+///
+/// 每条消息单独处理，`wait_run` 等待时仍可处理其他请求。
+pub async fn serve_stdio(state: AppState, caller: McpCaller) -> Result<()> {
+    let server = Arc::new(McpServer::new(state, caller));
+    let stdout = Arc::new(Mutex::new(tokio::io::stdout()));
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let server = server.clone();
+        let stdout = stdout.clone();
+        tokio::spawn(async move {
+            let response = match serde_json::from_str::<Value>(&line) {
+                Ok(message) => server.handle(message).await,
+                Err(e) => Some(error_response(
+                    Value::Null,
+                    -32700,
+                    &format!("Parse error: {}", e),
+                )),
+            };
+            if let Some(response) = response {
+                let mut out = stdout.lock().await;
+                let _ = out.write_all(format!("{}\n", response).as_bytes()).await;
+                let _ = out.flush().await;
+            }
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,626 @@
+//! Anthropic Messages-compatible endpoint (`POST /v1/messages`)
+//!
+//! Requests are translated to an `AgentRequest` and served by the same gateway
+//! agents as `/v1/chat/completions`, so any provider can answer Anthropic-format
+//! clients. See https://docs.anthropic.com/en/api/messages
+
+use super::{chat_caller, check_chat_budget, record_chat_usage, spawn_choices};
+use crate::api::router::AppState;
+use crate::types::{
+    AgentRequest, ChatMessage, ChatToolCall, SamplingParams, StreamEvent, TokenUsage,
+    ToolDefinition,
+};
+use axum::{
+    extract::{rejection::JsonRejection, Json, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Json as AxumJson, Response,
+    },
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+type ApiError = (StatusCode, AxumJson<serde_json::Value>);
+
+/// Error body in Anthropic's shape: `{"type": "error", "error": {"type", "message"}}`
+fn api_error(status: StatusCode, kind: &str, message: impl Into<String>) -> ApiError {
+    (status, AxumJson(error_body(kind, message)))
+}
+
+fn error_body(kind: &str, message: impl Into<String>) -> serde_json::Value {
+    serde_json::json!({
+        "type": "error",
+        "error": { "type": kind, "message": message.into() }
+    })
+}
+
+fn invalid_request(message: impl Into<String>) -> ApiError {
+    api_error(StatusCode::BAD_REQUEST, "invalid_request_error", message)
+}
+
+/// Messages API request
+#[derive(Debug, Deserialize)]
+pub struct MessagesRequest {
+    pub model: String,
+    pub max_tokens: u32,
+    pub messages: Vec<InputMessage>,
+    #[serde(default)]
+    pub system: Option<SystemPrompt>,
+    #[serde(default)]
+    pub stream: Option<bool>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
+    #[serde(default)]
+    pub tools: Vec<InputTool>,
+}
+
+/// `system` is a string or an array of text blocks
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SystemPrompt {
+    Text(String),
+    Blocks(Vec<InputBlock>),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InputMessage {
+    pub role: String,
+    pub content: InputContent,
+}
+
+/// Message content: a string or an array of content blocks
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum InputContent {
+    Text(String),
+    Blocks(Vec<InputBlock>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        #[serde(default)]
+        input: serde_json::Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        #[serde(default)]
+        content: Option<InputContent>,
+        #[serde(default)]
+        is_error: bool,
+    },
+    /// Earlier thinking isn't replayed to other providers
+    Thinking {},
+    RedactedThinking {},
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InputTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub input_schema: serde_json::Value,
+}
+
+/// Text of a content value; only text blocks are allowed
+fn content_text(content: &InputContent) -> Result<String, ApiError> {
+    match content {
+        InputContent::Text(text) => Ok(text.clone()),
+        InputContent::Blocks(blocks) => blocks_text(blocks),
+    }
+}
+
+fn blocks_text(blocks: &[InputBlock]) -> Result<String, ApiError> {
+    blocks
+        .iter()
+        .map(|block| match block {
+            InputBlock::Text { text } => Ok(text.as_str()),
+            _ => Err(invalid_request("Only text blocks are supported here")),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|texts| texts.join("\n"))
+}
+
+impl MessagesRequest {
+    /// Convert to an agent request
+    ///
+    /// Tool results in a user message become `tool` messages placed before its
+    /// text, so they directly follow the assistant message that called the tools.
+    fn agent_request(&self) -> Result<AgentRequest, ApiError> {
+        let mut messages = Vec::new();
+        for message in &self.messages {
+            let blocks = match &message.content {
+                InputContent::Text(text) => {
+                    messages.push(ChatMessage {
+                        role: message.role.clone(),
+                        content: text.clone(),
+                        tool_calls: vec![],
+                        tool_call_id: None,
+                    });
+                    continue;
+                }
+                InputContent::Blocks(blocks) => blocks,
+            };
+
+            let mut text = Vec::new();
+            let mut tool_calls = Vec::new();
+            for block in blocks {
+                match block {
+                    InputBlock::Text { text: t } => text.push(t.as_str()),
+                    InputBlock::ToolUse { id, name, input } => tool_calls.push(ChatToolCall {
+                        id: id.clone(),
+                        name: name.clone(),
+                        arguments: input.to_string(),
+                    }),
+                    InputBlock::ToolResult {
+                        tool_use_id,
+                        content,
+                        is_error,
+                    } => {
+                        let output = match content {
+                            Some(content) => content_text(content)?,
+                            None => String::new(),
+                        };
+                        let output = if *is_error {
+                            format!("Error: {}", output)
+                        } else {
+                            output
+                        };
+                        messages.push(ChatMessage::tool(tool_use_id.clone(), output));
+                    }
+                    InputBlock::Thinking {} | InputBlock::RedactedThinking {} => {}
+                    InputBlock::Unsupported => {
+                        return Err(invalid_request(
+                            "Only text, tool_use and tool_result blocks are supported",
+                        ))
+                    }
+                }
+            }
+            if !text.is_empty() || !tool_calls.is_empty() {
+                messages.push(ChatMessage {
+                    role: message.role.clone(),
+                    content: text.join("\n"),
+                    tool_calls,
+                    tool_call_id: None,
+                });
+            }
+        }
+        if messages.is_empty() {
+            return Err(invalid_request(
+                "messages: at least one message is required",
+            ));
+        }
+        if let Some(message) = self
+            .messages
+            .iter()
+            .find(|m| m.role != "user" && m.role != "assistant")
+        {
+            return Err(invalid_request(format!(
+                "messages: unexpected role '{}'",
+                message.role
+            )));
+        }
+
+        let system = match &self.system {
+            Some(SystemPrompt::Text(text)) => Some(text.clone()),
+            Some(SystemPrompt::Blocks(blocks)) => Some(blocks_text(blocks)?),
+            None => None,
+        };
+
+        Ok(AgentRequest {
+            messages,
+            system: system.filter(|s| !s.is_empty()),
+            sampling: SamplingParams {
+                temperature: self.temperature,
+                top_p: self.top_p,
+                max_tokens: Some(self.max_tokens),
+                stop: self.stop_sequences.clone(),
+                ..Default::default()
+            },
+            tools: self
+                .tools
+                .iter()
+                .map(|tool| ToolDefinition {
+                    name: tool.name.clone(),
+                    description: tool.description.clone(),
+                    input_schema: tool.input_schema.clone(),
+                })
+                .collect(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Messages API response
+#[derive(Debug, Serialize)]
+pub struct MessagesResponse {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub role: &'static str,
+    pub model: String,
+    pub content: Vec<OutputBlock>,
+    pub stop_reason: String,
+    pub stop_sequence: Option<String>,
+    pub usage: MessagesUsage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MessagesUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_creation_input_tokens: u64,
+    pub cache_read_input_tokens: u64,
+}
+
+impl From<TokenUsage> for MessagesUsage {
+    fn from(usage: TokenUsage) -> Self {
+        Self {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cache_creation_input_tokens: usage.cache_creation_input_tokens,
+            cache_read_input_tokens: usage.cache_read_input_tokens,
+        }
+    }
+}
+
+/// Internal finish reason (OpenAI's terms) as an Anthropic `stop_reason`
+fn stop_reason(finish_reason: Option<&str>, tool_use: bool) -> String {
+    let reason = match finish_reason {
+        Some("length") => "max_tokens",
+        Some("tool_calls") => "tool_use",
+        Some("content_filter") => "refusal",
+        Some(_) => "end_turn",
+        None if tool_use => "tool_use",
+        None => "end_turn",
+    };
+    reason.to_string()
+}
+
+/// tool_use input must be an object
+fn tool_input(arguments: &str) -> serde_json::Value {
+    serde_json::from_str(arguments)
+        .ok()
+        .filter(serde_json::Value::is_object)
+        .unwrap_or_else(|| serde_json::json!({}))
+}
+
+/// POST /v1/messages - Anthropic Messages-compatible endpoint, streaming when `stream` is set
+pub async fn anthropic_messages(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    payload: Result<Json<MessagesRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(req) = payload.map_err(|e| invalid_request(e.body_text()))?;
+    // Anthropic clients send the key as `x-api-key`
+    let mut headers = headers;
+    if let Some(key) = headers.get("x-api-key").cloned() {
+        if let Ok(value) = format!("Bearer {}", key.to_str().unwrap_or_default()).parse() {
+            headers
+                .entry(axum::http::header::AUTHORIZATION)
+                .or_insert(value);
+        }
+    }
+    let user_id = chat_caller(&state, &headers).await;
+    let request = req.agent_request()?;
+    check_chat_budget(&state, &user_id)
+        .await
+        .map_err(|(status, message)| {
+            let kind = match status {
+                StatusCode::PAYMENT_REQUIRED => "billing_error",
+                StatusCode::TOO_MANY_REQUESTS => "rate_limit_error",
+                _ => "api_error",
+            };
+            api_error(status, kind, message)
+        })?;
+
+    let rx = spawn_choices(&state, &user_id, &req.model, request, 1)
+        .map_err(|e| invalid_request(e.to_string()))?;
+    let id = format!("msg_{}", uuid::Uuid::new_v4().simple());
+    if req.stream == Some(true) {
+        let stream = stream_message(state, user_id, id, req.model, rx);
+        return Ok(Sse::new(stream).into_response());
+    }
+    let response = collect_message(&state, &user_id, id, req.model, rx).await?;
+    Ok(AxumJson(response).into_response())
+}
+
+/// Wait for the agent and build a `message` object
+async fn collect_message(
+    state: &AppState,
+    user_id: &str,
+    id: String,
+    model: String,
+    mut rx: mpsc::Receiver<(u32, StreamEvent)>,
+) -> Result<MessagesResponse, ApiError> {
+    let mut content: Vec<OutputBlock> = Vec::new();
+    let mut finish_reason = None;
+    let mut usage = TokenUsage::default();
+    let mut error = None;
+    while let Some((_, event)) = rx.recv().await {
+        match event {
+            StreamEvent::Token { content: text } if text.is_empty() => {}
+            StreamEvent::Token { content: text } => match content.last_mut() {
+                Some(OutputBlock::Text { text: last }) => last.push_str(&text),
+                _ => content.push(OutputBlock::Text { text }),
+            },
+            StreamEvent::ToolCallRequested {
+                id,
+                name,
+                arguments,
+            } => content.push(OutputBlock::ToolUse {
+                id,
+                name,
+                input: tool_input(&arguments),
+            }),
+            StreamEvent::Stop { reason } => finish_reason = Some(reason),
+            StreamEvent::Usage { usage: u } => usage = u,
+            // The gateway switched providers; only the new answer counts
+            StreamEvent::Restart { .. } => {
+                content.clear();
+                finish_reason = None;
+            }
+            // Thinking and server-side tool calls aren't returned to the client
+            StreamEvent::Thinking { .. }
+            | StreamEvent::ToolCallStarted { .. }
+            | StreamEvent::ToolCallFinished { .. }
+            | StreamEvent::Attempt { .. }
+            | StreamEvent::Done { .. } => {}
+            StreamEvent::Error {
+                message,
+                retry_after_secs,
+            } => error = Some((message, retry_after_secs)),
+        }
+    }
+
+    let usage = record_chat_usage(state, user_id, &id, &model, usage).await;
+    if let Some((message, retry_after_secs)) = error {
+        if retry_after_secs.is_some() {
+            return Err(api_error(
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limit_error",
+                message,
+            ));
+        }
+        return Err(api_error(StatusCode::BAD_GATEWAY, "api_error", message));
+    }
+
+    let tool_use = content
+        .iter()
+        .any(|block| matches!(block, OutputBlock::ToolUse { .. }));
+    Ok(MessagesResponse {
+        id,
+        kind: "message",
+        role: "assistant",
+        model,
+        content,
+        stop_reason: stop_reason(finish_reason.as_deref(), tool_use),
+        stop_sequence: None,
+        usage: usage.into(),
+    })
+}
+
+/// Writes named Messages API events; once the client disconnects the
+/// remaining events are still drained so the usage gets recorded
+struct EventWriter {
+    tx: Option<mpsc::Sender<Result<Event, std::convert::Infallible>>>,
+    /// Index of the next content block
+    next_block: usize,
+    /// Index of the open text block
+    text_block: Option<usize>,
+}
+
+impl EventWriter {
+    async fn send(&mut self, name: &str, data: serde_json::Value) {
+        if let Some(tx) = &self.tx {
+            let event = Event::default().event(name).data(data.to_string());
+            if tx.send(Ok(event)).await.is_err() {
+                self.tx = None;
+            }
+        }
+    }
+
+    async fn start_block(&mut self, block: serde_json::Value) -> usize {
+        let index = self.next_block;
+        self.next_block += 1;
+        self.send(
+            "content_block_start",
+            serde_json::json!({ "type": "content_block_start", "index": index, "content_block": block }),
+        )
+        .await;
+        index
+    }
+
+    async fn delta(&mut self, index: usize, delta: serde_json::Value) {
+        self.send(
+            "content_block_delta",
+            serde_json::json!({ "type": "content_block_delta", "index": index, "delta": delta }),
+        )
+        .await;
+    }
+
+    async fn stop_block(&mut self, index: usize) {
+        self.send(
+            "content_block_stop",
+            serde_json::json!({ "type": "content_block_stop", "index": index }),
+        )
+        .await;
+    }
+
+    async fn text(&mut self, text: String) {
+        if text.is_empty() {
+            return;
+        }
+        let index = match self.text_block {
+            Some(index) => index,
+            None => {
+                let index = self
+                    .start_block(serde_json::json!({ "type": "text", "text": "" }))
+                    .await;
+                self.text_block = Some(index);
+                index
+            }
+        };
+        self.delta(
+            index,
+            serde_json::json!({ "type": "text_delta", "text": text }),
+        )
+        .await;
+    }
+
+    async fn close_text(&mut self) {
+        if let Some(index) = self.text_block.take() {
+            self.stop_block(index).await;
+        }
+    }
+
+    /// A complete tool_use block; the input arrives as a single JSON delta
+    async fn tool_use(&mut self, id: String, name: String, arguments: String) {
+        self.close_text().await;
+        let index = self
+            .start_block(
+                serde_json::json!({ "type": "tool_use", "id": id, "name": name, "input": {} }),
+            )
+            .await;
+        let partial_json = tool_input(&arguments).to_string();
+        self.delta(
+            index,
+            serde_json::json!({ "type": "input_json_delta", "partial_json": partial_json }),
+        )
+        .await;
+        self.stop_block(index).await;
+    }
+}
+
+/// Convert the agent's events into Anthropic's SSE event sequence:
+/// `message_start`, content blocks, `message_delta`, `message_stop`
+fn stream_message(
+    state: AppState,
+    user_id: String,
+    id: String,
+    model: String,
+    mut rx: mpsc::Receiver<(u32, StreamEvent)>,
+) -> ReceiverStream<Result<Event, std::convert::Infallible>> {
+    let (tx, events) = mpsc::channel(100);
+    tokio::spawn(async move {
+        let mut writer = EventWriter {
+            tx: Some(tx),
+            next_block: 0,
+            text_block: None,
+        };
+        // Usage is only known at the end and goes in message_delta
+        writer
+            .send(
+                "message_start",
+                serde_json::json!({
+                    "type": "message_start",
+                    "message": {
+                        "id": id,
+                        "type": "message",
+                        "role": "assistant",
+                        "model": model,
+                        "content": [],
+                        "stop_reason": null,
+                        "stop_sequence": null,
+                        "usage": MessagesUsage::default(),
+                    }
+                }),
+            )
+            .await;
+        writer
+            .send("ping", serde_json::json!({ "type": "ping" }))
+            .await;
+
+        let mut finish_reason = None;
+        let mut tool_use = false;
+        let mut usage = TokenUsage::default();
+        let mut done = false;
+        let mut failed = false;
+        while let Some((_, event)) = rx.recv().await {
+            match event {
+                StreamEvent::Token { content } => writer.text(content).await,
+                StreamEvent::ToolCallRequested {
+                    id,
+                    name,
+                    arguments,
+                } => {
+                    tool_use = true;
+                    writer.tool_use(id, name, arguments).await;
+                }
+                StreamEvent::Stop { reason } => finish_reason = Some(reason),
+                StreamEvent::Usage { usage: u } => usage = u,
+                StreamEvent::Thinking { .. }
+                | StreamEvent::ToolCallStarted { .. }
+                | StreamEvent::ToolCallFinished { .. }
+                | StreamEvent::Attempt { .. } => {}
+                StreamEvent::Done { .. } => done = true,
+                // Sent blocks can't be taken back, so a restart ends the stream like an error
+                StreamEvent::Restart { reason, .. } => {
+                    failed = true;
+                    writer.send("error", error_body("api_error", reason)).await;
+                    writer.tx = None;
+                }
+                // Like Anthropic, a failure mid-stream is an `error` event and no message_stop
+                StreamEvent::Error { message, .. } => {
+                    failed = true;
+                    writer.send("error", error_body("api_error", message)).await;
+                    writer.tx = None;
+                }
+            }
+        }
+
+        let usage = record_chat_usage(&state, &user_id, &id, &model, usage).await;
+        if failed || !done {
+            return;
+        }
+        writer.close_text().await;
+        writer
+            .send(
+                "message_delta",
+                serde_json::json!({
+                    "type": "message_delta",
+                    "delta": {
+                        "stop_reason": stop_reason(finish_reason.as_deref(), tool_use),
+                        "stop_sequence": null,
+                    },
+                    "usage": MessagesUsage::from(usage),
+                }),
+            )
+            .await;
+        writer
+            .send(
+                "message_stop",
+                serde_json::json!({ "type": "message_stop" }),
+            )
+            .await;
+    });
+
+    ReceiverStream::new(events)
+}
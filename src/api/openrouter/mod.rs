@@ -1,86 +1,413 @@
-use crate::agent::{GatewayConfig, LoadBalancing, GATEWAY_MANAGER};
+use crate::agent::{
+    AgentHandle, CatalogModel, CircuitBreakerConfig, CircuitState, GatewayConfig, HealthStatus,
+    LoadBalancing, RateLimitConfig, RateLimitStatus,
+};
+use crate::api::handlers::budget_error;
+use crate::api::mcp::authenticate;
 use crate::api::router::AppState;
-use crate::types::{AgentConfig, StreamEvent};
+use crate::cache::ResponseCache;
+use crate::storage::UsageRecord;
+use crate::types::{
+    AgentConfig, AgentRequest, ChatMessage, ChatToolCall, RetryConfig, SamplingParams, StreamEvent,
+    TokenUsage, ToolDefinition,
+};
 use anyhow::Result;
 use axum::{
-    extract::{Json, Path, State},
-    http::StatusCode,
-    response::{IntoResponse, Json as AxumJson},
+    extract::{rejection::JsonRejection, Json, Path, State},
+    http::{
+        header::{AUTHORIZATION, CACHE_CONTROL, RETRY_AFTER},
+        HeaderMap, HeaderName, HeaderValue, StatusCode,
+    },
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Json as AxumJson, Response,
+    },
 };
-use futures::StreamExt;
+use futures::stream::{BoxStream, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 
+mod messages;
+
+pub use messages::anthropic_messages;
+
+/// Upper bound for `n`: every choice is a separate upstream request
+const MAX_CHOICES: u32 = 8;
+
+type ApiError = (StatusCode, AxumJson<serde_json::Value>);
+
+/// Response header telling whether the response cache answered: `hit` or `miss`
+const CACHE_STATUS: HeaderName = HeaderName::from_static("x-openrunner-cache");
+
+/// Events of every choice, tagged with the choice index
+type ChoiceEvents = BoxStream<'static, (u32, StreamEvent)>;
+
+/// Error body in OpenAI's shape, so SDKs surface the message and type
+fn api_error(status: StatusCode, kind: &str, message: impl Into<String>) -> ApiError {
+    (status, AxumJson(error_body(kind, message)))
+}
+
+/// Error of a failed completion: 429 with `Retry-After` when the gateway's
+/// client-side rate limit rejected it, 502 otherwise
+fn completion_error(message: String, retry_after_secs: Option<u64>) -> Response {
+    let Some(retry_after) = retry_after_secs else {
+        return api_error(StatusCode::BAD_GATEWAY, "api_error", message).into_response();
+    };
+    let mut response = api_error(
+        StatusCode::TOO_MANY_REQUESTS,
+        "rate_limit_exceeded",
+        message,
+    )
+    .into_response();
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
+
+fn error_body(kind: &str, message: impl Into<String>) -> serde_json::Value {
+    serde_json::json!({
+        "error": {
+            "message": message.into(),
+            "type": kind,
+            "param": null,
+            "code": null,
+        }
+    })
+}
+
 /// OpenRouter-compatible request structure
 #[derive(Debug, Deserialize)]
 pub struct OpenRouterRequest {
     pub model: String,
     pub messages: Vec<OpenRouterMessage>,
     pub stream: Option<bool>,
+    #[serde(default)]
+    pub stream_options: Option<StreamOptions>,
+    /// Number of choices to generate
+    #[serde(default)]
+    pub n: Option<u32>,
     pub temperature: Option<f32>,
     pub max_tokens: Option<u32>,
+    /// Newer name of `max_tokens`
+    #[serde(default)]
+    pub max_completion_tokens: Option<u32>,
     pub top_p: Option<f32>,
     pub frequency_penalty: Option<f32>,
     pub presence_penalty: Option<f32>,
+    #[serde(default)]
+    pub stop: Option<StopSequences>,
+    #[serde(default)]
+    pub seed: Option<i64>,
+    #[serde(default)]
+    pub response_format: Option<serde_json::Value>,
+    /// Functions the model may call; the caller runs them and sends the results back
+    #[serde(default)]
+    pub tools: Vec<OpenRouterTool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StreamOptions {
+    #[serde(default)]
+    pub include_usage: bool,
+}
+
+/// `stop` accepts either a single string or an array of strings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum StopSequences {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl StopSequences {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            StopSequences::One(s) => vec![s],
+            StopSequences::Many(v) => v,
+        }
+    }
+}
+
+/// Entry of the `tools` request field
+#[derive(Debug, Deserialize)]
+pub struct OpenRouterTool {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: OpenRouterFunction,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OpenRouterFunction {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub parameters: Option<serde_json::Value>,
+}
+
+impl OpenRouterRequest {
+    /// Convert to an agent request, lifting system messages into the system prompt
+    fn agent_request(&self) -> Result<AgentRequest, ApiError> {
+        let invalid =
+            |message: String| api_error(StatusCode::BAD_REQUEST, "invalid_request_error", message);
+
+        let mut system = Vec::new();
+        let mut messages = Vec::new();
+        for m in &self.messages {
+            let content = match &m.content {
+                Some(content) => content.text().map_err(invalid)?,
+                None => String::new(),
+            };
+            match m.role.as_str() {
+                "system" | "developer" => system.push(content),
+                "user" | "assistant" | "tool" => messages.push(ChatMessage {
+                    role: m.role.clone(),
+                    content,
+                    tool_calls: m
+                        .tool_calls
+                        .iter()
+                        .map(|call| ChatToolCall {
+                            id: call.id.clone(),
+                            name: call.function.name.clone(),
+                            arguments: call.function.arguments.clone(),
+                        })
+                        .collect(),
+                    tool_call_id: m.tool_call_id.clone(),
+                }),
+                role => return Err(invalid(format!("Unsupported message role '{}'", role))),
+            }
+        }
+        if messages.is_empty() {
+            return Err(invalid("messages must contain a non-system message".into()));
+        }
+
+        let mut tools = Vec::new();
+        for tool in &self.tools {
+            if tool.kind != "function" {
+                return Err(invalid(format!("Unsupported tool type '{}'", tool.kind)));
+            }
+            tools.push(ToolDefinition {
+                name: tool.function.name.clone(),
+                description: tool.function.description.clone(),
+                // Parameters may be omitted for functions without arguments
+                input_schema: tool
+                    .function
+                    .parameters
+                    .clone()
+                    .unwrap_or_else(|| serde_json::json!({ "type": "object", "properties": {} })),
+            });
+        }
+
+        Ok(AgentRequest {
+            messages,
+            system: (!system.is_empty()).then(|| system.join("\n\n")),
+            sampling: SamplingParams {
+                temperature: self.temperature,
+                top_p: self.top_p,
+                max_tokens: self.max_completion_tokens.or(self.max_tokens),
+                frequency_penalty: self.frequency_penalty,
+                presence_penalty: self.presence_penalty,
+                stop: self
+                    .stop
+                    .clone()
+                    .map(StopSequences::into_vec)
+                    .unwrap_or_default(),
+                seed: self.seed,
+                response_format: self.response_format.clone(),
+            },
+            tools,
+            ..Default::default()
+        })
+    }
+
+    fn choices(&self) -> Result<u32, ApiError> {
+        match self.n.unwrap_or(1) {
+            n @ 1..=MAX_CHOICES => Ok(n),
+            n => Err(api_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+                format!("n must be between 1 and {}, got {}", MAX_CHOICES, n),
+            )),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OpenRouterMessage {
     pub role: String,
-    pub content: String,
+    /// Null on assistant messages that only call tools
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<OpenRouterToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+/// Message content: a string or an array of content parts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContentPart {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+impl MessageContent {
+    /// Text parts are joined with newlines; other part types aren't supported
+    fn text(&self) -> Result<String, String> {
+        match self {
+            MessageContent::Text(text) => Ok(text.clone()),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .map(|part| match (part.kind.as_str(), &part.text) {
+                    ("text", Some(text)) => Ok(text.as_str()),
+                    (kind, _) => Err(format!("Unsupported content part type '{}'", kind)),
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map(|texts| texts.join("\n")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenRouterToolCall {
+    /// Position in the streamed `tool_calls` delta
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: OpenRouterFunctionCall,
+}
+
+impl OpenRouterToolCall {
+    fn function(id: String, name: String, arguments: String) -> Self {
+        Self {
+            index: None,
+            id,
+            kind: "function".to_string(),
+            function: OpenRouterFunctionCall { name, arguments },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenRouterFunctionCall {
+    pub name: String,
+    /// JSON-encoded arguments, as generated by the model
+    #[serde(default)]
+    pub arguments: String,
 }
 
 /// OpenRouter-compatible response structure
 #[derive(Debug, Serialize)]
 pub struct OpenRouterResponse {
     pub id: String,
+    pub object: &'static str,
+    pub created: i64,
     pub model: String,
     pub choices: Vec<OpenRouterChoice>,
     pub usage: OpenRouterUsage,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct OpenRouterChoice {
     pub index: u32,
     pub message: OpenRouterMessage,
+    pub logprobs: Option<serde_json::Value>,
     pub finish_reason: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct OpenRouterUsage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
 }
 
+impl From<TokenUsage> for OpenRouterUsage {
+    fn from(usage: TokenUsage) -> Self {
+        let prompt_tokens = (usage.input_tokens
+            + usage.cache_creation_input_tokens
+            + usage.cache_read_input_tokens) as u32;
+        let completion_tokens = usage.output_tokens as u32;
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+/// The parts of a cached `chat.completion` needed to replay it as chunks
+#[derive(Debug, Deserialize)]
+struct CachedCompletion {
+    choices: Vec<OpenRouterChoice>,
+    usage: OpenRouterUsage,
+}
+
 /// OpenRouter stream chunk
 #[derive(Debug, Serialize)]
 pub struct OpenRouterStreamChunk {
-    #[serde(rename = "id")]
     pub id: String,
-    #[serde(rename = "model")]
+    pub object: &'static str,
+    pub created: i64,
     pub model: String,
-    #[serde(rename = "choices")]
     pub choices: Vec<StreamChoice>,
-    #[serde(rename = "usage")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub usage: Option<OpenRouterUsage>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct StreamChoice {
-    #[serde(rename = "index")]
     pub index: u32,
-    #[serde(rename = "delta")]
-    pub delta: Option<StreamDelta>,
-    #[serde(rename = "finish_reason")]
+    pub delta: StreamDelta,
+    pub logprobs: Option<serde_json::Value>,
     pub finish_reason: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 pub struct StreamDelta {
-    #[serde(rename = "content")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub content: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<OpenRouterToolCall>,
+}
+
+/// Output of one choice, collected from its agent's events
+#[derive(Debug, Default)]
+struct ChoiceOutput {
+    text: String,
+    tool_calls: Vec<OpenRouterToolCall>,
+    finish_reason: Option<String>,
+    usage: TokenUsage,
+    done: bool,
+}
+
+impl ChoiceOutput {
+    /// Tool calls end the request even when the provider reports no reason
+    fn finish_reason(&self) -> String {
+        self.finish_reason.clone().unwrap_or_else(|| {
+            if self.tool_calls.is_empty() {
+                "stop".to_string()
+            } else {
+                "tool_calls".to_string()
+            }
+        })
+    }
 }
 
 /// Provider management request
@@ -92,6 +419,56 @@ pub struct ProviderRequest {
     pub api_key: Option<String>,
     pub base_url: Option<String>,
     pub fallback_providers: Option<Vec<String>>,
+    pub load_balancing: Option<LoadBalancing>,
+    pub restart_on_failure: Option<bool>,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+/// Provider update request; only the given fields change
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderPatch {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub api_key: Option<String>,
+    pub base_url: Option<String>,
+    pub fallback_providers: Option<Vec<String>>,
+    pub load_balancing: Option<LoadBalancing>,
+    pub restart_on_failure: Option<bool>,
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+impl ProviderPatch {
+    fn apply(self, config: &mut GatewayConfig) {
+        if let Some(provider) = self.provider {
+            config.provider = provider;
+        }
+        if self.model.is_some() {
+            config.model = self.model;
+        }
+        if self.api_key.is_some() {
+            config.api_key = self.api_key;
+        }
+        if self.base_url.is_some() {
+            config.base_url = self.base_url;
+        }
+        if let Some(fallback_providers) = self.fallback_providers {
+            config.fallback_providers = fallback_providers;
+        }
+        if self.load_balancing.is_some() {
+            config.load_balancing = self.load_balancing;
+        }
+        if let Some(restart_on_failure) = self.restart_on_failure {
+            config.restart_on_failure = restart_on_failure;
+        }
+        if let Some(circuit_breaker) = self.circuit_breaker {
+            config.circuit_breaker = circuit_breaker;
+        }
+        if self.rate_limit.is_some() {
+            config.rate_limit = self.rate_limit;
+        }
+    }
 }
 
 /// Provider list response
@@ -106,259 +483,712 @@ pub struct ProviderInfo {
     pub provider: String,
     pub model: Option<String>,
     pub base_url: Option<String>,
+    /// From the latest background health check; false until the first one
     pub healthy: bool,
+    /// Registered for every user rather than by the caller
+    pub shared: bool,
+    pub circuit: CircuitState,
+    pub in_flight: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitStatus>,
 }
 
 // ============ OpenRouter-compatible API Handlers ============
 
-/// POST /v1/chat/completions - OpenRouter-compatible chat endpoint
-pub async fn openrouter_chat_completions(
-    State(state): State<AppState>,
-    Json(req): Json<OpenRouterRequest>,
-) -> Result<impl IntoResponse, (StatusCode, AxumJson<serde_json::Value>)> {
-    let prompt = req
-        .messages
+/// Identify the caller by API key or login token; other requests count as "anonymous"
+async fn chat_caller(state: &AppState, headers: &HeaderMap) -> String {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let caller = match token {
+        Some(token) => authenticate(state, token).await,
+        None => None,
+    };
+    caller
+        .map(|c| c.user_id)
+        .unwrap_or_else(|| "anonymous".to_string())
+}
+
+/// Reject the request when one of the caller's budgets is exhausted (402 / 429)
+async fn check_chat_budget(state: &AppState, user_id: &str) -> Result<(), (StatusCode, String)> {
+    state.budgets.check(user_id, None).await.map_err(|e| {
+        let (status, Json(body)) = budget_error(e);
+        (status, body.error)
+    })?;
+    Ok(())
+}
+
+/// Price and persist the usage of one completion, then check budget warnings
+async fn record_chat_usage(
+    state: &AppState,
+    user_id: &str,
+    id: &str,
+    model: &str,
+    usage: TokenUsage,
+) -> TokenUsage {
+    let usage = state.run_manager.pricing().fill_cost(Some(model), usage);
+    if usage.is_empty() {
+        return usage;
+    }
+    let record = UsageRecord {
+        run_id: id.to_string(),
+        user_id: user_id.to_string(),
+        project_id: None,
+        agent_type: "gateway".to_string(),
+        model: Some(model.to_string()),
+        usage,
+        created_at: chrono::Utc::now(),
+    };
+    if let Err(e) = state.db.record_usage(&record).await {
+        tracing::warn!("Failed to record usage for {}: {}", id, e);
+    }
+    state
+        .budgets
+        .check_warnings(user_id, None, &TokenUsage::default())
+        .await;
+    usage
+}
+
+/// Whether the request's `Cache-Control` header carries `directive`
+fn cache_control(headers: &HeaderMap, directive: &str) -> bool {
+    headers
+        .get_all(CACHE_CONTROL)
         .iter()
-        .map(|m| m.content.clone())
-        .collect::<Vec<_>>()
-        .join("\n\n");
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|d| d.trim().eq_ignore_ascii_case(directive))
+}
 
+/// Response cache key of a deterministic (temperature 0) request, None when
+/// the cache is off or the request can't be cached
+///
+/// The model is keyed as the provider and upstream model it resolves to, so
+/// aliases share entries; the caller is part of the key since providers are
+/// registered per user.
+fn cache_key(
+    state: &AppState,
+    user_id: &str,
+    model: &str,
+    request: &AgentRequest,
+    n: u32,
+) -> Option<String> {
+    if !state.cache.enabled() || request.sampling.temperature != Some(0.0) {
+        return None;
+    }
+    let route = state.providers.scope(user_id).resolve(model).ok()?;
+    Some(ResponseCache::key(&serde_json::json!({
+        "user": user_id,
+        "provider": route.name,
+        "model": route.config.model,
+        "n": n,
+        "request": request,
+    })))
+}
+
+/// Answer a request from a cached `chat.completion`, replayed as a chunk
+/// stream when the request streams; None when the entry can't be read
+fn cached_response(
+    mut cached: serde_json::Value,
+    model: String,
+    stream: bool,
+    include_usage: bool,
+) -> Option<Response> {
+    if stream {
+        let completion = serde_json::from_value(cached).ok()?;
+        let chunks = replay_choices(model, completion, include_usage);
+        return Some(Sse::new(chunks).into_response());
+    }
+    let response = cached.as_object_mut()?;
+    response.insert(
+        "id".to_string(),
+        format!("chatcmpl-{}", uuid::Uuid::new_v4().simple()).into(),
+    );
+    response.insert("created".to_string(), chrono::Utc::now().timestamp().into());
+    response.insert("model".to_string(), model.into());
+    Some(AxumJson(cached).into_response())
+}
+
+/// Start one gateway agent per choice over the caller's providers; events arrive
+/// tagged with the choice index
+///
+/// The channel closes once every agent has finished.
+fn spawn_choices(
+    state: &AppState,
+    user_id: &str,
+    model: &str,
+    request: AgentRequest,
+    n: u32,
+) -> Result<mpsc::Receiver<(u32, StreamEvent)>> {
     let config = AgentConfig {
         agent_type: "gateway".to_string(),
-        model: Some(req.model.clone()),
-        env: std::collections::HashMap::new(),
+        model: Some(model.to_string()),
+        retry: RetryConfig::from_env("gateway"),
+        gateway: Some(state.providers.scope(user_id)),
         ..Default::default()
     };
 
-    let agent = crate::agent::create_agent(&config).map_err(|e| {
-        (
+    let (tx, rx) = mpsc::channel(100);
+    for index in 0..n {
+        let agent = state.registry.create(&config)?;
+        let (agent_tx, mut agent_rx) = mpsc::channel::<StreamEvent>(100);
+        let handle = AgentHandle::spawn(agent, agent_tx);
+        let request = request.clone();
+        tokio::spawn(async move { handle.execute(request).await });
+
+        // Both the agent and its handle may report Done; stop at the first one
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            while let Some(event) = agent_rx.recv().await {
+                let last = matches!(event, StreamEvent::Done { .. } | StreamEvent::Error { .. });
+                if tx.send((index, event)).await.is_err() || last {
+                    break;
+                }
+            }
+        });
+    }
+    Ok(rx)
+}
+
+/// POST /v1/chat/completions - OpenAI-compatible chat endpoint, streaming when `stream` is set
+pub async fn openrouter_chat_completions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    payload: Result<Json<OpenRouterRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(req) = payload.map_err(|e| {
+        api_error(
             StatusCode::BAD_REQUEST,
-            AxumJson(serde_json::json!({
-                "error": e.to_string()
-            })),
+            "invalid_request_error",
+            e.body_text(),
         )
     })?;
+    let user_id = chat_caller(&state, &headers).await;
+    let request = req.agent_request()?;
+    let n = req.choices()?;
+    let stream = req.stream == Some(true);
+    let include_usage = req.stream_options.is_some_and(|o| o.include_usage);
+    check_chat_budget(&state, &user_id)
+        .await
+        .map_err(|(status, message)| {
+            let kind = if status.is_server_error() {
+                "server_error"
+            } else {
+                "insufficient_quota"
+            };
+            api_error(status, kind, message)
+        })?;
 
-    let (tx, mut rx) = mpsc::channel::<StreamEvent>(100);
-    let handle = crate::agent::AgentHandle::spawn(agent, tx);
+    // `no-cache` skips the lookup, `no-store` keeps the answer out of the cache
+    let cache_key = cache_key(&state, &user_id, &req.model, &request, n);
+    if let Some(key) = &cache_key {
+        if !cache_control(&headers, "no-cache") {
+            let cached = state.cache.get(key).await;
+            if let Some(mut response) =
+                cached.and_then(|c| cached_response(c, req.model.clone(), stream, include_usage))
+            {
+                response
+                    .headers_mut()
+                    .insert(CACHE_STATUS, HeaderValue::from_static("hit"));
+                return Ok(response);
+            }
+        }
+    }
+    let store_key = cache_key
+        .as_ref()
+        .filter(|_| !cache_control(&headers, "no-store"))
+        .cloned();
 
-    // Execute
-    let run_handle = tokio::spawn(async move { handle.run(prompt).await });
+    let rx = spawn_choices(&state, &user_id, &req.model, request, n).map_err(|e| {
+        api_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            e.to_string(),
+        )
+    })?;
+    let mut events: ChoiceEvents = ReceiverStream::new(rx).boxed();
+    let mut rate_limited = false;
+    if stream {
+        // A request the rate limit rejects fails before any output, so it
+        // can still get a 429 instead of an error chunk
+        let first = events.next().await;
+        rate_limited = matches!(
+            &first,
+            Some((
+                _,
+                StreamEvent::Error {
+                    retry_after_secs: Some(_),
+                    ..
+                }
+            ))
+        );
+        events = futures::stream::iter(first).chain(events).boxed();
+    }
+    let mut response = if stream && !rate_limited {
+        let chunks = stream_choices(
+            state,
+            user_id,
+            req.model,
+            n,
+            include_usage,
+            events,
+            store_key,
+        );
+        Sse::new(chunks).into_response()
+    } else {
+        match collect_choices(&state, &user_id, req.model, n, events).await {
+            Ok(response) => {
+                if let Some(key) = &store_key {
+                    let cached = serde_json::to_value(&response).unwrap();
+                    state.cache.put(key, &cached).await;
+                }
+                AxumJson(response).into_response()
+            }
+            Err(response) => response,
+        }
+    };
+    if cache_key.is_some() {
+        response
+            .headers_mut()
+            .insert(CACHE_STATUS, HeaderValue::from_static("miss"));
+    }
+    Ok(response)
+}
 
-    let mut output = String::new();
-    while let Some(event) = rx.recv().await {
+/// POST /v1/chat/completions/stream - same as `stream: true`, kept for existing clients
+pub async fn openrouter_chat_completions_stream(
+    state: State<AppState>,
+    headers: HeaderMap,
+    payload: Result<Json<OpenRouterRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let payload = payload.map(|Json(mut req)| {
+        req.stream = Some(true);
+        Json(req)
+    });
+    openrouter_chat_completions(state, headers, payload).await
+}
+
+/// Wait for every choice and build a `chat.completion` object
+async fn collect_choices(
+    state: &AppState,
+    user_id: &str,
+    model: String,
+    n: u32,
+    mut events: ChoiceEvents,
+) -> Result<OpenRouterResponse, Response> {
+    let mut outputs: Vec<ChoiceOutput> = (0..n).map(|_| ChoiceOutput::default()).collect();
+    let mut error = None;
+    while let Some((index, event)) = events.next().await {
+        let output = &mut outputs[index as usize];
         match event {
-            StreamEvent::Token { content } => output.push_str(&content),
-            StreamEvent::Done { .. } => break,
-            StreamEvent::Error { message } => {
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    AxumJson(serde_json::json!({
-                        "error": message
-                    })),
-                ));
+            StreamEvent::Token { content } => output.text.push_str(&content),
+            StreamEvent::ToolCallRequested {
+                id,
+                name,
+                arguments,
+            } => {
+                let call = OpenRouterToolCall::function(id, name, arguments);
+                output.tool_calls.push(call);
+            }
+            StreamEvent::Stop { reason } => output.finish_reason = Some(reason),
+            StreamEvent::Usage { usage } => output.usage = usage,
+            // The gateway switched providers; only the new answer counts
+            StreamEvent::Restart { .. } => {
+                output.text.clear();
+                output.tool_calls.clear();
+                output.finish_reason = None;
             }
+            // Thinking and server-side tool calls aren't part of the chat completions format
+            StreamEvent::Thinking { .. }
+            | StreamEvent::ToolCallStarted { .. }
+            | StreamEvent::ToolCallFinished { .. }
+            | StreamEvent::Attempt { .. }
+            | StreamEvent::Done { .. } => {}
+            StreamEvent::Error {
+                message,
+                retry_after_secs,
+            } => error = Some((message, retry_after_secs)),
         }
     }
 
-    let _ = run_handle.await;
+    // Failed choices may still have used tokens
+    let id = format!("chatcmpl-{}", uuid::Uuid::new_v4().simple());
+    let mut usage = TokenUsage::default();
+    for output in &outputs {
+        usage.add(&output.usage);
+    }
+    let usage = record_chat_usage(state, user_id, &id, &model, usage).await;
+    if let Some((message, retry_after_secs)) = error {
+        return Err(completion_error(message, retry_after_secs));
+    }
+    Ok(completion(id, model, outputs, usage))
+}
 
-    let response = OpenRouterResponse {
-        id: uuid::Uuid::new_v4().to_string(),
-        model: req.model,
-        choices: vec![OpenRouterChoice {
-            index: 0,
+/// The `chat.completion` object of finished choices
+fn completion(
+    id: String,
+    model: String,
+    outputs: Vec<ChoiceOutput>,
+    usage: TokenUsage,
+) -> OpenRouterResponse {
+    let choices = outputs
+        .into_iter()
+        .zip(0..)
+        .map(|(output, index)| OpenRouterChoice {
+            index,
+            finish_reason: output.finish_reason(),
             message: OpenRouterMessage {
                 role: "assistant".to_string(),
-                content: output,
+                content: (!output.text.is_empty() || output.tool_calls.is_empty())
+                    .then_some(MessageContent::Text(output.text)),
+                // Stream positions don't belong in a message
+                tool_calls: output
+                    .tool_calls
+                    .into_iter()
+                    .map(|call| OpenRouterToolCall {
+                        index: None,
+                        ..call
+                    })
+                    .collect(),
+                tool_call_id: None,
             },
-            finish_reason: "stop".to_string(),
-        }],
-        usage: OpenRouterUsage {
-            prompt_tokens: 0, // Would need to calculate actual token usage
-            completion_tokens: 0,
-            total_tokens: 0,
-        },
-    };
+            logprobs: None,
+        })
+        .collect();
 
-    Ok(AxumJson(response))
+    OpenRouterResponse {
+        id,
+        object: "chat.completion",
+        created: chrono::Utc::now().timestamp(),
+        model,
+        choices,
+        usage: usage.into(),
+    }
 }
 
-/// GET /v1/chat/completions - OpenRouter-compatible streaming chat endpoint
-pub async fn openrouter_chat_completions_stream(
-    State(state): State<AppState>,
-    Json(req): Json<OpenRouterRequest>,
-) -> Result<impl IntoResponse, (StatusCode, AxumJson<serde_json::Value>)> {
-    let prompt = req
-        .messages
-        .iter()
-        .map(|m| m.content.clone())
-        .collect::<Vec<_>>()
-        .join("\n\n");
+/// Writes `chat.completion.chunk` events; once the client disconnects the
+/// remaining events are still drained so the usage gets recorded
+struct ChunkWriter {
+    tx: Option<mpsc::Sender<Result<Event, std::convert::Infallible>>>,
+    id: String,
+    created: i64,
+    model: String,
+}
 
-    let config = AgentConfig {
-        agent_type: "gateway".to_string(),
-        model: Some(req.model.clone()),
-        env: std::collections::HashMap::new(),
-        ..Default::default()
-    };
+impl ChunkWriter {
+    fn new(tx: mpsc::Sender<Result<Event, std::convert::Infallible>>, model: String) -> Self {
+        Self {
+            tx: Some(tx),
+            id: format!("chatcmpl-{}", uuid::Uuid::new_v4().simple()),
+            created: chrono::Utc::now().timestamp(),
+            model,
+        }
+    }
 
-    let agent = crate::agent::create_agent(&config).map_err(|e| {
-        (
-            StatusCode::BAD_REQUEST,
-            AxumJson(serde_json::json!({
-                "error": e.to_string()
-            })),
-        )
-    })?;
+    async fn send(&mut self, data: String) {
+        if let Some(tx) = &self.tx {
+            if tx.send(Ok(Event::default().data(data))).await.is_err() {
+                self.tx = None;
+            }
+        }
+    }
 
-    let (tx, rx) = mpsc::channel::<StreamEvent>(100);
-    let handle = crate::agent::AgentHandle::spawn(agent, tx);
+    async fn chunk(&mut self, choices: Vec<StreamChoice>, usage: Option<OpenRouterUsage>) {
+        let chunk = OpenRouterStreamChunk {
+            id: self.id.clone(),
+            object: "chat.completion.chunk",
+            created: self.created,
+            model: self.model.clone(),
+            choices,
+            usage,
+        };
+        self.send(serde_json::to_string(&chunk).unwrap()).await;
+    }
 
-    // Execute in background
-    let _run_handle = tokio::spawn(async move { handle.run(prompt).await });
+    async fn delta(&mut self, index: u32, delta: StreamDelta, finish_reason: Option<String>) {
+        let choice = StreamChoice {
+            index,
+            delta,
+            logprobs: None,
+            finish_reason,
+        };
+        self.chunk(vec![choice], None).await;
+    }
+}
 
-    // Clone model for use in closure
-    let model_for_stream = req.model.clone();
+/// Convert the agents' events into an OpenAI chunk stream ending with `[DONE]`;
+/// a completed answer is cached under `cache_key`
+fn stream_choices(
+    state: AppState,
+    user_id: String,
+    model: String,
+    n: u32,
+    include_usage: bool,
+    mut events: ChoiceEvents,
+    cache_key: Option<String>,
+) -> ReceiverStream<Result<Event, std::convert::Infallible>> {
+    let (tx, chunks) = mpsc::channel(100);
+    tokio::spawn(async move {
+        let mut writer = ChunkWriter::new(tx, model);
+        let mut outputs: Vec<ChoiceOutput> = (0..n).map(|_| ChoiceOutput::default()).collect();
+        let mut failed = false;
 
-    // Convert events to OpenRouter stream format
-    let stream = ReceiverStream::new(rx).map(move |event| {
-        let model = model_for_stream.clone();
-        match event {
-            StreamEvent::Token { content } => {
-                let chunk = OpenRouterStreamChunk {
-                    id: uuid::Uuid::new_v4().to_string(),
-                    model,
-                    choices: vec![StreamChoice {
-                        index: 0,
-                        delta: Some(StreamDelta {
-                            content: Some(content),
-                        }),
-                        finish_reason: None,
-                    }],
-                    usage: None,
+        for index in 0..n {
+            let delta = StreamDelta {
+                role: Some("assistant"),
+                content: Some(String::new()),
+                ..Default::default()
+            };
+            writer.delta(index, delta, None).await;
+        }
+
+        while let Some((index, event)) = events.next().await {
+            let output = &mut outputs[index as usize];
+            match event {
+                StreamEvent::Token { content } => {
+                    output.text.push_str(&content);
+                    let delta = StreamDelta {
+                        content: Some(content),
+                        ..Default::default()
+                    };
+                    writer.delta(index, delta, None).await;
+                }
+                StreamEvent::ToolCallRequested {
+                    id,
+                    name,
+                    arguments,
+                } => {
+                    let mut call = OpenRouterToolCall::function(id, name, arguments);
+                    call.index = Some(output.tool_calls.len());
+                    output.tool_calls.push(call.clone());
+                    let delta = StreamDelta {
+                        tool_calls: vec![call],
+                        ..Default::default()
+                    };
+                    writer.delta(index, delta, None).await;
+                }
+                StreamEvent::Stop { reason } => output.finish_reason = Some(reason),
+                StreamEvent::Usage { usage } => output.usage = usage,
+                // Thinking and server-side tool calls aren't part of the chat completions format
+                StreamEvent::Thinking { .. }
+                | StreamEvent::ToolCallStarted { .. }
+                | StreamEvent::ToolCallFinished { .. }
+                | StreamEvent::Attempt { .. } => {}
+                StreamEvent::Done { .. } => {
+                    output.done = true;
+                    let finish_reason = output.finish_reason();
+                    writer
+                        .delta(index, StreamDelta::default(), Some(finish_reason))
+                        .await;
+                }
+                // Like OpenAI, a failure mid-stream is an error object and no `[DONE]`.
+                // Sent chunks can't be taken back, so a restart ends the stream the same way.
+                StreamEvent::Error { message, .. }
+                | StreamEvent::Restart {
+                    reason: message, ..
+                } => {
+                    if !failed {
+                        failed = true;
+                        writer
+                            .send(error_body("api_error", message).to_string())
+                            .await;
+                        writer.tx = None;
+                    }
+                }
+            }
+        }
+
+        let mut usage = TokenUsage::default();
+        for output in &outputs {
+            usage.add(&output.usage);
+        }
+        let usage = record_chat_usage(&state, &user_id, &writer.id, &writer.model, usage).await;
+        if failed || outputs.iter().any(|o| !o.done) {
+            return;
+        }
+        // Usage goes in a final chunk without choices, as with OpenAI's include_usage
+        if include_usage {
+            writer.chunk(vec![], Some(usage.into())).await;
+        }
+        writer.send("[DONE]".to_string()).await;
+
+        if let Some(key) = cache_key {
+            let response = completion(writer.id, writer.model, outputs, usage);
+            let cached = serde_json::to_value(&response).unwrap();
+            state.cache.put(&key, &cached).await;
+        }
+    });
+
+    ReceiverStream::new(chunks)
+}
+
+/// Replay a cached `chat.completion` as a chunk stream: each choice's text
+/// and tool calls in one delta each, then its finish reason
+fn replay_choices(
+    model: String,
+    completion: CachedCompletion,
+    include_usage: bool,
+) -> ReceiverStream<Result<Event, std::convert::Infallible>> {
+    let (tx, chunks) = mpsc::channel(100);
+    tokio::spawn(async move {
+        let mut writer = ChunkWriter::new(tx, model);
+        for choice in &completion.choices {
+            let delta = StreamDelta {
+                role: Some("assistant"),
+                content: Some(String::new()),
+                ..Default::default()
+            };
+            writer.delta(choice.index, delta, None).await;
+        }
+        for choice in completion.choices {
+            let text = choice.message.content.and_then(|c| c.text().ok());
+            if let Some(text) = text.filter(|t| !t.is_empty()) {
+                let delta = StreamDelta {
+                    content: Some(text),
+                    ..Default::default()
                 };
-                Ok::<_, std::convert::Infallible>(
-                    axum::response::sse::Event::default()
-                        .event("chat.completion.chunk")
-                        .data(serde_json::to_string(&chunk).unwrap()),
-                )
+                writer.delta(choice.index, delta, None).await;
             }
-            StreamEvent::Done { .. } => {
-                let chunk = OpenRouterStreamChunk {
-                    id: uuid::Uuid::new_v4().to_string(),
-                    model,
-                    choices: vec![StreamChoice {
-                        index: 0,
-                        delta: Some(StreamDelta { content: None }),
-                        finish_reason: Some("stop".to_string()),
-                    }],
-                    usage: Some(OpenRouterUsage {
-                        prompt_tokens: 0,
-                        completion_tokens: 0,
-                        total_tokens: 0,
-                    }),
+            if !choice.message.tool_calls.is_empty() {
+                let tool_calls = choice
+                    .message
+                    .tool_calls
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, call)| OpenRouterToolCall {
+                        index: Some(index),
+                        ..call
+                    })
+                    .collect();
+                let delta = StreamDelta {
+                    tool_calls,
+                    ..Default::default()
                 };
-                Ok(axum::response::sse::Event::default()
-                    .event("chat.completion.chunk")
-                    .data(serde_json::to_string(&chunk).unwrap()))
+                writer.delta(choice.index, delta, None).await;
             }
-            StreamEvent::Error { message } => Ok(axum::response::sse::Event::default()
-                .event("error")
-                .data(message)),
+            writer
+                .delta(
+                    choice.index,
+                    StreamDelta::default(),
+                    Some(choice.finish_reason),
+                )
+                .await;
         }
+        if include_usage {
+            writer.chunk(vec![], Some(completion.usage)).await;
+        }
+        writer.send("[DONE]".to_string()).await;
     });
 
-    let sse = axum::response::sse::Sse::new(stream);
-    Ok(sse)
+    ReceiverStream::new(chunks)
 }
 
-/// GET /v1/models - List available models
-pub async fn openrouter_models(
-    State(state): State<AppState>,
-) -> Result<impl IntoResponse, (StatusCode, AxumJson<serde_json::Value>)> {
-    let models = vec![
-        "openai/gpt-4",
-        "openai/gpt-4o",
-        "openai/gpt-3.5-turbo",
-        "anthropic/claude-3-sonnet-20240229",
-        "anthropic/claude-3-haiku-20240307",
-        "anthropic/claude-3-opus-20240229",
-        "google/gemini-pro",
-        "google/gemini-ultra",
-    ];
-
-    let response = serde_json::json!({
-        "data": models.iter().map(|model| {
-            serde_json::json!({
-                "id": model,
-                "object": "model",
-                "owned_by": "openai",
-                "context_length": 128000
-            })
-        }).collect::<Vec<_>>(),
-        "object": "list"
-    });
-
-    Ok(AxumJson(response))
+/// GET /v1/models - Models of every registered provider plus configured aliases
+pub async fn openrouter_models(State(state): State<AppState>) -> impl IntoResponse {
+    let models = state.models.list().await;
+    AxumJson(serde_json::json!({
+        "object": "list",
+        "data": models,
+    }))
 }
 
-/// GET /v1/models/{model_id} - Get model details
+/// GET /v1/models/{model_id} - Model details; IDs are `provider/model` or an alias
 pub async fn openrouter_model_details(
+    State(state): State<AppState>,
     Path(model_id): Path<String>,
-) -> Result<impl IntoResponse, (StatusCode, AxumJson<serde_json::Value>)> {
-    let response = serde_json::json!({
-        "id": model_id,
-        "object": "model",
-        "owned_by": "openai",
-        "context_length": 128000,
-        "top_provider": {
-            "id": "openai",
-            "name": "OpenAI",
-            "group": "openai",
-            "model": "gpt-4"
-        }
-    });
-
-    Ok(AxumJson(response))
+) -> Result<AxumJson<CatalogModel>, ApiError> {
+    state
+        .models
+        .get(&model_id)
+        .await
+        .map(AxumJson)
+        .ok_or_else(|| {
+            let mut body = error_body(
+                "invalid_request_error",
+                format!("The model '{}' does not exist", model_id),
+            );
+            body["error"]["param"] = "model".into();
+            body["error"]["code"] = "model_not_found".into();
+            (StatusCode::NOT_FOUND, AxumJson(body))
+        })
 }
 
 // ============ Provider Management API ============
 
-/// GET /api/providers - List all registered providers
+/// The user whose providers a management request works on; a login token or API key is required
+async fn provider_owner(state: &AppState, headers: &HeaderMap) -> Result<String, ApiError> {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let caller = match token {
+        Some(token) => authenticate(state, token).await,
+        None => None,
+    };
+    caller.map(|c| c.user_id).ok_or_else(|| {
+        api_error(
+            StatusCode::UNAUTHORIZED,
+            "authentication_error",
+            "A login token or API key is required",
+        )
+    })
+}
+
+fn storage_error(e: anyhow::Error) -> ApiError {
+    api_error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "server_error",
+        e.to_string(),
+    )
+}
+
+fn provider_not_found(name: &str) -> ApiError {
+    api_error(
+        StatusCode::NOT_FOUND,
+        "not_found_error",
+        format!("Provider '{}' is not registered by you", name),
+    )
+}
+
+/// GET /api/providers - The caller's providers, then the shared ones
 pub async fn list_providers(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
 ) -> Result<impl IntoResponse, (StatusCode, AxumJson<serde_json::Value>)> {
-    let providers = GATEWAY_MANAGER.list_providers();
+    let user_id = provider_owner(&state, &headers).await?;
+    let gateway = state.providers.scope(&user_id);
+
+    // Listing never probes providers; `HealthMonitor` checks them in the background
+    let own_health: HashMap<String, HealthStatus> =
+        state.health.user_providers(&user_id).into_iter().collect();
+    let shared_health: HashMap<String, HealthStatus> =
+        state.health.providers().into_iter().collect();
 
     let mut provider_infos = Vec::new();
-    for provider_name in providers {
-        if let Some(config) = GATEWAY_MANAGER.get_provider(&provider_name) {
-            let agent_config = AgentConfig {
-                agent_type: config.provider.clone(),
-                model: config.model.clone(),
-                env: std::collections::HashMap::from([
-                    (
-                        "OPENROUTER_API_KEY".to_string(),
-                        config.api_key.clone().unwrap_or_default(),
-                    ),
-                    (
-                        "OPENROUTER_BASE_URL".to_string(),
-                        config.base_url.clone().unwrap_or_default(),
-                    ),
-                ]),
-                ..Default::default()
+    for provider_name in gateway.list_providers() {
+        if let Some(config) = gateway.get_provider(&provider_name) {
+            let shared = gateway.get_own_provider(&provider_name).is_none();
+            let status = if shared {
+                shared_health.get(&provider_name)
+            } else {
+                own_health.get(&provider_name)
             };
-
-            let agent = crate::agent::create_agent(&agent_config).unwrap();
-            let healthy = agent.health_check().await.is_ok();
+            let healthy = status.is_some_and(|s| s.available);
+            let health = gateway.provider_health(&provider_name);
+            let rate_limit = gateway
+                .rate_limiter(&provider_name)
+                .map(|limiter| limiter.status());
 
             provider_infos.push(ProviderInfo {
+                shared,
                 name: provider_name,
                 provider: config.provider,
                 model: config.model,
                 base_url: config.base_url,
                 healthy,
+                circuit: health.state(),
+                in_flight: health.in_flight(),
+                rate_limit,
             });
         }
     }
@@ -368,62 +1198,126 @@ pub async fn list_providers(
     }))
 }
 
-/// POST /api/providers - Register a new provider
+/// POST /api/providers - Register or replace one of the caller's providers
 pub async fn register_provider(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
     Json(req): Json<ProviderRequest>,
 ) -> Result<impl IntoResponse, (StatusCode, AxumJson<serde_json::Value>)> {
+    let user_id = provider_owner(&state, &headers).await?;
+    if !crate::secrets::is_valid_name(&req.name) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            "Provider names may only contain letters, digits, '_', '.' and '-'",
+        ));
+    }
     let config = GatewayConfig {
         provider: req.provider,
         model: req.model,
         api_key: req.api_key,
         base_url: req.base_url,
         fallback_providers: req.fallback_providers.unwrap_or_default(),
-        load_balancing: Some(LoadBalancing::RoundRobin),
+        load_balancing: req.load_balancing.or(Some(LoadBalancing::RoundRobin)),
+        restart_on_failure: req.restart_on_failure.unwrap_or_default(),
+        circuit_breaker: req.circuit_breaker.unwrap_or_default(),
+        rate_limit: req.rate_limit,
     };
+    if let Err(message) = config.validate() {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            message,
+        ));
+    }
+
+    state
+        .providers
+        .put(&user_id, &req.name, config)
+        .await
+        .map_err(storage_error)?;
+
+    Ok(AxumJson(serde_json::json!({ "ok": true })))
+}
 
-    GATEWAY_MANAGER.register_provider(req.name, config);
+/// PATCH /api/providers/{provider_name} - Update one of the caller's providers
+pub async fn update_provider(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(provider_name): Path<String>,
+    Json(patch): Json<ProviderPatch>,
+) -> Result<impl IntoResponse, (StatusCode, AxumJson<serde_json::Value>)> {
+    let user_id = provider_owner(&state, &headers).await?;
+    let Some(mut patched) = state
+        .providers
+        .scope(&user_id)
+        .get_own_provider(&provider_name)
+    else {
+        return Err(provider_not_found(&provider_name));
+    };
+    patch.clone().apply(&mut patched);
+    if let Err(message) = patched.validate() {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            message,
+        ));
+    }
+    let updated = state
+        .providers
+        .update(&user_id, &provider_name, |config| patch.apply(config))
+        .await
+        .map_err(storage_error)?;
+    if !updated {
+        return Err(provider_not_found(&provider_name));
+    }
 
     Ok(AxumJson(serde_json::json!({ "ok": true })))
 }
 
-/// DELETE /api/providers/{provider_name} - Remove a provider
+/// DELETE /api/providers/{provider_name} - Remove one of the caller's providers
 pub async fn remove_provider(
-    Path(_provider_name): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(provider_name): Path<String>,
 ) -> Result<impl IntoResponse, (StatusCode, AxumJson<serde_json::Value>)> {
-    // Remove from gateway manager
-    // Note: This is a simplified implementation
-    // In a real system, you'd want to persist this configuration
+    let user_id = provider_owner(&state, &headers).await?;
+    let removed = state
+        .providers
+        .delete(&user_id, &provider_name)
+        .await
+        .map_err(storage_error)?;
+    if !removed {
+        return Err(provider_not_found(&provider_name));
+    }
 
     Ok(AxumJson(serde_json::json!({ "ok": true })))
 }
 
-/// POST /api/providers/health-check - Health check all providers
+/// POST /api/providers/health-check - Health check the caller's and the shared providers
 pub async fn health_check_providers(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
 ) -> Result<impl IntoResponse, (StatusCode, AxumJson<serde_json::Value>)> {
-    let providers = GATEWAY_MANAGER.list_providers();
-
-    let mut results = std::collections::HashMap::new();
-    for provider_name in providers {
-        if let Some(config) = GATEWAY_MANAGER.get_provider(&provider_name) {
-            let agent_config = AgentConfig {
-                agent_type: config.provider.clone(),
-                model: config.model.clone(),
-                env: std::collections::HashMap::from([
-                    (
-                        "OPENROUTER_API_KEY".to_string(),
-                        config.api_key.clone().unwrap_or_default(),
-                    ),
-                    (
-                        "OPENROUTER_BASE_URL".to_string(),
-                        config.base_url.clone().unwrap_or_default(),
-                    ),
-                ]),
-                ..Default::default()
-            };
+    let user_id = provider_owner(&state, &headers).await?;
+    let gateway = state.providers.scope(&user_id);
 
-            let agent = crate::agent::create_agent(&agent_config).unwrap();
+    let mut results = HashMap::new();
+    for provider_name in gateway.list_providers() {
+        if let Some(config) = gateway.get_provider(&provider_name) {
+            // Only the caller's own key may be used to probe the caller's provider
+            let own = gateway.get_own_provider(&provider_name).is_some();
+            if own && !config.has_own_key() {
+                results.insert(provider_name, false);
+                continue;
+            }
+            let agent = crate::agent::create_agent(&config.agent_config()).map_err(|e| {
+                api_error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "server_error",
+                    format!("Provider '{}' can't be checked: {}", provider_name, e),
+                )
+            })?;
             let healthy = agent.health_check().await.is_ok();
 
             results.insert(provider_name, healthy);
@@ -1,13 +1,24 @@
+use std::path::Path;
+use std::sync::Arc;
+
 use axum::{
-    routing::{delete, get, post},
+    routing::{delete, get, post, put},
     Router,
 };
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 
 use super::handlers;
+use super::mcp;
 use super::openrouter;
+use crate::agent::{AgentRegistry, HealthMonitor, ModelCatalog, AGENT_REGISTRY};
+use crate::budget::Budgets;
+use crate::cache::{ResponseCache, ResponseCacheConfig};
+use crate::policy::AgentPolicy;
+use crate::pricing::PricingTable;
+use crate::providers::ProviderStore;
 use crate::run::{RunManager, RunStore};
+use crate::secrets::{SecretCipher, SecretStore};
 use crate::storage::Db;
 
 /// 应用状态
@@ -15,16 +26,80 @@ use crate::storage::Db;
 pub struct AppState {
     pub run_manager: RunManager,
     pub db: Db,
+    /// 客户端 agent 配置（env / extra_args）校验策略
+    pub policy: Arc<AgentPolicy>,
+    /// 加密密钥库
+    pub secrets: SecretStore,
+    /// 可用的 agent（与 run_manager 共享）
+    pub registry: AgentRegistry,
+    /// agent / provider 健康状态缓存
+    pub health: HealthMonitor,
+    /// 用户和项目的预算
+    pub budgets: Budgets,
+    /// provider 模型目录（`/v1/models`）
+    pub models: ModelCatalog,
+    /// 用户注册的 gateway provider
+    pub providers: ProviderStore,
+    /// `/v1/chat/completions` 确定性请求的响应缓存
+    pub cache: ResponseCache,
 }
 
 impl AppState {
     pub async fn new() -> Self {
-        let store = RunStore::new();
-        let run_manager = RunManager::new(store);
-        let db = Db::new("data/openrunner.db")
+        Self::with_data_dir("data").await
+    }
+
+    /// 数据库和主密钥存放在指定目录
+    pub async fn with_data_dir(dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref();
+        let db = Db::new(&dir.join("openrunner.db").to_string_lossy())
             .await
             .expect("Failed to initialize database");
-        Self { run_manager, db }
+        let cipher = SecretCipher::load(&dir.join("master.key").to_string_lossy())
+            .expect("Failed to load master key");
+        let secrets = SecretStore::new(db.clone(), cipher);
+        match secrets.migrate_saved_env().await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("Moved plaintext credentials of {} configs to secrets", n),
+            Err(e) => tracing::error!("Failed to migrate plaintext credentials: {}", e),
+        }
+        let store = RunStore::new();
+        let registry = AGENT_REGISTRY.clone();
+        let budgets = Budgets::new(db.clone());
+        let providers = ProviderStore::new(db.clone(), secrets.clone());
+        if let Err(e) = providers.load().await {
+            tracing::error!("Failed to load gateway providers: {}", e);
+        }
+        let cache = ResponseCache::new(db.clone(), ResponseCacheConfig::from_env());
+        let run_manager = RunManager::new(store)
+            .with_registry(registry.clone())
+            .with_secrets(secrets.clone())
+            .with_db(db.clone())
+            .with_pricing(PricingTable::from_env())
+            .with_budgets(budgets.clone());
+        Self {
+            run_manager,
+            db,
+            policy: Arc::new(AgentPolicy::from_env()),
+            secrets,
+            health: HealthMonitor::new(registry.clone()).with_scopes(providers.scopes()),
+            models: ModelCatalog::new(registry.clone()),
+            registry,
+            budgets,
+            providers,
+            cache,
+        }
+    }
+
+    /// 替换 agent 注册表（同时用于 run_manager）
+    ///
+    /// 只需追加自定义 agent 时，直接调用 `state.registry.register(...)` 即可。
+    pub fn with_registry(mut self, registry: AgentRegistry) -> Self {
+        self.run_manager = self.run_manager.with_registry(registry.clone());
+        self.health = HealthMonitor::new(registry.clone()).with_scopes(self.providers.scopes());
+        self.models = self.models.with_registry(registry.clone());
+        self.registry = registry;
+        self
     }
 }
 
@@ -36,6 +111,8 @@ pub async fn create_router() -> Router {
 
 /// 使用指定状态创建路由
 pub fn create_router_with_state(state: AppState) -> Router {
+    state.health.start();
+
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
@@ -52,6 +129,7 @@ pub fn create_router_with_state(state: AppState) -> Router {
         .route("/api/auth/register", post(handlers::register))
         // Runs API
         .route("/api/runs", post(handlers::create_run))
+        .route("/api/runs/:run_id", get(handlers::get_run))
         .route("/api/runs/:run_id/events", get(handlers::run_events))
         // Chat API (fallback)
         .route("/api/chat", post(handlers::chat))
@@ -61,10 +139,30 @@ pub fn create_router_with_state(state: AppState) -> Router {
         // Agent Defaults API
         .route("/api/agent-defaults", get(handlers::get_agent_defaults))
         .route("/api/agent-defaults", post(handlers::set_agent_default))
+        // Secrets API
+        .route("/api/secrets", get(handlers::list_secrets))
+        .route("/api/secrets", post(handlers::set_secret))
+        .route("/api/secrets/:name", delete(handlers::delete_secret))
+        // API Keys API
+        .route("/api/api-keys", get(handlers::list_api_keys))
+        .route("/api/api-keys", post(handlers::create_api_key))
+        .route("/api/api-keys/:id", delete(handlers::delete_api_key))
+        // Usage API
+        .route("/api/usage", get(handlers::get_usage))
+        // Budgets API
+        .route("/api/budgets", get(handlers::list_budgets))
+        .route("/api/budgets", post(handlers::set_budget))
+        .route("/api/budgets/:id", delete(handlers::delete_budget))
         // Projects API
         .route("/api/projects", get(handlers::list_projects))
         .route("/api/projects", post(handlers::create_project))
         .route("/api/projects/:project_id", delete(handlers::delete_project))
+        .route(
+            "/api/projects/:project_id/mcp-servers",
+            put(handlers::set_project_mcp_servers),
+        )
+        // MCP server (Streamable HTTP)
+        .route("/mcp", post(mcp::mcp_post).get(mcp::mcp_get))
         // OpenRouter-compatible API endpoints
         .route(
             "/v1/chat/completions",
@@ -74,9 +172,10 @@ pub fn create_router_with_state(state: AppState) -> Router {
             "/v1/chat/completions/stream",
             post(openrouter::openrouter_chat_completions_stream),
         )
+        .route("/v1/messages", post(openrouter::anthropic_messages))
         .route("/v1/models", get(openrouter::openrouter_models))
         .route(
-            "/v1/models/:model_id",
+            "/v1/models/*model_id",
             get(openrouter::openrouter_model_details),
         )
         // Provider management API
@@ -84,7 +183,8 @@ pub fn create_router_with_state(state: AppState) -> Router {
         .route("/api/providers", post(openrouter::register_provider))
         .route(
             "/api/providers/:provider_name",
-            axum::routing::delete(openrouter::remove_provider),
+            axum::routing::delete(openrouter::remove_provider)
+                .patch(openrouter::update_provider),
         )
         .route(
             "/api/providers/health-check",
@@ -0,0 +1,33 @@
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+
+/// API key 前缀，便于识别和与 JWT 区分
+pub const API_KEY_PREFIX: &str = "ork_";
+
+/// 列表中展示的 key 开头长度（含前缀）
+const DISPLAY_PREFIX_LEN: usize = 12;
+
+/// 生成新的 API key（只在创建时返回给用户一次）
+pub fn generate_api_key() -> String {
+    let random: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect();
+    format!("{}{}", API_KEY_PREFIX, random)
+}
+
+/// 存储用的 key 摘要（SHA-256，十六进制）
+pub fn hash_api_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// 列表中展示的 key 开头
+pub fn display_prefix(key: &str) -> String {
+    key.chars().take(DISPLAY_PREFIX_LEN).collect()
+}
+
+/// 看起来是 API key（而不是 JWT）
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(API_KEY_PREFIX)
+}
@@ -1,5 +1,7 @@
+pub mod api_key;
 pub mod jwt;
 
+pub use api_key::{generate_api_key, hash_api_key, is_api_key};
 pub use jwt::{create_token, verify_token, AuthError, Claims, TOKEN_EXPIRY_SECS};
 
 use serde::{Deserialize, Serialize};
@@ -0,0 +1,348 @@
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::Db;
+use crate::types::TokenUsage;
+
+/// 预算作用范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetScope {
+    User,
+    Project,
+}
+
+impl BudgetScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BudgetScope::User => "user",
+            BudgetScope::Project => "project",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "user" => Some(BudgetScope::User),
+            "project" => Some(BudgetScope::Project),
+            _ => None,
+        }
+    }
+}
+
+/// 预算周期（按 UTC 计算）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetPeriod {
+    Daily,
+    Monthly,
+}
+
+impl BudgetPeriod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BudgetPeriod::Daily => "daily",
+            BudgetPeriod::Monthly => "monthly",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "daily" => Some(BudgetPeriod::Daily),
+            "monthly" => Some(BudgetPeriod::Monthly),
+            _ => None,
+        }
+    }
+
+    /// 当前周期的开始时间
+    pub fn start(self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let date = match self {
+            BudgetPeriod::Daily => now.date_naive(),
+            BudgetPeriod::Monthly => {
+                NaiveDate::from_ymd_opt(now.year(), now.month(), 1).unwrap_or(now.date_naive())
+            }
+        };
+        date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()
+    }
+
+    /// 当前周期的标识，用于每个周期只告警一次
+    pub fn key(self, now: DateTime<Utc>) -> String {
+        match self {
+            BudgetPeriod::Daily => now.format("%Y-%m-%d").to_string(),
+            BudgetPeriod::Monthly => now.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+/// 用户或项目的预算
+///
+/// `max_tokens` / `max_cost_usd` 限制一个周期内的总用量，`max_run_*` 限制单个 Run。
+/// token 数包含缓存写入和命中的部分；没有费用信息的用量按 0 美元计。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Budget {
+    #[serde(default)]
+    pub id: String,
+    pub scope: BudgetScope,
+    /// 用户 ID 或项目 ID
+    pub scope_id: String,
+    pub period: BudgetPeriod,
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+    #[serde(default)]
+    pub max_cost_usd: Option<f64>,
+    #[serde(default)]
+    pub max_run_tokens: Option<u64>,
+    #[serde(default)]
+    pub max_run_cost_usd: Option<f64>,
+    /// 用量达到上限的这一比例时告警（如 0.8），为空不告警
+    #[serde(default)]
+    pub warn_ratio: Option<f64>,
+    /// 告警时 POST 到该地址
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+}
+
+/// 预算已用完
+#[derive(Debug, thiserror::Error)]
+pub enum BudgetError {
+    #[error("{period} token budget of {scope} '{scope_id}' exhausted ({used}/{limit} tokens)")]
+    Tokens {
+        scope: &'static str,
+        scope_id: String,
+        period: &'static str,
+        used: u64,
+        limit: u64,
+    },
+    #[error("{period} cost budget of {scope} '{scope_id}' exhausted ({used:.4}/{limit:.4} USD)")]
+    Cost {
+        scope: &'static str,
+        scope_id: String,
+        period: &'static str,
+        used: f64,
+        limit: f64,
+    },
+    #[error(transparent)]
+    Storage(#[from] anyhow::Error),
+}
+
+/// 单个 Run 可以使用的上限：单 Run 限额和各周期剩余额度中的最小值
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RunLimit {
+    pub max_tokens: Option<u64>,
+    pub max_cost_usd: Option<f64>,
+}
+
+impl RunLimit {
+    fn cap_tokens(&mut self, limit: u64) {
+        self.max_tokens = Some(self.max_tokens.map_or(limit, |t| t.min(limit)));
+    }
+
+    fn cap_cost(&mut self, limit: f64) {
+        self.max_cost_usd = Some(self.max_cost_usd.map_or(limit, |c| c.min(limit)));
+    }
+
+    /// 超出上限时返回原因
+    pub fn exceeded(&self, usage: &TokenUsage) -> Option<String> {
+        if let Some(limit) = self.max_tokens {
+            let used = usage.total_tokens();
+            if used > limit {
+                return Some(format!("run used {} tokens, limit is {}", used, limit));
+            }
+        }
+        if let (Some(limit), Some(used)) = (self.max_cost_usd, usage.cost_usd) {
+            if used > limit {
+                return Some(format!(
+                    "run cost {:.4} USD, limit is {:.4} USD",
+                    used, limit
+                ));
+            }
+        }
+        None
+    }
+}
+
+/// 预算告警（`budget_warning` 事件和 webhook 的内容）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BudgetWarning {
+    pub budget_id: String,
+    pub scope: BudgetScope,
+    pub scope_id: String,
+    pub period: BudgetPeriod,
+    pub used_tokens: u64,
+    pub used_cost_usd: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_cost_usd: Option<f64>,
+    pub warn_ratio: f64,
+}
+
+/// 预算检查和告警
+#[derive(Clone)]
+pub struct Budgets {
+    db: Db,
+    client: reqwest::Client,
+}
+
+impl Budgets {
+    pub fn new(db: Db) -> Self {
+        Self {
+            db,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// 启动 Run 或调用模型前检查用户和项目的预算，返回该次调用可用的上限
+    pub async fn check(
+        &self,
+        user_id: &str,
+        project_id: Option<&str>,
+    ) -> Result<RunLimit, BudgetError> {
+        let now = Utc::now();
+        let mut limit = RunLimit::default();
+        for budget in self.db.budgets_for(user_id, project_id).await? {
+            let (used_tokens, used_cost) = self
+                .db
+                .usage_total(budget.scope, &budget.scope_id, budget.period.start(now))
+                .await?;
+
+            if let Some(max) = budget.max_tokens {
+                if used_tokens >= max {
+                    return Err(BudgetError::Tokens {
+                        scope: budget.scope.as_str(),
+                        scope_id: budget.scope_id,
+                        period: budget.period.as_str(),
+                        used: used_tokens,
+                        limit: max,
+                    });
+                }
+                limit.cap_tokens(max - used_tokens);
+            }
+            if let Some(max) = budget.max_cost_usd {
+                if used_cost >= max {
+                    return Err(BudgetError::Cost {
+                        scope: budget.scope.as_str(),
+                        scope_id: budget.scope_id,
+                        period: budget.period.as_str(),
+                        used: used_cost,
+                        limit: max,
+                    });
+                }
+                limit.cap_cost(max - used_cost);
+            }
+            if let Some(max) = budget.max_run_tokens {
+                limit.cap_tokens(max);
+            }
+            if let Some(max) = budget.max_run_cost_usd {
+                limit.cap_cost(max);
+            }
+        }
+        Ok(limit)
+    }
+
+    /// 已记录的用量加上 `pending`（进行中的 Run）达到告警比例时告警
+    ///
+    /// 每个预算每个周期只告警一次；配置了 webhook 的在后台发送。
+    pub async fn check_warnings(
+        &self,
+        user_id: &str,
+        project_id: Option<&str>,
+        pending: &TokenUsage,
+    ) -> Vec<BudgetWarning> {
+        let budgets = match self.db.budgets_for(user_id, project_id).await {
+            Ok(budgets) => budgets,
+            Err(e) => {
+                tracing::warn!("Failed to load budgets: {}", e);
+                return vec![];
+            }
+        };
+
+        let now = Utc::now();
+        let mut warnings = Vec::new();
+        for budget in budgets {
+            let Some(ratio) = budget.warn_ratio else {
+                continue;
+            };
+            let (used_tokens, used_cost) = match self
+                .db
+                .usage_total(budget.scope, &budget.scope_id, budget.period.start(now))
+                .await
+            {
+                Ok(total) => total,
+                Err(e) => {
+                    tracing::warn!("Failed to load usage for budget {}: {}", budget.id, e);
+                    continue;
+                }
+            };
+            let used_tokens = used_tokens + pending.total_tokens();
+            let used_cost = used_cost + pending.cost_usd.unwrap_or(0.0);
+
+            let reached = budget
+                .max_tokens
+                .is_some_and(|max| used_tokens as f64 >= max as f64 * ratio)
+                || budget
+                    .max_cost_usd
+                    .is_some_and(|max| used_cost >= max * ratio);
+            if !reached {
+                continue;
+            }
+            match self
+                .db
+                .mark_budget_warned(&budget.id, &budget.period.key(now))
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    tracing::warn!("Failed to mark budget {} as warned: {}", budget.id, e);
+                    continue;
+                }
+            }
+
+            let warning = BudgetWarning {
+                budget_id: budget.id.clone(),
+                scope: budget.scope,
+                scope_id: budget.scope_id.clone(),
+                period: budget.period,
+                used_tokens,
+                used_cost_usd: used_cost,
+                max_tokens: budget.max_tokens,
+                max_cost_usd: budget.max_cost_usd,
+                warn_ratio: ratio,
+            };
+            tracing::info!(
+                "Budget {} of {} '{}' reached {:.0}%",
+                budget.id,
+                budget.scope.as_str(),
+                budget.scope_id,
+                ratio * 100.0
+            );
+            if let Some(url) = budget.webhook_url {
+                self.send_webhook(url, warning.clone());
+            }
+            warnings.push(warning);
+        }
+        warnings
+    }
+
+    fn send_webhook(&self, url: String, warning: BudgetWarning) {
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            let body = serde_json::json!({ "type": "budget_warning", "warning": warning });
+            let result = client
+                .post(&url)
+                .json(&body)
+                .timeout(std::time::Duration::from_secs(10))
+                .send()
+                .await
+                .and_then(|r| r.error_for_status());
+            // webhook 地址可能带有 token，不写入日志
+            if let Err(e) = result {
+                tracing::warn!(
+                    "Budget webhook for {} failed: {}",
+                    warning.budget_id,
+                    e.without_url()
+                );
+            }
+        });
+    }
+}
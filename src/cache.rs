@@ -0,0 +1,119 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::storage::Db;
+
+/// 响应缓存配置
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseCacheConfig {
+    pub enabled: bool,
+    /// 响应写入后的有效期
+    pub ttl: Duration,
+    /// 超过该大小的响应不缓存
+    pub max_entry_bytes: usize,
+    /// 缓存总大小，超出时先淘汰最早写入的响应
+    pub max_total_bytes: u64,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl: Duration::from_secs(24 * 60 * 60),
+            max_entry_bytes: 1024 * 1024,
+            max_total_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+impl ResponseCacheConfig {
+    /// `OPENRUNNER_RESPONSE_CACHE=1` 时开启；有效期取 `OPENRUNNER_RESPONSE_CACHE_TTL_SECS`，
+    /// 大小限制取 `OPENRUNNER_RESPONSE_CACHE_MAX_ENTRY_BYTES` / `OPENRUNNER_RESPONSE_CACHE_MAX_BYTES`
+    pub fn from_env() -> Self {
+        fn var<T: FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok().and_then(|v| v.trim().parse().ok())
+        }
+        let default = Self::default();
+        Self {
+            enabled: std::env::var("OPENRUNNER_RESPONSE_CACHE")
+                .is_ok_and(|v| matches!(v.trim(), "1" | "true")),
+            ttl: var("OPENRUNNER_RESPONSE_CACHE_TTL_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.ttl),
+            max_entry_bytes: var("OPENRUNNER_RESPONSE_CACHE_MAX_ENTRY_BYTES")
+                .unwrap_or(default.max_entry_bytes),
+            max_total_bytes: var("OPENRUNNER_RESPONSE_CACHE_MAX_BYTES")
+                .unwrap_or(default.max_total_bytes),
+        }
+    }
+}
+
+/// 确定性 gateway 请求的响应缓存，存放在数据库中
+///
+/// 键由调用方从规范化后的请求算出（`ResponseCache::key`），值是完整的 JSON 响应。
+/// 读写数据库失败时只记录日志，按未命中处理。
+#[derive(Clone)]
+pub struct ResponseCache {
+    db: Db,
+    config: Arc<ResponseCacheConfig>,
+}
+
+impl ResponseCache {
+    pub fn new(db: Db, config: ResponseCacheConfig) -> Self {
+        Self {
+            db,
+            config: Arc::new(config),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    pub fn config(&self) -> &ResponseCacheConfig {
+        &self.config
+    }
+
+    /// 请求的缓存键：JSON（对象的键按字典序）的 SHA-256
+    pub fn key(request: &Value) -> String {
+        format!("{:x}", Sha256::digest(request.to_string().as_bytes()))
+    }
+
+    /// 未过期的缓存响应
+    pub async fn get(&self, key: &str) -> Option<Value> {
+        let json = match self.db.get_cached_response(key, &chrono::Utc::now()).await {
+            Ok(json) => json?,
+            Err(e) => {
+                tracing::warn!("Failed to read cached response: {}", e);
+                return None;
+            }
+        };
+        serde_json::from_str(&json).ok()
+    }
+
+    /// 写入响应并淘汰过期、超出总大小的响应，返回是否写入
+    pub async fn put(&self, key: &str, response: &Value) -> bool {
+        let json = response.to_string();
+        if json.len() > self.config.max_entry_bytes {
+            return false;
+        }
+        let now = chrono::Utc::now();
+        let expires_at = now + self.config.ttl;
+        if let Err(e) = self.db.put_cached_response(key, &json, &expires_at).await {
+            tracing::warn!("Failed to cache response: {}", e);
+            return false;
+        }
+        if let Err(e) = self
+            .db
+            .prune_response_cache(&now, self.config.max_total_bytes)
+            .await
+        {
+            tracing::warn!("Failed to prune response cache: {}", e);
+        }
+        true
+    }
+}
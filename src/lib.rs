@@ -1,13 +1,27 @@
 pub mod agent;
 pub mod api;
 pub mod auth;
+pub mod budget;
+pub mod cache;
+pub mod policy;
+pub mod pricing;
+pub mod providers;
+pub mod redact;
 pub mod run;
+pub mod secrets;
 pub mod storage;
 pub mod types;
 
 pub use agent::{create_agent, Agent, AgentHandle};
 pub use api::{create_router, create_router_with_state, AppState};
 pub use auth::{LoginRequest, LoginResponse, User};
+pub use budget::{Budget, BudgetError, Budgets, RunLimit};
+pub use cache::{ResponseCache, ResponseCacheConfig};
+pub use policy::{AgentPolicy, PolicyError, PolicyRule};
+pub use pricing::{ModelPrice, PricingTable};
+pub use providers::ProviderStore;
+pub use redact::{Redactor, StreamRedactor};
 pub use run::{Run, RunEvent, RunManager, RunStatus, RunStore};
+pub use secrets::{SecretCipher, SecretStore};
 pub use storage::Db;
 pub use types::*;
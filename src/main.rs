@@ -1,20 +1,38 @@
 use anyhow::Result;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // `openrunner mcp`：以 stdio MCP server 运行，stdout 只输出协议消息
+    let mcp_stdio = std::env::args().nth(1).as_deref() == Some("mcp");
+
     // 初始化日志
+    let writer = if mcp_stdio {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "openrunner=info,tower_http=debug".into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_writer(writer))
         .init();
 
     // 初始化 LLM Gateway providers
     openrunner::agent::init_default_providers();
 
+    // 加载配置文件中声明的 CLI agent
+    if let Err(e) = openrunner::agent::load_agent_definitions(&openrunner::agent::AGENT_REGISTRY) {
+        tracing::error!("Failed to load agent definitions: {}", e);
+    }
+
+    if mcp_stdio {
+        return run_mcp_stdio().await;
+    }
+
     // 创建路由
     let app = openrunner::create_router().await;
 
@@ -33,6 +51,7 @@ async fn main() -> Result<()> {
     tracing::info!("  POST /api/runs            - Create a run");
     tracing::info!("  GET  /api/runs/:id/events - Stream run events (SSE)");
     tracing::info!("  POST /api/chat            - Non-streaming chat (fallback)");
+    tracing::info!("  POST /mcp                 - MCP server (API key)");
     tracing::info!("");
     tracing::info!("OpenRouter-compatible API:");
     tracing::info!("  POST /v1/chat/completions - Chat completions");
@@ -51,3 +70,17 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// stdio MCP server：用 `OPENRUNNER_API_KEY` 识别调用者，Run 在本进程中执行
+async fn run_mcp_stdio() -> Result<()> {
+    let api_key = std::env::var("OPENRUNNER_API_KEY")
+        .map_err(|_| anyhow::anyhow!("OPENRUNNER_API_KEY is required for `openrunner mcp`"))?;
+
+    let state = openrunner::AppState::new().await;
+    let caller = openrunner::api::mcp::authenticate(&state, &api_key)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("Invalid OPENRUNNER_API_KEY"))?;
+
+    tracing::info!("Serving MCP over stdio for user {}", caller.user_id);
+    openrunner::api::mcp::serve_stdio(state, caller).await
+}
@@ -0,0 +1,245 @@
+use serde::Deserialize;
+
+use crate::types::AgentConfig;
+
+/// 策略校验失败
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PolicyError {
+    #[error("env key '{key}' is not allowed for agent '{agent_type}'")]
+    EnvKey { key: String, agent_type: String },
+    #[error("argument '{arg}' is not allowed for agent '{agent_type}'")]
+    Arg { arg: String, agent_type: String },
+    #[error("MCP server '{server}': command '{command}' is not allowed")]
+    McpCommand { server: String, command: String },
+    #[error("MCP server '{server}': env key '{key}' is not allowed")]
+    McpEnvKey { server: String, key: String },
+    #[error("MCP server '{server}': argument '{arg}' is not allowed")]
+    McpArg { server: String, arg: String },
+}
+
+/// 单条策略规则
+///
+/// 模式支持精确匹配、以 `*` 结尾的前缀匹配（`*` 匹配全部）和以 `*` 开头的后缀匹配。
+/// `*_allow` 为 None 表示不限制，只检查 deny 列表。
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PolicyRule {
+    /// 适用的 agent 类型，空表示全部
+    #[serde(default)]
+    pub agent_types: Vec<String>,
+    /// 适用的用户角色，空表示全部
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub env_allow: Option<Vec<String>>,
+    #[serde(default)]
+    pub env_deny: Vec<String>,
+    #[serde(default)]
+    pub args_allow: Option<Vec<String>>,
+    #[serde(default)]
+    pub args_deny: Vec<String>,
+    /// 允许启动的 MCP stdio server 命令，默认为空：不允许任何 stdio server
+    ///
+    /// server 的 env 和 args 按本规则的 env / args 规则检查。
+    #[serde(default)]
+    pub mcp_commands: Vec<String>,
+    /// 跳过内置的 env / 参数 deny 列表，只按本规则检查
+    #[serde(default)]
+    pub override_builtin: bool,
+}
+
+/// 会改变进程加载行为或 agent 沙箱边界的环境变量
+const DEFAULT_ENV_DENY: &[&str] = &[
+    "LD_*",
+    "DYLD_*",
+    "PATH",
+    "HOME",
+    "SHELL",
+    "BASH_ENV",
+    "ENV",
+    "NODE_OPTIONS",
+    "PYTHONPATH",
+    "PYTHONSTARTUP",
+    "XDG_CONFIG_HOME",
+    // provider 地址由服务端配置，改写后服务端的 API key 会被发往调用方指定的主机
+    "*_BASE_URL",
+    "*_API_BASE",
+];
+
+/// 会扩大文件访问范围或绕过审批的 CLI 参数
+const DEFAULT_ARGS_DENY: &[&str] = &[
+    "--add-dir",
+    "--mcp-config",
+    "--settings",
+    "--permission-mode",
+    "--dangerously-bypass-approvals-and-sandbox",
+    "--sandbox",
+    "-s",
+    "--cd",
+    "-C",
+    "--config",
+    "-c",
+    "--work-dir",
+];
+
+fn pattern_matches(pattern: &str, value: &str) -> bool {
+    if let Some(prefix) = pattern.strip_suffix('*') {
+        value.starts_with(prefix)
+    } else if let Some(suffix) = pattern.strip_prefix('*') {
+        value.ends_with(suffix)
+    } else {
+        pattern == value
+    }
+}
+
+impl PolicyRule {
+    /// 内置的兜底规则：对所有 agent 和角色生效
+    pub fn builtin() -> Self {
+        Self {
+            env_deny: DEFAULT_ENV_DENY.iter().map(|s| s.to_string()).collect(),
+            args_deny: DEFAULT_ARGS_DENY.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn matches(&self, agent_type: &str, roles: &[String]) -> bool {
+        let agent_ok = self.agent_types.is_empty()
+            || self
+                .agent_types
+                .iter()
+                .any(|a| pattern_matches(a, agent_type));
+        let role_ok = self.roles.is_empty() || self.roles.iter().any(|r| roles.contains(r));
+        agent_ok && role_ok
+    }
+
+    fn env_allowed(&self, key: &str) -> bool {
+        if self.env_deny.iter().any(|p| pattern_matches(p, key)) {
+            return false;
+        }
+        match &self.env_allow {
+            Some(allow) => allow.iter().any(|p| pattern_matches(p, key)),
+            None => true,
+        }
+    }
+
+    /// 只检查 flag（以 `-` 开头），`--flag=value` 按 `--flag` 匹配
+    fn arg_allowed(&self, arg: &str) -> bool {
+        if !arg.starts_with('-') {
+            return true;
+        }
+        let flag = arg.split('=').next().unwrap_or(arg);
+        if self.args_deny.iter().any(|p| pattern_matches(p, flag)) {
+            return false;
+        }
+        match &self.args_allow {
+            Some(allow) => allow.iter().any(|p| pattern_matches(p, flag)),
+            None => true,
+        }
+    }
+}
+
+/// 服务端 agent 配置策略：限制客户端可以传入的 env 和 extra_args
+///
+/// 规则按顺序匹配，第一条匹配 (agent_type, 角色) 的规则生效，没有匹配时使用内置规则。
+/// 内置的 deny 列表总是先于匹配的规则检查，规则中的 allow 不能放开它们，
+/// 除非该规则设置了 `override_builtin`。
+#[derive(Debug, Clone, Deserialize)]
+pub struct AgentPolicy {
+    #[serde(default)]
+    pub rules: Vec<PolicyRule>,
+}
+
+impl Default for AgentPolicy {
+    fn default() -> Self {
+        Self {
+            rules: vec![PolicyRule::builtin()],
+        }
+    }
+}
+
+impl AgentPolicy {
+    /// 从 `OPENRUNNER_POLICY_FILE`（JSON）加载规则，内置规则始终作为最后一条兜底
+    pub fn from_env() -> Self {
+        let mut rules = match std::env::var("OPENRUNNER_POLICY_FILE") {
+            Ok(path) => match std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|s| serde_json::from_str::<AgentPolicy>(&s).map_err(Into::into))
+            {
+                Ok(policy) => {
+                    tracing::info!("Loaded {} policy rules from {}", policy.rules.len(), path);
+                    policy.rules
+                }
+                Err(e) => {
+                    tracing::error!("Failed to load policy file {}: {}", path, e);
+                    vec![]
+                }
+            },
+            Err(_) => vec![],
+        };
+        rules.push(PolicyRule::builtin());
+        Self { rules }
+    }
+
+    /// 校验 agent 配置中的 env、extra_args 和 MCP stdio server
+    pub fn check(&self, config: &AgentConfig, roles: &[String]) -> Result<(), PolicyError> {
+        let builtin = PolicyRule::builtin();
+        let rule = self
+            .rules
+            .iter()
+            .find(|r| r.matches(&config.agent_type, roles))
+            .unwrap_or(&builtin);
+        let env_allowed = |key: &str| {
+            (rule.override_builtin || builtin.env_allowed(key)) && rule.env_allowed(key)
+        };
+        let arg_allowed = |arg: &str| {
+            (rule.override_builtin || builtin.arg_allowed(arg)) && rule.arg_allowed(arg)
+        };
+
+        let mut keys: Vec<&String> = config.env.keys().collect();
+        keys.sort();
+        if let Some(key) = keys.into_iter().find(|k| !env_allowed(k)) {
+            return Err(PolicyError::EnvKey {
+                key: key.clone(),
+                agent_type: config.agent_type.clone(),
+            });
+        }
+
+        if let Some(arg) = config.extra_args.iter().find(|a| !arg_allowed(a)) {
+            return Err(PolicyError::Arg {
+                arg: arg.clone(),
+                agent_type: config.agent_type.clone(),
+            });
+        }
+
+        for server in &config.mcp_servers {
+            let Some(command) = &server.command else {
+                continue;
+            };
+            if !rule
+                .mcp_commands
+                .iter()
+                .any(|p| pattern_matches(p, command))
+            {
+                return Err(PolicyError::McpCommand {
+                    server: server.name.clone(),
+                    command: command.clone(),
+                });
+            }
+            let mut keys: Vec<&String> = server.env.keys().collect();
+            keys.sort();
+            if let Some(key) = keys.into_iter().find(|k| !env_allowed(k)) {
+                return Err(PolicyError::McpEnvKey {
+                    server: server.name.clone(),
+                    key: key.clone(),
+                });
+            }
+            if let Some(arg) = server.args.iter().find(|a| !arg_allowed(a)) {
+                return Err(PolicyError::McpArg {
+                    server: server.name.clone(),
+                    arg: arg.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
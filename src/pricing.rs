@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::TokenUsage;
+
+/// 单个模型的价格（美元 / 百万 token）
+///
+/// 缓存写入、读取未配置时按输入价格计算。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_write: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read: Option<f64>,
+}
+
+impl ModelPrice {
+    /// 按用量计算费用
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        let per_token = |price: f64, tokens: u64| price * tokens as f64 / 1_000_000.0;
+        per_token(self.input, usage.input_tokens)
+            + per_token(self.output, usage.output_tokens)
+            + per_token(
+                self.cache_write.unwrap_or(self.input),
+                usage.cache_creation_input_tokens,
+            )
+            + per_token(
+                self.cache_read.unwrap_or(self.input),
+                usage.cache_read_input_tokens,
+            )
+    }
+}
+
+/// 模型价格表，用于补全 provider / CLI 没有上报的费用
+///
+/// 模型名支持精确匹配和以 `*` 结尾的前缀匹配，前缀越长优先级越高；
+/// 带 provider 前缀的模型（如 `openai/gpt-4o`）找不到时再按 `/` 之后的部分匹配。
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PricingTable {
+    #[serde(default)]
+    pub models: HashMap<String, ModelPrice>,
+}
+
+impl PricingTable {
+    /// 从 `OPENRUNNER_PRICING_FILE`（JSON）加载，未设置时为空表
+    pub fn from_env() -> Self {
+        match std::env::var("OPENRUNNER_PRICING_FILE") {
+            Ok(path) => match std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|s| serde_json::from_str::<PricingTable>(&s).map_err(Into::into))
+            {
+                Ok(table) => {
+                    tracing::info!("Loaded {} model prices from {}", table.models.len(), path);
+                    table
+                }
+                Err(e) => {
+                    tracing::error!("Failed to load pricing file {}: {}", path, e);
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// 查找模型价格
+    pub fn get(&self, model: &str) -> Option<&ModelPrice> {
+        self.lookup(model).or_else(|| {
+            model
+                .rsplit_once('/')
+                .and_then(|(_, name)| self.lookup(name))
+        })
+    }
+
+    fn lookup(&self, model: &str) -> Option<&ModelPrice> {
+        if let Some(price) = self.models.get(model) {
+            return Some(price);
+        }
+        self.models
+            .iter()
+            .filter_map(|(pattern, price)| {
+                let prefix = pattern.strip_suffix('*')?;
+                model.starts_with(prefix).then_some((prefix.len(), price))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, price)| price)
+    }
+
+    /// 用量中没有费用时按价格表补全
+    pub fn fill_cost(&self, model: Option<&str>, mut usage: TokenUsage) -> TokenUsage {
+        if usage.cost_usd.is_none() {
+            usage.cost_usd = model
+                .and_then(|m| self.get(m))
+                .map(|price| price.cost(&usage));
+        }
+        usage
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use dashmap::DashMap;
+
+use crate::agent::{GatewayConfig, GatewayManager, GATEWAY_MANAGER};
+use crate::secrets::{is_valid_name, references, secret_ref, SecretStore};
+use crate::storage::Db;
+
+/// 用户注册的 gateway provider，持久化到数据库
+///
+/// 每个用户的 provider 注册在各自的 `GatewayManager`（`GATEWAY_MANAGER.scoped()`）中，
+/// 同名时覆盖全局 provider，其他名称、别名和路由规则沿用全局的。
+/// API key 加密存入该用户的密钥库（`gateway.<name>.api_key`），数据库中的配置只保存引用；
+/// 也可以直接传 `${secret:NAME}` 引用已有的密钥。
+#[derive(Clone)]
+pub struct ProviderStore {
+    db: Db,
+    secrets: SecretStore,
+    scopes: Arc<DashMap<String, GatewayManager>>,
+}
+
+impl ProviderStore {
+    pub fn new(db: Db, secrets: SecretStore) -> Self {
+        Self {
+            db,
+            secrets,
+            scopes: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// 用户可用的 provider：自己注册的加上全局的
+    pub fn scope(&self, user_id: &str) -> GatewayManager {
+        self.scopes
+            .entry(user_id.to_string())
+            .or_insert_with(|| GATEWAY_MANAGER.scoped())
+            .clone()
+    }
+
+    /// 各用户的 gateway manager（按用户 ID），供健康检查遍历
+    pub fn scopes(&self) -> Arc<DashMap<String, GatewayManager>> {
+        self.scopes.clone()
+    }
+
+    /// 从数据库加载所有用户的 provider（启动时调用），返回加载的数量
+    ///
+    /// 无法解析或解密的记录跳过并记录错误。
+    pub async fn load(&self) -> Result<usize> {
+        let mut loaded = 0;
+        for row in self.db.list_gateway_providers().await? {
+            let config = serde_json::from_str::<GatewayConfig>(&row.config_json)
+                .map_err(anyhow::Error::from);
+            let config = match config {
+                Ok(config) => self.resolve(&row.user_id, config).await,
+                Err(e) => Err(e),
+            };
+            match config {
+                Ok(config) => {
+                    self.scope(&row.user_id).register_provider(row.name, config);
+                    loaded += 1;
+                }
+                Err(e) => tracing::error!(
+                    "Failed to load gateway provider '{}' of {}: {}",
+                    row.name,
+                    row.user_id,
+                    e
+                ),
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// 保存并注册 provider，同名时替换
+    pub async fn put(&self, user_id: &str, name: &str, config: GatewayConfig) -> Result<()> {
+        if !is_valid_name(name) {
+            anyhow::bail!("Invalid provider name: {}", name);
+        }
+        let secret = api_key_secret(name);
+        let mut stored = config.clone();
+        let resolved = self.resolve(user_id, config).await?;
+
+        match stored.api_key.as_deref() {
+            Some(key) if !key.is_empty() && references(key).is_empty() => {
+                self.secrets.set(user_id, &secret, key).await?;
+                stored.api_key = Some(secret_ref(&secret));
+            }
+            // 仍然引用自己的密钥（更新其他字段时）
+            Some(key) if references(key) == [secret.clone()] => {}
+            _ => {
+                self.secrets.delete(user_id, &secret).await?;
+            }
+        }
+        self.db
+            .put_gateway_provider(user_id, name, &serde_json::to_string(&stored)?)
+            .await?;
+        self.scope(user_id)
+            .register_provider(name.to_string(), resolved);
+        Ok(())
+    }
+
+    /// 修改已保存的 provider，不存在时返回 false
+    ///
+    /// `update` 看到的 API key 是保存的引用，未修改时原样保留。
+    pub async fn update(
+        &self,
+        user_id: &str,
+        name: &str,
+        update: impl FnOnce(&mut GatewayConfig),
+    ) -> Result<bool> {
+        let Some(row) = self.db.get_gateway_provider(user_id, name).await? else {
+            return Ok(false);
+        };
+        let mut config: GatewayConfig = serde_json::from_str(&row.config_json)?;
+        update(&mut config);
+        self.put(user_id, name, config).await?;
+        Ok(true)
+    }
+
+    /// 删除 provider 和它的 API key，不存在时返回 false
+    pub async fn delete(&self, user_id: &str, name: &str) -> Result<bool> {
+        let removed = self.db.delete_gateway_provider(user_id, name).await?;
+        self.secrets.delete(user_id, &api_key_secret(name)).await?;
+        self.scope(user_id).remove_provider(name);
+        Ok(removed)
+    }
+
+    /// 把 API key 中的 `${secret:NAME}` 引用替换为明文
+    async fn resolve(&self, user_id: &str, mut config: GatewayConfig) -> Result<GatewayConfig> {
+        if let Some(api_key) = config.api_key.take() {
+            let env = HashMap::from([("API_KEY".to_string(), api_key)]);
+            let mut resolved = self.secrets.resolve_env(user_id, &env).await?;
+            config.api_key = resolved.remove("API_KEY");
+        }
+        Ok(config)
+    }
+}
+
+/// 保存 provider API key 的密钥名
+fn api_key_secret(name: &str) -> String {
+    format!("gateway.{}.api_key", name)
+}
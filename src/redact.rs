@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::secrets::{is_sensitive_key, references};
+
+/// 替换敏感内容的占位符
+pub const REDACTED: &str = "[REDACTED]";
+
+/// 短于该长度的已知密钥值不做替换，避免误伤普通文本
+const MIN_SECRET_LEN: usize = 8;
+
+/// 流式输出最多缓冲的字节数，超过后强制输出
+const MAX_PENDING: usize = 4096;
+
+/// 常见 token 格式：(正则, 替换文本)
+static PATTERNS: Lazy<Vec<(Regex, String)>> = Lazy::new(|| {
+    let redacted = REDACTED.to_string();
+    vec![
+        // Authorization 头，保留 `Bearer ` 前缀
+        (
+            Regex::new(r"(?i)\b(bearer\s+)[A-Za-z0-9._~+/=-]{16,}").unwrap(),
+            format!("${{1}}{}", REDACTED),
+        ),
+        // OpenAI / Anthropic / OpenRouter 等 `sk-` 前缀的 key
+        (
+            Regex::new(r"\bsk-[A-Za-z0-9_-]{16,}").unwrap(),
+            redacted.clone(),
+        ),
+        // GitHub token
+        (
+            Regex::new(r"\b(gh[pousr]_[A-Za-z0-9]{30,}|github_pat_[A-Za-z0-9_]{22,})").unwrap(),
+            redacted.clone(),
+        ),
+        // AWS access key id
+        (
+            Regex::new(r"\bAKIA[0-9A-Z]{16}\b").unwrap(),
+            redacted.clone(),
+        ),
+        // Slack token
+        (
+            Regex::new(r"\bxox[abprs]-[A-Za-z0-9-]{10,}").unwrap(),
+            redacted.clone(),
+        ),
+        // OpenRunner API key
+        (Regex::new(r"\bork_[A-Za-z0-9]{32,}").unwrap(), redacted),
+    ]
+});
+
+/// 敏感内容过滤器：替换已知密钥值和常见 token 格式
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    /// 已知密钥值（按长度降序，优先替换长的）
+    secrets: Vec<String>,
+}
+
+impl Redactor {
+    pub fn new(secrets: impl IntoIterator<Item = String>) -> Self {
+        let mut secrets: Vec<String> = secrets
+            .into_iter()
+            .filter(|s| s.len() >= MIN_SECRET_LEN)
+            .collect();
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+        secrets.dedup();
+        Self { secrets }
+    }
+
+    /// 以 env 中看起来保存凭据的值作为已知密钥
+    pub fn from_env(env: &HashMap<String, String>) -> Self {
+        Self::new(
+            env.iter()
+                .filter(|(k, _)| is_sensitive_key(k))
+                .map(|(_, v)| v.clone()),
+        )
+    }
+
+    /// 在 `from_env` 基础上，原始 env 中引用了密钥的值解析后也视为密钥
+    pub fn from_resolved_env(
+        original: &HashMap<String, String>,
+        resolved: &HashMap<String, String>,
+    ) -> Self {
+        Self::new(
+            resolved
+                .iter()
+                .filter(|(k, _)| {
+                    is_sensitive_key(k)
+                        || original.get(*k).is_some_and(|v| !references(v).is_empty())
+                })
+                .map(|(_, v)| v.clone()),
+        )
+    }
+
+    /// 过滤文本，返回 (过滤后文本, 替换次数)
+    pub fn redact(&self, text: &str) -> (String, usize) {
+        let mut result = text.to_string();
+        let mut count = 0;
+
+        for secret in &self.secrets {
+            let n = result.matches(secret.as_str()).count();
+            if n > 0 {
+                count += n;
+                result = result.replace(secret.as_str(), REDACTED);
+            }
+        }
+
+        for (pattern, replacement) in PATTERNS.iter() {
+            let n = pattern.find_iter(&result).count();
+            if n > 0 {
+                count += n;
+                result = pattern
+                    .replace_all(&result, replacement.as_str())
+                    .into_owned();
+            }
+        }
+
+        (result, count)
+    }
+}
+
+/// 只按常见 token 格式过滤（用于日志等拿不到密钥值的场景）
+pub fn redact_text(text: &str) -> String {
+    Redactor::default().redact(text).0
+}
+
+/// 过滤 CLI 参数列表（用于日志）
+pub fn redact_args(args: &[String]) -> Vec<String> {
+    args.iter().map(|a| redact_text(a)).collect()
+}
+
+/// 流式文本过滤器
+///
+/// agent 输出按 token 分片到达，密钥可能被切在两个分片之间。这里缓冲末尾
+/// 未结束的单词（以及紧挨着的 `Bearer` 前缀），等遇到空白或结束时再过滤输出。
+#[derive(Debug, Default)]
+pub struct StreamRedactor {
+    redactor: Redactor,
+    pending: String,
+    count: usize,
+}
+
+impl StreamRedactor {
+    pub fn new(redactor: Redactor) -> Self {
+        Self {
+            redactor,
+            pending: String::new(),
+            count: 0,
+        }
+    }
+
+    /// 追加一个分片，返回可以安全输出的部分（可能为空）
+    pub fn push(&mut self, chunk: &str) -> String {
+        self.pending.push_str(chunk);
+
+        let cut = if self.pending.len() > MAX_PENDING {
+            self.pending.len()
+        } else {
+            safe_cut(&self.pending)
+        };
+        if cut == 0 {
+            return String::new();
+        }
+
+        let rest = self.pending.split_off(cut);
+        let ready = std::mem::replace(&mut self.pending, rest);
+        self.redact(&ready)
+    }
+
+    /// 输出剩余缓冲
+    pub fn finish(&mut self) -> String {
+        let ready = std::mem::take(&mut self.pending);
+        self.redact(&ready)
+    }
+
+    /// 过滤单段完整文本（如错误信息），计入替换次数
+    pub fn redact(&mut self, text: &str) -> String {
+        let (text, n) = self.redactor.redact(text);
+        self.count += n;
+        text
+    }
+
+    /// 累计替换次数
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+/// 最后一个空白之后的内容可能是未完整的 token，不能输出
+fn safe_cut(text: &str) -> usize {
+    let Some((idx, ws)) = text.char_indices().rev().find(|(_, c)| c.is_whitespace()) else {
+        return 0;
+    };
+    let cut = idx + ws.len_utf8();
+
+    // `Bearer <token>` 中 token 还没到齐时，前缀也一起保留
+    let head = text[..cut].trim_end();
+    let word_start = head
+        .char_indices()
+        .rev()
+        .find(|(_, c)| c.is_whitespace())
+        .map(|(i, c)| i + c.len_utf8())
+        .unwrap_or(0);
+    if head[word_start..].eq_ignore_ascii_case("bearer") {
+        return word_start;
+    }
+    cut
+}
@@ -1,12 +1,23 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::budget::BudgetWarning;
+use crate::types::TokenUsage;
+
 /// SSE 事件类型
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum RunEvent {
     /// 增量消息
     MessageDelta(MessageDelta),
+    /// 增量思考过程
+    ThinkingDelta(ThinkingDelta),
+    /// token 用量
+    Usage(TokenUsage),
+    /// 用量达到预算告警比例
+    BudgetWarning(BudgetWarning),
+    /// gateway 切换了 provider，之前的 message_delta 作废
+    OutputReset(OutputReset),
     /// 工具调用开始
     ToolCallStarted(ToolCallStarted),
     /// 工具调用完成
@@ -22,6 +33,18 @@ pub struct MessageDelta {
     pub delta: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThinkingDelta {
+    pub delta: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputReset {
+    /// 失败的 provider
+    pub provider: String,
+    pub reason: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolCallStarted {
     pub tool_call_id: String,
@@ -60,6 +83,10 @@ impl RunEvent {
     pub fn event_type(&self) -> &'static str {
         match self {
             RunEvent::MessageDelta(_) => "message_delta",
+            RunEvent::ThinkingDelta(_) => "thinking_delta",
+            RunEvent::Usage(_) => "usage",
+            RunEvent::BudgetWarning(_) => "budget_warning",
+            RunEvent::OutputReset(_) => "output_reset",
             RunEvent::ToolCallStarted(_) => "tool_call_started",
             RunEvent::ToolCallFinished(_) => "tool_call_finished",
             RunEvent::RunCompleted(_) => "run_completed",
@@ -71,6 +98,13 @@ impl RunEvent {
     pub fn event_data(&self) -> serde_json::Value {
         match self {
             RunEvent::MessageDelta(d) => serde_json::json!({ "delta": d.delta }),
+            RunEvent::ThinkingDelta(d) => serde_json::json!({ "delta": d.delta }),
+            RunEvent::Usage(u) => serde_json::to_value(u).unwrap_or_default(),
+            RunEvent::BudgetWarning(w) => serde_json::to_value(w).unwrap_or_default(),
+            RunEvent::OutputReset(r) => serde_json::json!({
+                "provider": r.provider,
+                "reason": r.reason
+            }),
             RunEvent::ToolCallStarted(t) => serde_json::json!({
                 "tool_call_id": t.tool_call_id,
                 "name": t.name,
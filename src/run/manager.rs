@@ -1,22 +1,89 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
 use chrono::Utc;
-use tokio::sync::mpsc;
+use dashmap::DashMap;
+use tokio::sync::{mpsc, Notify};
 use uuid::Uuid;
 
 use super::{
-    CompletedMessage, MessageDelta, Run, RunCompleted, RunEvent, RunFailed, RunStatus, RunStore,
+    CompletedMessage, MessageDelta, OutputReset, Run, RunCompleted, RunEvent, RunFailed, RunStatus,
+    RunStore, ThinkingDelta, ToolCallFinished, ToolCallStarted,
 };
-use crate::agent::{create_agent, AgentHandle};
-use crate::types::{AgentConfig, StreamEvent};
+use crate::agent::{AgentHandle, AgentRegistry, AGENT_REGISTRY};
+use crate::budget::Budgets;
+use crate::pricing::PricingTable;
+use crate::redact::{Redactor, StreamRedactor};
+use crate::secrets::SecretStore;
+use crate::storage::{Db, UsageRecord};
+use crate::types::{AgentConfig, AgentRequest, StreamEvent};
+
+/// `wait_run` 检查 Run 状态的间隔
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(200);
 
 /// Run 管理器 - 负责创建和管理 agent 执行
 #[derive(Clone)]
 pub struct RunManager {
     store: RunStore,
+    registry: AgentRegistry,
+    secrets: Option<SecretStore>,
+    /// 用量记录（未设置时只保存在内存中的 Run 上）
+    db: Option<Db>,
+    /// 补全 agent 未上报的费用
+    pricing: Arc<PricingTable>,
+    /// 预算告警
+    budgets: Option<Budgets>,
+    /// 运行中 Run 的取消信号
+    cancels: Arc<DashMap<String, Arc<Notify>>>,
 }
 
 impl RunManager {
     pub fn new(store: RunStore) -> Self {
-        Self { store }
+        Self {
+            store,
+            registry: AGENT_REGISTRY.clone(),
+            secrets: None,
+            db: None,
+            pricing: Arc::new(PricingTable::default()),
+            budgets: None,
+            cancels: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// 使用指定的 agent 注册表（默认为全局注册表）
+    pub fn with_registry(mut self, registry: AgentRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// 启动 agent 前用密钥库解析 env 中的 `${secret:NAME}` 引用
+    pub fn with_secrets(mut self, secrets: SecretStore) -> Self {
+        self.secrets = Some(secrets);
+        self
+    }
+
+    /// Run 结束时把 token 用量写入数据库
+    pub fn with_db(mut self, db: Db) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// 使用指定的价格表计算费用
+    pub fn with_pricing(mut self, pricing: PricingTable) -> Self {
+        self.pricing = Arc::new(pricing);
+        self
+    }
+
+    /// 用量达到预算告警比例时推送 `budget_warning` 事件并调用 webhook
+    pub fn with_budgets(mut self, budgets: Budgets) -> Self {
+        self.budgets = Some(budgets);
+        self
+    }
+
+    /// 价格表
+    pub fn pricing(&self) -> &PricingTable {
+        &self.pricing
     }
 
     /// 创建新 Run
@@ -39,51 +106,215 @@ impl RunManager {
         run_id
     }
 
-    /// 启动 Run 执行
+    /// 启动 Run 执行（以 Run 的输入文本作为 prompt）
     pub async fn start_run(&self, run_id: &str, config: AgentConfig) -> anyhow::Result<()> {
         let run = self
             .store
             .get(run_id)
             .ok_or_else(|| anyhow::anyhow!("Run not found: {}", run_id))?;
+        let mut request = AgentRequest::new(run.input_text);
+        request.working_dir = config.working_dir.clone();
+        self.start_request(run_id, config, request).await
+    }
+
+    /// 以结构化请求启动 Run 执行
+    pub async fn start_request(
+        &self,
+        run_id: &str,
+        mut config: AgentConfig,
+        request: AgentRequest,
+    ) -> anyhow::Result<()> {
+        let run = self
+            .store
+            .get(run_id)
+            .ok_or_else(|| anyhow::anyhow!("Run not found: {}", run_id))?;
+        self.store.set_config(run_id, config.masked());
+
+        // 启动失败时 Run 直接结束，不会停留在 Pending
+        let prepared = match self.resolve_secrets(&run.user_id, &mut config).await {
+            Ok(envs) => self.registry.create(&config).map(|agent| (envs, agent)),
+            Err(e) => Err(e),
+        };
+        let ((original_env, resolved_env), agent) = match prepared {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                self.store.set_error(run_id, e.to_string());
+                return Err(e);
+            }
+        };
 
-        // 创建 agent
-        let agent = create_agent(&config)?;
+        // 输出中的密钥值和常见 token 在存储、转发前过滤
+        let redactor = Redactor::from_resolved_env(&original_env, &resolved_env);
+        let mut thinking_redactor = StreamRedactor::new(redactor.clone());
+        let mut redactor = StreamRedactor::new(redactor);
 
         // 创建内部 channel 接收 agent 事件
         let (agent_tx, mut agent_rx) = mpsc::channel::<StreamEvent>(100);
 
         // 启动 agent
         let handle = AgentHandle::spawn(agent, agent_tx);
+        let agent_task = handle.abort_handle();
 
         // 更新状态为运行中
         self.store.update_status(run_id, RunStatus::Running);
 
+        let cancel = Arc::new(Notify::new());
+        self.cancels.insert(run_id.to_string(), cancel.clone());
+
         let store = self.store.clone();
+        let cancels = self.cancels.clone();
+        let db = self.db.clone();
+        let pricing = self.pricing.clone();
+        let budgets = self.budgets.clone();
+        let model = config.model.clone();
+        let limit = run.limit;
         let rid = run_id.to_string();
-        let prompt = run.input_text.clone();
 
         // 启动事件转发任务
         tokio::spawn(async move {
             // 在单独的 task 中启动 agent 执行
-            let run_task = tokio::spawn(async move { handle.run(prompt).await });
+            let run_task = tokio::spawn(async move { handle.execute(request).await });
 
             // 并发处理：转发事件
             let mut output = String::new();
 
             loop {
-                match agent_rx.recv().await {
+                let event = tokio::select! {
+                    biased;
+                    _ = cancel.notified() => {
+                        agent_task.abort();
+                        flush_redactors(
+                            &store,
+                            &rid,
+                            &mut output,
+                            &mut redactor,
+                            &mut thinking_redactor,
+                        )
+                        .await;
+
+                        if let Some(tx) = store.get_event_tx(&rid) {
+                            let _ = tx
+                                .send(RunEvent::RunFailed(RunFailed {
+                                    error: "Run cancelled".to_string(),
+                                }))
+                                .await;
+                        }
+                        break;
+                    }
+                    event = agent_rx.recv() => event,
+                };
+
+                match event {
                     Some(StreamEvent::Token { content }) => {
-                        output.push_str(&content);
-                        store.append_output(&rid, &content);
+                        // 过滤后再存储和转发，未完整的 token 留到下一个分片
+                        let content = redactor.push(&content);
+                        forward_delta(&store, &rid, &mut output, content).await;
+                    }
+                    Some(StreamEvent::Thinking { content }) => {
+                        let content = thinking_redactor.push(&content);
+                        forward_thinking(&store, &rid, content).await;
+                    }
+                    Some(StreamEvent::Usage { usage }) => {
+                        // agent 可能多次上报，每次都是到目前为止的累计用量
+                        let usage = pricing.fill_cost(model.as_deref(), usage);
+                        store.set_usage(&rid, usage);
+                        if let Some(tx) = store.get_event_tx(&rid) {
+                            let _ = tx.send(RunEvent::Usage(usage)).await;
+                        }
+
+                        if let Some(ref budgets) = budgets {
+                            let warnings = budgets
+                                .check_warnings(&run.user_id, run.project_id.as_deref(), &usage)
+                                .await;
+                            if let Some(tx) = store.get_event_tx(&rid) {
+                                for warning in warnings {
+                                    let _ = tx.send(RunEvent::BudgetWarning(warning)).await;
+                                }
+                            }
+                        }
+
+                        if let Some(reason) = limit.and_then(|l| l.exceeded(&usage)) {
+                            agent_task.abort();
+                            flush_redactors(
+                                &store,
+                                &rid,
+                                &mut output,
+                                &mut redactor,
+                                &mut thinking_redactor,
+                            )
+                            .await;
 
-                        // 转发给订阅者
+                            let error = format!("budget_exceeded: {}", reason);
+                            store.set_error(&rid, error.clone());
+                            if let Some(tx) = store.get_event_tx(&rid) {
+                                let _ = tx.send(RunEvent::RunFailed(RunFailed { error })).await;
+                            }
+                            break;
+                        }
+                    }
+                    Some(StreamEvent::ToolCallStarted { id, name, input }) => {
+                        // 工具调用前的文本已经完整，先输出缓冲
+                        let tail = redactor.finish();
+                        forward_delta(&store, &rid, &mut output, tail).await;
+
+                        let redacted = redactor.redact(&input.to_string());
+                        let input = serde_json::from_str(&redacted)
+                            .unwrap_or(serde_json::Value::String(redacted));
                         if let Some(tx) = store.get_event_tx(&rid) {
                             let _ = tx
-                                .send(RunEvent::MessageDelta(MessageDelta { delta: content }))
+                                .send(RunEvent::ToolCallStarted(ToolCallStarted {
+                                    tool_call_id: id,
+                                    name,
+                                    input: Some(input),
+                                }))
                                 .await;
                         }
                     }
+                    Some(StreamEvent::ToolCallFinished {
+                        id,
+                        output: result,
+                        ok,
+                    }) => {
+                        let result = redactor.redact(&result);
+                        if let Some(tx) = store.get_event_tx(&rid) {
+                            let _ = tx
+                                .send(RunEvent::ToolCallFinished(ToolCallFinished {
+                                    tool_call_id: id,
+                                    output: Some(result),
+                                    ok,
+                                }))
+                                .await;
+                        }
+                    }
+                    Some(StreamEvent::Restart { provider, reason }) => {
+                        // 丢弃失败 provider 的输出（包括尚未转发的缓冲）
+                        let _ = redactor.finish();
+                        output.clear();
+                        store.clear_output(&rid);
+                        if let Some(tx) = store.get_event_tx(&rid) {
+                            let _ = tx
+                                .send(RunEvent::OutputReset(OutputReset {
+                                    provider,
+                                    reason: redactor.redact(&reason),
+                                }))
+                                .await;
+                        }
+                    }
+                    Some(StreamEvent::Attempt { mut attempt }) => {
+                        attempt.error = attempt.error.map(|e| redactor.redact(&e));
+                        store.add_attempt(&rid, attempt);
+                    }
+                    // Run 不提供客户端工具，停止原因也不单独上报
+                    Some(StreamEvent::ToolCallRequested { .. } | StreamEvent::Stop { .. }) => {}
                     Some(StreamEvent::Done { .. }) => {
+                        flush_redactors(
+                            &store,
+                            &rid,
+                            &mut output,
+                            &mut redactor,
+                            &mut thinking_redactor,
+                        )
+                        .await;
                         store.update_status(&rid, RunStatus::Completed);
 
                         if let Some(tx) = store.get_event_tx(&rid) {
@@ -99,7 +330,16 @@ impl RunManager {
                         }
                         break;
                     }
-                    Some(StreamEvent::Error { message }) => {
+                    Some(StreamEvent::Error { message, .. }) => {
+                        let message = redactor.redact(&message);
+                        flush_redactors(
+                            &store,
+                            &rid,
+                            &mut output,
+                            &mut redactor,
+                            &mut thinking_redactor,
+                        )
+                        .await;
                         store.set_error(&rid, message.clone());
 
                         if let Some(tx) = store.get_event_tx(&rid) {
@@ -109,10 +349,25 @@ impl RunManager {
                         }
                         break;
                     }
-                    None => break,
+                    None => {
+                        flush_redactors(
+                            &store,
+                            &rid,
+                            &mut output,
+                            &mut redactor,
+                            &mut thinking_redactor,
+                        )
+                        .await;
+                        break;
+                    }
                 }
             }
 
+            cancels.remove(&rid);
+            if let Some(ref db) = db {
+                record_usage(db, &store, &rid).await;
+            }
+
             // 等待 agent 任务完成
             let _ = run_task.await;
         });
@@ -120,6 +375,43 @@ impl RunManager {
         Ok(())
     }
 
+    /// 检查配置引用的密钥都存在，在 Run 创建之前调用
+    pub async fn check_secrets(&self, user_id: &str, config: &AgentConfig) -> anyhow::Result<()> {
+        self.resolve_secrets(user_id, &mut config.clone())
+            .await
+            .map(|_| ())
+    }
+
+    /// 把配置中的密钥引用解析为明文，返回过滤用的 (原始 env, 解析后 env)
+    ///
+    /// 密钥引用只在这里解析为明文，不写入 run 记录
+    async fn resolve_secrets(
+        &self,
+        user_id: &str,
+        config: &mut AgentConfig,
+    ) -> anyhow::Result<(HashMap<String, String>, HashMap<String, String>)> {
+        let mut original_env = config.env.clone();
+        let mut resolved_env = config.env.clone();
+        if let Some(ref secrets) = self.secrets {
+            config.env = secrets.resolve_env(user_id, &config.env).await?;
+            resolved_env = config.env.clone();
+
+            // MCP server 的 env / headers 同样支持密钥引用，一并加入过滤
+            for server in &mut config.mcp_servers {
+                for values in [&mut server.env, &mut server.headers] {
+                    let resolved = secrets.resolve_env(user_id, values).await?;
+                    for (k, v) in values.iter() {
+                        let key = format!("mcp.{}.{}", server.name, k);
+                        original_env.insert(key.clone(), v.clone());
+                        resolved_env.insert(key, resolved[k].clone());
+                    }
+                    *values = resolved;
+                }
+            }
+        }
+        Ok((original_env, resolved_env))
+    }
+
     /// 订阅 Run 事件
     pub fn subscribe(&self, run_id: &str) -> Option<mpsc::Receiver<RunEvent>> {
         let (tx, rx) = mpsc::channel::<RunEvent>(100);
@@ -132,14 +424,30 @@ impl RunManager {
         }
     }
 
-    /// 取消 Run
+    /// 取消 Run，返回 false 表示 Run 不存在或已结束
     pub fn cancel_run(&self, run_id: &str) -> bool {
-        if let Some(_run) = self.store.get(run_id) {
-            self.store.update_status(run_id, RunStatus::Cancelled);
-            // TODO: 实际终止 agent 进程
-            true
-        } else {
-            false
+        match self.store.get(run_id) {
+            Some(run) if !run.status.is_finished() => {
+                self.store.update_status(run_id, RunStatus::Cancelled);
+                if let Some((_, cancel)) = self.cancels.remove(run_id) {
+                    cancel.notify_one();
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// 等待 Run 结束，超时后返回当前状态
+    pub async fn wait_run(&self, run_id: &str, timeout: Duration) -> Option<Run> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let run = self.store.get(run_id)?;
+            let now = tokio::time::Instant::now();
+            if run.status.is_finished() || now >= deadline {
+                return Some(run);
+            }
+            tokio::time::sleep(WAIT_POLL_INTERVAL.min(deadline - now)).await;
         }
     }
 
@@ -153,3 +461,71 @@ impl RunManager {
         &self.store
     }
 }
+
+/// 持久化已结束 Run 的 token 用量，没有上报用量的 Run 不记录
+async fn record_usage(db: &Db, store: &RunStore, run_id: &str) {
+    let Some(run) = store.get(run_id) else {
+        return;
+    };
+    let Some(usage) = run.usage else {
+        return;
+    };
+    let (agent_type, model) = run
+        .config
+        .map(|c| (c.agent_type, c.model))
+        .unwrap_or_default();
+    let record = UsageRecord {
+        run_id: run.id,
+        user_id: run.user_id,
+        project_id: run.project_id,
+        agent_type,
+        model,
+        usage,
+        created_at: run.created_at,
+    };
+    if let Err(e) = db.record_usage(&record).await {
+        tracing::warn!("Failed to record usage for run {}: {}", run_id, e);
+    }
+}
+
+/// 追加输出并转发给订阅者
+async fn forward_delta(store: &RunStore, run_id: &str, output: &mut String, content: String) {
+    if content.is_empty() {
+        return;
+    }
+    output.push_str(&content);
+    store.append_output(run_id, &content);
+
+    if let Some(tx) = store.get_event_tx(run_id) {
+        let _ = tx
+            .send(RunEvent::MessageDelta(MessageDelta { delta: content }))
+            .await;
+    }
+}
+
+/// Run 结束时输出两个过滤器缓冲的尾部，并记录过滤次数
+async fn flush_redactors(
+    store: &RunStore,
+    run_id: &str,
+    output: &mut String,
+    redactor: &mut StreamRedactor,
+    thinking_redactor: &mut StreamRedactor,
+) {
+    let thinking_tail = thinking_redactor.finish();
+    forward_thinking(store, run_id, thinking_tail).await;
+    let tail = redactor.finish();
+    forward_delta(store, run_id, output, tail).await;
+    store.set_redactions(run_id, redactor.count() + thinking_redactor.count());
+}
+
+/// 转发思考过程（不写入输出）
+async fn forward_thinking(store: &RunStore, run_id: &str, content: String) {
+    if content.is_empty() {
+        return;
+    }
+    if let Some(tx) = store.get_event_tx(run_id) {
+        let _ = tx
+            .send(RunEvent::ThinkingDelta(ThinkingDelta { delta: content }))
+            .await;
+    }
+}
@@ -3,8 +3,8 @@ mod manager;
 mod store;
 
 pub use events::{
-    CompletedMessage, MessageDelta, RunCompleted, RunEvent, RunFailed, ToolCallFinished,
-    ToolCallStarted,
+    CompletedMessage, MessageDelta, OutputReset, RunCompleted, RunEvent, RunFailed, ThinkingDelta,
+    ToolCallFinished, ToolCallStarted,
 };
 pub use manager::RunManager;
-pub use store::{Run, RunStatus, RunStore};
+pub use store::{Run, RunStatus, RunStore, RunSummary};
@@ -5,6 +5,8 @@ use std::sync::Arc;
 use tokio::sync::mpsc;
 
 use super::RunEvent;
+use crate::budget::RunLimit;
+use crate::types::{AgentConfig, ProviderAttempt, TokenUsage};
 
 /// Run 状态
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -17,22 +19,83 @@ pub enum RunStatus {
     Cancelled,
 }
 
+impl RunStatus {
+    /// 已完成、失败或取消
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            RunStatus::Completed | RunStatus::Failed | RunStatus::Cancelled
+        )
+    }
+}
+
 /// Run 信息
 #[derive(Debug, Clone)]
 pub struct Run {
     pub id: String,
     pub session_id: Option<String>,
     pub user_id: String,
+    /// 所属项目（用于用量统计）
+    pub project_id: Option<String>,
     pub status: RunStatus,
     pub input_text: String,
     pub output: String,
     pub error: Option<String>,
+    /// 实际生效的 agent 配置（env 值已脱敏）
+    pub config: Option<AgentConfig>,
+    /// 输出和错误中被过滤的敏感内容次数
+    pub redactions: usize,
+    /// token 用量和费用（由 agent 上报）
+    pub usage: Option<TokenUsage>,
+    /// 预算允许的用量上限，超出后 Run 以 budget_exceeded 失败
+    pub limit: Option<RunLimit>,
+    /// 向 provider 发出的每次请求（含重试）
+    pub attempts: Vec<ProviderAttempt>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     /// 事件发送器（用于广播给订阅者）
     pub event_tx: Option<mpsc::Sender<RunEvent>>,
 }
 
+/// Run 信息（API 返回）
+#[derive(Debug, Clone, Serialize)]
+pub struct RunSummary {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    pub status: RunStatus,
+    pub output: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<AgentConfig>,
+    pub redactions: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<ProviderAttempt>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<&Run> for RunSummary {
+    fn from(run: &Run) -> Self {
+        Self {
+            id: run.id.clone(),
+            session_id: run.session_id.clone(),
+            status: run.status.clone(),
+            output: run.output.clone(),
+            error: run.error.clone(),
+            config: run.config.clone(),
+            redactions: run.redactions,
+            usage: run.usage,
+            attempts: run.attempts.clone(),
+            created_at: run.created_at,
+            updated_at: run.updated_at,
+        }
+    }
+}
+
 /// Run 存储
 #[derive(Clone)]
 pub struct RunStore {
@@ -59,10 +122,16 @@ impl RunStore {
             id: run_id.clone(),
             session_id,
             user_id,
+            project_id: None,
             status: RunStatus::Pending,
             input_text,
             output: String::new(),
             error: None,
+            config: None,
+            redactions: 0,
+            usage: None,
+            limit: None,
+            attempts: Vec::new(),
             created_at: now,
             updated_at: now,
             event_tx: None,
@@ -92,6 +161,14 @@ impl RunStore {
         }
     }
 
+    /// 清空输出（gateway 切换 provider 后重新开始）
+    pub fn clear_output(&self, run_id: &str) {
+        if let Some(mut run) = self.runs.get_mut(run_id) {
+            run.output.clear();
+            run.updated_at = Utc::now();
+        }
+    }
+
     /// 设置错误
     pub fn set_error(&self, run_id: &str, error: String) {
         if let Some(mut run) = self.runs.get_mut(run_id) {
@@ -101,6 +178,47 @@ impl RunStore {
         }
     }
 
+    /// 记录生效的 agent 配置
+    pub fn set_config(&self, run_id: &str, config: AgentConfig) {
+        if let Some(mut run) = self.runs.get_mut(run_id) {
+            run.config = Some(config);
+        }
+    }
+
+    /// 记录过滤的敏感内容次数
+    pub fn set_redactions(&self, run_id: &str, count: usize) {
+        if let Some(mut run) = self.runs.get_mut(run_id) {
+            run.redactions = count;
+        }
+    }
+
+    /// 记录所属项目
+    pub fn set_project(&self, run_id: &str, project_id: String) {
+        if let Some(mut run) = self.runs.get_mut(run_id) {
+            run.project_id = Some(project_id);
+        }
+    }
+
+    /// 设置预算上限（需在启动前调用）
+    pub fn set_limit(&self, run_id: &str, limit: RunLimit) {
+        if let Some(mut run) = self.runs.get_mut(run_id) {
+            run.limit = Some(limit);
+        }
+    }
+
+    /// 记录一次 provider 请求
+    pub fn add_attempt(&self, run_id: &str, attempt: ProviderAttempt) {
+        if let Some(mut run) = self.runs.get_mut(run_id) {
+            run.attempts.push(attempt);
+        }
+    }
+
+    pub fn set_usage(&self, run_id: &str, usage: TokenUsage) {
+        if let Some(mut run) = self.runs.get_mut(run_id) {
+            run.usage = Some(usage);
+        }
+    }
+
     /// 设置事件发送器
     pub fn set_event_tx(&self, run_id: &str, tx: mpsc::Sender<RunEvent>) {
         if let Some(mut run) = self.runs.get_mut(run_id) {
@@ -133,12 +251,7 @@ impl RunStore {
         let to_remove: Vec<String> = self
             .runs
             .iter()
-            .filter(|r| {
-                matches!(
-                    r.status,
-                    RunStatus::Completed | RunStatus::Failed | RunStatus::Cancelled
-                ) && (now - r.updated_at).num_seconds() > max_age_secs
-            })
+            .filter(|r| r.status.is_finished() && (now - r.updated_at).num_seconds() > max_age_secs)
             .map(|r| r.id.clone())
             .collect();
 
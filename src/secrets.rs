@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::Serialize;
+
+use crate::storage::Db;
+
+/// env 值中的密钥引用前缀，完整格式为 `${secret:NAME}`
+const REF_PREFIX: &str = "${secret:";
+
+/// 看起来保存凭据的 env key 片段（按 `_` 分割后匹配）
+const SENSITIVE_KEY_PARTS: &[&str] = &[
+    "KEY",
+    "APIKEY",
+    "TOKEN",
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "CREDENTIALS",
+    "AUTHORIZATION",
+];
+
+/// env 引用的密钥不存在
+#[derive(Debug, thiserror::Error)]
+#[error("Secret '{0}' not found")]
+pub struct SecretNotFound(pub String);
+
+/// 使用服务端 master key 的 AES-256-GCM 加解密
+pub struct SecretCipher {
+    cipher: Aes256Gcm,
+}
+
+impl SecretCipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(key.into()),
+        }
+    }
+
+    /// 加载 master key
+    ///
+    /// 优先使用 `OPENRUNNER_MASTER_KEY`（base64 编码的 32 字节），
+    /// 否则读取 `key_path`，文件不存在时生成新 key 并以 0600 权限写入。
+    pub fn load(key_path: &str) -> Result<Self> {
+        let encoded = match std::env::var("OPENRUNNER_MASTER_KEY") {
+            Ok(v) => v,
+            Err(_) => load_or_create_key_file(key_path)?,
+        };
+        let bytes = BASE64.decode(encoded.trim())?;
+        let key: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Master key must be 32 bytes"))?;
+        Ok(Self::new(&key))
+    }
+
+    /// 加密，返回 (nonce, ciphertext)
+    pub fn encrypt(&self, plaintext: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt secret"))?;
+        Ok((nonce.to_vec(), ciphertext))
+    }
+
+    pub fn decrypt(&self, nonce: &[u8], ciphertext: &[u8]) -> Result<String> {
+        if nonce.len() != 12 {
+            anyhow::bail!("Invalid secret nonce");
+        }
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt secret (wrong master key?)"))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+}
+
+fn load_or_create_key_file(path: &str) -> Result<String> {
+    if let Ok(existing) = std::fs::read_to_string(path) {
+        return Ok(existing);
+    }
+    if let Some(parent) = Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let encoded = BASE64.encode(Aes256Gcm::generate_key(OsRng));
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    std::io::Write::write_all(&mut options.open(path)?, encoded.as_bytes())?;
+    tracing::warn!(
+        "Generated new secret master key at {} (set OPENRUNNER_MASTER_KEY in production)",
+        path
+    );
+    Ok(encoded)
+}
+
+/// 密钥信息（API 只返回名称和末 4 位）
+#[derive(Debug, Clone, Serialize)]
+pub struct SecretInfo {
+    pub name: String,
+    pub last4: String,
+    pub updated_at: String,
+}
+
+/// 按用户隔离的加密密钥库
+#[derive(Clone)]
+pub struct SecretStore {
+    db: Db,
+    cipher: Arc<SecretCipher>,
+}
+
+impl SecretStore {
+    pub fn new(db: Db, cipher: SecretCipher) -> Self {
+        Self {
+            db,
+            cipher: Arc::new(cipher),
+        }
+    }
+
+    pub async fn set(&self, user_id: &str, name: &str, value: &str) -> Result<SecretInfo> {
+        if !is_valid_name(name) {
+            anyhow::bail!("Invalid secret name: {}", name);
+        }
+        let (nonce, ciphertext) = self.cipher.encrypt(value)?;
+        let last4 = last4(value);
+        self.db
+            .put_secret(user_id, name, &nonce, &ciphertext, &last4)
+            .await?;
+        Ok(SecretInfo {
+            name: name.to_string(),
+            last4,
+            updated_at: chrono::Utc::now().to_rfc3339(),
+        })
+    }
+
+    pub async fn get(&self, user_id: &str, name: &str) -> Result<Option<String>> {
+        match self.db.get_secret(user_id, name).await? {
+            Some(row) => Ok(Some(self.cipher.decrypt(&row.nonce, &row.ciphertext)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn list(&self, user_id: &str) -> Result<Vec<SecretInfo>> {
+        Ok(self
+            .db
+            .list_secrets(user_id)
+            .await?
+            .into_iter()
+            .map(|r| SecretInfo {
+                name: r.name,
+                last4: r.last4,
+                updated_at: r.updated_at,
+            })
+            .collect())
+    }
+
+    pub async fn delete(&self, user_id: &str, name: &str) -> Result<bool> {
+        self.db.delete_secret(user_id, name).await
+    }
+
+    /// 把 env 中的 `${secret:NAME}` 引用替换为明文（只在启动 agent 时调用）
+    pub async fn resolve_env(
+        &self,
+        user_id: &str,
+        env: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>> {
+        let mut cache: HashMap<String, String> = HashMap::new();
+        let mut resolved = HashMap::new();
+        for (key, value) in env {
+            for name in references(value) {
+                if cache.contains_key(&name) {
+                    continue;
+                }
+                let secret = self
+                    .get(user_id, &name)
+                    .await?
+                    .ok_or_else(|| SecretNotFound(name.clone()))?;
+                cache.insert(name, secret);
+            }
+            let mut value = value.clone();
+            for (name, secret) in &cache {
+                value = value.replace(&secret_ref(name), secret);
+            }
+            resolved.insert(key.clone(), value);
+        }
+        Ok(resolved)
+    }
+
+    /// 把敏感 key 的明文值存入密钥库，返回改为引用后的 env
+    ///
+    /// 密钥名为 `<scope>.<KEY>`，scope 标识保存这份 env 的对象（见 `secret_scope`），
+    /// 如 `session.<id>.ANTHROPIC_API_KEY`，不同对象的同名 key 互不覆盖。
+    pub async fn externalize_env(
+        &self,
+        user_id: &str,
+        scope: &str,
+        env: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>> {
+        let mut result = HashMap::new();
+        for (key, value) in env {
+            if is_sensitive_key(key) && !value.is_empty() && references(value).is_empty() {
+                let name = format!("{}.{}", scope, key);
+                self.set(user_id, &name, value).await?;
+                result.insert(key.clone(), secret_ref(&name));
+            } else {
+                result.insert(key.clone(), value.clone());
+            }
+        }
+        Ok(result)
+    }
+
+    /// 把旧版本保存在 session 和 agent 默认配置中的明文凭据移入密钥库（启动时调用），
+    /// 返回迁移的记录数
+    pub async fn migrate_saved_env(&self) -> Result<usize> {
+        let mut migrated = 0;
+        for user_id in self.db.list_env_owners().await? {
+            for session in self.db.list_sessions(&user_id).await? {
+                let scope = secret_scope("session", &session.id);
+                let env = self.externalize_env(&user_id, &scope, &session.env).await?;
+                if env != session.env {
+                    self.db
+                        .upsert_session(
+                            &user_id,
+                            &session.id,
+                            None,
+                            None,
+                            None,
+                            Some(env),
+                            None,
+                            None,
+                            None,
+                            None,
+                        )
+                        .await?;
+                    migrated += 1;
+                }
+            }
+            for mut default in self.db.get_agent_defaults(&user_id).await? {
+                let scope = secret_scope("defaults", &default.agent_type);
+                let env = self.externalize_env(&user_id, &scope, &default.env).await?;
+                if env != default.env {
+                    default.env = env;
+                    self.db.set_agent_default(&user_id, &default).await?;
+                    migrated += 1;
+                }
+            }
+        }
+        Ok(migrated)
+    }
+}
+
+/// 对象（如 `session`、`defaults`、`project`）的密钥名前缀 `<kind>.<id>`，
+/// id 中密钥名不允许的字符替换为 `_`
+pub fn secret_scope(kind: &str, id: &str) -> String {
+    let id: String = id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.{}", kind, id)
+}
+
+/// 密钥名只允许字母、数字和 `_` `.` `-`
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 128
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// 生成 `${secret:NAME}` 引用
+pub fn secret_ref(name: &str) -> String {
+    format!("{}{}}}", REF_PREFIX, name)
+}
+
+/// 值是否是单个密钥引用
+pub fn is_secret_ref(value: &str) -> bool {
+    let refs = references(value);
+    refs.len() == 1 && value == secret_ref(&refs[0])
+}
+
+/// env key 是否看起来保存凭据（如 `OPENAI_API_KEY`、`GITHUB_TOKEN`）
+pub fn is_sensitive_key(key: &str) -> bool {
+    key.to_ascii_uppercase()
+        .split(['_', '-'])
+        .any(|part| SENSITIVE_KEY_PARTS.contains(&part))
+}
+
+/// 提取值中引用的密钥名
+pub fn references(value: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find(REF_PREFIX) {
+        let after = &rest[start + REF_PREFIX.len()..];
+        match after.find('}') {
+            Some(end) => {
+                names.push(after[..end].to_string());
+                rest = &after[end + 1..];
+            }
+            None => break,
+        }
+    }
+    names
+}
+
+/// 末 4 位（太短的值不展示）
+fn last4(value: &str) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    if chars.len() < 12 {
+        return String::new();
+    }
+    chars[chars.len() - 4..].iter().collect()
+}
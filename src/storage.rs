@@ -2,13 +2,16 @@ use std::collections::HashMap;
 use std::path::Path;
 
 use anyhow::Result;
-use chrono::Utc;
+use chrono::{DateTime, SecondsFormat, Utc};
 use sqlx::{
     sqlite::{SqliteConnectOptions, SqlitePoolOptions},
     SqlitePool,
 };
 
-use crate::types::{Attachment, SessionData, SessionMessage};
+use crate::budget::{Budget, BudgetPeriod, BudgetScope};
+use crate::types::{
+    Attachment, McpServerConfig, SamplingParams, SessionData, SessionMessage, TokenUsage,
+};
 
 #[derive(Debug, sqlx::FromRow)]
 struct SessionRow {
@@ -30,6 +33,7 @@ struct ProjectRow {
     id: String,
     name: String,
     path: String,
+    mcp_servers_json: Option<String>,
     created_at: String,
     updated_at: String,
 }
@@ -136,6 +140,8 @@ impl Db {
                 model TEXT,
                 env_json TEXT,
                 extra_args_json TEXT,
+                sampling_json TEXT,
+                mcp_servers_json TEXT,
                 updated_at TEXT NOT NULL,
                 PRIMARY KEY (user_id, agent_type)
             );
@@ -152,6 +158,7 @@ impl Db {
                 user_id TEXT NOT NULL,
                 name TEXT NOT NULL,
                 path TEXT NOT NULL,
+                mcp_servers_json TEXT,
                 created_at TEXT NOT NULL,
                 updated_at TEXT NOT NULL,
                 UNIQUE(user_id, name)
@@ -161,6 +168,130 @@ impl Db {
         .execute(&self.pool)
         .await?;
 
+        // Secrets table - encrypted per-user credentials referenced as ${secret:NAME}
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS secrets (
+                user_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                nonce BLOB NOT NULL,
+                ciphertext BLOB NOT NULL,
+                last4 TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                PRIMARY KEY (user_id, name)
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // API keys table - only the SHA-256 of each key is stored
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS api_keys (
+                id TEXT PRIMARY KEY,
+                user_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                key_hash TEXT NOT NULL UNIQUE,
+                prefix TEXT NOT NULL,
+                roles_json TEXT,
+                created_at TEXT NOT NULL,
+                last_used_at TEXT
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Token usage of finished runs, one row per run
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS run_usage (
+                run_id TEXT PRIMARY KEY,
+                user_id TEXT NOT NULL,
+                project_id TEXT,
+                agent_type TEXT NOT NULL,
+                model TEXT,
+                input_tokens INTEGER NOT NULL,
+                output_tokens INTEGER NOT NULL,
+                cache_creation_input_tokens INTEGER NOT NULL,
+                cache_read_input_tokens INTEGER NOT NULL,
+                cost_usd REAL,
+                created_at TEXT NOT NULL
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_run_usage_user_created ON run_usage(user_id, created_at)",
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Spending caps, at most one per scope and period
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS budgets (
+                id TEXT PRIMARY KEY,
+                scope TEXT NOT NULL,
+                scope_id TEXT NOT NULL,
+                period TEXT NOT NULL,
+                max_tokens INTEGER,
+                max_cost_usd REAL,
+                max_run_tokens INTEGER,
+                max_run_cost_usd REAL,
+                warn_ratio REAL,
+                webhook_url TEXT,
+                warned_period TEXT,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                UNIQUE(scope, scope_id, period)
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Gateway providers registered by users; API keys are kept in the secrets table
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS gateway_providers (
+                user_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                config_json TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                PRIMARY KEY (user_id, name)
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        // Responses of deterministic gateway requests, see `ResponseCache`
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS response_cache (
+                key TEXT PRIMARY KEY,
+                response_json TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                created_at TEXT NOT NULL,
+                expires_at TEXT NOT NULL
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_response_cache_created ON response_cache(created_at)",
+        )
+        .execute(&self.pool)
+        .await?;
+
         // Add project_id to sessions if not exists
         // PRAGMA table_info returns: cid, name, type, notnull, dflt_value, pk
         let cols: Vec<(i32, String, String, i32, Option<String>, i32)> =
@@ -175,6 +306,39 @@ impl Db {
                 .ok();
         }
 
+        // Add sampling_json to agent_defaults if not exists
+        let cols: Vec<(i32, String, String, i32, Option<String>, i32)> =
+            sqlx::query_as("PRAGMA table_info(agent_defaults)")
+                .fetch_all(&self.pool)
+                .await?;
+        let has_sampling = cols.iter().any(|(_, name, _, _, _, _)| name == "sampling_json");
+        if !has_sampling {
+            sqlx::query("ALTER TABLE agent_defaults ADD COLUMN sampling_json TEXT")
+                .execute(&self.pool)
+                .await
+                .ok();
+        }
+
+        // Add mcp_servers_json to agent_defaults and projects if not exists
+        for table in ["agent_defaults", "projects"] {
+            let cols: Vec<(i32, String, String, i32, Option<String>, i32)> =
+                sqlx::query_as(&format!("PRAGMA table_info({})", table))
+                    .fetch_all(&self.pool)
+                    .await?;
+            let has_mcp = cols
+                .iter()
+                .any(|(_, name, _, _, _, _)| name == "mcp_servers_json");
+            if !has_mcp {
+                sqlx::query(&format!(
+                    "ALTER TABLE {} ADD COLUMN mcp_servers_json TEXT",
+                    table
+                ))
+                .execute(&self.pool)
+                .await
+                .ok();
+            }
+        }
+
         Ok(())
     }
 
@@ -400,6 +564,13 @@ fn parse_attachments(value: &Option<String>) -> Option<Vec<Attachment>> {
     value.as_ref().and_then(|v| serde_json::from_str(v).ok())
 }
 
+fn parse_mcp_servers(value: &Option<String>) -> Vec<McpServerConfig> {
+    value
+        .as_ref()
+        .and_then(|v| serde_json::from_str(v).ok())
+        .unwrap_or_default()
+}
+
 // ============ Agent Defaults ============
 
 #[derive(Debug, sqlx::FromRow)]
@@ -408,6 +579,8 @@ struct AgentDefaultRow {
     model: Option<String>,
     env_json: Option<String>,
     extra_args_json: Option<String>,
+    sampling_json: Option<String>,
+    mcp_servers_json: Option<String>,
 }
 
 /// Agent default configuration
@@ -420,13 +593,17 @@ pub struct AgentDefault {
     pub env: HashMap<String, String>,
     #[serde(default)]
     pub extra_args: Vec<String>,
+    #[serde(default)]
+    pub sampling: SamplingParams,
+    #[serde(default)]
+    pub mcp_servers: Vec<McpServerConfig>,
 }
 
 impl Db {
     pub async fn get_agent_defaults(&self, user_id: &str) -> Result<Vec<AgentDefault>> {
         let rows = sqlx::query_as::<_, AgentDefaultRow>(
             r#"
-            SELECT agent_type, model, env_json, extra_args_json
+            SELECT agent_type, model, env_json, extra_args_json, sampling_json, mcp_servers_json
             FROM agent_defaults
             WHERE user_id = ?
             "#,
@@ -442,39 +619,53 @@ impl Db {
                 model: r.model,
                 env: parse_env(&r.env_json).unwrap_or_default(),
                 extra_args: parse_args(&r.extra_args_json).unwrap_or_default(),
+                sampling: r
+                    .sampling_json
+                    .as_ref()
+                    .and_then(|v| serde_json::from_str(v).ok())
+                    .unwrap_or_default(),
+                mcp_servers: parse_mcp_servers(&r.mcp_servers_json),
             });
         }
         Ok(result)
     }
 
-    pub async fn set_agent_default(
-        &self,
-        user_id: &str,
-        agent_type: &str,
-        model: Option<String>,
-        env: HashMap<String, String>,
-        extra_args: Vec<String>,
-    ) -> Result<()> {
+    /// Users with saved sessions or agent defaults
+    pub async fn list_env_owners(&self) -> Result<Vec<String>> {
+        let rows: Vec<(String,)> =
+            sqlx::query_as("SELECT user_id FROM sessions UNION SELECT user_id FROM agent_defaults")
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(rows.into_iter().map(|(user_id,)| user_id).collect())
+    }
+
+    pub async fn set_agent_default(&self, user_id: &str, default: &AgentDefault) -> Result<()> {
         let now = chrono::Utc::now().to_rfc3339();
-        let env_json = serde_json::to_string(&env)?;
-        let extra_args_json = serde_json::to_string(&extra_args)?;
+        let env_json = serde_json::to_string(&default.env)?;
+        let extra_args_json = serde_json::to_string(&default.extra_args)?;
+        let sampling_json = serde_json::to_string(&default.sampling)?;
+        let mcp_servers_json = serde_json::to_string(&default.mcp_servers)?;
 
         sqlx::query(
             r#"
-            INSERT INTO agent_defaults (user_id, agent_type, model, env_json, extra_args_json, updated_at)
-            VALUES (?, ?, ?, ?, ?, ?)
+            INSERT INTO agent_defaults (user_id, agent_type, model, env_json, extra_args_json, sampling_json, mcp_servers_json, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
             ON CONFLICT(user_id, agent_type) DO UPDATE SET
                 model = excluded.model,
                 env_json = excluded.env_json,
                 extra_args_json = excluded.extra_args_json,
+                sampling_json = excluded.sampling_json,
+                mcp_servers_json = excluded.mcp_servers_json,
                 updated_at = excluded.updated_at
             "#,
         )
         .bind(user_id)
-        .bind(agent_type)
-        .bind(model)
+        .bind(&default.agent_type)
+        .bind(&default.model)
         .bind(env_json)
         .bind(extra_args_json)
+        .bind(sampling_json)
+        .bind(mcp_servers_json)
         .bind(now)
         .execute(&self.pool)
         .await?;
@@ -486,7 +677,7 @@ impl Db {
     pub async fn list_projects(&self, user_id: &str) -> Result<Vec<crate::types::Project>> {
         let rows = sqlx::query_as::<_, ProjectRow>(
             r#"
-            SELECT id, name, path, created_at, updated_at
+            SELECT id, name, path, mcp_servers_json, created_at, updated_at
             FROM projects
             WHERE user_id = ?
             ORDER BY name ASC
@@ -502,6 +693,7 @@ impl Db {
                 id: r.id,
                 name: r.name,
                 path: r.path,
+                mcp_servers: parse_mcp_servers(&r.mcp_servers_json),
                 created_at: r.created_at,
                 updated_at: r.updated_at,
             })
@@ -511,7 +703,7 @@ impl Db {
     pub async fn get_project(&self, user_id: &str, project_id: &str) -> Result<Option<crate::types::Project>> {
         let row = sqlx::query_as::<_, ProjectRow>(
             r#"
-            SELECT id, name, path, created_at, updated_at
+            SELECT id, name, path, mcp_servers_json, created_at, updated_at
             FROM projects
             WHERE id = ? AND user_id = ?
             "#,
@@ -525,23 +717,32 @@ impl Db {
             id: r.id,
             name: r.name,
             path: r.path,
+            mcp_servers: parse_mcp_servers(&r.mcp_servers_json),
             created_at: r.created_at,
             updated_at: r.updated_at,
         }))
     }
 
-    pub async fn create_project(&self, user_id: &str, id: &str, name: &str, path: &str) -> Result<()> {
+    pub async fn create_project(
+        &self,
+        user_id: &str,
+        id: &str,
+        name: &str,
+        path: &str,
+        mcp_servers: &[McpServerConfig],
+    ) -> Result<()> {
         let now = Utc::now().to_rfc3339();
         sqlx::query(
             r#"
-            INSERT INTO projects (id, user_id, name, path, created_at, updated_at)
-            VALUES (?, ?, ?, ?, ?, ?)
+            INSERT INTO projects (id, user_id, name, path, mcp_servers_json, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(id)
         .bind(user_id)
         .bind(name)
         .bind(path)
+        .bind(serde_json::to_string(mcp_servers)?)
         .bind(&now)
         .bind(&now)
         .execute(&self.pool)
@@ -550,6 +751,25 @@ impl Db {
         Ok(())
     }
 
+    /// 更新项目的 MCP server，项目不存在时返回 false
+    pub async fn set_project_mcp_servers(
+        &self,
+        user_id: &str,
+        project_id: &str,
+        mcp_servers: &[McpServerConfig],
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE projects SET mcp_servers_json = ?, updated_at = ? WHERE id = ? AND user_id = ?",
+        )
+        .bind(serde_json::to_string(mcp_servers)?)
+        .bind(Utc::now().to_rfc3339())
+        .bind(project_id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn delete_project(&self, user_id: &str, project_id: &str) -> Result<()> {
         sqlx::query("DELETE FROM projects WHERE id = ? AND user_id = ?")
             .bind(project_id)
@@ -559,3 +779,629 @@ impl Db {
         Ok(())
     }
 }
+
+// ============ Secrets ============
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct SecretRow {
+    pub name: String,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub last4: String,
+    pub updated_at: String,
+}
+
+impl Db {
+    pub async fn put_secret(
+        &self,
+        user_id: &str,
+        name: &str,
+        nonce: &[u8],
+        ciphertext: &[u8],
+        last4: &str,
+    ) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+        sqlx::query(
+            r#"
+            INSERT INTO secrets (user_id, name, nonce, ciphertext, last4, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(user_id, name) DO UPDATE SET
+                nonce = excluded.nonce,
+                ciphertext = excluded.ciphertext,
+                last4 = excluded.last4,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(name)
+        .bind(nonce)
+        .bind(ciphertext)
+        .bind(last4)
+        .bind(&now)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_secret(&self, user_id: &str, name: &str) -> Result<Option<SecretRow>> {
+        let row = sqlx::query_as::<_, SecretRow>(
+            r#"
+            SELECT name, nonce, ciphertext, last4, updated_at
+            FROM secrets
+            WHERE user_id = ? AND name = ?
+            "#,
+        )
+        .bind(user_id)
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    pub async fn list_secrets(&self, user_id: &str) -> Result<Vec<SecretRow>> {
+        let rows = sqlx::query_as::<_, SecretRow>(
+            r#"
+            SELECT name, nonce, ciphertext, last4, updated_at
+            FROM secrets
+            WHERE user_id = ?
+            ORDER BY name ASC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    pub async fn delete_secret(&self, user_id: &str, name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM secrets WHERE user_id = ? AND name = ?")
+            .bind(user_id)
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+// ============ API Keys ============
+
+/// API key metadata (the key itself is never stored)
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub name: String,
+    /// Leading characters of the key, for recognition
+    pub prefix: String,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
+
+/// Caller identified by an API key
+#[derive(Debug, Clone)]
+pub struct ApiKeyOwner {
+    pub user_id: String,
+    pub roles: Vec<String>,
+}
+
+impl Db {
+    pub async fn create_api_key(
+        &self,
+        user_id: &str,
+        name: &str,
+        key_hash: &str,
+        prefix: &str,
+        roles: &[String],
+    ) -> Result<ApiKeyInfo> {
+        let info = ApiKeyInfo {
+            id: format!("key_{}", uuid::Uuid::new_v4().simple()),
+            name: name.to_string(),
+            prefix: prefix.to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            last_used_at: None,
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO api_keys (id, user_id, name, key_hash, prefix, roles_json, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&info.id)
+        .bind(user_id)
+        .bind(name)
+        .bind(key_hash)
+        .bind(prefix)
+        .bind(serde_json::to_string(roles)?)
+        .bind(&info.created_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(info)
+    }
+
+    pub async fn list_api_keys(&self, user_id: &str) -> Result<Vec<ApiKeyInfo>> {
+        let rows = sqlx::query_as::<_, ApiKeyInfo>(
+            r#"
+            SELECT id, name, prefix, created_at, last_used_at
+            FROM api_keys
+            WHERE user_id = ?
+            ORDER BY created_at ASC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    pub async fn delete_api_key(&self, user_id: &str, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM api_keys WHERE id = ? AND user_id = ?")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Look up the caller by key hash and record the usage time
+    pub async fn find_api_key_owner(&self, key_hash: &str) -> Result<Option<ApiKeyOwner>> {
+        let row: Option<(String, Option<String>)> =
+            sqlx::query_as("SELECT user_id, roles_json FROM api_keys WHERE key_hash = ?")
+                .bind(key_hash)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some((user_id, roles_json)) = row else {
+            return Ok(None);
+        };
+
+        sqlx::query("UPDATE api_keys SET last_used_at = ? WHERE key_hash = ?")
+            .bind(Utc::now().to_rfc3339())
+            .bind(key_hash)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(Some(ApiKeyOwner {
+            user_id,
+            roles: roles_json
+                .as_deref()
+                .and_then(|v| serde_json::from_str(v).ok())
+                .unwrap_or_default(),
+        }))
+    }
+}
+
+// ============ Usage ============
+
+/// Token usage of one finished run
+#[derive(Debug, Clone)]
+pub struct UsageRecord {
+    pub run_id: String,
+    pub user_id: String,
+    pub project_id: Option<String>,
+    pub agent_type: String,
+    pub model: Option<String>,
+    pub usage: TokenUsage,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Dimension to aggregate usage by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageGroupBy {
+    #[default]
+    User,
+    Project,
+    Agent,
+    Model,
+}
+
+impl UsageGroupBy {
+    fn column(self) -> &'static str {
+        match self {
+            UsageGroupBy::User => "user_id",
+            UsageGroupBy::Project => "project_id",
+            UsageGroupBy::Agent => "agent_type",
+            UsageGroupBy::Model => "model",
+        }
+    }
+}
+
+/// Aggregated usage of one group
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct UsageSummary {
+    /// Group value; null for runs without a project or model
+    pub key: Option<String>,
+    pub runs: i64,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub cache_creation_input_tokens: i64,
+    pub cache_read_input_tokens: i64,
+    /// Null when none of the runs had a known cost
+    pub cost_usd: Option<f64>,
+}
+
+/// Timestamps are stored in one fixed format so that text comparison orders them
+fn usage_timestamp(t: &DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+impl Db {
+    pub async fn record_usage(&self, record: &UsageRecord) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO run_usage (
+                run_id, user_id, project_id, agent_type, model,
+                input_tokens, output_tokens, cache_creation_input_tokens, cache_read_input_tokens,
+                cost_usd, created_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&record.run_id)
+        .bind(&record.user_id)
+        .bind(&record.project_id)
+        .bind(&record.agent_type)
+        .bind(&record.model)
+        .bind(record.usage.input_tokens as i64)
+        .bind(record.usage.output_tokens as i64)
+        .bind(record.usage.cache_creation_input_tokens as i64)
+        .bind(record.usage.cache_read_input_tokens as i64)
+        .bind(record.usage.cost_usd)
+        .bind(usage_timestamp(&record.created_at))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Sum usage per group, optionally for one user and within `[from, to)`
+    pub async fn usage_summary(
+        &self,
+        user_id: Option<&str>,
+        group_by: UsageGroupBy,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<UsageSummary>> {
+        let sql = format!(
+            r#"
+            SELECT {column} AS key,
+                   COUNT(*) AS runs,
+                   SUM(input_tokens) AS input_tokens,
+                   SUM(output_tokens) AS output_tokens,
+                   SUM(cache_creation_input_tokens) AS cache_creation_input_tokens,
+                   SUM(cache_read_input_tokens) AS cache_read_input_tokens,
+                   SUM(cost_usd) AS cost_usd
+            FROM run_usage
+            WHERE (?1 IS NULL OR user_id = ?1)
+              AND (?2 IS NULL OR created_at >= ?2)
+              AND (?3 IS NULL OR created_at < ?3)
+            GROUP BY {column}
+            ORDER BY cost_usd DESC, input_tokens + output_tokens DESC
+            "#,
+            column = group_by.column()
+        );
+        let rows = sqlx::query_as::<_, UsageSummary>(&sql)
+            .bind(user_id)
+            .bind(from.as_ref().map(usage_timestamp))
+            .bind(to.as_ref().map(usage_timestamp))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+}
+
+// ============ Budgets ============
+
+#[derive(Debug, sqlx::FromRow)]
+struct BudgetRow {
+    id: String,
+    scope: String,
+    scope_id: String,
+    period: String,
+    max_tokens: Option<i64>,
+    max_cost_usd: Option<f64>,
+    max_run_tokens: Option<i64>,
+    max_run_cost_usd: Option<f64>,
+    warn_ratio: Option<f64>,
+    webhook_url: Option<String>,
+}
+
+impl BudgetRow {
+    fn into_budget(self) -> Option<Budget> {
+        Some(Budget {
+            scope: BudgetScope::parse(&self.scope)?,
+            period: BudgetPeriod::parse(&self.period)?,
+            id: self.id,
+            scope_id: self.scope_id,
+            max_tokens: self.max_tokens.map(|v| v as u64),
+            max_cost_usd: self.max_cost_usd,
+            max_run_tokens: self.max_run_tokens.map(|v| v as u64),
+            max_run_cost_usd: self.max_run_cost_usd,
+            warn_ratio: self.warn_ratio,
+            webhook_url: self.webhook_url,
+        })
+    }
+}
+
+const BUDGET_COLUMNS: &str = "id, scope, scope_id, period, max_tokens, max_cost_usd, \
+     max_run_tokens, max_run_cost_usd, warn_ratio, webhook_url";
+
+impl Db {
+    pub async fn list_budgets(&self) -> Result<Vec<Budget>> {
+        let rows = sqlx::query_as::<_, BudgetRow>(&format!(
+            "SELECT {} FROM budgets ORDER BY scope, scope_id, period",
+            BUDGET_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(BudgetRow::into_budget)
+            .collect())
+    }
+
+    /// Budgets that apply to a user and, if given, a project
+    pub async fn budgets_for(
+        &self,
+        user_id: &str,
+        project_id: Option<&str>,
+    ) -> Result<Vec<Budget>> {
+        let rows = sqlx::query_as::<_, BudgetRow>(&format!(
+            r#"
+            SELECT {} FROM budgets
+            WHERE (scope = 'user' AND scope_id = ?) OR (scope = 'project' AND scope_id = ?)
+            ORDER BY scope, period
+            "#,
+            BUDGET_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(project_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(BudgetRow::into_budget)
+            .collect())
+    }
+
+    /// Create or replace the budget for the same scope and period
+    pub async fn upsert_budget(&self, budget: &Budget) -> Result<Budget> {
+        let now = Utc::now().to_rfc3339();
+        let id = format!("budget_{}", uuid::Uuid::new_v4().simple());
+        sqlx::query(
+            r#"
+            INSERT INTO budgets (
+                id, scope, scope_id, period, max_tokens, max_cost_usd,
+                max_run_tokens, max_run_cost_usd, warn_ratio, webhook_url, created_at, updated_at
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(scope, scope_id, period) DO UPDATE SET
+                max_tokens = excluded.max_tokens,
+                max_cost_usd = excluded.max_cost_usd,
+                max_run_tokens = excluded.max_run_tokens,
+                max_run_cost_usd = excluded.max_run_cost_usd,
+                warn_ratio = excluded.warn_ratio,
+                webhook_url = excluded.webhook_url,
+                warned_period = NULL,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(&id)
+        .bind(budget.scope.as_str())
+        .bind(&budget.scope_id)
+        .bind(budget.period.as_str())
+        .bind(budget.max_tokens.map(|v| v as i64))
+        .bind(budget.max_cost_usd)
+        .bind(budget.max_run_tokens.map(|v| v as i64))
+        .bind(budget.max_run_cost_usd)
+        .bind(budget.warn_ratio)
+        .bind(&budget.webhook_url)
+        .bind(&now)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+
+        let row = sqlx::query_as::<_, BudgetRow>(&format!(
+            "SELECT {} FROM budgets WHERE scope = ? AND scope_id = ? AND period = ?",
+            BUDGET_COLUMNS
+        ))
+        .bind(budget.scope.as_str())
+        .bind(&budget.scope_id)
+        .bind(budget.period.as_str())
+        .fetch_one(&self.pool)
+        .await?;
+        row.into_budget()
+            .ok_or_else(|| anyhow::anyhow!("Invalid budget row"))
+    }
+
+    pub async fn delete_budget(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM budgets WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Record that a warning was sent for `period_key`; false if it already was
+    pub async fn mark_budget_warned(&self, id: &str, period_key: &str) -> Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE budgets SET warned_period = ?
+            WHERE id = ? AND (warned_period IS NULL OR warned_period != ?)
+            "#,
+        )
+        .bind(period_key)
+        .bind(id)
+        .bind(period_key)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Total tokens and cost recorded for a user or project since `since`
+    pub async fn usage_total(
+        &self,
+        scope: BudgetScope,
+        scope_id: &str,
+        since: DateTime<Utc>,
+    ) -> Result<(u64, f64)> {
+        let column = match scope {
+            BudgetScope::User => "user_id",
+            BudgetScope::Project => "project_id",
+        };
+        let (tokens, cost): (i64, f64) = sqlx::query_as(&format!(
+            r#"
+            SELECT COALESCE(SUM(input_tokens + output_tokens
+                                + cache_creation_input_tokens + cache_read_input_tokens), 0),
+                   COALESCE(SUM(cost_usd), 0.0)
+            FROM run_usage
+            WHERE {} = ? AND created_at >= ?
+            "#,
+            column
+        ))
+        .bind(scope_id)
+        .bind(usage_timestamp(&since))
+        .fetch_one(&self.pool)
+        .await?;
+        Ok((tokens.max(0) as u64, cost))
+    }
+}
+
+// ============ Gateway providers ============
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct GatewayProviderRow {
+    pub user_id: String,
+    pub name: String,
+    pub config_json: String,
+}
+
+impl Db {
+    pub async fn put_gateway_provider(
+        &self,
+        user_id: &str,
+        name: &str,
+        config_json: &str,
+    ) -> Result<()> {
+        let now = Utc::now().to_rfc3339();
+        sqlx::query(
+            r#"
+            INSERT INTO gateway_providers (user_id, name, config_json, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(user_id, name) DO UPDATE SET
+                config_json = excluded.config_json,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(name)
+        .bind(config_json)
+        .bind(&now)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_gateway_provider(
+        &self,
+        user_id: &str,
+        name: &str,
+    ) -> Result<Option<GatewayProviderRow>> {
+        let row = sqlx::query_as::<_, GatewayProviderRow>(
+            "SELECT user_id, name, config_json FROM gateway_providers WHERE user_id = ? AND name = ?",
+        )
+        .bind(user_id)
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    /// Providers of every user, for loading at startup
+    pub async fn list_gateway_providers(&self) -> Result<Vec<GatewayProviderRow>> {
+        let rows = sqlx::query_as::<_, GatewayProviderRow>(
+            "SELECT user_id, name, config_json FROM gateway_providers ORDER BY user_id, name",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    pub async fn delete_gateway_provider(&self, user_id: &str, name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM gateway_providers WHERE user_id = ? AND name = ?")
+            .bind(user_id)
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+// ============ Response cache ============
+
+impl Db {
+    /// Cached response under `key`, unless it expired before `now`
+    pub async fn get_cached_response(
+        &self,
+        key: &str,
+        now: &DateTime<Utc>,
+    ) -> Result<Option<String>> {
+        let row: Option<(String,)> = sqlx::query_as(
+            "SELECT response_json FROM response_cache WHERE key = ? AND expires_at > ?",
+        )
+        .bind(key)
+        .bind(usage_timestamp(now))
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|(json,)| json))
+    }
+
+    pub async fn put_cached_response(
+        &self,
+        key: &str,
+        response_json: &str,
+        expires_at: &DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO response_cache (key, response_json, size, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(key) DO UPDATE SET
+                response_json = excluded.response_json,
+                size = excluded.size,
+                created_at = excluded.created_at,
+                expires_at = excluded.expires_at
+            "#,
+        )
+        .bind(key)
+        .bind(response_json)
+        .bind(response_json.len() as i64)
+        .bind(usage_timestamp(&Utc::now()))
+        .bind(usage_timestamp(expires_at))
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Drop expired responses, then the oldest ones until the rest fit in
+    /// `max_bytes`; returns the number of responses removed
+    pub async fn prune_response_cache(&self, now: &DateTime<Utc>, max_bytes: u64) -> Result<u64> {
+        let expired = sqlx::query("DELETE FROM response_cache WHERE expires_at <= ?")
+            .bind(usage_timestamp(now))
+            .execute(&self.pool)
+            .await?;
+        let evicted = sqlx::query(
+            r#"
+            DELETE FROM response_cache WHERE key IN (
+                SELECT key FROM (
+                    SELECT key, SUM(size) OVER (ORDER BY created_at DESC, key) AS running
+                    FROM response_cache
+                ) WHERE running > ?
+            )
+            "#,
+        )
+        .bind(max_bytes as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(expired.rows_affected() + evicted.rows_affected())
+    }
+}
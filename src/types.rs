@@ -7,10 +7,94 @@ use uuid::Uuid;
 pub enum StreamEvent {
     /// 增量文本输出
     Token { content: String },
+    /// 模型思考过程（如 Anthropic extended thinking），不计入最终输出
+    Thinking { content: String },
+    /// 本次调用的 token 用量（在 Done 之前发送）
+    Usage { usage: TokenUsage },
+    /// 开始执行工具调用
+    ToolCallStarted {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    /// 工具调用结束
+    ToolCallFinished {
+        id: String,
+        output: String,
+        ok: bool,
+    },
+    /// 模型请求调用客户端提供的工具（`AgentRequest::tools`），由调用方执行后在下一次请求中带回结果
+    ToolCallRequested {
+        id: String,
+        name: String,
+        /// 模型生成的 JSON 参数文本
+        arguments: String,
+    },
+    /// 模型停止生成的原因，取值同 OpenAI 的 finish_reason：stop / length / tool_calls / content_filter
+    Stop { reason: String },
+    /// gateway 在输出开始后切换了 provider：此前的输出作废，之后的事件来自新的 provider
+    Restart { provider: String, reason: String },
+    /// 向 provider 发出的一次 HTTP 请求（含重试），记录在 Run 上用于排查
+    Attempt { attempt: ProviderAttempt },
     /// 执行完成
     Done { session_id: Uuid },
     /// 执行出错
-    Error { message: String },
+    Error {
+        message: String,
+        /// 被 gateway 的客户端限流拒绝时，建议的重试等待秒数
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        retry_after_secs: Option<u64>,
+    },
+}
+
+/// token 用量
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// 写入 prompt 缓存的输入 token
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub cache_creation_input_tokens: u64,
+    /// 命中 prompt 缓存的输入 token
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub cache_read_input_tokens: u64,
+    /// 费用（美元），由 provider 或 CLI 上报，未上报时按价格表计算
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+}
+
+impl TokenUsage {
+    /// 累加另一次调用的用量
+    pub fn add(&mut self, other: &TokenUsage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_creation_input_tokens += other.cache_creation_input_tokens;
+        self.cache_read_input_tokens += other.cache_read_input_tokens;
+        self.cost_usd = match (self.cost_usd, other.cost_usd) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+    }
+
+    /// 总 token 数（含缓存写入和命中）
+    pub fn total_tokens(&self) -> u64 {
+        self.input_tokens
+            + self.output_tokens
+            + self.cache_creation_input_tokens
+            + self.cache_read_input_tokens
+    }
+
+    /// 所有 token 均为 0
+    pub fn is_empty(&self) -> bool {
+        self.input_tokens == 0
+            && self.output_tokens == 0
+            && self.cache_creation_input_tokens == 0
+            && self.cache_read_input_tokens == 0
+    }
+}
+
+fn is_zero(v: &u64) -> bool {
+    *v == 0
 }
 
 /// Agent 执行配置
@@ -34,6 +118,258 @@ pub struct AgentConfig {
     /// 环境变量（可选）
     #[serde(default)]
     pub env: std::collections::HashMap<String, String>,
+    /// 子进程沙箱（仅 CLI agent 生效，由服务端配置）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<SandboxConfig>,
+    /// 内置工具（仅 HTTP 模型 agent 生效，由服务端配置）
+    #[serde(default)]
+    pub tools: ToolsConfig,
+    /// 请求 provider 失败时的重试（仅 HTTP 模型 agent 生效，由服务端配置）
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// MCP server（CLI agent 透传给 CLI，HTTP 模型 agent 作为工具使用）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mcp_servers: Vec<McpServerConfig>,
+    /// gateway agent 查找 provider 的注册表（调用方注册的 provider），
+    /// 未设置时使用全局的 `GATEWAY_MANAGER`（由服务端设置）
+    #[serde(skip)]
+    pub gateway: Option<crate::agent::GatewayManager>,
+}
+
+/// MCP server 配置：stdio 命令（`command`）或 HTTP 端点（`url`）二选一
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct McpServerConfig {
+    /// 名称，同一 run 内唯一
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// stdio server 的环境变量，值可以使用 `${secret:NAME}` 引用
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub env: std::collections::HashMap<String, String>,
+    /// Streamable HTTP 端点
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// HTTP 请求头，值可以使用 `${secret:NAME}` 引用
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub headers: std::collections::HashMap<String, String>,
+}
+
+impl McpServerConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty()
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "Invalid MCP server name '{}': use letters, numbers, hyphens and underscores",
+                self.name
+            ));
+        }
+        match (&self.command, &self.url) {
+            (Some(_), None) | (None, Some(_)) => Ok(()),
+            _ => Err(format!(
+                "MCP server '{}' needs exactly one of command or url",
+                self.name
+            )),
+        }
+    }
+
+    /// 按名称合并，`overrides` 中的同名 server 替换 `base` 中的
+    pub fn merge(base: &[Self], overrides: &[Self]) -> Vec<Self> {
+        let mut servers: Vec<Self> = base
+            .iter()
+            .filter(|s| !overrides.iter().any(|o| o.name == s.name))
+            .cloned()
+            .collect();
+        servers.extend(overrides.iter().cloned());
+        servers
+    }
+}
+
+/// HTTP 模型 agent 的内置工具配置
+///
+/// 请求带有工作目录时，agent 可以调用这些工具读写工作目录中的文件、执行命令，
+/// 直到模型不再请求工具或达到迭代上限。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolsConfig {
+    /// 允许使用的工具：read_file、write_file、list_files、run_shell
+    ///
+    /// 默认不含 run_shell：命令只在隔离沙箱中才受限于工作目录。
+    #[serde(default = "default_tools")]
+    pub allow: Vec<String>,
+    /// 最多请求模型的轮数
+    #[serde(default = "default_max_iterations")]
+    pub max_iterations: u32,
+    /// 单条命令超时（秒）
+    #[serde(default = "default_shell_timeout")]
+    pub shell_timeout_secs: u64,
+}
+
+impl Default for ToolsConfig {
+    fn default() -> Self {
+        Self {
+            allow: default_tools(),
+            max_iterations: default_max_iterations(),
+            shell_timeout_secs: default_shell_timeout(),
+        }
+    }
+}
+
+fn default_tools() -> Vec<String> {
+    ["read_file", "write_file", "list_files"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn default_max_iterations() -> u32 {
+    25
+}
+
+fn default_shell_timeout() -> u64 {
+    120
+}
+
+/// HTTP 模型 agent 请求 provider 的重试配置
+///
+/// 只重试还没开始输出的请求：连接失败、超时、429 和 5xx。等待时间按指数退避加随机抖动，
+/// provider 通过 `Retry-After` 或 `x-ratelimit-*` 给出的时间优先。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// 首次请求之外最多重试的次数，0 表示不重试
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// 第一次重试前的基础等待（毫秒），之后每次翻倍
+    #[serde(default = "default_base_delay_ms")]
+    pub base_delay_ms: u64,
+    /// 单次等待的上限（毫秒）；provider 要求等待更久时不再重试
+    #[serde(default = "default_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: default_max_retries(),
+            base_delay_ms: default_base_delay_ms(),
+            max_delay_ms: default_max_delay_ms(),
+        }
+    }
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_base_delay_ms() -> u64 {
+    500
+}
+
+fn default_max_delay_ms() -> u64 {
+    30_000
+}
+
+/// 向 provider 发出的一次请求
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProviderAttempt {
+    /// agent 类型：openai / anthropic / openrouter
+    pub provider: String,
+    /// 第几次请求，从 1 开始
+    pub attempt: u32,
+    /// HTTP 状态码，连接失败时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// 重试前的等待（毫秒），不再重试时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+    pub duration_ms: u64,
+}
+
+/// 沙箱后端
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SandboxBackend {
+    /// 不隔离，只应用资源限制
+    #[default]
+    None,
+    /// bubblewrap (bwrap) Linux namespaces
+    Bubblewrap,
+    /// rootless podman 容器
+    Podman,
+}
+
+/// CLI agent 子进程沙箱配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SandboxConfig {
+    #[serde(default)]
+    pub backend: SandboxBackend,
+    /// 是否允许访问网络
+    #[serde(default = "default_true")]
+    pub network: bool,
+    /// 内存上限（MB）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_mb: Option<u64>,
+    /// CPU 时间上限（秒）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_secs: Option<u64>,
+    /// 进程数上限
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_pids: Option<u64>,
+    /// 额外只读挂载的路径（如 CLI 安装目录）
+    #[serde(default)]
+    pub ro_binds: Vec<String>,
+    /// 额外读写挂载的路径（如 ~/.claude）
+    #[serde(default)]
+    pub rw_binds: Vec<String>,
+    /// podman 镜像
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// 客户端指定的工作目录必须位于此目录之内（项目路径不受限制）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace_root: Option<String>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            backend: SandboxBackend::None,
+            network: true,
+            memory_mb: None,
+            cpu_secs: None,
+            max_pids: None,
+            ro_binds: vec![],
+            rw_binds: vec![],
+            image: None,
+            workspace_root: None,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl AgentConfig {
+    /// 用于记录和展示的副本：env 只保留 key 和 `${secret:NAME}` 引用，其余值替换为 `***`
+    pub fn masked(&self) -> Self {
+        let mut config = self.clone();
+        let mcp_values = config
+            .mcp_servers
+            .iter_mut()
+            .flat_map(|s| s.env.values_mut().chain(s.headers.values_mut()));
+        for value in config.env.values_mut().chain(mcp_values) {
+            if !crate::secrets::is_secret_ref(value) {
+                *value = "***".to_string();
+            }
+        }
+        config
+    }
 }
 
 fn default_agent_type() -> String {
@@ -53,8 +389,211 @@ impl Default for AgentConfig {
             extra_args: vec![],
             model: None,
             env: std::collections::HashMap::new(),
+            sandbox: None,
+            tools: ToolsConfig::default(),
+            retry: RetryConfig::default(),
+            mcp_servers: vec![],
+            gateway: None,
+        }
+    }
+}
+
+// ============ Agent 请求 ============
+
+/// 对话消息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    /// user / assistant / tool
+    pub role: String,
+    pub content: String,
+    /// assistant 消息中模型请求的工具调用
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ChatToolCall>,
+    /// tool 消息回应的工具调用 ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: "user".to_string(),
+            content: content.into(),
+            tool_calls: vec![],
+            tool_call_id: None,
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: "assistant".to_string(),
+            content: content.into(),
+            tool_calls: vec![],
+            tool_call_id: None,
+        }
+    }
+
+    /// 工具调用的结果
+    pub fn tool(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: "tool".to_string(),
+            content: content.into(),
+            tool_calls: vec![],
+            tool_call_id: Some(tool_call_id.into()),
+        }
+    }
+}
+
+/// 对话历史中的工具调用
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatToolCall {
+    pub id: String,
+    pub name: String,
+    /// 模型生成的 JSON 参数文本
+    pub arguments: String,
+}
+
+/// 采样参数，未设置的字段使用 provider 默认值
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SamplingParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// 输出格式，如 `{"type": "json_object"}`，原样传给 provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<serde_json::Value>,
+}
+
+impl SamplingParams {
+    /// 未设置的字段取 `defaults` 中的值
+    pub fn or(self, defaults: &SamplingParams) -> Self {
+        Self {
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+            frequency_penalty: self.frequency_penalty.or(defaults.frequency_penalty),
+            presence_penalty: self.presence_penalty.or(defaults.presence_penalty),
+            stop: if self.stop.is_empty() {
+                defaults.stop.clone()
+            } else {
+                self.stop
+            },
+            seed: self.seed.or(defaults.seed),
+            response_format: self
+                .response_format
+                .or_else(|| defaults.response_format.clone()),
+        }
+    }
+}
+
+/// 工具定义（JSON Schema 描述参数）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub input_schema: serde_json::Value,
+}
+
+/// provider 提供的模型
+///
+/// 字段取决于 provider 的 `/models` 返回了什么，未知的留空。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    /// provider 内的模型 ID（不带 provider 前缀）
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// 发布时间（Unix 秒）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owned_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<crate::pricing::ModelPrice>,
+    /// 输入模态，如 `text`、`image`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub input_modalities: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub output_modalities: Vec<String>,
+}
+
+/// 结构化的 agent 请求
+///
+/// `messages` 的最后一条为本轮用户输入，之前的为历史对话。只支持字符串 prompt 的
+/// agent 通过 `to_prompt()` 得到拼接后的文本。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AgentRequest {
+    pub messages: Vec<ChatMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    /// 附件元信息，只会发给声明了 `attachments` 能力的 agent
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    #[serde(default)]
+    pub sampling: SamplingParams,
+    #[serde(default)]
+    pub tools: Vec<ToolDefinition>,
+}
+
+impl AgentRequest {
+    /// 单条用户输入
+    pub fn new(prompt: impl Into<String>) -> Self {
+        Self {
+            messages: vec![ChatMessage::user(prompt)],
+            ..Default::default()
         }
     }
+
+    /// 最后一条用户输入
+    pub fn prompt(&self) -> &str {
+        self.messages
+            .iter()
+            .rev()
+            .find(|m| m.role == "user")
+            .map(|m| m.content.as_str())
+            .unwrap_or("")
+    }
+
+    /// 拼接为单个 prompt
+    ///
+    /// 只有一条用户消息且没有 system 时原样返回；否则依次拼接 system 和带角色前缀的历史对话。
+    pub fn to_prompt(&self) -> String {
+        if self.system.is_none() && self.messages.len() == 1 {
+            return self.messages[0].content.clone();
+        }
+
+        let mut parts = Vec::new();
+        if let Some(ref system) = self.system {
+            parts.push(system.clone());
+        }
+        for message in &self.messages {
+            let role = match message.role.as_str() {
+                "assistant" => "Assistant",
+                "system" => "System",
+                "tool" => "Tool",
+                _ => "User",
+            };
+            parts.push(format!("{}: {}", role, message.content));
+        }
+        parts.join("\n\n")
+    }
 }
 
 // ============ API 请求/响应类型 ============
@@ -96,6 +635,9 @@ pub struct RunMetadata {
     pub extra_args: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub project_id: Option<String>,
+    /// 采样参数，未设置的字段使用该 agent 类型的默认配置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<SamplingParams>,
 }
 
 /// POST /api/runs 请求
@@ -154,6 +696,8 @@ pub struct Project {
     pub id: String,
     pub name: String,
     pub path: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mcp_servers: Vec<McpServerConfig>,
     pub created_at: String,
     pub updated_at: String,
 }
@@ -162,6 +706,14 @@ pub struct Project {
 #[derive(Debug, Deserialize)]
 pub struct CreateProjectRequest {
     pub name: String,
+    #[serde(default)]
+    pub mcp_servers: Vec<McpServerConfig>,
+}
+
+/// PUT /api/projects/:id/mcp-servers 请求
+#[derive(Debug, Deserialize)]
+pub struct SetMcpServersRequest {
+    pub mcp_servers: Vec<McpServerConfig>,
 }
 
 #[derive(Debug, Serialize)]
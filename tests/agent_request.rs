@@ -0,0 +1,120 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use openrunner::agent::{Agent, AgentCapabilities, AgentInfo, AgentRegistry, MockAgent};
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::types::{AgentRequest, ChatMessage, StreamEvent};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+/// 只实现 `run` 的 agent，记录收到的 prompt
+struct PromptAgent {
+    prompts: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait]
+impl Agent for PromptAgent {
+    async fn run(&self, prompt: String, _tx: mpsc::Sender<StreamEvent>) -> anyhow::Result<()> {
+        self.prompts.lock().unwrap().push(prompt);
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "prompt"
+    }
+}
+
+fn conversation() -> AgentRequest {
+    AgentRequest {
+        system: Some("Be brief.".to_string()),
+        messages: vec![
+            ChatMessage::user("first"),
+            ChatMessage::assistant("answer"),
+            ChatMessage::tool("call-1", "42"),
+            ChatMessage::user("second"),
+        ],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn prompts_are_flattened_with_role_prefixes() {
+    assert_eq!(AgentRequest::new("just this").to_prompt(), "just this");
+
+    let request = conversation();
+    assert_eq!(request.prompt(), "second");
+    assert_eq!(
+        request.to_prompt(),
+        "Be brief.\n\nUser: first\n\nAssistant: answer\n\nTool: 42\n\nUser: second"
+    );
+    assert_eq!(AgentRequest::default().prompt(), "");
+}
+
+#[tokio::test]
+async fn execute_defaults_to_run_with_the_flattened_prompt() {
+    let prompts = Arc::new(Mutex::new(vec![]));
+    let agent = PromptAgent {
+        prompts: prompts.clone(),
+    };
+    let (tx, _rx) = mpsc::channel(1);
+    agent.execute(conversation(), tx).await.unwrap();
+    assert_eq!(*prompts.lock().unwrap(), [conversation().to_prompt()]);
+    assert_eq!(agent.capabilities(), AgentCapabilities::default());
+}
+
+#[tokio::test]
+async fn attachments_require_an_agent_that_supports_them() {
+    let registry = AgentRegistry::with_builtins();
+    registry.register(
+        AgentInfo::new("files", "mock with attachments").capabilities(AgentCapabilities {
+            attachments: true,
+            ..Default::default()
+        }),
+        |c| Ok(Box::new(MockAgent::new(c.clone()))),
+    );
+    let dir = std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()));
+    let state = AppState::with_data_dir(&dir).await.with_registry(registry);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, create_router_with_state(state))
+            .await
+            .unwrap()
+    });
+
+    let client = reqwest::Client::new();
+    let attachments = json!([{ "name": "notes.txt", "type": "text/plain", "size": 12 }]);
+    let run = |agent_type: &str| {
+        json!({
+            "input": { "text": "hi", "attachments": attachments },
+            "metadata": { "agent_type": agent_type },
+        })
+    };
+
+    let response = client
+        .post(format!("{}/api/runs", url))
+        .json(&run("mock"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "Agent 'mock' does not support attachments");
+
+    let response = client
+        .post(format!("{}/api/runs", url))
+        .json(&run("files"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .post(format!("{}/api/chat", url))
+        .json(&json!({ "message": "hi", "agent_type": "mock", "attachments": attachments }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
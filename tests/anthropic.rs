@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, StatusCode},
+    response::Response,
+    routing::post,
+    Json, Router,
+};
+use futures::StreamExt;
+use openrunner::agent::{Agent, AnthropicAgent};
+use openrunner::types::{
+    AgentConfig, AgentRequest, ChatMessage, StreamEvent, TokenUsage, ToolsConfig,
+};
+use tokio::sync::mpsc;
+
+/// 一次完整的流式响应：thinking、两个文本增量、ping、usage
+const STREAM: &str = concat!(
+    "event: message_start\n",
+    "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"type\":\"message\",\"role\":\"assistant\",\"content\":[],\"model\":\"claude-test\",\"stop_reason\":null,\"usage\":{\"input_tokens\":12,\"output_tokens\":1,\"cache_read_input_tokens\":3}}}\n\n",
+    "event: content_block_start\n",
+    "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"thinking\",\"thinking\":\"\"}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"thinking_delta\",\"thinking\":\"让我想想\"}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"signature_delta\",\"signature\":\"abc\"}}\n\n",
+    "event: content_block_stop\n",
+    "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+    "event: ping\n",
+    "data: {\"type\":\"ping\"}\n\n",
+    "event: content_block_start\n",
+    "data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"text_delta\",\"text\":\"你好\"}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"text_delta\",\"text\":\", world\"}}\n\n",
+    "event: content_block_stop\n",
+    "data: {\"type\":\"content_block_stop\",\"index\":1}\n\n",
+    "event: message_delta\n",
+    "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":6}}\n\n",
+    "event: message_stop\n",
+    "data: {\"type\":\"message_stop\"}\n\n",
+);
+
+#[derive(Clone)]
+struct Mock {
+    status: StatusCode,
+    /// 第 n 次请求返回第 n 个响应，超出后重复最后一个
+    bodies: Vec<&'static str>,
+    requests: Arc<Mutex<Vec<serde_json::Value>>>,
+}
+
+async fn messages(State(mock): State<Mock>, Json(body): Json<serde_json::Value>) -> Response {
+    let body_text = {
+        let mut requests = mock.requests.lock().unwrap();
+        requests.push(body);
+        mock.bodies[(requests.len() - 1).min(mock.bodies.len() - 1)]
+    };
+    if mock.status != StatusCode::OK {
+        return Response::builder()
+            .status(mock.status)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body_text))
+            .unwrap();
+    }
+
+    // 按 7 字节切片发送，事件和多字节字符都会被拆开
+    let chunks: Vec<Result<Vec<u8>, std::io::Error>> = body_text
+        .as_bytes()
+        .chunks(7)
+        .map(|c| Ok(c.to_vec()))
+        .collect();
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .body(Body::from_stream(futures::stream::iter(chunks)))
+        .unwrap()
+}
+
+/// 启动 mock server，返回 base url 和收到的请求体
+async fn serve(
+    status: StatusCode,
+    body: &'static str,
+) -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+    serve_turns(status, vec![body]).await
+}
+
+async fn serve_turns(
+    status: StatusCode,
+    bodies: Vec<&'static str>,
+) -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let app = Router::new()
+        .route("/v1/messages", post(messages))
+        .with_state(Mock {
+            status,
+            bodies,
+            requests: requests.clone(),
+        });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    (format!("http://{}/v1", addr), requests)
+}
+
+fn agent(base_url: &str, extra_env: &[(&str, &str)]) -> AnthropicAgent {
+    agent_with(base_url, extra_env, ToolsConfig::default())
+}
+
+fn agent_with(base_url: &str, extra_env: &[(&str, &str)], tools: ToolsConfig) -> AnthropicAgent {
+    let mut env = HashMap::from([
+        ("ANTHROPIC_API_KEY".to_string(), "test-key".to_string()),
+        ("ANTHROPIC_BASE_URL".to_string(), base_url.to_string()),
+    ]);
+    for (k, v) in extra_env {
+        env.insert(k.to_string(), v.to_string());
+    }
+    AnthropicAgent::new(AgentConfig {
+        agent_type: "anthropic".to_string(),
+        model: Some("claude-test".to_string()),
+        env,
+        tools,
+        ..Default::default()
+    })
+}
+
+async fn execute(
+    agent: &AnthropicAgent,
+    request: AgentRequest,
+) -> (anyhow::Result<()>, Vec<StreamEvent>) {
+    let (tx, mut rx) = mpsc::channel(100);
+    let result = agent.execute(request, tx).await;
+    let mut events = Vec::new();
+    while let Ok(event) = rx.try_recv() {
+        events.push(event);
+    }
+    (result, events)
+}
+
+#[tokio::test]
+async fn streams_text_thinking_and_usage() {
+    let (base_url, requests) = serve(StatusCode::OK, STREAM).await;
+    let mut request = AgentRequest::new("hi");
+    request.system = Some("Be brief.".to_string());
+    request.messages.insert(0, ChatMessage::user("earlier"));
+    request.messages.insert(1, ChatMessage::assistant("reply"));
+
+    let (result, events) = execute(&agent(&base_url, &[]), request).await;
+    result.unwrap();
+
+    let mut text = String::new();
+    let mut thinking = String::new();
+    let mut usage = None;
+    for event in &events {
+        match event {
+            StreamEvent::Token { content } => text.push_str(content),
+            StreamEvent::Thinking { content } => thinking.push_str(content),
+            StreamEvent::Usage { usage: u } => usage = Some(*u),
+            _ => {}
+        }
+    }
+    assert_eq!(text, "你好, world");
+    assert_eq!(thinking, "让我想想");
+    assert_eq!(
+        usage,
+        Some(TokenUsage {
+            input_tokens: 12,
+            output_tokens: 6,
+            cache_creation_input_tokens: 0,
+            cache_read_input_tokens: 3,
+            cost_usd: None,
+        })
+    );
+    assert!(matches!(events.last(), Some(StreamEvent::Done { .. })));
+
+    let body = requests.lock().unwrap()[0].clone();
+    assert_eq!(body["system"], "Be brief.");
+    assert_eq!(body["max_tokens"], 4096);
+    assert_eq!(body["stream"], true);
+    assert_eq!(body["messages"].as_array().unwrap().len(), 3);
+    assert_eq!(body["messages"][1]["role"], "assistant");
+    assert!(body.get("thinking").is_none());
+}
+
+#[tokio::test]
+async fn thinking_budget_is_sent() {
+    let (base_url, requests) = serve(StatusCode::OK, STREAM).await;
+    let agent = agent(&base_url, &[("ANTHROPIC_THINKING_BUDGET", "2048")]);
+    let (result, _) = execute(&agent, AgentRequest::new("hi")).await;
+    result.unwrap();
+
+    let body = requests.lock().unwrap()[0].clone();
+    assert_eq!(body["thinking"]["type"], "enabled");
+    assert_eq!(body["thinking"]["budget_tokens"], 2048);
+    assert_eq!(body["max_tokens"], 4096 + 2048);
+}
+
+#[tokio::test]
+async fn explicit_max_tokens_is_kept() {
+    let (base_url, requests) = serve(StatusCode::OK, STREAM).await;
+    let mut request = AgentRequest::new("hi");
+    request.sampling.max_tokens = Some(100);
+    request.sampling.stop = vec!["END".to_string()];
+    let (result, _) = execute(&agent(&base_url, &[]), request).await;
+    result.unwrap();
+
+    let body = requests.lock().unwrap()[0].clone();
+    assert_eq!(body["max_tokens"], 100);
+    assert_eq!(body["stop_sequences"][0], "END");
+}
+
+#[tokio::test]
+async fn error_event_fails_the_run() {
+    const BODY: &str = concat!(
+        "event: message_start\n",
+        "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":5,\"output_tokens\":1}}}\n\n",
+        "event: error\n",
+        "data: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+    );
+    let (base_url, _) = serve(StatusCode::OK, BODY).await;
+    let (result, events) = execute(&agent(&base_url, &[]), AgentRequest::new("hi")).await;
+
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("overloaded_error"), "{}", err);
+    assert!(err.contains("Overloaded"), "{}", err);
+    assert!(!events.iter().any(|e| matches!(e, StreamEvent::Done { .. })));
+}
+
+#[tokio::test]
+async fn truncated_stream_is_an_error() {
+    const BODY: &str = concat!(
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"partial\"}}\n\n",
+    );
+    let (base_url, _) = serve(StatusCode::OK, BODY).await;
+    let (result, _) = execute(&agent(&base_url, &[]), AgentRequest::new("hi")).await;
+    assert!(result.unwrap_err().to_string().contains("message_stop"));
+}
+
+#[tokio::test]
+async fn broken_stream_is_reported_once() {
+    let app = Router::new().route(
+        "/v1/messages",
+        post(|| async {
+            let chunks: Vec<Result<&'static str, std::io::Error>> = vec![
+                Ok("event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"partial\"}}\n\n"),
+                Err(std::io::Error::other("connection reset")),
+            ];
+            // 先让第一块到达客户端，再断开
+            let chunks = futures::stream::iter(chunks).then(|chunk| async move {
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                chunk
+            });
+            Response::builder()
+                .header(header::CONTENT_TYPE, "text/event-stream")
+                .body(Body::from_stream(chunks))
+                .unwrap()
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let (result, events) = execute(
+        &agent(&format!("http://{}/v1", addr), &[]),
+        AgentRequest::new("hi"),
+    )
+    .await;
+    assert!(result.unwrap_err().to_string().starts_with("Stream error"));
+    // 错误只通过返回值报告，由 AgentHandle 转成一次 Error 事件
+    assert!(!events
+        .iter()
+        .any(|e| matches!(e, StreamEvent::Error { .. })));
+}
+
+#[tokio::test]
+async fn http_error_body_is_reported() {
+    const BODY: &str = r#"{"type":"error","error":{"type":"invalid_request_error","message":"max_tokens: field required"}}"#;
+    let (base_url, _) = serve(StatusCode::BAD_REQUEST, BODY).await;
+    let (result, _) = execute(&agent(&base_url, &[]), AgentRequest::new("hi")).await;
+
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("400"), "{}", err);
+    assert!(
+        err.contains("invalid_request_error: max_tokens: field required"),
+        "{}",
+        err
+    );
+}
+
+/// 请求 read_file 工具的一轮响应（input 分两片到达）
+const TOOL_USE: &str = concat!(
+    "event: message_start\n",
+    "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":10,\"output_tokens\":1}}}\n\n",
+    "event: content_block_start\n",
+    "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Reading. \"}}\n\n",
+    "event: content_block_stop\n",
+    "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+    "event: content_block_start\n",
+    "data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"read_file\",\"input\":{}}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"path\\\": \"}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"\\\"notes.txt\\\"}\"}}\n\n",
+    "event: content_block_stop\n",
+    "data: {\"type\":\"content_block_stop\",\"index\":1}\n\n",
+    "event: message_delta\n",
+    "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"output_tokens\":20}}\n\n",
+    "event: message_stop\n",
+    "data: {\"type\":\"message_stop\"}\n\n",
+);
+
+#[tokio::test]
+async fn tool_loop_reads_file_and_continues() {
+    let dir = std::env::temp_dir().join(format!("openrunner-tools-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("notes.txt"), "hello from disk").unwrap();
+
+    let (base_url, requests) = serve_turns(StatusCode::OK, vec![TOOL_USE, STREAM]).await;
+    let mut request = AgentRequest::new("what is in notes.txt?");
+    request.working_dir = Some(dir.to_string_lossy().into_owned());
+    let (result, events) = execute(&agent(&base_url, &[]), request).await;
+    result.unwrap();
+
+    let started: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            StreamEvent::ToolCallStarted { id, name, input } => Some((id, name, input)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(started.len(), 1);
+    assert_eq!(started[0].0, "toolu_1");
+    assert_eq!(started[0].1, "read_file");
+    assert_eq!(started[0].2["path"], "notes.txt");
+    assert!(events.iter().any(|e| matches!(
+        e,
+        StreamEvent::ToolCallFinished { output, ok: true, .. } if output == "hello from disk"
+    )));
+
+    // 每轮上报一次累计用量，最后一次为两轮之和
+    let usages: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            StreamEvent::Usage { usage } => Some(*usage),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(usages.len(), 2);
+    let usage = usages.last();
+    assert_eq!(usage.unwrap().input_tokens, 22);
+    assert_eq!(usage.unwrap().output_tokens, 26);
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    let tools: Vec<_> = requests[0]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["name"].as_str().unwrap())
+        .collect();
+    // 没有沙箱时默认不提供 run_shell
+    assert_eq!(tools, vec!["read_file", "write_file", "list_files"]);
+
+    let messages = requests[1]["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 3);
+    assert_eq!(messages[1]["role"], "assistant");
+    assert_eq!(messages[1]["content"][1]["type"], "tool_use");
+    assert_eq!(messages[1]["content"][1]["input"]["path"], "notes.txt");
+    assert_eq!(messages[2]["role"], "user");
+    assert_eq!(messages[2]["content"][0]["type"], "tool_result");
+    assert_eq!(messages[2]["content"][0]["tool_use_id"], "toolu_1");
+    assert_eq!(messages[2]["content"][0]["content"], "hello from disk");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn tool_paths_cannot_escape_working_dir() {
+    const ESCAPE: &str = concat!(
+        "event: message_start\n",
+        "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":1}}}\n\n",
+        "event: content_block_start\n",
+        "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"read_file\",\"input\":{}}}\n\n",
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"path\\\":\\\"../../etc/passwd\\\"}\"}}\n\n",
+        "event: content_block_stop\n",
+        "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+        "event: message_stop\n",
+        "data: {\"type\":\"message_stop\"}\n\n",
+    );
+    let dir = std::env::temp_dir().join(format!("openrunner-tools-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let (base_url, requests) = serve_turns(StatusCode::OK, vec![ESCAPE, STREAM]).await;
+    let mut request = AgentRequest::new("hi");
+    request.working_dir = Some(dir.to_string_lossy().into_owned());
+    let (result, events) = execute(&agent(&base_url, &[]), request).await;
+    result.unwrap();
+
+    assert!(events.iter().any(|e| matches!(
+        e,
+        StreamEvent::ToolCallFinished { output, ok: false, .. } if output.contains("outside the working directory")
+    )));
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests[1]["messages"][2]["content"][0]["is_error"], true);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn tool_loop_stops_at_max_iterations() {
+    let dir = std::env::temp_dir().join(format!("openrunner-tools-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // 模型一直请求工具
+    let (base_url, requests) = serve_turns(StatusCode::OK, vec![TOOL_USE]).await;
+    let tools = ToolsConfig {
+        allow: vec!["read_file".to_string()],
+        max_iterations: 3,
+        ..Default::default()
+    };
+    let mut request = AgentRequest::new("hi");
+    request.working_dir = Some(dir.to_string_lossy().into_owned());
+    let (result, _) = execute(&agent_with(&base_url, &[], tools), request).await;
+
+    assert!(result.unwrap_err().to_string().contains("3 iterations"));
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[0]["tools"].as_array().unwrap().len(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn no_tools_without_working_dir() {
+    let (base_url, requests) = serve(StatusCode::OK, STREAM).await;
+    let (result, _) = execute(&agent(&base_url, &[]), AgentRequest::new("hi")).await;
+    result.unwrap();
+    assert!(requests.lock().unwrap()[0].get("tools").is_none());
+}
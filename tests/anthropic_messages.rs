@@ -0,0 +1,364 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, StatusCode},
+    response::Response,
+    routing::post,
+    Json, Router,
+};
+use openrunner::agent::{AgentInfo, AgentRegistry, GatewayAgent, GatewayConfig};
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::types::{AgentConfig, RetryConfig};
+use serde_json::{json, Value};
+
+/// anthropic-python `client.messages.create(...)` 发出的请求：system 块、用户提问、
+/// 带 tool_use 的 assistant 消息和 tool_result
+const SDK_REQUEST: &str = r#"{
+  "max_tokens": 1024,
+  "messages": [
+    {"role": "user", "content": "What's the weather in Paris?"},
+    {"role": "assistant", "content": [
+      {"type": "text", "text": "Let me check."},
+      {"type": "tool_use", "id": "toolu_01A09q90qw90lq917835lq9", "name": "get_weather", "input": {"city": "Paris"}}
+    ]},
+    {"role": "user", "content": [
+      {"type": "tool_result", "tool_use_id": "toolu_01A09q90qw90lq917835lq9", "content": [{"type": "text", "text": "18°C and sunny"}]},
+      {"type": "text", "text": "Thanks!"}
+    ]}
+  ],
+  "model": "gpt-4o-mini",
+  "stop_sequences": ["\n\nHuman:"],
+  "system": [{"type": "text", "text": "You are a weather bot."}],
+  "temperature": 0.2,
+  "tools": [
+    {
+      "name": "get_weather",
+      "description": "Get the weather for a city",
+      "input_schema": {"type": "object", "properties": {"city": {"type": "string"}}, "required": ["city"]}
+    }
+  ]
+}"#;
+
+/// OpenAI 的流式响应：role、两个文本增量、finish_reason、usage、[DONE]
+const TEXT_STREAM: &str = concat!(
+    "data: {\"id\":\"chatcmpl-B9MBs8CjcvOU2jLn4n570S5qMJKcT\",\"object\":\"chat.completion.chunk\",\"created\":1741570283,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_06737a9306\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\",\"refusal\":null},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MBs8CjcvOU2jLn4n570S5qMJKcT\",\"object\":\"chat.completion.chunk\",\"created\":1741570283,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_06737a9306\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"It's 18°C\"},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MBs8CjcvOU2jLn4n570S5qMJKcT\",\"object\":\"chat.completion.chunk\",\"created\":1741570283,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_06737a9306\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\" and sunny in Paris.\"},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MBs8CjcvOU2jLn4n570S5qMJKcT\",\"object\":\"chat.completion.chunk\",\"created\":1741570283,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_06737a9306\",\"choices\":[{\"index\":0,\"delta\":{},\"logprobs\":null,\"finish_reason\":\"stop\"}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MBs8CjcvOU2jLn4n570S5qMJKcT\",\"object\":\"chat.completion.chunk\",\"created\":1741570283,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_06737a9306\",\"choices\":[],\"usage\":{\"prompt_tokens\":52,\"completion_tokens\":9,\"total_tokens\":61,\"prompt_tokens_details\":{\"cached_tokens\":0,\"audio_tokens\":0},\"completion_tokens_details\":{\"reasoning_tokens\":0,\"audio_tokens\":0,\"accepted_prediction_tokens\":0,\"rejected_prediction_tokens\":0}}}\n\n",
+    "data: [DONE]\n\n",
+);
+
+/// 模型请求调用 get_weather，参数分片到达
+const TOOL_CALL_STREAM: &str = concat!(
+    "data: {\"id\":\"chatcmpl-B9MHD\",\"object\":\"chat.completion.chunk\",\"created\":1741570611,\"model\":\"gpt-4o-mini-2024-07-18\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":null,\"tool_calls\":[{\"index\":0,\"id\":\"call_DdmO9pD3xa9XTPNJ32zg2hcA\",\"type\":\"function\",\"function\":{\"name\":\"get_weather\",\"arguments\":\"\"}}],\"refusal\":null},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MHD\",\"object\":\"chat.completion.chunk\",\"created\":1741570611,\"model\":\"gpt-4o-mini-2024-07-18\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"city\\\"\"}}]},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MHD\",\"object\":\"chat.completion.chunk\",\"created\":1741570611,\"model\":\"gpt-4o-mini-2024-07-18\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\":\\\"Paris\\\"}\"}}]},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MHD\",\"object\":\"chat.completion.chunk\",\"created\":1741570611,\"model\":\"gpt-4o-mini-2024-07-18\",\"choices\":[{\"index\":0,\"delta\":{},\"logprobs\":null,\"finish_reason\":\"tool_calls\"}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MHD\",\"object\":\"chat.completion.chunk\",\"created\":1741570611,\"model\":\"gpt-4o-mini-2024-07-18\",\"choices\":[],\"usage\":{\"prompt_tokens\":60,\"completion_tokens\":15,\"total_tokens\":75}}\n\n",
+    "data: [DONE]\n\n",
+);
+
+#[derive(Clone)]
+struct Upstream {
+    status: StatusCode,
+    body: &'static str,
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+async fn chat_completions(State(upstream): State<Upstream>, Json(body): Json<Value>) -> Response {
+    upstream.requests.lock().unwrap().push(body);
+    let content_type = if upstream.status == StatusCode::OK {
+        "text/event-stream"
+    } else {
+        "application/json"
+    };
+    Response::builder()
+        .status(upstream.status)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(upstream.body))
+        .unwrap()
+}
+
+async fn listen(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+/// 启动上游 mock 和 OpenRunner（gateway 指向 mock），返回 OpenRunner 地址和上游收到的请求
+async fn serve(status: StatusCode, body: &'static str) -> (String, Arc<Mutex<Vec<Value>>>) {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let upstream = Upstream {
+        status,
+        body,
+        requests: requests.clone(),
+    };
+    let upstream_url = listen(
+        Router::new()
+            .route("/v1/chat/completions", post(chat_completions))
+            .with_state(upstream),
+    )
+    .await;
+
+    let registry = AgentRegistry::with_builtins();
+    registry.register(AgentInfo::new("gateway", "test gateway"), move |config| {
+        Ok(Box::new(
+            GatewayAgent::new(GatewayConfig {
+                provider: "openai".to_string(),
+                model: config.model.clone(),
+                api_key: Some("sk-test".to_string()),
+                base_url: Some(format!("{}/v1", upstream_url)),
+                fallback_providers: vec![],
+                load_balancing: None,
+                restart_on_failure: false,
+                circuit_breaker: Default::default(),
+                rate_limit: None,
+            })
+            // 上游的错误响应直接返回，不重试
+            .with_base_config(AgentConfig {
+                retry: RetryConfig {
+                    max_retries: 0,
+                    ..Default::default()
+                },
+                ..config.clone()
+            }),
+        ))
+    });
+    let data_dir = std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()));
+    let state = AppState::with_data_dir(&data_dir)
+        .await
+        .with_registry(registry);
+    let url = listen(create_router_with_state(state)).await;
+    (url, requests)
+}
+
+fn sdk_request(extra: Value) -> Value {
+    let mut request: Value = serde_json::from_str(SDK_REQUEST).unwrap();
+    for (key, value) in extra.as_object().unwrap() {
+        request[key] = value.clone();
+    }
+    request
+}
+
+async fn post_json(url: &str, body: &Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/v1/messages", url))
+        .header("x-api-key", "sk-ant-test")
+        .header("anthropic-version", "2023-06-01")
+        .json(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+/// 解析带事件名的 SSE：每个事件一行 event、一行 data
+fn events(body: &str) -> Vec<(String, Value)> {
+    body.split("\n\n")
+        .filter(|event| !event.trim().is_empty())
+        .map(|event| {
+            let (name, data) = event
+                .split_once('\n')
+                .unwrap_or_else(|| panic!("missing data line: {:?}", event));
+            let name = name
+                .strip_prefix("event: ")
+                .unwrap_or_else(|| panic!("not an event line: {:?}", name));
+            let data = data
+                .strip_prefix("data: ")
+                .unwrap_or_else(|| panic!("not a data line: {:?}", data));
+            let data: Value = serde_json::from_str(data).unwrap();
+            assert_eq!(data["type"], name);
+            (name.to_string(), data)
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn sdk_request_translates_blocks_and_tools() {
+    let (url, requests) = serve(StatusCode::OK, TEXT_STREAM).await;
+
+    let response = post_json(&url, &sdk_request(json!({}))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+
+    assert!(body["id"].as_str().unwrap().starts_with("msg_"));
+    assert_eq!(body["type"], "message");
+    assert_eq!(body["role"], "assistant");
+    assert_eq!(body["model"], "gpt-4o-mini");
+    assert_eq!(
+        body["content"],
+        json!([{ "type": "text", "text": "It's 18°C and sunny in Paris." }])
+    );
+    assert_eq!(body["stop_reason"], "end_turn");
+    assert_eq!(body["stop_sequence"], Value::Null);
+    assert_eq!(body["usage"]["input_tokens"], 52);
+    assert_eq!(body["usage"]["output_tokens"], 9);
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    let upstream = &requests[0];
+    assert_eq!(upstream["model"], "gpt-4o-mini");
+    assert_eq!(upstream["max_tokens"], 1024);
+    assert_eq!(upstream["stop"], json!(["\n\nHuman:"]));
+    assert_eq!(upstream["temperature"], json!(0.2));
+    assert_eq!(
+        upstream["messages"],
+        json!([
+            { "role": "system", "content": "You are a weather bot." },
+            { "role": "user", "content": "What's the weather in Paris?" },
+            { "role": "assistant", "content": "Let me check.", "tool_calls": [{
+                "id": "toolu_01A09q90qw90lq917835lq9",
+                "type": "function",
+                "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" }
+            }] },
+            { "role": "tool", "content": "18°C and sunny", "tool_call_id": "toolu_01A09q90qw90lq917835lq9" },
+            { "role": "user", "content": "Thanks!" }
+        ])
+    );
+    assert_eq!(upstream["tools"][0]["function"]["name"], "get_weather");
+    assert_eq!(
+        upstream["tools"][0]["function"]["parameters"]["required"],
+        json!(["city"])
+    );
+}
+
+#[tokio::test]
+async fn stream_uses_anthropic_event_sequence() {
+    let (url, _) = serve(StatusCode::OK, TEXT_STREAM).await;
+
+    let response = post_json(&url, &sdk_request(json!({ "stream": true }))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()[header::CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .starts_with("text/event-stream"));
+
+    let events = events(&response.text().await.unwrap());
+    let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "message_start",
+            "ping",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop"
+        ]
+    );
+
+    let message = &events[0].1["message"];
+    assert!(message["id"].as_str().unwrap().starts_with("msg_"));
+    assert_eq!(message["role"], "assistant");
+    assert_eq!(message["content"], json!([]));
+    assert_eq!(
+        events[2].1["content_block"],
+        json!({ "type": "text", "text": "" })
+    );
+    let text: String = events
+        .iter()
+        .filter(|(name, _)| name == "content_block_delta")
+        .map(|(_, data)| {
+            assert_eq!(data["index"], 0);
+            assert_eq!(data["delta"]["type"], "text_delta");
+            data["delta"]["text"].as_str().unwrap()
+        })
+        .collect();
+    assert_eq!(text, "It's 18°C and sunny in Paris.");
+
+    let delta = &events[6].1;
+    assert_eq!(delta["delta"]["stop_reason"], "end_turn");
+    assert_eq!(delta["usage"]["output_tokens"], 9);
+}
+
+#[tokio::test]
+async fn tool_use_is_returned_to_the_caller() {
+    let (url, requests) = serve(StatusCode::OK, TOOL_CALL_STREAM).await;
+
+    let body: Value = post_json(&url, &sdk_request(json!({})))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["stop_reason"], "tool_use");
+    assert_eq!(
+        body["content"],
+        json!([{
+            "type": "tool_use",
+            "id": "call_DdmO9pD3xa9XTPNJ32zg2hcA",
+            "name": "get_weather",
+            "input": { "city": "Paris" }
+        }])
+    );
+    // 工具由调用方执行，不会再次请求模型
+    assert_eq!(requests.lock().unwrap().len(), 1);
+
+    let response = post_json(&url, &sdk_request(json!({ "stream": true }))).await;
+    let events = events(&response.text().await.unwrap());
+    let start = events
+        .iter()
+        .find(|(name, _)| name == "content_block_start")
+        .unwrap();
+    assert_eq!(
+        start.1["content_block"],
+        json!({
+            "type": "tool_use",
+            "id": "call_DdmO9pD3xa9XTPNJ32zg2hcA",
+            "name": "get_weather",
+            "input": {}
+        })
+    );
+    let delta = events
+        .iter()
+        .find(|(name, _)| name == "content_block_delta")
+        .unwrap();
+    assert_eq!(delta.1["delta"]["type"], "input_json_delta");
+    assert_eq!(delta.1["delta"]["partial_json"], "{\"city\":\"Paris\"}");
+    let message_delta = events
+        .iter()
+        .find(|(name, _)| name == "message_delta")
+        .unwrap();
+    assert_eq!(message_delta.1["delta"]["stop_reason"], "tool_use");
+}
+
+#[tokio::test]
+async fn errors_use_anthropic_shape() {
+    let (url, _) = serve(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        r#"{"error":{"message":"The server had an error","type":"server_error"}}"#,
+    )
+    .await;
+
+    // 缺少 max_tokens
+    let mut request = sdk_request(json!({}));
+    request.as_object_mut().unwrap().remove("max_tokens");
+    let response = post_json(&url, &request).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "error");
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("max_tokens"));
+
+    // 上游出错
+    let response = post_json(&url, &sdk_request(json!({}))).await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "error");
+    assert_eq!(body["error"]["type"], "api_error");
+
+    // 流式请求出错时发送 error 事件，没有 message_stop
+    let response = post_json(&url, &sdk_request(json!({ "stream": true }))).await;
+    let events = events(&response.text().await.unwrap());
+    let (name, data) = events.last().unwrap();
+    assert_eq!(name, "error");
+    assert_eq!(data["error"]["type"], "api_error");
+    assert!(!events.iter().any(|(name, _)| name == "message_stop"));
+}
@@ -0,0 +1,411 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+use chrono::{TimeZone, Utc};
+use openrunner::agent::{Agent, AgentInfo};
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::auth::create_token;
+use openrunner::budget::{Budget, BudgetError, BudgetPeriod, BudgetScope, RunLimit};
+use openrunner::run::RunStatus;
+use openrunner::storage::UsageRecord;
+use openrunner::types::{StreamEvent, TokenUsage};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+fn data_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()))
+}
+
+fn budget(scope: BudgetScope, scope_id: &str, period: BudgetPeriod) -> Budget {
+    Budget {
+        id: String::new(),
+        scope,
+        scope_id: scope_id.to_string(),
+        period,
+        max_tokens: None,
+        max_cost_usd: None,
+        max_run_tokens: None,
+        max_run_cost_usd: None,
+        warn_ratio: None,
+        webhook_url: None,
+    }
+}
+
+fn usage(tokens: u64, cost_usd: Option<f64>) -> TokenUsage {
+    TokenUsage {
+        input_tokens: tokens,
+        cost_usd,
+        ..Default::default()
+    }
+}
+
+/// 记录一条当前时间的用量
+async fn spend(state: &AppState, user_id: &str, project_id: Option<&str>, usage: TokenUsage) {
+    state
+        .db
+        .record_usage(&UsageRecord {
+            run_id: uuid::Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            project_id: project_id.map(str::to_string),
+            agent_type: "mock".to_string(),
+            model: None,
+            usage,
+            created_at: Utc::now(),
+        })
+        .await
+        .unwrap();
+}
+
+#[test]
+fn periods_start_at_utc_midnight_and_month_start() {
+    let now = Utc.with_ymd_and_hms(2026, 2, 14, 17, 30, 5).unwrap();
+    assert_eq!(
+        BudgetPeriod::Daily.start(now),
+        Utc.with_ymd_and_hms(2026, 2, 14, 0, 0, 0).unwrap()
+    );
+    assert_eq!(
+        BudgetPeriod::Monthly.start(now),
+        Utc.with_ymd_and_hms(2026, 2, 1, 0, 0, 0).unwrap()
+    );
+    assert_eq!(BudgetPeriod::Daily.key(now), "2026-02-14");
+    assert_eq!(BudgetPeriod::Monthly.key(now), "2026-02");
+    assert_eq!(BudgetPeriod::parse("monthly"), Some(BudgetPeriod::Monthly));
+    assert_eq!(BudgetPeriod::parse("weekly"), None);
+    assert_eq!(BudgetScope::parse("project"), Some(BudgetScope::Project));
+}
+
+#[test]
+fn run_limits_report_the_first_exceeded_limit() {
+    let limit = RunLimit {
+        max_tokens: Some(100),
+        max_cost_usd: Some(0.5),
+    };
+    assert_eq!(limit.exceeded(&usage(100, Some(0.5))), None);
+    assert_eq!(
+        limit.exceeded(&usage(101, Some(0.5))).as_deref(),
+        Some("run used 101 tokens, limit is 100")
+    );
+    assert_eq!(
+        limit.exceeded(&usage(10, Some(0.75))).as_deref(),
+        Some("run cost 0.7500 USD, limit is 0.5000 USD")
+    );
+    // 没有费用信息时不按费用限制
+    assert_eq!(limit.exceeded(&usage(10, None)), None);
+    assert_eq!(RunLimit::default().exceeded(&usage(u64::MAX, None)), None);
+}
+
+#[tokio::test]
+async fn checks_cap_runs_at_the_smallest_remaining_allowance() {
+    let state = AppState::with_data_dir(&data_dir()).await;
+    let db = &state.db;
+    let budgets = &state.budgets;
+    assert_eq!(
+        budgets.check("alice", None).await.unwrap(),
+        RunLimit::default()
+    );
+
+    db.upsert_budget(&Budget {
+        max_tokens: Some(1000),
+        max_run_cost_usd: Some(2.0),
+        ..budget(BudgetScope::User, "alice", BudgetPeriod::Monthly)
+    })
+    .await
+    .unwrap();
+    db.upsert_budget(&Budget {
+        max_cost_usd: Some(1.0),
+        max_run_tokens: Some(500),
+        ..budget(BudgetScope::Project, "proj-1", BudgetPeriod::Daily)
+    })
+    .await
+    .unwrap();
+    spend(&state, "alice", None, usage(600, Some(0.25))).await;
+    // 别的用户的用量不计入
+    spend(&state, "bob", None, usage(10_000, Some(100.0))).await;
+
+    let limit = budgets.check("alice", None).await.unwrap();
+    assert_eq!(limit.max_tokens, Some(400));
+    assert_eq!(limit.max_cost_usd, Some(2.0));
+
+    spend(&state, "carol", Some("proj-1"), usage(10, Some(0.75))).await;
+    let limit = budgets.check("alice", Some("proj-1")).await.unwrap();
+    assert_eq!(limit.max_tokens, Some(400));
+    assert_eq!(limit.max_cost_usd, Some(0.25));
+
+    spend(&state, "alice", None, usage(400, None)).await;
+    let error = budgets.check("alice", None).await.unwrap_err();
+    assert!(matches!(
+        error,
+        BudgetError::Tokens {
+            used: 1000,
+            limit: 1000,
+            ..
+        }
+    ));
+    assert_eq!(
+        error.to_string(),
+        "monthly token budget of user 'alice' exhausted (1000/1000 tokens)"
+    );
+
+    spend(&state, "carol", Some("proj-1"), usage(10, Some(0.25))).await;
+    let error = budgets.check("dave", Some("proj-1")).await.unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "daily cost budget of project 'proj-1' exhausted (1.0000/1.0000 USD)"
+    );
+}
+
+#[tokio::test]
+async fn warnings_are_sent_once_per_period() {
+    let received: Arc<Mutex<Vec<Value>>> = Arc::default();
+    let app = Router::new()
+        .route(
+            "/hook",
+            post(
+                |State(received): State<Arc<Mutex<Vec<Value>>>>, Json(body): Json<Value>| async move {
+                    received.lock().unwrap().push(body);
+                    StatusCode::NO_CONTENT
+                },
+            ),
+        )
+        .with_state(received.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let hook = format!("http://{}/hook", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let state = AppState::with_data_dir(&data_dir()).await;
+    let saved = state
+        .db
+        .upsert_budget(&Budget {
+            max_tokens: Some(1000),
+            warn_ratio: Some(0.8),
+            webhook_url: Some(hook),
+            ..budget(BudgetScope::User, "alice", BudgetPeriod::Daily)
+        })
+        .await
+        .unwrap();
+    spend(&state, "alice", None, usage(500, Some(0.1))).await;
+
+    // 已记录的用量加上进行中的 Run
+    let budgets = &state.budgets;
+    assert!(budgets
+        .check_warnings("alice", None, &usage(299, None))
+        .await
+        .is_empty());
+    let warnings = budgets
+        .check_warnings("alice", None, &usage(300, None))
+        .await;
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].budget_id, saved.id);
+    assert_eq!(warnings[0].used_tokens, 800);
+    assert!(budgets
+        .check_warnings("alice", None, &usage(400, None))
+        .await
+        .is_empty());
+
+    for _ in 0..50 {
+        if !received.lock().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let sent = received.lock().unwrap().clone();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0]["type"], "budget_warning");
+    assert_eq!(sent[0]["warning"]["used_tokens"], 800);
+    assert_eq!(sent[0]["warning"]["warn_ratio"], 0.8);
+
+    // 修改预算后重新告警
+    state
+        .db
+        .upsert_budget(&Budget {
+            max_tokens: Some(1000),
+            warn_ratio: Some(0.5),
+            ..budget(BudgetScope::User, "alice", BudgetPeriod::Daily)
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        budgets
+            .check_warnings("alice", None, &usage(0, None))
+            .await
+            .len(),
+        1
+    );
+}
+
+/// 每次上报 100 token 的用量，共五次
+struct TokenAgent;
+
+#[async_trait]
+impl Agent for TokenAgent {
+    async fn run(&self, _prompt: String, tx: mpsc::Sender<StreamEvent>) -> anyhow::Result<()> {
+        for i in 1..=5 {
+            tx.send(StreamEvent::Token {
+                content: format!("{} ", i),
+            })
+            .await?;
+            tx.send(StreamEvent::Usage {
+                usage: usage(i * 100, None),
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "tokens"
+    }
+}
+
+#[tokio::test]
+async fn budgets_api_is_admin_only_and_blocks_runs() {
+    let state = AppState::with_data_dir(&data_dir()).await;
+    state
+        .registry
+        .register(AgentInfo::new("budget_tokens", "tokens"), |_| {
+            Ok(Box::new(TokenAgent))
+        });
+    let manager = state.run_manager.clone();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, create_router_with_state(state))
+            .await
+            .unwrap()
+    });
+
+    let client = reqwest::Client::new();
+    let user_id = uuid::Uuid::new_v4().to_string();
+    let user = create_token(&user_id, "tester", &[]).unwrap();
+    let admin = create_token("admin-user", "admin", &["admin".to_string()]).unwrap();
+    let send = |method: reqwest::Method, path: &str, token: &str, body: Option<Value>| {
+        let mut request = client
+            .request(method, format!("{}{}", url, path))
+            .bearer_auth(token);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        async move {
+            let response = request.send().await.unwrap();
+            let status = response.status();
+            (
+                status,
+                response.json::<Value>().await.unwrap_or(Value::Null),
+            )
+        }
+    };
+    let run = json!({ "input": { "text": "hi" }, "metadata": { "agent_type": "budget_tokens" } });
+    let user_budget = |limits: Value| {
+        let mut body = json!({
+            "scope": "user",
+            "scope_id": user_id,
+            "period": "daily",
+            "webhook_url": "https://hooks.example.com/secret-token",
+        });
+        body.as_object_mut()
+            .unwrap()
+            .extend(limits.as_object().unwrap().clone());
+        body
+    };
+
+    // 只有 admin 可以设置预算
+    let (status, _) = send(
+        reqwest::Method::POST,
+        "/api/budgets",
+        &user,
+        Some(user_budget(json!({ "max_tokens": 10 }))),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, body) = send(
+        reqwest::Method::POST,
+        "/api/budgets",
+        &admin,
+        Some(user_budget(json!({}))),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "budget must set at least one limit");
+    let (status, body) = send(
+        reqwest::Method::POST,
+        "/api/budgets",
+        &admin,
+        Some(user_budget(json!({ "max_tokens": 10, "warn_ratio": 1.5 }))),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "warn_ratio must be in (0, 1]");
+
+    // 单个 Run 超出上限时中止
+    let (status, saved) = send(
+        reqwest::Method::POST,
+        "/api/budgets",
+        &admin,
+        Some(user_budget(json!({ "max_run_tokens": 250 }))),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = send(reqwest::Method::POST, "/api/runs", &user, Some(run.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let run_id = body["run_id"].as_str().unwrap();
+    let finished = manager
+        .wait_run(run_id, Duration::from_secs(10))
+        .await
+        .unwrap();
+    assert_eq!(finished.status, RunStatus::Failed);
+    assert_eq!(
+        finished.error.as_deref(),
+        Some("budget_exceeded: run used 300 tokens, limit is 250")
+    );
+
+    // 周期额度用完后拒绝新的 Run
+    let (_, replaced) = send(
+        reqwest::Method::POST,
+        "/api/budgets",
+        &admin,
+        Some(user_budget(json!({ "max_tokens": 300 }))),
+    )
+    .await;
+    assert_eq!(replaced["id"], saved["id"]);
+    let mut status = StatusCode::OK;
+    let mut body = Value::Null;
+    for _ in 0..50 {
+        (status, body) = send(reqwest::Method::POST, "/api/runs", &user, Some(run.clone())).await;
+        if status != StatusCode::OK {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        body["error"],
+        format!(
+            "budget_exceeded: daily token budget of user '{}' exhausted (300/300 tokens)",
+            user_id
+        )
+    );
+
+    // 用户看得到自己的预算和用量，但看不到 webhook 地址
+    let (_, body) = send(reqwest::Method::GET, "/api/budgets", &user, None).await;
+    assert_eq!(body["budgets"].as_array().unwrap().len(), 1);
+    assert_eq!(body["budgets"][0]["used_tokens"], 300);
+    assert!(body["budgets"][0]["webhook_url"].is_null());
+    let (_, body) = send(reqwest::Method::GET, "/api/budgets", &admin, None).await;
+    assert!(body["budgets"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|b| b["webhook_url"] == "https://hooks.example.com/secret-token"));
+
+    let path = format!("/api/budgets/{}", saved["id"].as_str().unwrap());
+    let (status, _) = send(reqwest::Method::DELETE, &path, &user, None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send(reqwest::Method::DELETE, &path, &admin, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(reqwest::Method::DELETE, &path, &admin, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(reqwest::Method::POST, "/api/runs", &user, Some(run)).await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,333 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Router,
+};
+use futures::StreamExt;
+use openrunner::agent::{
+    Agent, CircuitBreakerConfig, CircuitState, GatewayAgent, GatewayConfig, LoadBalancing,
+    ProviderHealth, GATEWAY_MANAGER,
+};
+use openrunner::types::{AgentConfig, AgentRequest, RetryConfig, StreamEvent};
+use tokio::sync::{mpsc, Notify};
+
+/// stub provider 对下一个请求的响应方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// 完整返回 provider 名称
+    Ok,
+    /// 500
+    Fail,
+    /// 输出一个 token 后断开连接
+    Partial,
+    /// 等到 `release` 通知后再返回
+    Hold,
+}
+
+struct Stub {
+    name: String,
+    mode: Mutex<Mode>,
+    requests: AtomicUsize,
+    release: Notify,
+}
+
+impl Stub {
+    fn set(&self, mode: Mode) {
+        *self.mode.lock().unwrap() = mode;
+    }
+
+    fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+fn chunk(content: &str) -> String {
+    format!(
+        "data: {}\n\n",
+        serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 1,
+            "model": "stub",
+            "choices": [{ "index": 0, "delta": { "content": content }, "finish_reason": null }]
+        })
+    )
+}
+
+async fn chat_completions(State(stub): State<Arc<Stub>>) -> Response {
+    stub.requests.fetch_add(1, Ordering::SeqCst);
+    let mode = *stub.mode.lock().unwrap();
+    match mode {
+        Mode::Fail => return (StatusCode::INTERNAL_SERVER_ERROR, "upstream down").into_response(),
+        Mode::Hold => stub.release.notified().await,
+        Mode::Ok | Mode::Partial => {}
+    }
+
+    let body = if mode == Mode::Partial {
+        // 第一个 token 发出后连接异常中断
+        let parts: Vec<Result<Bytes, std::io::Error>> = vec![
+            Ok(Bytes::from(chunk("partial "))),
+            Err(std::io::Error::other("connection reset")),
+        ];
+        Body::from_stream(futures::stream::iter(parts).then(|part| async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            part
+        }))
+    } else {
+        Body::from(format!("{}data: [DONE]\n\n", chunk(&stub.name)))
+    };
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .body(body)
+        .unwrap()
+}
+
+/// 启动一个 OpenAI 兼容的 stub provider，并以随机名称注册到 `GATEWAY_MANAGER`
+async fn stub(prefix: &str, mode: Mode) -> Arc<Stub> {
+    let name = format!(
+        "{}-{}",
+        prefix,
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    );
+    let stub = Arc::new(Stub {
+        name: name.clone(),
+        mode: Mutex::new(mode),
+        requests: AtomicUsize::new(0),
+        release: Notify::new(),
+    });
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = Router::new()
+        .route("/v1/chat/completions", post(chat_completions))
+        .with_state(stub.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    GATEWAY_MANAGER.register_provider(
+        name,
+        GatewayConfig {
+            provider: "openai".to_string(),
+            model: Some("stub".to_string()),
+            api_key: Some("sk-stub".to_string()),
+            base_url: Some(format!("http://{}/v1", addr)),
+            fallback_providers: vec![],
+            load_balancing: None,
+            restart_on_failure: false,
+            circuit_breaker: CircuitBreakerConfig::default(),
+            rate_limit: None,
+        },
+    );
+    stub
+}
+
+/// 以 `primary` 的注册配置为主、`fallbacks` 为备选的 gateway agent
+fn gateway(
+    primary: &Stub,
+    fallbacks: &[&Stub],
+    edit: impl FnOnce(&mut GatewayConfig),
+) -> GatewayAgent {
+    let mut config = GATEWAY_MANAGER.get_provider(&primary.name).unwrap();
+    config.fallback_providers = fallbacks.iter().map(|s| s.name.clone()).collect();
+    edit(&mut config);
+    // 只测试 provider 之间的切换，不在同一个 provider 上重试
+    let base = AgentConfig {
+        retry: RetryConfig {
+            max_retries: 0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    GatewayAgent::new(config)
+        .named(primary.name.clone())
+        .with_base_config(base)
+}
+
+async fn run(agent: &GatewayAgent) -> (anyhow::Result<()>, Vec<StreamEvent>) {
+    let (tx, mut rx) = mpsc::channel(100);
+    let result = agent.execute(AgentRequest::new("hi".to_string()), tx).await;
+    let mut events = Vec::new();
+    while let Some(event) = rx.recv().await {
+        events.push(event);
+    }
+    (result, events)
+}
+
+fn text(events: &[StreamEvent]) -> String {
+    events
+        .iter()
+        .filter_map(|e| match e {
+            StreamEvent::Token { content } => Some(content.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn fails_over_before_the_first_token() {
+    let primary = stub("down", Mode::Fail).await;
+    let fallback = stub("up", Mode::Ok).await;
+
+    let (result, events) = run(&gateway(&primary, &[&fallback], |_| {})).await;
+    result.unwrap();
+    assert_eq!(text(&events), fallback.name);
+    // 失败的尝试不会把错误事件交给调用方
+    assert!(!events
+        .iter()
+        .any(|e| matches!(e, StreamEvent::Error { .. })));
+    assert_eq!(primary.requests(), 1);
+    assert_eq!(fallback.requests(), 1);
+}
+
+#[tokio::test]
+async fn does_not_splice_after_partial_output() {
+    let primary = stub("partial", Mode::Partial).await;
+    let fallback = stub("up", Mode::Ok).await;
+
+    let (result, events) = run(&gateway(&primary, &[&fallback], |_| {})).await;
+    assert!(result.is_err());
+    assert_eq!(text(&events), "partial ");
+    assert_eq!(fallback.requests(), 0);
+}
+
+#[tokio::test]
+async fn restarts_with_a_marker_when_enabled() {
+    let primary = stub("partial", Mode::Partial).await;
+    let fallback = stub("up", Mode::Ok).await;
+
+    let agent = gateway(&primary, &[&fallback], |c| c.restart_on_failure = true);
+    let (result, events) = run(&agent).await;
+    result.unwrap();
+
+    let restart = events
+        .iter()
+        .position(
+            |e| matches!(e, StreamEvent::Restart { provider, .. } if *provider == primary.name),
+        )
+        .expect("restart marker");
+    assert_eq!(text(&events[..restart]), "partial ");
+    assert_eq!(text(&events[restart..]), fallback.name);
+}
+
+#[tokio::test]
+async fn circuit_opens_and_recovers_through_a_probe() {
+    let primary = stub("flaky", Mode::Fail).await;
+    let fallback = stub("up", Mode::Ok).await;
+    let agent = gateway(&primary, &[&fallback], |c| {
+        c.circuit_breaker = CircuitBreakerConfig {
+            failure_threshold: 2,
+            cooldown_ms: 200,
+        }
+    });
+    let health = GATEWAY_MANAGER.provider_health(&primary.name);
+
+    for _ in 0..2 {
+        run(&agent).await.0.unwrap();
+    }
+    assert_eq!(health.state(), CircuitState::Open);
+
+    // 熔断期间直接跳过
+    let (result, events) = run(&agent).await;
+    result.unwrap();
+    assert_eq!(text(&events), fallback.name);
+    assert_eq!(primary.requests(), 2);
+
+    // 冷却后放行一个探测请求，失败则重新熔断
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert_eq!(health.state(), CircuitState::HalfOpen);
+    run(&agent).await.0.unwrap();
+    assert_eq!(primary.requests(), 3);
+    assert_eq!(health.state(), CircuitState::Open);
+
+    // 探测成功后恢复
+    primary.set(Mode::Ok);
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    let (result, events) = run(&agent).await;
+    result.unwrap();
+    assert_eq!(text(&events), primary.name);
+    assert_eq!(health.state(), CircuitState::Closed);
+}
+
+#[tokio::test]
+async fn only_one_half_open_probe_at_a_time() {
+    let config = CircuitBreakerConfig {
+        failure_threshold: 1,
+        cooldown_ms: 20,
+    };
+    let health = Arc::new(ProviderHealth::default());
+    health.record_failure(&config);
+    assert!(health.acquire().is_none());
+
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    let probe = health.acquire().unwrap();
+    assert!(health.acquire().is_none());
+    assert_eq!(health.in_flight(), 1);
+
+    // 探测没有结果就结束时，下一个请求接着探测
+    drop(probe);
+    assert_eq!(health.in_flight(), 0);
+    let probe = health.acquire().unwrap();
+    health.record_success(Duration::from_millis(10));
+    drop(probe);
+    assert_eq!(health.state(), CircuitState::Closed);
+    assert_eq!(health.latency(), Some(Duration::from_millis(10)));
+}
+
+#[tokio::test]
+async fn least_loaded_avoids_busy_providers() {
+    let busy = stub("busy", Mode::Hold).await;
+    let idle = stub("idle", Mode::Ok).await;
+    let agent = Arc::new(gateway(&busy, &[&idle], |c| {
+        c.load_balancing = Some(LoadBalancing::LeastLoaded)
+    }));
+
+    // 两者都空闲时选第一个
+    let first = tokio::spawn({
+        let agent = agent.clone();
+        async move { run(&agent).await }
+    });
+    while GATEWAY_MANAGER.provider_health(&busy.name).in_flight() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let (result, events) = run(&agent).await;
+    result.unwrap();
+    assert_eq!(text(&events), idle.name);
+
+    busy.release.notify_one();
+    let (result, events) = first.await.unwrap();
+    result.unwrap();
+    assert_eq!(text(&events), busy.name);
+    assert_eq!(GATEWAY_MANAGER.provider_health(&busy.name).in_flight(), 0);
+}
+
+#[tokio::test]
+async fn latency_weighted_prefers_faster_providers() {
+    let slow = stub("slow", Mode::Ok).await;
+    let fast = stub("fast", Mode::Ok).await;
+    GATEWAY_MANAGER
+        .provider_health(&slow.name)
+        .record_success(Duration::from_secs(2));
+    GATEWAY_MANAGER
+        .provider_health(&fast.name)
+        .record_success(Duration::from_millis(1));
+
+    let agent = gateway(&slow, &[&fast], |c| {
+        c.load_balancing = Some(LoadBalancing::LatencyWeighted)
+    });
+    for _ in 0..10 {
+        run(&agent).await.0.unwrap();
+    }
+    assert!(
+        fast.requests() >= 8,
+        "fast: {}, slow: {}",
+        fast.requests(),
+        slow.requests()
+    );
+}
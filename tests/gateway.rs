@@ -0,0 +1,258 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::Response,
+    routing::post,
+    Json, Router,
+};
+use openrunner::agent::{Agent, GatewayAgent, GatewayConfig, RouteRule, GATEWAY_MANAGER};
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::types::{AgentConfig, AgentRequest};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+const OPENAI_STREAM: &str = concat!(
+    "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"from openai\"},\"finish_reason\":null}]}\n\n",
+    "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+    "data: [DONE]\n\n",
+);
+
+const ANTHROPIC_STREAM: &str = concat!(
+    "event: message_start\n",
+    "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":5,\"output_tokens\":1}}}\n\n",
+    "event: content_block_start\n",
+    "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"from anthropic\"}}\n\n",
+    "event: content_block_stop\n",
+    "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+    "event: message_delta\n",
+    "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":2}}\n\n",
+    "event: message_stop\n",
+    "data: {\"type\":\"message_stop\"}\n\n",
+);
+
+/// 上游收到的请求：路径、凭据和请求体
+#[derive(Debug, Clone)]
+struct Received {
+    path: &'static str,
+    credential: String,
+    body: Value,
+}
+
+type Requests = Arc<Mutex<Vec<Received>>>;
+
+fn stream(body: &'static str) -> Response {
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+async fn chat_completions(
+    State(requests): State<Requests>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    requests.lock().unwrap().push(Received {
+        path: "/chat/completions",
+        credential: headers[header::AUTHORIZATION].to_str().unwrap().to_string(),
+        body,
+    });
+    stream(OPENAI_STREAM)
+}
+
+async fn messages(
+    State(requests): State<Requests>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    requests.lock().unwrap().push(Received {
+        path: "/messages",
+        credential: headers["x-api-key"].to_str().unwrap().to_string(),
+        body,
+    });
+    stream(ANTHROPIC_STREAM)
+}
+
+async fn listen(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+/// 启动同时提供 OpenAI 和 Anthropic 接口的上游 mock
+async fn upstream() -> (String, Requests) {
+    let requests = Requests::default();
+    let url = listen(
+        Router::new()
+            .route("/v1/chat/completions", post(chat_completions))
+            .route("/v1/messages", post(messages))
+            .with_state(requests.clone()),
+    )
+    .await;
+    (format!("{}/v1", url), requests)
+}
+
+/// `GATEWAY_MANAGER` 是全局的，名称带随机后缀以免测试之间互相影响
+fn unique(prefix: &str) -> String {
+    format!(
+        "{}-{}",
+        prefix,
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    )
+}
+
+fn register(name: &str, provider: &str, api_key: &str, base_url: &str) {
+    GATEWAY_MANAGER.register_provider(
+        name.to_string(),
+        GatewayConfig {
+            provider: provider.to_string(),
+            model: Some(format!("{}-default", provider)),
+            api_key: Some(api_key.to_string()),
+            base_url: Some(base_url.to_string()),
+            fallback_providers: vec![],
+            load_balancing: None,
+            restart_on_failure: false,
+            circuit_breaker: Default::default(),
+            rate_limit: None,
+        },
+    );
+}
+
+#[tokio::test]
+async fn resolves_prefix_alias_and_rules() {
+    let openai = unique("oa");
+    let anthropic = unique("an");
+    register(&openai, "openai", "sk-oa", "http://127.0.0.1:9/v1");
+    register(&anthropic, "anthropic", "sk-an", "http://127.0.0.1:9/v1");
+    let family = unique("claude");
+    // 未注册的 provider 跳过，继续匹配下一条
+    GATEWAY_MANAGER.add_route(RouteRule {
+        pattern: format!("{}-*-beta", family),
+        provider: unique("missing"),
+    });
+    GATEWAY_MANAGER.add_route(RouteRule {
+        pattern: format!("{}-*", family),
+        provider: anthropic.clone(),
+    });
+    let alias = unique("fast");
+    GATEWAY_MANAGER.register_alias(alias.clone(), format!("{}/gpt-4o-mini", openai));
+
+    // provider 前缀：去掉前缀后作为上游模型名，模型名中的其他 `/` 保留
+    let route = GATEWAY_MANAGER
+        .resolve(&format!("{}/openai/gpt-4o", openai))
+        .unwrap();
+    assert_eq!(route.name, openai);
+    assert_eq!(route.config.provider, "openai");
+    assert_eq!(route.config.model.as_deref(), Some("openai/gpt-4o"));
+    assert_eq!(route.config.api_key.as_deref(), Some("sk-oa"));
+
+    // 只有前缀时使用 provider 的默认模型
+    let route = GATEWAY_MANAGER.resolve(&format!("{}/", openai)).unwrap();
+    assert_eq!(route.config.model.as_deref(), Some("openai-default"));
+
+    let route = GATEWAY_MANAGER.resolve(&alias).unwrap();
+    assert_eq!(route.name, openai);
+    assert_eq!(route.config.model.as_deref(), Some("gpt-4o-mini"));
+
+    let model = format!("{}-sonnet-4-beta", family);
+    let route = GATEWAY_MANAGER.resolve(&model).unwrap();
+    assert_eq!(route.name, anthropic);
+    assert_eq!(route.config.model.as_deref(), Some(model.as_str()));
+
+    let err = GATEWAY_MANAGER
+        .resolve(&unique("unknown"))
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("No provider"), "{}", err);
+}
+
+#[tokio::test]
+async fn chat_completions_reach_the_routed_provider() {
+    let (base_url, requests) = upstream().await;
+    let openai = unique("oa");
+    let anthropic = unique("an");
+    register(&openai, "openai", "sk-oa", &base_url);
+    register(&anthropic, "anthropic", "sk-an", &base_url);
+    let family = unique("claude");
+    GATEWAY_MANAGER.add_route(RouteRule {
+        pattern: format!("{}-*", family),
+        provider: anthropic.clone(),
+    });
+
+    let data_dir = std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()));
+    let url = listen(create_router_with_state(
+        AppState::with_data_dir(&data_dir).await,
+    ))
+    .await;
+    let chat = |model: String| {
+        let url = url.clone();
+        async move {
+            let response = reqwest::Client::new()
+                .post(format!("{}/v1/chat/completions", url))
+                .json(&json!({
+                    "model": model,
+                    "messages": [{ "role": "user", "content": "hi" }]
+                }))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: Value = response.json().await.unwrap();
+            body["choices"][0]["message"]["content"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        }
+    };
+
+    assert_eq!(chat(format!("{}/gpt-4o", openai)).await, "from openai");
+    let model = format!("{}-sonnet-4", family);
+    assert_eq!(chat(model.clone()).await, "from anthropic");
+
+    // 每个 provider 收到自己的凭据和去掉前缀的模型名
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].path, "/chat/completions");
+    assert_eq!(requests[0].credential, "Bearer sk-oa");
+    assert_eq!(requests[0].body["model"], "gpt-4o");
+    assert_eq!(requests[1].path, "/messages");
+    assert_eq!(requests[1].credential, "sk-an");
+    assert_eq!(requests[1].body["model"], model);
+}
+
+#[tokio::test]
+async fn caller_env_cannot_replace_provider_credentials() {
+    let (base_url, requests) = upstream().await;
+    let (other_url, other_requests) = upstream().await;
+    let openai = unique("oa");
+    register(&openai, "openai", "sk-oa", &base_url);
+
+    let caller = AgentConfig {
+        env: [
+            ("OPENAI_API_KEY", "sk-caller"),
+            ("OPENAI_BASE_URL", other_url.as_str()),
+            ("ANTHROPIC_API_KEY", "sk-caller"),
+            ("CUSTOM_FLAG", "1"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect(),
+        ..Default::default()
+    };
+    let agent = GatewayAgent::new(GATEWAY_MANAGER.get_provider(&openai).unwrap())
+        .named(openai.clone())
+        .with_base_config(caller);
+    let (tx, _rx) = mpsc::channel(100);
+    agent.execute(AgentRequest::new("hi"), tx).await.unwrap();
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].credential, "Bearer sk-oa");
+    assert!(other_requests.lock().unwrap().is_empty());
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use openrunner::agent::{
+    load_agent_definitions, Agent, AgentDefinition, AgentRegistry, GenericCliAgent, OutputFormat,
+    PromptInput,
+};
+use openrunner::types::{AgentConfig, StreamEvent};
+use tokio::sync::mpsc;
+
+/// 以 `sh -c <script>` 运行的定义；脚本中 `$@` 是 OpenRunner 追加的参数
+fn definition(script: &str) -> AgentDefinition {
+    toml::from_str::<AgentDefinition>(&format!(
+        "name = \"script\"\nbinary = \"sh\"\nargs = [\"-c\", {:?}, \"sh\"]",
+        script
+    ))
+    .unwrap()
+}
+
+/// 运行 agent，返回输出的文本和结果
+async fn run(definition: AgentDefinition, config: AgentConfig) -> (String, anyhow::Result<()>) {
+    let agent = GenericCliAgent::new(definition, config);
+    let (tx, mut rx) = mpsc::channel(16);
+    let collect = tokio::spawn(async move {
+        let mut text = String::new();
+        while let Some(event) = rx.recv().await {
+            if let StreamEvent::Token { content } = event {
+                text.push_str(&content);
+            }
+        }
+        text
+    });
+    let result = tokio::time::timeout(Duration::from_secs(10), agent.run("hello".into(), tx))
+        .await
+        .expect("agent run timed out");
+    (collect.await.unwrap(), result)
+}
+
+#[tokio::test]
+async fn arguments_are_built_from_the_definition() {
+    let mut def = definition(r#"for a in "$@"; do echo "[$a]"; done"#);
+    def.model_flag = Some("--model".to_string());
+    def.cwd_flag = Some("--cwd".to_string());
+    def.prompt = PromptInput::Flag {
+        flag: "-p".to_string(),
+    };
+    let config = AgentConfig {
+        model: Some("m1".to_string()),
+        working_dir: Some("/tmp".to_string()),
+        extra_args: vec!["--verbose".to_string()],
+        ..Default::default()
+    };
+    let (text, result) = run(def, config).await;
+    result.unwrap();
+    assert_eq!(
+        text,
+        "[--model]\n[m1]\n[--cwd]\n[/tmp]\n[--verbose]\n[-p]\n[hello]\n"
+    );
+}
+
+#[tokio::test]
+async fn prompts_can_be_written_to_stdin() {
+    let mut def = definition(r#"echo "args: $#"; cat"#);
+    def.prompt = PromptInput::Stdin;
+    let (text, result) = run(def, AgentConfig::default()).await;
+    result.unwrap();
+    assert_eq!(text, "args: 0\nhello\n");
+}
+
+#[tokio::test]
+async fn definition_env_expands_request_env() {
+    let mut def = definition(r#"echo "$GREETING""#);
+    def.env = HashMap::from([("GREETING".to_string(), "hi ${NAME}${MISSING}!".to_string())]);
+    let config = AgentConfig {
+        env: HashMap::from([("NAME".to_string(), "there".to_string())]),
+        ..Default::default()
+    };
+    let (text, result) = run(def, config).await;
+    result.unwrap();
+    assert_eq!(text, "hi there!\n");
+}
+
+#[tokio::test]
+async fn jsonl_output_is_filtered_by_event_type() {
+    let mut def = definition(
+        r#"echo 'not json'
+echo '{"type":"text","part":{"text":"a"}}'
+echo '{"type":"tool","part":{"text":"skipped"}}'
+echo '{"type":"text","part":{"text":["b"]}}'
+echo '{"type":"error","error":{"message":"boom"}}'
+echo '{"type":"text","part":{"text":"c"}}'"#,
+    );
+    def.output = OutputFormat::Jsonl {
+        text_path: "part.text".to_string(),
+        error_path: Some("error.message".to_string()),
+        type_path: Some("type".to_string()),
+        text_types: vec!["text".to_string()],
+    };
+    let (text, result) = run(def, AgentConfig::default()).await;
+    assert_eq!(text, "a[\"b\"]c");
+    assert_eq!(result.unwrap_err().to_string(), "script error: boom");
+}
+
+#[tokio::test]
+async fn large_stderr_output_does_not_block_the_agent() {
+    // 超过管道缓冲区的 stderr 输出之后才写 stdout
+    let def = definition(
+        r#"i=0; while [ $i -lt 4000 ]; do echo "progress line $i ................" >&2; i=$((i+1)); done
+echo done; echo "fatal: bad" >&2; exit 3"#,
+    );
+    let (text, result) = run(def, AgentConfig::default()).await;
+    assert_eq!(text, "done\n");
+    let error = result.unwrap_err().to_string();
+    assert!(error.starts_with("script failed: progress line 0"));
+    assert!(error.ends_with("fatal: bad"));
+
+    let (_, result) = run(definition("exit 4"), AgentConfig::default()).await;
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "script exited with status: exit status: 4"
+    );
+}
+
+#[test]
+fn definitions_are_loaded_from_toml_and_skip_conflicts() {
+    let path =
+        std::env::temp_dir().join(format!("openrunner-agents-{}.toml", uuid::Uuid::new_v4()));
+    std::fs::write(
+        &path,
+        r#"
+[[agents]]
+name = "mock"
+binary = "mock-cli"
+
+[[agents]]
+name = "my_cli"
+binary = "my-cli"
+install = "pip install my-cli"
+prompt = { mode = "flag", flag = "-p" }
+output = { format = "jsonl", text_path = "text" }
+"#,
+    )
+    .unwrap();
+    std::env::set_var("OPENRUNNER_AGENTS_FILE", &path);
+
+    let registry = AgentRegistry::with_builtins();
+    assert_eq!(load_agent_definitions(&registry).unwrap(), 1);
+    let info = registry.info("my_cli").unwrap();
+    assert_eq!(info.install.as_deref(), Some("pip install my-cli"));
+    let agent = registry
+        .create(&AgentConfig {
+            agent_type: "my_cli".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(agent.name(), "my_cli");
+}
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use axum::{http::StatusCode, routing::get, Json, Router};
+use dashmap::DashMap;
+use openrunner::agent::{
+    version_probe, AgentInfo, AgentRegistry, GatewayConfig, GatewayManager, HealthMonitor,
+    MockAgent, GATEWAY_MANAGER,
+};
+use serde_json::json;
+
+async fn listen(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+fn provider(base_url: &str) -> GatewayConfig {
+    GatewayConfig {
+        provider: "openai".to_string(),
+        model: Some("stub".to_string()),
+        api_key: Some("sk-stub".to_string()),
+        base_url: Some(base_url.to_string()),
+        fallback_providers: vec![],
+        load_balancing: None,
+        restart_on_failure: false,
+        circuit_breaker: Default::default(),
+        rate_limit: None,
+    }
+}
+
+fn command(command: &[&str]) -> Vec<String> {
+    command.iter().map(|c| c.to_string()).collect()
+}
+
+#[tokio::test]
+async fn agents_report_cached_status_after_a_refresh() {
+    let registry = AgentRegistry::new();
+    registry.register_with_probe(
+        AgentInfo::new("ok", "sh"),
+        |c| Ok(Box::new(MockAgent::new(c.clone()))),
+        version_probe(command(&["sh", "-c", "echo 2.0.1"]), None),
+    );
+    registry.register_with_probe(
+        AgentInfo::new("broken", "sh"),
+        |c| Ok(Box::new(MockAgent::new(c.clone()))),
+        version_probe(command(&["sh", "-c", "exit 2"]), None),
+    );
+    let monitor = HealthMonitor::new(registry.clone());
+
+    let before = monitor.agents();
+    assert_eq!(before.len(), 2);
+    assert!(before.iter().all(|h| !h.status.available
+        && h.status.last_checked.is_none()
+        && h.status.error.as_deref() == Some("Health check pending")));
+
+    monitor.refresh().await;
+    let after = monitor.agents();
+    let ok = &after[0].status;
+    assert_eq!(after[0].info.agent_type, "ok");
+    assert!(ok.available);
+    assert_eq!(ok.version.as_deref(), Some("2.0.1"));
+    assert!(ok.latency_ms.is_some());
+    assert_eq!(ok.last_success, ok.last_checked);
+
+    let broken = &after[1].status;
+    assert!(!broken.available);
+    assert!(broken
+        .error
+        .as_deref()
+        .unwrap()
+        .starts_with("sh not available"));
+    assert!(broken.last_checked.is_some() && broken.last_success.is_none());
+
+    // 注销的 agent 不再出现
+    registry.unregister("broken");
+    monitor.refresh().await;
+    assert_eq!(monitor.agents().len(), 1);
+}
+
+#[tokio::test]
+async fn user_providers_are_checked_per_scope() {
+    let healthy = listen(Router::new().route(
+        "/v1/models",
+        get(|| async { Json(json!({ "object": "list", "data": [] })) }),
+    ))
+    .await;
+    let failing = listen(Router::new().route(
+        "/v1/models",
+        get(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+    ))
+    .await;
+
+    let scopes: Arc<DashMap<String, GatewayManager>> = Arc::new(DashMap::new());
+    let alice = GATEWAY_MANAGER.scoped();
+    alice.register_provider("up".to_string(), provider(&format!("{}/v1", healthy)));
+    alice.register_provider("down".to_string(), provider(&format!("{}/v1", failing)));
+    scopes.insert("alice".to_string(), alice.clone());
+    let bob = GATEWAY_MANAGER.scoped();
+    bob.register_provider("up".to_string(), provider(&format!("{}/v1", failing)));
+    scopes.insert("bob".to_string(), bob);
+
+    let monitor = HealthMonitor::new(AgentRegistry::new()).with_scopes(scopes.clone());
+    assert!(monitor.user_providers("carol").is_empty());
+    monitor.refresh().await;
+
+    let alice_status = monitor.user_providers("alice");
+    assert_eq!(
+        alice_status
+            .iter()
+            .map(|(name, status)| (name.as_str(), status.available))
+            .collect::<Vec<_>>(),
+        [("down", false), ("up", true)]
+    );
+    // 同名 provider 按用户分别记录
+    let bob_status = monitor.user_providers("bob");
+    assert_eq!(bob_status.len(), 1);
+    assert!(!bob_status[0].1.available);
+    // 用户 provider 不出现在全局列表中
+    assert!(!monitor
+        .providers()
+        .iter()
+        .any(|(name, _)| name == "up" || name == "down"));
+
+    alice.remove_provider("down");
+    monitor.refresh().await;
+    assert_eq!(monitor.user_providers("alice").len(), 1);
+}
@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use openrunner::agent::{McpClient, ToolBox, ToolCall};
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::auth::create_token;
+use openrunner::types::{AgentConfig, McpServerConfig, StreamEvent};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+/// 按 method 返回固定响应的 stdio server；tools/call 回显环境变量
+const STDIO_SERVER: &str = r#"
+while read -r line; do
+  id=$(printf '%s' "$line" | sed -n 's/.*"id":\([0-9]*\).*/\1/p')
+  case "$line" in
+    *'"method":"initialize"'*)
+      echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"protocolVersion\":\"2025-06-18\",\"capabilities\":{\"tools\":{}}}}" ;;
+    *'"method":"tools/list"'*'"cursor"'*)
+      echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"tools\":[{\"name\":\"fail\"}]}}" ;;
+    *'"method":"tools/list"'*)
+      echo '{"jsonrpc":"2.0","method":"notifications/tools/list_changed"}'
+      echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"tools\":[{\"name\":\"say.hi\",\"description\":\"Greets\",\"inputSchema\":{\"type\":\"object\",\"properties\":{\"who\":{\"type\":\"string\"}}}}],\"nextCursor\":\"2\"}}" ;;
+    *'"name":"fail"'*)
+      echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"content\":[{\"type\":\"text\",\"text\":\"it broke\"}],\"isError\":true}}" ;;
+    *'"method":"tools/call"'*)
+      echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":{\"content\":[{\"type\":\"text\",\"text\":\"$GREETING\"},{\"type\":\"text\",\"text\":\"${OPENRUNNER_MCP_TEST_SECRET:-unset}\"}]}}" ;;
+    *'"id"'*)
+      echo "{\"jsonrpc\":\"2.0\",\"id\":$id,\"error\":{\"code\":-32601,\"message\":\"Method not found\"}}" ;;
+  esac
+done
+"#;
+
+fn stdio_server(name: &str) -> McpServerConfig {
+    McpServerConfig {
+        name: name.to_string(),
+        command: Some("sh".to_string()),
+        args: vec!["-c".to_string(), STDIO_SERVER.to_string()],
+        env: HashMap::from([("GREETING".to_string(), "hello".to_string())]),
+        url: None,
+        headers: HashMap::new(),
+    }
+}
+
+fn http_server(name: &str, url: &str) -> McpServerConfig {
+    McpServerConfig {
+        name: name.to_string(),
+        command: None,
+        args: vec![],
+        env: HashMap::new(),
+        url: Some(url.to_string()),
+        headers: HashMap::from([("Authorization".to_string(), "Bearer mcp-token".to_string())]),
+    }
+}
+
+#[test]
+fn server_configs_are_validated_and_merged() {
+    assert!(stdio_server("files_1").validate().is_ok());
+    assert!(http_server("remote", "http://localhost/mcp")
+        .validate()
+        .is_ok());
+    assert_eq!(
+        stdio_server("bad name").validate().unwrap_err(),
+        "Invalid MCP server name 'bad name': use letters, numbers, hyphens and underscores"
+    );
+    let mut both = stdio_server("both");
+    both.url = Some("http://localhost/mcp".to_string());
+    assert_eq!(
+        both.validate().unwrap_err(),
+        "MCP server 'both' needs exactly one of command or url"
+    );
+
+    // 同名 server 由后者替换，其余保留
+    let base = vec![stdio_server("a"), stdio_server("b")];
+    let overrides = vec![http_server("b", "http://localhost/mcp"), stdio_server("c")];
+    let merged = McpServerConfig::merge(&base, &overrides);
+    assert_eq!(
+        merged.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+        ["a", "b", "c"]
+    );
+    assert!(merged[1].url.is_some());
+}
+
+#[tokio::test]
+async fn stdio_servers_run_with_only_their_own_env() {
+    std::env::set_var("OPENRUNNER_MCP_TEST_SECRET", "server-secret");
+    let client = McpClient::connect(&stdio_server("local"), None, None)
+        .await
+        .unwrap();
+    assert_eq!(client.name(), "local");
+
+    // 跟随分页游标；缺少 inputSchema 时使用空对象
+    let tools = client.list_tools().await.unwrap();
+    assert_eq!(
+        tools.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(),
+        ["say.hi", "fail"]
+    );
+    assert_eq!(tools[0].description, "Greets");
+    assert_eq!(tools[1].input_schema, json!({ "type": "object" }));
+
+    let output = client
+        .call_tool("say.hi", json!({ "who": "me" }))
+        .await
+        .unwrap();
+    assert_eq!(output, "hello\nunset");
+    let error = client.call_tool("fail", Value::Null).await.unwrap_err();
+    assert_eq!(error.to_string(), "it broke");
+    let error = client.call_tool("say.hi", json!([1])).await.unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Tool arguments must be a JSON object, got: [1]"
+    );
+}
+
+#[derive(Clone, Default)]
+struct Recorded {
+    requests: Arc<Mutex<Vec<(HeaderMap, Value)>>>,
+}
+
+/// Streamable HTTP server：initialize 分配会话，tools/call 以 SSE 返回
+async fn http_mcp(
+    State(recorded): State<Recorded>,
+    headers: HeaderMap,
+    Json(message): Json<Value>,
+) -> Response {
+    recorded
+        .requests
+        .lock()
+        .unwrap()
+        .push((headers, message.clone()));
+    let id = message["id"].clone();
+    match message["method"].as_str().unwrap() {
+        "initialize" => (
+            [("Mcp-Session-Id", "session-1")],
+            Json(json!({ "jsonrpc": "2.0", "id": id, "result": { "capabilities": {} } })),
+        )
+            .into_response(),
+        "tools/list" => Json(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": { "tools": [{ "name": "lookup", "inputSchema": { "type": "object" } }] },
+        }))
+        .into_response(),
+        "tools/call" => {
+            let progress =
+                json!({ "jsonrpc": "2.0", "method": "notifications/progress", "params": {} });
+            let other = json!({ "jsonrpc": "2.0", "id": 999, "result": {} });
+            let response = json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": { "content": [], "structuredContent": { "answer": 42 } },
+            });
+            Response::builder()
+                .header(header::CONTENT_TYPE, "text/event-stream")
+                .body(Body::from(format!(
+                    "data: {}\n\ndata: {}\n\ndata: {}\n\n",
+                    progress, other, response
+                )))
+                .unwrap()
+        }
+        _ => StatusCode::ACCEPTED.into_response(),
+    }
+}
+
+async fn serve_http() -> (String, Recorded) {
+    let recorded = Recorded::default();
+    let app = Router::new()
+        .route("/mcp", post(http_mcp))
+        .with_state(recorded.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (format!("http://{}/mcp", addr), recorded)
+}
+
+#[tokio::test]
+async fn http_servers_keep_the_session_and_read_sse_responses() {
+    let (url, recorded) = serve_http().await;
+    let client = McpClient::connect(&http_server("remote", &url), None, None)
+        .await
+        .unwrap();
+    let output = client.call_tool("lookup", json!({})).await.unwrap();
+    assert_eq!(output, r#"{"answer":42}"#);
+
+    let requests = recorded.requests.lock().unwrap();
+    let methods: Vec<_> = requests
+        .iter()
+        .map(|(_, m)| m["method"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        methods,
+        ["initialize", "notifications/initialized", "tools/call"]
+    );
+    for (i, (headers, _)) in requests.iter().enumerate() {
+        assert_eq!(headers["authorization"], "Bearer mcp-token");
+        assert_eq!(headers["mcp-protocol-version"], "2025-06-18");
+        // 拿到会话后的请求都带上会话 id
+        assert_eq!(
+            headers.get("mcp-session-id").is_some(),
+            i > 0,
+            "request {}",
+            i
+        );
+    }
+}
+
+#[tokio::test]
+async fn toolbox_exposes_mcp_tools_with_prefixed_names() {
+    let (url, _) = serve_http().await;
+    let config = AgentConfig {
+        mcp_servers: vec![
+            stdio_server("local"),
+            http_server("remote", &url),
+            // 连接失败的 server 被跳过
+            http_server("down", "http://127.0.0.1:1/mcp"),
+        ],
+        ..Default::default()
+    };
+    // 没有工作目录时只提供 MCP 工具
+    let toolbox = ToolBox::new(&config, None).await.unwrap();
+    let names: Vec<_> = toolbox.definitions().into_iter().map(|d| d.name).collect();
+    assert_eq!(
+        names,
+        [
+            "mcp__local__say_hi",
+            "mcp__local__fail",
+            "mcp__remote__lookup"
+        ]
+    );
+
+    let (tx, mut rx) = mpsc::channel(10);
+    let call = ToolCall {
+        id: "call-1".to_string(),
+        name: "mcp__local__say_hi".to_string(),
+        input: json!({}),
+    };
+    let result = toolbox.call(&call, &tx).await;
+    assert!(result.ok);
+    assert_eq!(result.output, "hello\nunset");
+    assert!(matches!(
+        rx.recv().await,
+        Some(StreamEvent::ToolCallStarted { name, .. }) if name == "mcp__local__say_hi"
+    ));
+
+    let only_down = AgentConfig {
+        mcp_servers: vec![http_server("down", "http://127.0.0.1:1/mcp")],
+        ..Default::default()
+    };
+    assert!(ToolBox::new(&only_down, None).await.is_none());
+}
+
+#[tokio::test]
+async fn stdio_servers_are_checked_against_the_policy() {
+    let dir = std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()));
+    let state = AppState::with_data_dir(&dir).await;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, create_router_with_state(state))
+            .await
+            .unwrap()
+    });
+    let client = reqwest::Client::new();
+    let token = create_token(&uuid::Uuid::new_v4().to_string(), "tester", &[]).unwrap();
+    let post = |path: &str, body: Value| {
+        client
+            .post(format!("{}{}", url, path))
+            .bearer_auth(&token)
+            .json(&body)
+            .send()
+    };
+
+    // 默认策略不允许任何 stdio 命令，agent 默认配置在保存时即被拒绝
+    let response = post(
+        "/api/agent-defaults",
+        json!({ "agent_type": "openai", "mcp_servers": [stdio_server("local")] }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(
+        body["error"],
+        "MCP server 'local': command 'sh' is not allowed"
+    );
+
+    // 项目中的 server 在创建 Run 时检查
+    let project: Value = post(
+        "/api/projects",
+        json!({ "name": "mcp-policy", "mcp_servers": [stdio_server("local")] }),
+    )
+    .await
+    .unwrap()
+    .json()
+    .await
+    .unwrap();
+    let response = post(
+        "/api/runs",
+        json!({
+            "input": { "text": "hi" },
+            "metadata": { "agent_type": "openai", "project_id": project["id"] },
+        }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(
+        body["error"],
+        "MCP server 'local': command 'sh' is not allowed"
+    );
+}
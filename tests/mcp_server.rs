@@ -0,0 +1,243 @@
+use axum::http::StatusCode;
+use openrunner::api::mcp::{authenticate, McpCaller, McpServer};
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::auth::{create_token, generate_api_key, hash_api_key};
+use serde_json::{json, Value};
+
+fn data_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()))
+}
+
+fn caller(user_id: &str) -> McpCaller {
+    McpCaller {
+        user_id: user_id.to_string(),
+        roles: vec![],
+    }
+}
+
+async fn request(server: &McpServer, method: &str, params: Value) -> Value {
+    server
+        .handle(json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }))
+        .await
+        .unwrap()
+}
+
+/// 调用工具，返回 (isError, 文本内容, structuredContent)
+async fn call(server: &McpServer, name: &str, arguments: Value) -> (bool, String, Value) {
+    let response = request(
+        server,
+        "tools/call",
+        json!({ "name": name, "arguments": arguments }),
+    )
+    .await;
+    let result = &response["result"];
+    (
+        result["isError"].as_bool().unwrap(),
+        result["content"][0]["text"].as_str().unwrap().to_string(),
+        result["structuredContent"].clone(),
+    )
+}
+
+#[tokio::test]
+async fn handshake_and_tool_list_follow_the_protocol() {
+    let state = AppState::with_data_dir(&data_dir()).await;
+    let server = McpServer::new(state, caller("alice"));
+
+    let response = request(
+        &server,
+        "initialize",
+        json!({ "protocolVersion": "2025-03-26" }),
+    )
+    .await;
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["result"]["protocolVersion"], "2025-03-26");
+    assert_eq!(response["result"]["capabilities"], json!({ "tools": {} }));
+    // 不支持的版本回退到最新版本
+    let response = request(
+        &server,
+        "initialize",
+        json!({ "protocolVersion": "1999-01-01" }),
+    )
+    .await;
+    assert_eq!(response["result"]["protocolVersion"], "2025-06-18");
+
+    // 通知和客户端的响应不回复
+    assert!(server
+        .handle(json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }))
+        .await
+        .is_none());
+    assert!(server
+        .handle(json!({ "jsonrpc": "2.0", "id": 5, "result": {} }))
+        .await
+        .is_none());
+
+    assert_eq!(
+        request(&server, "ping", Value::Null).await["result"],
+        json!({})
+    );
+    let response = request(&server, "resources/list", Value::Null).await;
+    assert_eq!(response["error"]["code"], -32601);
+
+    let response = request(&server, "tools/list", Value::Null).await;
+    let names: Vec<_> = response["result"]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "create_run",
+            "get_run",
+            "wait_run",
+            "cancel_run",
+            "list_projects",
+            "read_project_file"
+        ]
+    );
+
+    let response = request(&server, "tools/call", json!({ "name": "nope" })).await;
+    assert_eq!(response["error"]["code"], -32602);
+    assert_eq!(response["error"]["message"], "Unknown tool: nope");
+    let response = request(&server, "tools/call", json!({})).await;
+    assert_eq!(response["error"]["message"], "Missing tool name");
+}
+
+#[tokio::test]
+async fn runs_are_only_visible_to_their_owner() {
+    let state = AppState::with_data_dir(&data_dir()).await;
+    let alice = McpServer::new(state.clone(), caller("alice"));
+    let bob = McpServer::new(state, caller("bob"));
+
+    let (is_error, text, _) = call(&alice, "create_run", json!({})).await;
+    assert!(is_error);
+    assert!(text.starts_with("Invalid arguments: "), "{}", text);
+
+    let (is_error, _, run) = call(
+        &alice,
+        "create_run",
+        json!({ "prompt": "hello there", "agent_type": "mock" }),
+    )
+    .await;
+    assert!(!is_error);
+    let run_id = run["run_id"].as_str().unwrap().to_string();
+
+    // 别人的 Run 和不存在的 Run 一样
+    for tool in ["get_run", "wait_run", "cancel_run"] {
+        let (is_error, text, _) = call(&bob, tool, json!({ "run_id": run_id })).await;
+        assert!(is_error, "{}", tool);
+        assert_eq!(text, format!("Run not found: {}", run_id));
+    }
+
+    let (is_error, _, summary) = call(
+        &alice,
+        "wait_run",
+        json!({ "run_id": run_id, "timeout_secs": 30 }),
+    )
+    .await;
+    assert!(!is_error);
+    assert_eq!(summary["status"], "completed");
+    assert!(summary["output"].as_str().unwrap().contains("hello there"));
+
+    let (_, _, result) = call(&alice, "cancel_run", json!({ "run_id": run_id })).await;
+    assert_eq!(result, json!({ "cancelled": false, "status": "completed" }));
+}
+
+#[tokio::test]
+async fn project_files_are_read_inside_the_project() {
+    let state = AppState::with_data_dir(&data_dir()).await;
+    let project_dir = data_dir();
+    std::fs::create_dir_all(&project_dir).unwrap();
+    std::fs::write(project_dir.join("README.md"), "# Demo").unwrap();
+    state
+        .db
+        .create_project(
+            "alice",
+            "proj-1",
+            "demo",
+            &project_dir.to_string_lossy(),
+            &[],
+        )
+        .await
+        .unwrap();
+    let alice = McpServer::new(state.clone(), caller("alice"));
+    let bob = McpServer::new(state, caller("bob"));
+
+    let (_, _, projects) = call(&alice, "list_projects", json!({})).await;
+    assert_eq!(projects["projects"][0]["id"], "proj-1");
+    let (_, _, projects) = call(&bob, "list_projects", json!({})).await;
+    assert_eq!(projects["projects"], json!([]));
+
+    let args = |path: &str| json!({ "project_id": "proj-1", "path": path });
+    let (is_error, text, structured) = call(&alice, "read_project_file", args("README.md")).await;
+    assert!(!is_error);
+    assert_eq!(text, "# Demo");
+    assert!(structured.is_null());
+
+    let (is_error, text, _) = call(&alice, "read_project_file", args("../outside.txt")).await;
+    assert!(is_error);
+    assert!(text.contains("outside the working directory"), "{}", text);
+    let (is_error, text, _) = call(&bob, "read_project_file", args("README.md")).await;
+    assert!(is_error);
+    assert_eq!(text, "Project not found: proj-1");
+}
+
+#[tokio::test]
+async fn http_transport_requires_an_api_key_or_token() {
+    let state = AppState::with_data_dir(&data_dir()).await;
+    let key = generate_api_key();
+    state
+        .db
+        .create_api_key("alice", "mcp", &hash_api_key(&key), "or_", &[])
+        .await
+        .unwrap();
+    assert_eq!(authenticate(&state, &key).await.unwrap().user_id, "alice");
+    assert!(authenticate(&state, &generate_api_key()).await.is_none());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/mcp", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, create_router_with_state(state))
+            .await
+            .unwrap()
+    });
+    let client = reqwest::Client::new();
+    let ping = json!({ "jsonrpc": "2.0", "id": 7, "method": "ping" });
+
+    let response = client.post(&url).json(&ping).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client
+        .post(&url)
+        .bearer_auth(generate_api_key())
+        .json(&ping)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let token = create_token("bob", "bob", &[]).unwrap();
+    for credential in [key.as_str(), token.as_str()] {
+        let response = client
+            .post(&url)
+            .bearer_auth(credential)
+            .json(&ping)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body, json!({ "jsonrpc": "2.0", "id": 7, "result": {} }));
+    }
+
+    let response = client
+        .post(&url)
+        .bearer_auth(&key)
+        .json(&json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+}
@@ -0,0 +1,230 @@
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, StatusCode},
+    response::Response,
+    routing::get,
+    Router,
+};
+use openrunner::agent::{CatalogConfig, GatewayConfig, ModelCatalog, GATEWAY_MANAGER};
+use openrunner::api::router::{create_router_with_state, AppState};
+use serde_json::{json, Value};
+
+/// OpenAI `GET /v1/models`
+const OPENAI_MODELS: &str = r#"{
+  "object": "list",
+  "data": [
+    {"id": "gpt-4o-mini", "object": "model", "created": 1721172741, "owned_by": "system"},
+    {"id": "gpt-4o", "object": "model", "created": 1715367049, "owned_by": "system"}
+  ]
+}"#;
+
+/// Anthropic `GET /v1/models`
+const ANTHROPIC_MODELS: &str = r#"{
+  "data": [
+    {"type": "model", "id": "claude-sonnet-4-20250514", "display_name": "Claude Sonnet 4", "created_at": "2025-05-22T00:00:00Z"}
+  ],
+  "has_more": false,
+  "first_id": "claude-sonnet-4-20250514",
+  "last_id": "claude-sonnet-4-20250514"
+}"#;
+
+#[derive(Clone)]
+struct Upstream {
+    status: Arc<AtomicU16>,
+    body: &'static str,
+    requests: Arc<AtomicUsize>,
+}
+
+async fn models(State(upstream): State<Upstream>) -> Response {
+    upstream.requests.fetch_add(1, Ordering::SeqCst);
+    Response::builder()
+        .status(upstream.status.load(Ordering::SeqCst))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(upstream.body))
+        .unwrap()
+}
+
+async fn listen(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+/// 启动返回 `body` 的上游 mock 并注册为 gateway provider，返回 provider 名称和上游状态
+///
+/// `GATEWAY_MANAGER` 是全局的，名称带随机后缀以免测试之间互相影响。
+async fn provider(kind: &str, body: &'static str) -> (String, Upstream) {
+    let upstream = Upstream {
+        status: Arc::new(AtomicU16::new(200)),
+        body,
+        requests: Arc::new(AtomicUsize::new(0)),
+    };
+    let url = listen(
+        Router::new()
+            .route("/v1/models", get(models))
+            .with_state(upstream.clone()),
+    )
+    .await;
+
+    let name = format!(
+        "{}-{}",
+        kind,
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    );
+    GATEWAY_MANAGER.register_provider(
+        name.clone(),
+        GatewayConfig {
+            provider: kind.to_string(),
+            model: None,
+            api_key: Some("sk-test".to_string()),
+            base_url: Some(format!("{}/v1", url)),
+            fallback_providers: vec![],
+            load_balancing: None,
+            restart_on_failure: false,
+            circuit_breaker: Default::default(),
+            rate_limit: None,
+        },
+    );
+    (name, upstream)
+}
+
+async fn state() -> AppState {
+    let data_dir = std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()));
+    AppState::with_data_dir(&data_dir).await
+}
+
+async fn get_json(url: &str) -> (StatusCode, Value) {
+    let response = reqwest::get(url).await.unwrap();
+    let status = response.status();
+    (status, response.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn lists_provider_models_with_aliases_and_metadata() {
+    let (openai, _) = provider("openai", OPENAI_MODELS).await;
+    let (anthropic, _) = provider("anthropic", ANTHROPIC_MODELS).await;
+
+    GATEWAY_MANAGER.register_alias(
+        format!("fast-{}", openai),
+        format!("{}/gpt-4o-mini", openai),
+    );
+    let config: CatalogConfig = serde_json::from_value(json!({
+        "models": {
+            format!("{}/gpt-4o-mini", openai): {
+                "context_length": 128000,
+                "pricing": { "input": 0.15, "output": 0.6 },
+                "input_modalities": ["text", "image"]
+            },
+            format!("{}/ft:gpt-4o-mini:acme", openai): { "name": "Acme fine-tune" }
+        }
+    }))
+    .unwrap();
+    let mut state = state().await;
+    state.models = state.models.with_config(config);
+    let url = listen(create_router_with_state(state)).await;
+
+    let (status, body) = get_json(&format!("{}/v1/models", url)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["object"], "list");
+    let ours: Vec<&Value> = body["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|m| m["id"].as_str().unwrap().contains(&openai) || m["provider"] == anthropic)
+        .collect();
+    let ids: Vec<&str> = ours.iter().map(|m| m["id"].as_str().unwrap()).collect();
+    let mut expected = vec![
+        format!("{}/claude-sonnet-4-20250514", anthropic),
+        format!("fast-{}", openai),
+        format!("{}/ft:gpt-4o-mini:acme", openai),
+        format!("{}/gpt-4o", openai),
+        format!("{}/gpt-4o-mini", openai),
+    ];
+    expected.sort();
+    assert_eq!(ids, expected);
+
+    let mini = ours
+        .iter()
+        .find(|m| m["id"] == format!("{}/gpt-4o-mini", openai))
+        .unwrap();
+    assert_eq!(
+        **mini,
+        json!({
+            "id": format!("{}/gpt-4o-mini", openai),
+            "object": "model",
+            "created": 1721172741,
+            "owned_by": "system",
+            "provider": openai,
+            "context_length": 128000,
+            "pricing": { "input": 0.15, "output": 0.6 },
+            "input_modalities": ["text", "image"]
+        })
+    );
+
+    let alias = ours
+        .iter()
+        .find(|m| m["id"] == format!("fast-{}", openai))
+        .unwrap();
+    assert_eq!(alias["alias_for"], format!("{}/gpt-4o-mini", openai));
+    assert_eq!(alias["context_length"], 128000);
+
+    let claude = ours.iter().find(|m| m["provider"] == anthropic).unwrap();
+    assert_eq!(claude["name"], "Claude Sonnet 4");
+    assert_eq!(claude["owned_by"], "anthropic");
+    assert_eq!(claude["created"], 1747872000);
+
+    let fine_tune = ours
+        .iter()
+        .find(|m| m["id"] == format!("{}/ft:gpt-4o-mini:acme", openai))
+        .unwrap();
+    assert_eq!(fine_tune["name"], "Acme fine-tune");
+    assert_eq!(fine_tune["provider"], openai);
+}
+
+#[tokio::test]
+async fn model_details_and_unknown_models() {
+    let (openai, _) = provider("openai", OPENAI_MODELS).await;
+    let url = listen(create_router_with_state(state().await)).await;
+
+    let (status, body) = get_json(&format!("{}/v1/models/{}/gpt-4o", url, openai)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["id"], format!("{}/gpt-4o", openai));
+    assert_eq!(body["created"], 1715367049);
+
+    let (status, body) = get_json(&format!("{}/v1/models/{}/gpt-5-turbo", url, openai)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+    assert_eq!(body["error"]["code"], "model_not_found");
+}
+
+#[tokio::test]
+async fn provider_models_are_cached_until_the_ttl() {
+    let (openai, upstream) = provider("openai", OPENAI_MODELS).await;
+    let state = state().await;
+
+    let catalog = state.models.clone().with_ttl(Duration::from_secs(600));
+    catalog.list().await;
+    let models = catalog.list().await;
+    assert!(models.iter().any(|m| m.id == format!("{}/gpt-4o", openai)));
+    assert_eq!(upstream.requests.load(Ordering::SeqCst), 1);
+
+    // 过期后重新拉取；拉取失败时保留上一次的结果
+    let catalog = catalog.with_ttl(Duration::ZERO);
+    upstream.status.store(500, Ordering::SeqCst);
+    let models = catalog.list().await;
+    assert_eq!(upstream.requests.load(Ordering::SeqCst), 2);
+    assert!(models.iter().any(|m| m.id == format!("{}/gpt-4o", openai)));
+
+    let fresh = ModelCatalog::new(state.registry.clone());
+    assert!(!fresh
+        .list()
+        .await
+        .iter()
+        .any(|m| m.id == format!("{}/gpt-4o", openai)));
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    body::Body, extract::State, http::header, response::Response, routing::post, Json, Router,
+};
+use futures::StreamExt;
+use openrunner::agent::{Agent, OpenAIAgent};
+use openrunner::types::{AgentConfig, AgentRequest, StreamEvent, ToolDefinition};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+async fn listen(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}/v1", addr)
+}
+
+/// 第一轮分两块请求 read_file，之后回答文本
+const TOOL_CALL: &str = concat!(
+    "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"type\":\"function\",\"function\":{\"name\":\"read_file\",\"arguments\":\"{\\\"path\\\":\"}}]}}]}\n\n",
+    "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"notes.txt\\\"}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n",
+    "data: {\"id\":\"c1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[],\"usage\":{\"prompt_tokens\":10,\"completion_tokens\":5,\"total_tokens\":15}}\n\n",
+    "data: [DONE]\n\n",
+);
+
+const ANSWER: &str = concat!(
+    "data: {\"id\":\"c2\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"it says hello\"},\"finish_reason\":\"stop\"}]}\n\n",
+    "data: {\"id\":\"c2\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[],\"usage\":{\"prompt_tokens\":20,\"completion_tokens\":3,\"total_tokens\":23}}\n\n",
+    "data: [DONE]\n\n",
+);
+
+#[derive(Clone)]
+struct Upstream {
+    /// 第 n 次请求返回第 n 个响应，超出后重复最后一个
+    bodies: Vec<&'static str>,
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+async fn chat_completions(State(upstream): State<Upstream>, Json(body): Json<Value>) -> Response {
+    let text = {
+        let mut requests = upstream.requests.lock().unwrap();
+        requests.push(body);
+        upstream.bodies[(requests.len() - 1).min(upstream.bodies.len() - 1)]
+    };
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .body(Body::from(text))
+        .unwrap()
+}
+
+async fn serve_turns(bodies: Vec<&'static str>) -> (String, Arc<Mutex<Vec<Value>>>) {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let url = listen(
+        Router::new()
+            .route("/v1/chat/completions", post(chat_completions))
+            .with_state(Upstream {
+                bodies,
+                requests: requests.clone(),
+            }),
+    )
+    .await;
+    (url, requests)
+}
+
+fn temp_dir() -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("openrunner-tools-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn agent(base_url: &str) -> OpenAIAgent {
+    OpenAIAgent::new(AgentConfig {
+        agent_type: "openai".to_string(),
+        model: Some("gpt-test".to_string()),
+        env: HashMap::from([
+            ("OPENAI_API_KEY".to_string(), "sk-test".to_string()),
+            ("OPENAI_BASE_URL".to_string(), base_url.to_string()),
+        ]),
+        ..Default::default()
+    })
+}
+
+async fn execute(
+    agent: &OpenAIAgent,
+    request: AgentRequest,
+) -> (anyhow::Result<()>, Vec<StreamEvent>) {
+    let (tx, mut rx) = mpsc::channel(100);
+    let result = agent.execute(request, tx).await;
+    let mut events = Vec::new();
+    while let Ok(event) = rx.try_recv() {
+        events.push(event);
+    }
+    (result, events)
+}
+
+#[tokio::test]
+async fn broken_streams_are_reported_once() {
+    let url = listen(Router::new().route(
+        "/v1/chat/completions",
+        post(|| async {
+            let chunks: Vec<Result<&'static str, std::io::Error>> = vec![
+                Ok("data: {\"id\":\"c\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"par\"}}]}\n\n"),
+                Err(std::io::Error::other("connection reset")),
+            ];
+            Response::builder()
+                .header(header::CONTENT_TYPE, "text/event-stream")
+                .body(Body::from_stream(futures::stream::iter(chunks).then(
+                    |chunk| async move {
+                        // 先让第一块到达客户端，再断开
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        chunk
+                    },
+                )))
+                .unwrap()
+        }),
+    ))
+    .await;
+
+    let (result, events) = execute(&agent(&url), AgentRequest::new("hi")).await;
+    let error = result.unwrap_err().to_string();
+    assert!(error.starts_with("Stream error"), "{}", error);
+    // 错误只通过返回值报告，由 AgentHandle 转成一次 Error 事件
+    assert!(
+        !events
+            .iter()
+            .any(|e| matches!(e, StreamEvent::Error { .. })),
+        "{:?}",
+        events
+    );
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, StreamEvent::Token { content } if content == "par")));
+}
+
+#[tokio::test]
+async fn tool_loop_runs_builtin_tools_and_continues() {
+    let dir = temp_dir();
+    std::fs::write(dir.join("notes.txt"), "hello").unwrap();
+    let (url, requests) = serve_turns(vec![TOOL_CALL, ANSWER]).await;
+
+    let mut request = AgentRequest::new("what is in notes.txt?");
+    request.working_dir = Some(dir.to_string_lossy().into_owned());
+    let (result, events) = execute(&agent(&url), request).await;
+    result.unwrap();
+
+    assert!(events.iter().any(|e| matches!(
+        e,
+        StreamEvent::ToolCallStarted { id, name, input }
+            if id == "call_1" && name == "read_file" && input["path"] == "notes.txt"
+    )));
+    assert!(events.iter().any(|e| matches!(
+        e,
+        StreamEvent::ToolCallFinished { output, ok: true, .. } if output == "hello"
+    )));
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, StreamEvent::Stop { reason } if reason == "stop")));
+    // 每轮上报累计用量
+    let usages: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            StreamEvent::Usage { usage } => Some((usage.input_tokens, usage.output_tokens)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(usages, [(10, 5), (30, 8)]);
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    let tools: Vec<_> = requests[0]["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["function"]["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(tools, ["read_file", "write_file", "list_files"]);
+
+    let messages = requests[1]["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 3);
+    assert_eq!(messages[1]["role"], "assistant");
+    assert_eq!(messages[1]["tool_calls"][0]["id"], "call_1");
+    assert_eq!(
+        messages[1]["tool_calls"][0]["function"]["arguments"],
+        r#"{"path":"notes.txt"}"#
+    );
+    assert_eq!(
+        messages[2],
+        json!({ "role": "tool", "tool_call_id": "call_1", "content": "hello" })
+    );
+}
+
+#[tokio::test]
+async fn caller_tools_are_returned_to_the_caller() {
+    let (url, requests) = serve_turns(vec![TOOL_CALL, ANSWER]).await;
+    let mut request = AgentRequest::new("hi");
+    request.tools = vec![ToolDefinition {
+        name: "read_file".to_string(),
+        description: "Caller's own reader".to_string(),
+        input_schema: json!({ "type": "object" }),
+    }];
+    let (result, events) = execute(&agent(&url), request).await;
+    result.unwrap();
+
+    // 没有工作目录时不提供内置工具，调用交给调用方执行
+    assert!(events.iter().any(|e| matches!(
+        e,
+        StreamEvent::ToolCallRequested { id, name, arguments }
+            if id == "call_1" && name == "read_file" && arguments == r#"{"path":"notes.txt"}"#
+    )));
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, StreamEvent::Stop { reason } if reason == "tool_calls")));
+    assert!(!events
+        .iter()
+        .any(|e| matches!(e, StreamEvent::ToolCallStarted { .. })));
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0]["tools"][0]["function"]["name"], "read_file");
+}
+
+#[tokio::test]
+async fn tool_loop_stops_at_max_iterations() {
+    let dir = temp_dir();
+    let (url, requests) = serve_turns(vec![TOOL_CALL]).await;
+    let mut agent_config = AgentConfig {
+        agent_type: "openai".to_string(),
+        ..Default::default()
+    };
+    agent_config.tools.max_iterations = 2;
+    agent_config.env = HashMap::from([
+        ("OPENAI_API_KEY".to_string(), "sk-test".to_string()),
+        ("OPENAI_BASE_URL".to_string(), url),
+    ]);
+
+    let mut request = AgentRequest::new("hi");
+    request.working_dir = Some(dir.to_string_lossy().into_owned());
+    let (result, _) = execute(&OpenAIAgent::new(agent_config), request).await;
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "Tool loop stopped after 2 iterations"
+    );
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
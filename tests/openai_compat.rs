@@ -0,0 +1,425 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, StatusCode},
+    response::Response,
+    routing::post,
+    Json, Router,
+};
+use openrunner::agent::{AgentInfo, AgentRegistry, GatewayAgent, GatewayConfig};
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::types::{AgentConfig, RetryConfig};
+use serde_json::{json, Value};
+
+/// openai-python `client.chat.completions.create(...)` 发出的请求：system、用户提问、
+/// 带 tool_calls 的 assistant 消息（content 为 null）和工具结果
+const SDK_REQUEST: &str = r#"{
+  "messages": [
+    {"role": "system", "content": "You are a weather bot."},
+    {"role": "user", "content": "What's the weather in Paris?"},
+    {"role": "assistant", "content": null, "tool_calls": [
+      {"id": "call_abc123", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}}
+    ]},
+    {"role": "tool", "tool_call_id": "call_abc123", "content": "18°C and sunny"}
+  ],
+  "model": "gpt-4o-mini",
+  "stop": ["\n\n"],
+  "temperature": 0.2,
+  "tools": [
+    {"type": "function", "function": {
+      "name": "get_weather",
+      "description": "Get the weather for a city",
+      "parameters": {"type": "object", "properties": {"city": {"type": "string"}}, "required": ["city"]}
+    }}
+  ]
+}"#;
+
+/// OpenAI 的流式响应：role、两个文本增量、finish_reason、usage、[DONE]
+const TEXT_STREAM: &str = concat!(
+    "data: {\"id\":\"chatcmpl-B9MBs8CjcvOU2jLn4n570S5qMJKcT\",\"object\":\"chat.completion.chunk\",\"created\":1741570283,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_06737a9306\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\",\"refusal\":null},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MBs8CjcvOU2jLn4n570S5qMJKcT\",\"object\":\"chat.completion.chunk\",\"created\":1741570283,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_06737a9306\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"It's 18°C\"},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MBs8CjcvOU2jLn4n570S5qMJKcT\",\"object\":\"chat.completion.chunk\",\"created\":1741570283,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_06737a9306\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\" and sunny in Paris.\"},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MBs8CjcvOU2jLn4n570S5qMJKcT\",\"object\":\"chat.completion.chunk\",\"created\":1741570283,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_06737a9306\",\"choices\":[{\"index\":0,\"delta\":{},\"logprobs\":null,\"finish_reason\":\"stop\"}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MBs8CjcvOU2jLn4n570S5qMJKcT\",\"object\":\"chat.completion.chunk\",\"created\":1741570283,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_06737a9306\",\"choices\":[],\"usage\":{\"prompt_tokens\":52,\"completion_tokens\":9,\"total_tokens\":61,\"prompt_tokens_details\":{\"cached_tokens\":0,\"audio_tokens\":0},\"completion_tokens_details\":{\"reasoning_tokens\":0,\"audio_tokens\":0,\"accepted_prediction_tokens\":0,\"rejected_prediction_tokens\":0}}}\n\n",
+    "data: [DONE]\n\n",
+);
+
+/// 模型请求调用 get_weather，参数分片到达
+const TOOL_CALL_STREAM: &str = concat!(
+    "data: {\"id\":\"chatcmpl-B9MHD\",\"object\":\"chat.completion.chunk\",\"created\":1741570611,\"model\":\"gpt-4o-mini-2024-07-18\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":null,\"tool_calls\":[{\"index\":0,\"id\":\"call_DdmO9pD3xa9XTPNJ32zg2hcA\",\"type\":\"function\",\"function\":{\"name\":\"get_weather\",\"arguments\":\"\"}}],\"refusal\":null},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MHD\",\"object\":\"chat.completion.chunk\",\"created\":1741570611,\"model\":\"gpt-4o-mini-2024-07-18\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"city\\\"\"}}]},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MHD\",\"object\":\"chat.completion.chunk\",\"created\":1741570611,\"model\":\"gpt-4o-mini-2024-07-18\",\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\":\\\"Paris\\\"}\"}}]},\"logprobs\":null,\"finish_reason\":null}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MHD\",\"object\":\"chat.completion.chunk\",\"created\":1741570611,\"model\":\"gpt-4o-mini-2024-07-18\",\"choices\":[{\"index\":0,\"delta\":{},\"logprobs\":null,\"finish_reason\":\"tool_calls\"}],\"usage\":null}\n\n",
+    "data: {\"id\":\"chatcmpl-B9MHD\",\"object\":\"chat.completion.chunk\",\"created\":1741570611,\"model\":\"gpt-4o-mini-2024-07-18\",\"choices\":[],\"usage\":{\"prompt_tokens\":60,\"completion_tokens\":15,\"total_tokens\":75}}\n\n",
+    "data: [DONE]\n\n",
+);
+
+/// 达到 max_tokens 被截断
+const LENGTH_STREAM: &str = concat!(
+    "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"gpt-4o-mini\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"It's\"},\"finish_reason\":null}]}\n\n",
+    "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"gpt-4o-mini\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"length\"}]}\n\n",
+    "data: [DONE]\n\n",
+);
+
+#[derive(Clone)]
+struct Upstream {
+    status: StatusCode,
+    body: &'static str,
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+async fn chat_completions(State(upstream): State<Upstream>, Json(body): Json<Value>) -> Response {
+    upstream.requests.lock().unwrap().push(body);
+    let content_type = if upstream.status == StatusCode::OK {
+        "text/event-stream"
+    } else {
+        "application/json"
+    };
+    Response::builder()
+        .status(upstream.status)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(upstream.body))
+        .unwrap()
+}
+
+async fn listen(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+/// 启动上游 mock 和 OpenRunner（gateway 指向 mock），返回 OpenRunner 地址和上游收到的请求
+async fn serve(status: StatusCode, body: &'static str) -> (String, Arc<Mutex<Vec<Value>>>) {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let upstream = Upstream {
+        status,
+        body,
+        requests: requests.clone(),
+    };
+    let upstream_url = listen(
+        Router::new()
+            .route("/v1/chat/completions", post(chat_completions))
+            .with_state(upstream),
+    )
+    .await;
+
+    let registry = AgentRegistry::with_builtins();
+    registry.register(AgentInfo::new("gateway", "test gateway"), move |config| {
+        Ok(Box::new(
+            GatewayAgent::new(GatewayConfig {
+                provider: "openai".to_string(),
+                model: config.model.clone(),
+                api_key: Some("sk-test".to_string()),
+                base_url: Some(format!("{}/v1", upstream_url)),
+                fallback_providers: vec![],
+                load_balancing: None,
+                restart_on_failure: false,
+                circuit_breaker: Default::default(),
+                rate_limit: None,
+            })
+            // 上游的错误响应直接返回，不重试
+            .with_base_config(AgentConfig {
+                retry: RetryConfig {
+                    max_retries: 0,
+                    ..Default::default()
+                },
+                ..config.clone()
+            }),
+        ))
+    });
+    let data_dir = std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()));
+    let state = AppState::with_data_dir(&data_dir)
+        .await
+        .with_registry(registry);
+    let url = listen(create_router_with_state(state)).await;
+    (url, requests)
+}
+
+fn sdk_request(extra: Value) -> Value {
+    let mut request: Value = serde_json::from_str(SDK_REQUEST).unwrap();
+    for (key, value) in extra.as_object().unwrap() {
+        request[key] = value.clone();
+    }
+    request
+}
+
+async fn post_json(url: &str, body: &Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/v1/chat/completions", url))
+        .json(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+/// 解析 SSE 响应体：每个事件只能有一行 `data:`，不带 event 名称
+fn data_lines(body: &str) -> Vec<String> {
+    body.split("\n\n")
+        .filter(|event| !event.trim().is_empty())
+        .map(|event| {
+            assert!(
+                !event.contains('\n'),
+                "unexpected event fields: {:?}",
+                event
+            );
+            event
+                .strip_prefix("data: ")
+                .unwrap_or_else(|| panic!("not a data line: {:?}", event))
+                .to_string()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn sdk_request_keeps_roles_tools_and_stop() {
+    let (url, requests) = serve(StatusCode::OK, TEXT_STREAM).await;
+
+    let response = post_json(&url, &sdk_request(json!({}))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: Value = response.json().await.unwrap();
+
+    assert!(body["id"].as_str().unwrap().starts_with("chatcmpl-"));
+    assert_eq!(body["object"], "chat.completion");
+    assert!(body["created"].as_i64().unwrap() > 0);
+    assert_eq!(body["model"], "gpt-4o-mini");
+    assert_eq!(
+        body["choices"],
+        json!([{
+            "index": 0,
+            "message": { "role": "assistant", "content": "It's 18°C and sunny in Paris." },
+            "logprobs": null,
+            "finish_reason": "stop"
+        }])
+    );
+    assert_eq!(
+        body["usage"],
+        json!({ "prompt_tokens": 52, "completion_tokens": 9, "total_tokens": 61 })
+    );
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    let upstream = &requests[0];
+    assert_eq!(upstream["model"], "gpt-4o-mini");
+    assert_eq!(upstream["stop"], json!(["\n\n"]));
+    assert_eq!(upstream["temperature"], json!(0.2));
+    assert_eq!(
+        upstream["messages"],
+        json!([
+            { "role": "system", "content": "You are a weather bot." },
+            { "role": "user", "content": "What's the weather in Paris?" },
+            { "role": "assistant", "tool_calls": [{
+                "id": "call_abc123",
+                "type": "function",
+                "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" }
+            }] },
+            { "role": "tool", "content": "18°C and sunny", "tool_call_id": "call_abc123" }
+        ])
+    );
+    assert_eq!(upstream["tools"][0]["type"], "function");
+    assert_eq!(upstream["tools"][0]["function"]["name"], "get_weather");
+    assert_eq!(
+        upstream["tools"][0]["function"]["parameters"]["required"],
+        json!(["city"])
+    );
+}
+
+#[tokio::test]
+async fn stream_on_main_route_uses_chunk_framing() {
+    let (url, _) = serve(StatusCode::OK, TEXT_STREAM).await;
+
+    let request =
+        sdk_request(json!({ "stream": true, "stream_options": { "include_usage": true } }));
+    let response = post_json(&url, &request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()[header::CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .starts_with("text/event-stream"));
+
+    let lines = data_lines(&response.text().await.unwrap());
+    assert_eq!(lines.last().unwrap(), "[DONE]");
+    let chunks: Vec<Value> = lines[..lines.len() - 1]
+        .iter()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    let id = chunks[0]["id"].as_str().unwrap();
+    assert!(id.starts_with("chatcmpl-"));
+    for chunk in &chunks {
+        assert_eq!(chunk["id"], id);
+        assert_eq!(chunk["object"], "chat.completion.chunk");
+        assert_eq!(chunk["model"], "gpt-4o-mini");
+        assert!(chunk["created"].as_i64().unwrap() > 0);
+    }
+
+    assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+    let content: String = chunks
+        .iter()
+        .filter_map(|c| c["choices"][0]["delta"]["content"].as_str())
+        .collect();
+    assert_eq!(content, "It's 18°C and sunny in Paris.");
+
+    let finish: Vec<&Value> = chunks
+        .iter()
+        .filter(|c| !c["choices"][0]["finish_reason"].is_null())
+        .collect();
+    assert_eq!(finish.len(), 1);
+    assert_eq!(finish[0]["choices"][0]["finish_reason"], "stop");
+    assert_eq!(finish[0]["choices"][0]["delta"], json!({}));
+
+    let usage = chunks.last().unwrap();
+    assert_eq!(usage["choices"], json!([]));
+    assert_eq!(usage["usage"]["total_tokens"], 61);
+}
+
+#[tokio::test]
+async fn usage_chunk_needs_include_usage() {
+    let (url, _) = serve(StatusCode::OK, TEXT_STREAM).await;
+
+    let response = post_json(&url, &sdk_request(json!({ "stream": true }))).await;
+    let lines = data_lines(&response.text().await.unwrap());
+    assert_eq!(lines.last().unwrap(), "[DONE]");
+    assert!(lines.iter().all(|line| !line.contains("\"usage\"")));
+}
+
+#[tokio::test]
+async fn tool_calls_are_returned_to_the_caller() {
+    let (url, requests) = serve(StatusCode::OK, TOOL_CALL_STREAM).await;
+
+    let body: Value = post_json(&url, &sdk_request(json!({})))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let choice = &body["choices"][0];
+    assert_eq!(choice["finish_reason"], "tool_calls");
+    assert_eq!(
+        choice["message"],
+        json!({
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{
+                "id": "call_DdmO9pD3xa9XTPNJ32zg2hcA",
+                "type": "function",
+                "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" }
+            }]
+        })
+    );
+    // 工具由调用方执行，不会再次请求模型
+    assert_eq!(requests.lock().unwrap().len(), 1);
+
+    let response = post_json(&url, &sdk_request(json!({ "stream": true }))).await;
+    let lines = data_lines(&response.text().await.unwrap());
+    let chunks: Vec<Value> = lines[..lines.len() - 1]
+        .iter()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let calls: Vec<&Value> = chunks
+        .iter()
+        .filter_map(|c| c["choices"][0]["delta"].get("tool_calls"))
+        .collect();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(calls[0][0]["index"], 0);
+    assert_eq!(calls[0][0]["id"], "call_DdmO9pD3xa9XTPNJ32zg2hcA");
+    assert_eq!(calls[0][0]["function"]["arguments"], "{\"city\":\"Paris\"}");
+    assert!(chunks
+        .iter()
+        .any(|c| c["choices"][0]["finish_reason"] == "tool_calls"));
+}
+
+#[tokio::test]
+async fn n_generates_independent_choices() {
+    let (url, requests) = serve(StatusCode::OK, TEXT_STREAM).await;
+
+    let body: Value = post_json(&url, &sdk_request(json!({ "n": 2 })))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let choices = body["choices"].as_array().unwrap();
+    assert_eq!(choices.len(), 2);
+    for (i, choice) in choices.iter().enumerate() {
+        assert_eq!(choice["index"], i);
+        assert_eq!(
+            choice["message"]["content"],
+            "It's 18°C and sunny in Paris."
+        );
+    }
+    assert_eq!(body["usage"]["total_tokens"], 122);
+    assert_eq!(requests.lock().unwrap().len(), 2);
+
+    let response = post_json(&url, &sdk_request(json!({ "n": 2, "stream": true }))).await;
+    let lines = data_lines(&response.text().await.unwrap());
+    assert_eq!(lines.last().unwrap(), "[DONE]");
+    let finished: Vec<i64> = lines[..lines.len() - 1]
+        .iter()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .filter(|c| c["choices"][0]["finish_reason"] == "stop")
+        .map(|c| c["choices"][0]["index"].as_i64().unwrap())
+        .collect();
+    assert_eq!(finished.len(), 2);
+    assert!(finished.contains(&0) && finished.contains(&1));
+}
+
+#[tokio::test]
+async fn length_finish_reason_is_passed_through() {
+    let (url, _) = serve(StatusCode::OK, LENGTH_STREAM).await;
+
+    let body: Value = post_json(&url, &sdk_request(json!({ "max_tokens": 1 })))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["choices"][0]["finish_reason"], "length");
+    assert_eq!(body["choices"][0]["message"]["content"], "It's");
+}
+
+#[tokio::test]
+async fn errors_use_openai_shape() {
+    let (url, _) = serve(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        r#"{"error":{"message":"The server had an error","type":"server_error"}}"#,
+    )
+    .await;
+
+    // 请求体不合法
+    let response = reqwest::Client::new()
+        .post(format!("{}/v1/chat/completions", url))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(r#"{"model": "gpt-4o-mini"}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("messages"));
+
+    let response = post_json(&url, &sdk_request(json!({ "n": 0 }))).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+
+    // 上游出错
+    let response = post_json(&url, &sdk_request(json!({}))).await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["type"], "api_error");
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("The server had an error"));
+
+    // 流式请求出错时发送 error 对象，没有 [DONE]
+    let response = post_json(&url, &sdk_request(json!({ "stream": true }))).await;
+    let lines = data_lines(&response.text().await.unwrap());
+    let last: Value = serde_json::from_str(lines.last().unwrap()).unwrap();
+    assert_eq!(last["error"]["type"], "api_error");
+    assert!(!lines.iter().any(|line| line == "[DONE]"));
+}
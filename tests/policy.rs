@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use openrunner::types::{AgentConfig, McpServerConfig};
+use openrunner::{AgentPolicy, PolicyError, PolicyRule};
+
+fn config(agent_type: &str, env: &[&str], args: &[&str]) -> AgentConfig {
+    AgentConfig {
+        agent_type: agent_type.to_string(),
+        env: env
+            .iter()
+            .map(|k| (k.to_string(), "value".to_string()))
+            .collect::<HashMap<_, _>>(),
+        extra_args: args.iter().map(|a| a.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+fn patterns(patterns: &[&str]) -> Vec<String> {
+    patterns.iter().map(|p| p.to_string()).collect()
+}
+
+fn roles(roles: &[&str]) -> Vec<String> {
+    patterns(roles)
+}
+
+#[test]
+fn builtin_rule_denies_loader_env_and_base_urls() {
+    let policy = AgentPolicy::default();
+    for key in [
+        "LD_PRELOAD",
+        "DYLD_INSERT_LIBRARIES",
+        "PATH",
+        "NODE_OPTIONS",
+        "OPENAI_BASE_URL",
+        "ANTHROPIC_BASE_URL",
+        "OPENAI_API_BASE",
+    ] {
+        assert_eq!(
+            policy.check(&config("claude_code", &[key], &[]), &[]),
+            Err(PolicyError::EnvKey {
+                key: key.to_string(),
+                agent_type: "claude_code".to_string(),
+            })
+        );
+    }
+    assert!(policy
+        .check(
+            &config("claude_code", &["ANTHROPIC_API_KEY", "MY_PATH"], &[]),
+            &[]
+        )
+        .is_ok());
+}
+
+#[test]
+fn builtin_rule_denies_flags_with_and_without_values() {
+    let policy = AgentPolicy::default();
+    for arg in ["--add-dir", "--add-dir=/", "-C", "--config=x"] {
+        assert!(
+            matches!(
+                policy.check(&config("codex", &[], &[arg]), &[]),
+                Err(PolicyError::Arg { .. })
+            ),
+            "{}",
+            arg
+        );
+    }
+    // 只检查 flag，位置参数和其他以 `-C` 开头的 flag 不受限制
+    assert!(policy
+        .check(&config("codex", &[], &["-C-like", "x"]), &[])
+        .is_ok());
+    assert!(policy
+        .check(&config("codex", &[], &["--model", "gpt-4o", "/"]), &[])
+        .is_ok());
+}
+
+#[test]
+fn first_matching_rule_wins() {
+    let policy = AgentPolicy {
+        rules: vec![
+            PolicyRule {
+                agent_types: patterns(&["claude_*"]),
+                env_allow: Some(patterns(&["ANTHROPIC_*"])),
+                args_allow: Some(patterns(&["--model"])),
+                ..Default::default()
+            },
+            PolicyRule {
+                env_deny: patterns(&["ANTHROPIC_*"]),
+                ..Default::default()
+            },
+            PolicyRule::builtin(),
+        ],
+    };
+
+    // claude_code 匹配第一条：只允许 ANTHROPIC_*，之后的规则不再生效
+    assert!(policy
+        .check(
+            &config("claude_code", &["ANTHROPIC_API_KEY"], &["--model"]),
+            &[]
+        )
+        .is_ok());
+    assert!(policy
+        .check(&config("claude_code", &["OTHER"], &[]), &[])
+        .is_err());
+    assert!(policy
+        .check(&config("claude_code", &[], &["--verbose"]), &[])
+        .is_err());
+
+    // 其他 agent 匹配第二条：拒绝 ANTHROPIC_*，其余只受内置 deny 列表限制
+    assert!(policy
+        .check(&config("codex", &["ANTHROPIC_API_KEY"], &[]), &[])
+        .is_err());
+    assert!(policy
+        .check(&config("codex", &["OPENAI_API_KEY"], &["--model"]), &[])
+        .is_ok());
+}
+
+#[test]
+fn rules_can_be_scoped_to_roles() {
+    let policy = AgentPolicy {
+        rules: vec![
+            PolicyRule {
+                roles: roles(&["admin"]),
+                override_builtin: true,
+                ..Default::default()
+            },
+            PolicyRule::builtin(),
+        ],
+    };
+    let config = config("claude_code", &["PATH"], &["--add-dir"]);
+    assert!(policy.check(&config, &roles(&["admin"])).is_ok());
+    assert!(policy.check(&config, &roles(&["user"])).is_err());
+    assert!(policy.check(&config, &[]).is_err());
+}
+
+#[test]
+fn builtin_denies_are_checked_before_rule_allows() {
+    let allow_all = PolicyRule {
+        env_allow: Some(patterns(&["*"])),
+        args_allow: Some(patterns(&["*"])),
+        ..Default::default()
+    };
+    let policy = AgentPolicy {
+        rules: vec![allow_all.clone()],
+    };
+    assert_eq!(
+        policy.check(&config("codex", &["LD_PRELOAD"], &[]), &[]),
+        Err(PolicyError::EnvKey {
+            key: "LD_PRELOAD".to_string(),
+            agent_type: "codex".to_string(),
+        })
+    );
+    assert!(matches!(
+        policy.check(&config("codex", &[], &["--add-dir=/"]), &[]),
+        Err(PolicyError::Arg { .. })
+    ));
+    assert!(policy
+        .check(&config("codex", &["OPENAI_API_KEY"], &["--model"]), &[])
+        .is_ok());
+
+    // 只有显式设置 override_builtin 的规则可以放开内置 deny 列表
+    let policy = AgentPolicy {
+        rules: vec![PolicyRule {
+            override_builtin: true,
+            ..allow_all
+        }],
+    };
+    assert!(policy
+        .check(&config("codex", &["LD_PRELOAD"], &["--add-dir=/"]), &[])
+        .is_ok());
+}
+
+#[test]
+fn no_matching_rule_applies_the_builtin_rule() {
+    let policy = AgentPolicy {
+        rules: vec![PolicyRule {
+            agent_types: patterns(&["codex"]),
+            env_deny: patterns(&["*"]),
+            ..Default::default()
+        }],
+    };
+    assert!(policy
+        .check(&config("claude_code", &["PATH"], &["--add-dir"]), &[])
+        .is_err());
+    assert!(policy
+        .check(&config("claude_code", &["ANTHROPIC_API_KEY"], &[]), &[])
+        .is_ok());
+    assert!(policy.check(&config("codex", &["A"], &[]), &[]).is_err());
+}
+
+#[test]
+fn policy_files_fall_back_to_the_builtin_rule() {
+    let path =
+        std::env::temp_dir().join(format!("openrunner-policy-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(
+        &path,
+        r#"{ "rules": [{ "agent_types": ["codex"], "env_allow": ["OPENAI_*"] }] }"#,
+    )
+    .unwrap();
+    std::env::set_var("OPENRUNNER_POLICY_FILE", &path);
+    let policy = AgentPolicy::from_env();
+    assert_eq!(policy.rules.len(), 2);
+
+    assert!(policy
+        .check(&config("codex", &["OPENAI_API_KEY"], &[]), &[])
+        .is_ok());
+    assert!(policy.check(&config("codex", &["HOME"], &[]), &[]).is_err());
+    // 文件中的规则不匹配时由内置规则兜底
+    assert!(policy
+        .check(&config("claude_code", &["LD_PRELOAD"], &[]), &[])
+        .is_err());
+    assert!(policy
+        .check(&config("claude_code", &["ANTHROPIC_API_KEY"], &[]), &[])
+        .is_ok());
+}
+
+fn mcp_server(name: &str, command: &str, env: &[&str], args: &[&str]) -> McpServerConfig {
+    McpServerConfig {
+        name: name.to_string(),
+        command: Some(command.to_string()),
+        args: patterns(args),
+        env: env
+            .iter()
+            .map(|k| (k.to_string(), "value".to_string()))
+            .collect(),
+        url: None,
+        headers: HashMap::new(),
+    }
+}
+
+fn with_mcp(servers: Vec<McpServerConfig>) -> AgentConfig {
+    AgentConfig {
+        mcp_servers: servers,
+        ..config("openai", &[], &[])
+    }
+}
+
+#[test]
+fn mcp_stdio_servers_need_an_allowed_command() {
+    // 内置规则不允许任何 stdio server，HTTP server 不受限制
+    let policy = AgentPolicy::default();
+    assert_eq!(
+        policy.check(&with_mcp(vec![mcp_server("files", "npx", &[], &[])]), &[]),
+        Err(PolicyError::McpCommand {
+            server: "files".to_string(),
+            command: "npx".to_string(),
+        })
+    );
+    let http = McpServerConfig {
+        url: Some("https://mcp.example.com/mcp".to_string()),
+        command: None,
+        ..mcp_server("docs", "", &[], &[])
+    };
+    assert!(policy.check(&with_mcp(vec![http]), &[]).is_ok());
+
+    let policy = AgentPolicy {
+        rules: vec![PolicyRule {
+            mcp_commands: patterns(&["npx", "/opt/mcp/*"]),
+            ..PolicyRule::builtin()
+        }],
+    };
+    let allowed = vec![
+        mcp_server("files", "npx", &["GITHUB_TOKEN"], &["-y", "server"]),
+        mcp_server("local", "/opt/mcp/search", &[], &[]),
+    ];
+    assert!(policy.check(&with_mcp(allowed), &[]).is_ok());
+    assert_eq!(
+        policy.check(&with_mcp(vec![mcp_server("shell", "sh", &[], &[])]), &[]),
+        Err(PolicyError::McpCommand {
+            server: "shell".to_string(),
+            command: "sh".to_string(),
+        })
+    );
+}
+
+#[test]
+fn mcp_server_env_and_args_follow_the_env_and_arg_rules() {
+    let policy = AgentPolicy {
+        rules: vec![PolicyRule {
+            mcp_commands: patterns(&["npx"]),
+            ..PolicyRule::builtin()
+        }],
+    };
+    assert_eq!(
+        policy.check(
+            &with_mcp(vec![mcp_server("files", "npx", &["LD_PRELOAD"], &[])]),
+            &[]
+        ),
+        Err(PolicyError::McpEnvKey {
+            server: "files".to_string(),
+            key: "LD_PRELOAD".to_string(),
+        })
+    );
+    assert_eq!(
+        policy.check(
+            &with_mcp(vec![mcp_server("files", "npx", &[], &["-c", "id"])]),
+            &[]
+        ),
+        Err(PolicyError::McpArg {
+            server: "files".to_string(),
+            arg: "-c".to_string(),
+        })
+    );
+}
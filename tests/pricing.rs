@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use chrono::{TimeZone, Utc};
+use openrunner::agent::{Agent, AgentInfo};
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::auth::create_token;
+use openrunner::pricing::{ModelPrice, PricingTable};
+use openrunner::storage::{UsageGroupBy, UsageRecord};
+use openrunner::types::{StreamEvent, TokenUsage};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+fn price(input: f64, output: f64) -> ModelPrice {
+    ModelPrice {
+        input,
+        output,
+        ..Default::default()
+    }
+}
+
+fn table() -> PricingTable {
+    PricingTable {
+        models: HashMap::from([
+            ("gpt-4o".to_string(), price(2.5, 10.0)),
+            ("gpt-4*".to_string(), price(30.0, 60.0)),
+            ("gpt-4o-mini*".to_string(), price(0.15, 0.6)),
+            (
+                "claude-sonnet-4*".to_string(),
+                ModelPrice {
+                    input: 3.0,
+                    output: 15.0,
+                    cache_write: Some(3.75),
+                    cache_read: Some(0.3),
+                },
+            ),
+        ]),
+    }
+}
+
+fn usage(input_tokens: u64, output_tokens: u64) -> TokenUsage {
+    TokenUsage {
+        input_tokens,
+        output_tokens,
+        ..Default::default()
+    }
+}
+
+fn assert_close(actual: Option<f64>, expected: f64) {
+    let actual = actual.unwrap();
+    assert!(
+        (actual - expected).abs() < 1e-9,
+        "{} != {}",
+        actual,
+        expected
+    );
+}
+
+#[test]
+fn models_match_exactly_then_by_longest_prefix() {
+    let table = table();
+    assert_eq!(table.get("gpt-4o"), Some(&price(2.5, 10.0)));
+    assert_eq!(table.get("gpt-4o-mini-2024-07-18"), Some(&price(0.15, 0.6)));
+    assert_eq!(table.get("gpt-4-turbo"), Some(&price(30.0, 60.0)));
+    // provider 前缀的模型按 `/` 之后的部分匹配
+    assert_eq!(table.get("openai/gpt-4o"), Some(&price(2.5, 10.0)));
+    assert_eq!(table.get("gpt-3.5-turbo"), None);
+    assert_eq!(table.get("openai/gpt-3.5-turbo"), None);
+}
+
+#[test]
+fn costs_fall_back_to_the_input_price_for_cache_tokens() {
+    let cached = TokenUsage {
+        input_tokens: 1_000_000,
+        output_tokens: 100_000,
+        cache_creation_input_tokens: 200_000,
+        cache_read_input_tokens: 1_000_000,
+        cost_usd: None,
+    };
+    let table = table();
+    // 3 + 1.5 + 0.75 + 0.3
+    assert_close(
+        Some(table.get("claude-sonnet-4-5").unwrap().cost(&cached)),
+        5.55,
+    );
+    // 未配置缓存价格时按输入价格：2.5 + 1 + 0.5 + 2.5
+    assert_close(Some(table.get("gpt-4o").unwrap().cost(&cached)), 6.5);
+
+    // 上报的费用优先，找不到价格时保持未知
+    let reported = TokenUsage {
+        cost_usd: Some(0.42),
+        ..usage(1_000_000, 0)
+    };
+    assert_eq!(
+        table.fill_cost(Some("gpt-4o"), reported).cost_usd,
+        Some(0.42)
+    );
+    assert_close(
+        table
+            .fill_cost(Some("gpt-4o"), usage(1_000_000, 0))
+            .cost_usd,
+        2.5,
+    );
+    assert_eq!(table.fill_cost(Some("unknown"), usage(1, 1)).cost_usd, None);
+    assert_eq!(table.fill_cost(None, usage(1, 1)).cost_usd, None);
+}
+
+#[test]
+fn pricing_files_load_from_the_environment() {
+    let path = std::env::temp_dir().join(format!("openrunner-test-{}.json", uuid::Uuid::new_v4()));
+    std::fs::write(
+        &path,
+        r#"{ "models": { "local-*": { "input": 1.0, "output": 2.0, "cache_read": 0.1 } } }"#,
+    )
+    .unwrap();
+    std::env::set_var("OPENRUNNER_PRICING_FILE", &path);
+    let table = PricingTable::from_env();
+    assert_eq!(
+        table.get("local-llama"),
+        Some(&ModelPrice {
+            input: 1.0,
+            output: 2.0,
+            cache_write: None,
+            cache_read: Some(0.1),
+        })
+    );
+
+    // 文件无效时使用空表
+    std::fs::write(&path, "not json").unwrap();
+    assert!(PricingTable::from_env().models.is_empty());
+    std::env::remove_var("OPENRUNNER_PRICING_FILE");
+}
+
+/// 上报用量但不带费用的 agent
+struct UsageAgent;
+
+#[async_trait]
+impl Agent for UsageAgent {
+    async fn run(&self, _prompt: String, tx: mpsc::Sender<StreamEvent>) -> anyhow::Result<()> {
+        tx.send(StreamEvent::Token {
+            content: "done".to_string(),
+        })
+        .await?;
+        // 多次上报时以最后一次的累计值为准
+        for (input, output) in [(400_000, 0), (1_000_000, 500_000)] {
+            tx.send(StreamEvent::Usage {
+                usage: usage(input, output),
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "usage"
+    }
+}
+
+fn record(
+    run_id: &str,
+    user_id: &str,
+    model: &str,
+    day: u32,
+    cost_usd: Option<f64>,
+) -> UsageRecord {
+    UsageRecord {
+        run_id: run_id.to_string(),
+        user_id: user_id.to_string(),
+        project_id: None,
+        agent_type: "openai".to_string(),
+        model: Some(model.to_string()),
+        usage: TokenUsage {
+            cost_usd,
+            ..usage(100, 10)
+        },
+        created_at: Utc.with_ymd_and_hms(2026, 3, day, 12, 0, 0).unwrap(),
+    }
+}
+
+#[tokio::test]
+async fn usage_is_aggregated_per_group_and_time_range() {
+    let dir = std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()));
+    let state = AppState::with_data_dir(&dir).await;
+    let db = &state.db;
+    db.record_usage(&record("r1", "alice", "gpt-4o", 1, Some(0.5)))
+        .await
+        .unwrap();
+    db.record_usage(&record("r2", "alice", "gpt-4o", 2, None))
+        .await
+        .unwrap();
+    db.record_usage(&record("r3", "alice", "local", 3, None))
+        .await
+        .unwrap();
+    db.record_usage(&record("r4", "bob", "gpt-4o", 2, Some(1.0)))
+        .await
+        .unwrap();
+    // 同一 Run 再次记录时覆盖
+    db.record_usage(&record("r4", "bob", "gpt-4o", 2, Some(2.0)))
+        .await
+        .unwrap();
+
+    let summary = db
+        .usage_summary(Some("alice"), UsageGroupBy::Model, None, None)
+        .await
+        .unwrap();
+    assert_eq!(summary.len(), 2);
+    assert_eq!(summary[0].key.as_deref(), Some("gpt-4o"));
+    assert_eq!(
+        (
+            summary[0].runs,
+            summary[0].input_tokens,
+            summary[0].output_tokens
+        ),
+        (2, 200, 20)
+    );
+    assert_close(summary[0].cost_usd, 0.5);
+    // 没有任何已知费用的分组费用为空
+    assert_eq!(summary[1].key.as_deref(), Some("local"));
+    assert_eq!(summary[1].cost_usd, None);
+
+    let summary = db
+        .usage_summary(None, UsageGroupBy::User, None, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        summary
+            .iter()
+            .map(|s| (s.key.as_deref().unwrap(), s.runs))
+            .collect::<Vec<_>>(),
+        [("bob", 1), ("alice", 3)]
+    );
+    assert_close(summary[0].cost_usd, 2.0);
+
+    // from 含、to 不含
+    let summary = db
+        .usage_summary(
+            None,
+            UsageGroupBy::Agent,
+            Some(Utc.with_ymd_and_hms(2026, 3, 2, 12, 0, 0).unwrap()),
+            Some(Utc.with_ymd_and_hms(2026, 3, 3, 12, 0, 0).unwrap()),
+        )
+        .await
+        .unwrap();
+    assert_eq!(summary.len(), 1);
+    assert_eq!(summary[0].runs, 2);
+}
+
+#[tokio::test]
+async fn finished_runs_are_priced_and_reported_by_the_usage_api() {
+    let dir = std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()));
+    let mut state = AppState::with_data_dir(&dir).await;
+    state
+        .registry
+        .register(AgentInfo::new("pricing_usage", "usage"), |_| {
+            Ok(Box::new(UsageAgent))
+        });
+    state.run_manager = state.run_manager.clone().with_pricing(table());
+    let manager = state.run_manager.clone();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, create_router_with_state(state))
+            .await
+            .unwrap()
+    });
+    let client = reqwest::Client::new();
+    let user_id = uuid::Uuid::new_v4().to_string();
+    let token = create_token(&user_id, "tester", &[]).unwrap();
+    let admin = create_token("admin-user", "admin", &["admin".to_string()]).unwrap();
+
+    let response = client
+        .post(format!("{}/api/runs", url))
+        .bearer_auth(&token)
+        .json(&json!({
+            "input": { "text": "hi" },
+            "metadata": { "agent_type": "pricing_usage", "model": "gpt-4o-2024-08-06" },
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let run_id = response.json::<Value>().await.unwrap()["run_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let run = manager
+        .wait_run(&run_id, Duration::from_secs(10))
+        .await
+        .unwrap();
+    let usage = run.usage.unwrap();
+    assert_eq!(
+        (usage.input_tokens, usage.output_tokens),
+        (1_000_000, 500_000)
+    );
+    // gpt-4o*: 30 + 30
+    assert_close(usage.cost_usd, 60.0);
+
+    // Run 结束后才写入数据库
+    let get_usage = |token: String, query: &'static str| {
+        let request = client
+            .get(format!("{}/api/usage{}", url, query))
+            .bearer_auth(token);
+        async move {
+            let response = request.send().await.unwrap();
+            (response.status(), response.json::<Value>().await.unwrap())
+        }
+    };
+    let mut body = Value::Null;
+    for _ in 0..50 {
+        body = get_usage(token.clone(), "?group_by=model").await.1;
+        if !body["usage"].as_array().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(body["group_by"], "model");
+    assert_eq!(body["usage"][0]["key"], "gpt-4o-2024-08-06");
+    assert_eq!(body["usage"][0]["runs"], 1);
+    assert_eq!(body["usage"][0]["cost_usd"], 60.0);
+
+    // 普通用户只看到自己的用量，admin 看到所有用户
+    let other = create_token("someone-else", "other", &[]).unwrap();
+    let (_, body) = get_usage(other, "").await;
+    assert_eq!(body["usage"], json!([]));
+    let (_, body) = get_usage(admin.clone(), "?group_by=user").await;
+    assert!(body["usage"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|s| s["key"] == user_id.as_str()));
+    let (_, body) = get_usage(admin.clone(), "?from=2999-01-01").await;
+    assert_eq!(body["usage"], json!([]));
+    assert_eq!(body["from"], "2999-01-01T00:00:00Z");
+
+    let (status, body) = get_usage(admin, "?to=yesterday").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["error"],
+        "Invalid 'to': expected RFC 3339 or YYYY-MM-DD"
+    );
+    let response = client
+        .get(format!("{}/api/usage", url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
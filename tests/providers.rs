@@ -0,0 +1,548 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, StatusCode},
+    response::Response,
+    routing::{get, post},
+    Json, Router,
+};
+use openrunner::agent::{GatewayConfig, GatewayManager};
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::auth::create_token;
+use serde_json::{json, Value};
+
+const API_KEY: &str = "sk-test-0123456789abcdef";
+
+async fn chat_completions() -> Response {
+    let chunk = json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 1,
+        "model": "stub",
+        "choices": [{ "index": 0, "delta": { "content": "from upstream" }, "finish_reason": "stop" }]
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .body(Body::from(format!("data: {}\n\ndata: [DONE]\n\n", chunk)))
+        .unwrap()
+}
+
+async fn listen(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+fn data_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()))
+}
+
+/// 以 `dir` 为数据目录启动 OpenRunner，返回地址和状态
+async fn serve(dir: &Path) -> (String, AppState) {
+    let state = AppState::with_data_dir(dir).await;
+    let url = listen(create_router_with_state(state.clone())).await;
+    (url, state)
+}
+
+/// 随机用户的登录 token
+fn login() -> (String, String) {
+    let user_id = uuid::Uuid::new_v4().to_string();
+    let token = create_token(&user_id, "tester", &[]).unwrap();
+    (user_id, token)
+}
+
+struct Client {
+    http: reqwest::Client,
+    url: String,
+    token: Option<String>,
+}
+
+impl Client {
+    fn new(url: &str, token: Option<&str>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url: url.to_string(),
+            token: token.map(str::to_string),
+        }
+    }
+
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = self.http.request(method, format!("{}{}", self.url, path));
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await.unwrap();
+        let status = response.status();
+        (status, response.json().await.unwrap_or(Value::Null))
+    }
+
+    /// 调用方可见的 provider 名称 -> 是否为全局 provider
+    async fn providers(&self) -> Vec<(String, bool)> {
+        let (status, body) = self
+            .send(reqwest::Method::GET, "/api/providers", None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        body["providers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| {
+                (
+                    p["name"].as_str().unwrap().to_string(),
+                    p["shared"].as_bool().unwrap(),
+                )
+            })
+            .collect()
+    }
+}
+
+fn register_body(name: &str, base_url: &str) -> Value {
+    json!({
+        "name": name,
+        "provider": "openai",
+        "model": "stub",
+        "api_key": API_KEY,
+        "base_url": base_url,
+        "rate_limit": { "requests_per_minute": 100 },
+    })
+}
+
+#[tokio::test]
+async fn registrations_survive_a_restart_with_encrypted_keys() {
+    let dir = data_dir();
+    let (url, _) = serve(&dir).await;
+    let (user_id, token) = login();
+    let client = Client::new(&url, Some(&token));
+
+    let (status, _) = client
+        .send(
+            reqwest::Method::POST,
+            "/api/providers",
+            Some(register_body("mine", "http://127.0.0.1:9/v1")),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(client
+        .providers()
+        .await
+        .contains(&("mine".to_string(), false)));
+
+    // 数据库中没有明文 key
+    let db = std::fs::read(dir.join("openrunner.db")).unwrap();
+    assert!(!db.windows(API_KEY.len()).any(|w| w == API_KEY.as_bytes()));
+
+    // 重启后从数据库加载，key 解密回明文
+    let (_, state) = serve(&dir).await;
+    let config = state
+        .providers
+        .scope(&user_id)
+        .get_own_provider("mine")
+        .unwrap();
+    assert_eq!(config.api_key.as_deref(), Some(API_KEY));
+    assert_eq!(config.base_url.as_deref(), Some("http://127.0.0.1:9/v1"));
+    assert_eq!(config.rate_limit.unwrap().requests_per_minute, Some(100));
+}
+
+#[tokio::test]
+async fn patch_updates_fields_and_keeps_the_key() {
+    let dir = data_dir();
+    let (url, _) = serve(&dir).await;
+    let (user_id, token) = login();
+    let client = Client::new(&url, Some(&token));
+    client
+        .send(
+            reqwest::Method::POST,
+            "/api/providers",
+            Some(register_body("mine", "http://127.0.0.1:9/v1")),
+        )
+        .await;
+
+    let (status, _) = client
+        .send(
+            reqwest::Method::PATCH,
+            "/api/providers/mine",
+            Some(json!({ "model": "gpt-4o", "restart_on_failure": true })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = client
+        .send(
+            reqwest::Method::PATCH,
+            "/api/providers/missing",
+            Some(json!({ "model": "gpt-4o" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, state) = serve(&dir).await;
+    let config = state
+        .providers
+        .scope(&user_id)
+        .get_own_provider("mine")
+        .unwrap();
+    assert_eq!(config.model.as_deref(), Some("gpt-4o"));
+    assert!(config.restart_on_failure);
+    assert_eq!(config.api_key.as_deref(), Some(API_KEY));
+}
+
+#[tokio::test]
+async fn delete_removes_the_provider_and_its_key() {
+    let dir = data_dir();
+    let (url, _) = serve(&dir).await;
+    let (user_id, token) = login();
+    let client = Client::new(&url, Some(&token));
+    client
+        .send(
+            reqwest::Method::POST,
+            "/api/providers",
+            Some(register_body("mine", "http://127.0.0.1:9/v1")),
+        )
+        .await;
+    let (_, secrets) = client
+        .send(reqwest::Method::GET, "/api/secrets", None)
+        .await;
+    assert_eq!(secrets["secrets"][0]["name"], "gateway.mine.api_key");
+
+    let (status, _) = client
+        .send(reqwest::Method::DELETE, "/api/providers/mine", None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = client
+        .send(reqwest::Method::DELETE, "/api/providers/mine", None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    assert!(!client
+        .providers()
+        .await
+        .iter()
+        .any(|(name, _)| name == "mine"));
+    let (_, secrets) = client
+        .send(reqwest::Method::GET, "/api/secrets", None)
+        .await;
+    assert_eq!(secrets["secrets"], json!([]));
+
+    let (_, state) = serve(&dir).await;
+    assert!(state
+        .providers
+        .scope(&user_id)
+        .get_own_provider("mine")
+        .is_none());
+}
+
+#[tokio::test]
+async fn providers_are_scoped_per_user() {
+    let upstream =
+        listen(Router::new().route("/v1/chat/completions", post(chat_completions))).await;
+    let (url, _) = serve(&data_dir()).await;
+    let (_, alice) = login();
+    let (_, bob) = login();
+    let alice = Client::new(&url, Some(&alice));
+    let bob = Client::new(&url, Some(&bob));
+    let anonymous = Client::new(&url, None);
+
+    let name = format!("own-{}", &uuid::Uuid::new_v4().simple().to_string()[..8]);
+    alice
+        .send(
+            reqwest::Method::POST,
+            "/api/providers",
+            Some(register_body(&name, &format!("{}/v1", upstream))),
+        )
+        .await;
+
+    assert!(!bob.providers().await.iter().any(|(n, _)| *n == name));
+    let (status, _) = bob
+        .send(
+            reqwest::Method::DELETE,
+            &format!("/api/providers/{}", name),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = anonymous
+        .send(reqwest::Method::GET, "/api/providers", None)
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // 只有注册者的请求能路由到它
+    let chat = json!({
+        "model": format!("{}/stub", name),
+        "messages": [{ "role": "user", "content": "hi" }],
+    });
+    let (status, body) = alice
+        .send(
+            reqwest::Method::POST,
+            "/v1/chat/completions",
+            Some(chat.clone()),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["choices"][0]["message"]["content"], "from upstream");
+
+    let (status, _) = bob
+        .send(reqwest::Method::POST, "/v1/chat/completions", Some(chat))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn rejects_invalid_provider_names_and_types() {
+    let (url, _) = serve(&data_dir()).await;
+    let (_, token) = login();
+    let client = Client::new(&url, Some(&token));
+    let (status, _) = client
+        .send(
+            reqwest::Method::POST,
+            "/api/providers",
+            Some(register_body("a/b", "http://127.0.0.1:9/v1")),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let mut body = register_body("mine", "http://127.0.0.1:9/v1");
+    body["provider"] = json!("claude_code");
+    let (status, body) = client
+        .send(reqwest::Method::POST, "/api/providers", Some(body))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["error"]["message"],
+        "Unknown provider 'claude_code': use one of openai, anthropic, openrouter"
+    );
+    assert!(client.providers().await.is_empty());
+
+    // 修改时同样校验
+    client
+        .send(
+            reqwest::Method::POST,
+            "/api/providers",
+            Some(register_body("mine", "http://127.0.0.1:9/v1")),
+        )
+        .await;
+    let (status, _) = client
+        .send(
+            reqwest::Method::PATCH,
+            "/api/providers/mine",
+            Some(json!({ "provider": "nope" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = client
+        .send(reqwest::Method::POST, "/api/providers/health-check", None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn providers_without_their_own_key_are_not_probed() {
+    let checks = Arc::new(AtomicUsize::new(0));
+    let counter = checks.clone();
+    let upstream = listen(Router::new().route(
+        "/v1/models",
+        get(move || async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Json(json!({ "object": "list", "data": [] }))
+        }),
+    ))
+    .await;
+    let (url, state) = serve(&data_dir()).await;
+    let (user_id, token) = login();
+    let client = Client::new(&url, Some(&token));
+
+    // 自定义地址必须带自己的 key
+    let (status, body) = client
+        .send(
+            reqwest::Method::POST,
+            "/api/providers",
+            Some(json!({ "name": "keyless", "provider": "openai", "base_url": format!("{}/v1", upstream) })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body["error"]["message"],
+        "A provider with a base_url needs its own api_key"
+    );
+
+    let (status, _) = client
+        .send(
+            reqwest::Method::POST,
+            "/api/providers",
+            Some(json!({ "name": "plain", "provider": "openai", "model": "stub" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = client
+        .send(
+            reqwest::Method::PATCH,
+            "/api/providers/plain",
+            Some(json!({ "base_url": format!("{}/v1", upstream) })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // 没有自己 key 的 provider 不会用服务器的 key 检查
+    let (status, body) = client
+        .send(reqwest::Method::POST, "/api/providers/health-check", None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["providers"]["plain"], false);
+    state.health.refresh().await;
+    let health = state.health.user_providers(&user_id);
+    assert_eq!(health[0].0, "plain");
+    assert_eq!(
+        health[0].1.error.as_deref(),
+        Some("Not checked: the provider has no API key")
+    );
+    assert_eq!(checks.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn listing_reads_health_from_the_monitor() {
+    let checks = Arc::new(AtomicUsize::new(0));
+    let counter = checks.clone();
+    let upstream = listen(Router::new().route(
+        "/v1/models",
+        get(move || async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Json(json!({ "object": "list", "data": [] }))
+        }),
+    ))
+    .await;
+    let (url, state) = serve(&data_dir()).await;
+    let (_, token) = login();
+    let client = Client::new(&url, Some(&token));
+    client
+        .send(
+            reqwest::Method::POST,
+            "/api/providers",
+            Some(register_body("mine", &format!("{}/v1", upstream))),
+        )
+        .await;
+
+    // 列出 provider 不会请求上游
+    let before = checks.load(Ordering::SeqCst);
+    client.providers().await;
+    client.providers().await;
+    assert_eq!(checks.load(Ordering::SeqCst), before);
+
+    state.health.refresh().await;
+    assert!(checks.load(Ordering::SeqCst) > before);
+    let (_, body) = client
+        .send(reqwest::Method::GET, "/api/providers", None)
+        .await;
+    assert_eq!(body["providers"][0]["name"], "mine");
+    assert_eq!(body["providers"][0]["healthy"], true);
+}
+
+#[tokio::test]
+async fn server_keys_are_not_sent_to_user_base_urls() {
+    std::env::set_var("OPENAI_API_KEY", "sk-server-secret");
+    let authorization = Arc::new(Mutex::new(Vec::new()));
+    let seen = authorization.clone();
+    let upstream = listen(Router::new().route(
+        "/v1/chat/completions",
+        post(move |headers: HeaderMap| async move {
+            seen.lock()
+                .unwrap()
+                .push(headers.get(header::AUTHORIZATION).cloned());
+            chat_completions().await
+        }),
+    ))
+    .await;
+    let (url, state) = serve(&data_dir()).await;
+    let (user_id, token) = login();
+
+    // 只有地址、没有 key 的 provider（绕过注册校验直接保存）
+    let mut mine = config("stub");
+    mine.base_url = Some(format!("{}/v1", upstream));
+    state.providers.put(&user_id, "mine", mine).await.unwrap();
+
+    let (status, body) = Client::new(&url, Some(&token))
+        .send(
+            reqwest::Method::POST,
+            "/v1/chat/completions",
+            Some(json!({
+                "model": "mine/stub",
+                "messages": [{ "role": "user", "content": "hi" }],
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    let message = body["error"]["message"].as_str().unwrap();
+    assert!(
+        message.contains("No OpenAI API key configured"),
+        "{}",
+        message
+    );
+    assert!(authorization.lock().unwrap().is_empty());
+}
+
+fn config(model: &str) -> GatewayConfig {
+    GatewayConfig {
+        provider: "openai".to_string(),
+        model: Some(model.to_string()),
+        api_key: None,
+        base_url: None,
+        fallback_providers: vec![],
+        load_balancing: None,
+        restart_on_failure: false,
+        circuit_breaker: Default::default(),
+        rate_limit: None,
+    }
+}
+
+#[test]
+fn scoped_managers_shadow_and_fall_back() {
+    let shared = GatewayManager::new();
+    shared.register_provider("openai".to_string(), config("shared"));
+    shared.register_provider("anthropic".to_string(), config("shared"));
+
+    let scope = shared.scoped();
+    scope.register_provider("openai".to_string(), config("own"));
+    scope.register_provider("extra".to_string(), config("own"));
+
+    assert_eq!(
+        scope.get_provider("openai").unwrap().model.as_deref(),
+        Some("own")
+    );
+    assert_eq!(
+        scope.get_provider("anthropic").unwrap().model.as_deref(),
+        Some("shared")
+    );
+    assert!(scope.get_own_provider("anthropic").is_none());
+    assert!(shared.get_provider("extra").is_none());
+
+    let mut names = scope.list_providers();
+    names.sort();
+    assert_eq!(names, ["anthropic", "extra", "openai"]);
+
+    // 自己的 provider 有独立的熔断器，其余与全局共享
+    assert!(!std::sync::Arc::ptr_eq(
+        &scope.provider_health("openai"),
+        &shared.provider_health("openai")
+    ));
+    assert!(std::sync::Arc::ptr_eq(
+        &scope.provider_health("anthropic"),
+        &shared.provider_health("anthropic")
+    ));
+
+    assert!(scope.remove_provider("openai"));
+    assert!(!scope.remove_provider("anthropic"));
+    assert_eq!(
+        scope.get_provider("openai").unwrap().model.as_deref(),
+        Some("shared")
+    );
+}
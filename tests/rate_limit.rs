@@ -0,0 +1,304 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{header, StatusCode},
+    response::Response,
+    routing::post,
+    Router,
+};
+use openrunner::agent::{
+    estimate_tokens, Agent, AgentHandle, GatewayConfig, RateLimitConfig, RateLimited, RateLimiter,
+    GATEWAY_MANAGER,
+};
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::auth::create_token;
+use openrunner::types::{AgentRequest, ChatMessage, StreamEvent};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+fn limiter(requests: Option<u32>, tokens: Option<u64>, max_wait_ms: u64) -> RateLimiter {
+    RateLimiter::new(RateLimitConfig {
+        requests_per_minute: requests,
+        tokens_per_minute: tokens,
+        max_wait_ms,
+    })
+}
+
+#[tokio::test]
+async fn rejects_requests_over_the_limit() {
+    let limiter = limiter(Some(2), None, 50);
+    limiter.acquire("p", 0).await.unwrap();
+    limiter.acquire("p", 0).await.unwrap();
+    assert_eq!(limiter.status().requests_available, Some(0));
+
+    // 下一个请求要等 30 秒，超过等待上限时立即失败
+    let started = Instant::now();
+    let e = limiter.acquire("p", 0).await.unwrap_err();
+    assert!(started.elapsed() < Duration::from_millis(50));
+    assert_eq!(e.provider, "p");
+    assert!(e.retry_after > Duration::from_secs(29));
+    assert_eq!(e.retry_after_secs(), 30);
+}
+
+#[tokio::test]
+async fn queues_until_the_bucket_refills() {
+    // 每秒补充 100 个 token
+    let limiter = limiter(None, Some(6000), 1000);
+    limiter.acquire("p", 6000).await.unwrap();
+
+    let started = Instant::now();
+    limiter.acquire("p", 50).await.unwrap();
+    let waited = started.elapsed();
+    assert!(
+        waited >= Duration::from_millis(400) && waited < Duration::from_millis(900),
+        "waited {:?}",
+        waited
+    );
+
+    // 超过容量的请求等桶满，同样受等待上限约束
+    assert!(limiter.acquire("p", 100_000).await.is_err());
+    assert_eq!(limiter.status().queued, 0);
+}
+
+#[tokio::test]
+async fn settles_reservations_with_actual_usage() {
+    let limiter = limiter(None, Some(1000), 0);
+    limiter.acquire("p", 500).await.unwrap();
+    limiter.settle(500, 800);
+    let available = limiter.status().tokens_available.unwrap();
+    assert!((200..=201).contains(&available), "available {}", available);
+
+    // 用量超出预估后额度可以为负，之后的请求需要等待
+    limiter.settle(0, 1000);
+    assert!(limiter.status().tokens_available.unwrap() < 0);
+    assert!(limiter.acquire("p", 1).await.is_err());
+}
+
+#[test]
+fn estimates_prompt_and_completion_tokens() {
+    let mut request = AgentRequest {
+        messages: vec![ChatMessage::user("x".repeat(400))],
+        system: Some("y".repeat(40)),
+        ..Default::default()
+    };
+    assert_eq!(estimate_tokens(&request), 110);
+    request.sampling.max_tokens = Some(256);
+    assert_eq!(estimate_tokens(&request), 366);
+}
+
+#[tokio::test]
+async fn zero_limits_are_rejected_and_never_enforced() {
+    let config = RateLimitConfig {
+        requests_per_minute: Some(0),
+        ..Default::default()
+    };
+    assert_eq!(
+        config.validate().unwrap_err(),
+        "rate_limit.requests_per_minute must be at least 1"
+    );
+    let config = RateLimitConfig {
+        tokens_per_minute: Some(0),
+        ..Default::default()
+    };
+    assert_eq!(
+        config.validate().unwrap_err(),
+        "rate_limit.tokens_per_minute must be at least 1"
+    );
+    assert!(RateLimitConfig::default().validate().is_ok());
+
+    // 绕过校验的 0 视为不限制，而不是永远等待
+    let limiter = limiter(Some(0), Some(0), 0);
+    limiter.acquire("p", 100).await.unwrap();
+    let status = limiter.status();
+    assert_eq!(
+        (status.requests_available, status.tokens_available),
+        (None, None)
+    );
+}
+
+/// 直接返回错误的 agent
+struct Failing(fn() -> anyhow::Error);
+
+#[async_trait]
+impl Agent for Failing {
+    async fn run(&self, _prompt: String, _tx: mpsc::Sender<StreamEvent>) -> anyhow::Result<()> {
+        Err((self.0)())
+    }
+
+    fn name(&self) -> &str {
+        "failing"
+    }
+}
+
+async fn error_event(error: fn() -> anyhow::Error) -> StreamEvent {
+    let (tx, mut rx) = mpsc::channel(10);
+    let handle = AgentHandle::spawn(Box::new(Failing(error)), tx);
+    assert!(handle.run("hi".to_string()).await.is_err());
+    rx.recv().await.unwrap()
+}
+
+#[tokio::test]
+async fn only_rate_limit_errors_carry_a_retry_after() {
+    let event = error_event(|| {
+        RateLimited {
+            provider: "p".to_string(),
+            retry_after: Duration::from_millis(200),
+        }
+        .into()
+    })
+    .await;
+    // 不足一秒向上取整
+    assert!(matches!(
+        event,
+        StreamEvent::Error { message, retry_after_secs: Some(1) }
+            if message.ends_with("retry after 1s")
+    ));
+
+    // 消息相同但不是限流错误时不带等待时间
+    let event = error_event(|| {
+        anyhow::anyhow!(
+            "rate_limited: provider 'p' is over its client-side rate limit, retry after 1s"
+        )
+    })
+    .await;
+    assert!(matches!(
+        event,
+        StreamEvent::Error {
+            retry_after_secs: None,
+            ..
+        }
+    ));
+}
+
+async fn chat_completions() -> Response {
+    let chunk = json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 1,
+        "model": "stub",
+        "choices": [{ "index": 0, "delta": { "content": "ok" }, "finish_reason": "stop" }]
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .body(Body::from(format!("data: {}\n\ndata: [DONE]\n\n", chunk)))
+        .unwrap()
+}
+
+async fn listen(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+#[tokio::test]
+async fn chat_completions_answer_429_once_the_limit_is_used_up() {
+    let upstream =
+        listen(Router::new().route("/v1/chat/completions", post(chat_completions))).await;
+    let name = format!(
+        "limited-{}",
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    );
+    GATEWAY_MANAGER.register_provider(
+        name.clone(),
+        GatewayConfig {
+            provider: "openai".to_string(),
+            model: Some("stub".to_string()),
+            api_key: Some("sk-stub".to_string()),
+            base_url: Some(format!("{}/v1", upstream)),
+            fallback_providers: vec![],
+            load_balancing: None,
+            restart_on_failure: false,
+            circuit_breaker: Default::default(),
+            rate_limit: Some(RateLimitConfig {
+                requests_per_minute: Some(1),
+                tokens_per_minute: None,
+                max_wait_ms: 100,
+            }),
+        },
+    );
+
+    let data_dir = std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()));
+    let url = listen(create_router_with_state(
+        AppState::with_data_dir(&data_dir).await,
+    ))
+    .await;
+    let client = reqwest::Client::new();
+    let chat = |stream: bool| {
+        client
+            .post(format!("{}/v1/chat/completions", url))
+            .json(&json!({
+                "model": format!("{}/stub", name),
+                "messages": [{ "role": "user", "content": "hi" }],
+                "stream": stream,
+            }))
+            .send()
+    };
+
+    let response = chat(false).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for stream in [false, true] {
+        let response = chat(stream).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(
+            (55..=60).contains(&retry_after),
+            "retry-after {}",
+            retry_after
+        );
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"]["type"], "rate_limit_exceeded");
+    }
+
+    // 重新注册相同的限制不会重置额度
+    let config = GATEWAY_MANAGER.get_provider(&name).unwrap();
+    GATEWAY_MANAGER.register_provider(name.clone(), config);
+
+    // 全局注册的 provider 对每个登录用户可见
+    let token = create_token("rate-limit-tester", "tester", &[]).unwrap();
+    let body: Value = client
+        .get(format!("{}/api/providers", url))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let provider = body["providers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|p| p["name"] == name.as_str())
+        .unwrap();
+    assert_eq!(provider["rate_limit"]["requests_per_minute"], 1);
+    assert_eq!(provider["rate_limit"]["requests_available"], 0);
+    assert_eq!(provider["rate_limit"]["queued"], 0);
+    assert_eq!(provider["shared"], true);
+
+    // 为 0 的限制在注册时被拒绝
+    let response = client
+        .post(format!("{}/api/providers", url))
+        .bearer_auth(create_token("rate-limit-tester", "tester", &[]).unwrap())
+        .json(&json!({
+            "name": format!("{}-zero", name),
+            "provider": "openai",
+            "rate_limit": { "requests_per_minute": 0 },
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(
+        body["error"]["message"],
+        "rate_limit.requests_per_minute must be at least 1"
+    );
+}
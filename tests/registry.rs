@@ -0,0 +1,117 @@
+use openrunner::agent::{
+    version_probe, Agent, AgentCapabilities, AgentInfo, AgentRegistry, MockAgent,
+};
+use openrunner::types::AgentConfig;
+
+fn config(agent_type: &str) -> AgentConfig {
+    AgentConfig {
+        agent_type: agent_type.to_string(),
+        ..Default::default()
+    }
+}
+
+fn probe(command: &[&str]) -> Vec<String> {
+    command.iter().map(|c| c.to_string()).collect()
+}
+
+#[test]
+fn builtins_are_registered() {
+    let registry = AgentRegistry::with_builtins();
+    let types: Vec<String> = registry.list().into_iter().map(|i| i.agent_type).collect();
+    for agent_type in ["claude_code", "codex", "mock", "openai", "anthropic"] {
+        assert!(types.iter().any(|t| t == agent_type), "{:?}", types);
+    }
+    assert!(registry.create(&config("mock")).is_ok());
+    let error = registry.create(&config("nope")).err().unwrap();
+    assert_eq!(error.to_string(), "Unknown agent type: nope");
+}
+
+#[test]
+fn registering_replaces_and_unregistering_removes() {
+    let registry = AgentRegistry::new();
+    registry.register(AgentInfo::new("a", "first"), |c| {
+        Ok(Box::new(MockAgent::new(c.clone())))
+    });
+    registry.register(AgentInfo::new("b", "second"), |c| {
+        Ok(Box::new(MockAgent::new(c.clone())))
+    });
+    registry.register(AgentInfo::new("a", "replaced"), |_| {
+        anyhow::bail!("not configured")
+    });
+
+    let list = registry.list();
+    assert_eq!(
+        list.iter()
+            .map(|i| (i.agent_type.as_str(), i.description.as_str()))
+            .collect::<Vec<_>>(),
+        [("a", "replaced"), ("b", "second")]
+    );
+    assert!(registry.create(&config("a")).is_err());
+
+    // 克隆共享同一份数据
+    let clone = registry.clone();
+    assert!(clone.unregister("a"));
+    assert!(!clone.unregister("a"));
+    assert!(!registry.contains("a"));
+    assert!(registry.contains("b"));
+}
+
+#[test]
+fn capabilities_default_to_what_the_agent_reports() {
+    let registry = AgentRegistry::new();
+    registry.register(AgentInfo::new("inferred", "mock"), |c| {
+        Ok(Box::new(MockAgent::new(c.clone())))
+    });
+    let declared = AgentCapabilities {
+        mcp: true,
+        ..Default::default()
+    };
+    registry.register(
+        AgentInfo::new("declared", "mock").capabilities(declared),
+        |c| Ok(Box::new(MockAgent::new(c.clone()))),
+    );
+
+    let mock = MockAgent::new(config("mock"));
+    assert_eq!(
+        registry.info("inferred").unwrap().capabilities,
+        mock.capabilities()
+    );
+    assert_eq!(registry.info("declared").unwrap().capabilities, declared);
+}
+
+#[tokio::test]
+async fn checks_use_the_probe_or_the_agent() {
+    let registry = AgentRegistry::new();
+    registry.register(AgentInfo::new("plain", "mock"), |c| {
+        Ok(Box::new(MockAgent::new(c.clone())))
+    });
+    registry.register_with_probe(
+        AgentInfo::new("versioned", "sh"),
+        |c| Ok(Box::new(MockAgent::new(c.clone()))),
+        version_probe(probe(&["sh", "-c", "echo; echo ' v1.2.3 '; echo x"]), None),
+    );
+    registry.register_with_probe(
+        AgentInfo::new("failing", "sh"),
+        |c| Ok(Box::new(MockAgent::new(c.clone()))),
+        version_probe(
+            probe(&["sh", "-c", "exit 1"]),
+            Some("npm i -g x".to_string()),
+        ),
+    );
+    registry.register_with_probe(
+        AgentInfo::new("missing", "missing"),
+        |c| Ok(Box::new(MockAgent::new(c.clone()))),
+        version_probe(probe(&["openrunner-missing-binary"]), None),
+    );
+
+    assert_eq!(registry.check("plain").await.unwrap(), None);
+    assert_eq!(
+        registry.check("versioned").await.unwrap().as_deref(),
+        Some("v1.2.3")
+    );
+    let error = registry.check("failing").await.unwrap_err().to_string();
+    assert!(error.starts_with("sh not available"), "{}", error);
+    assert!(error.ends_with("Install: npm i -g x"), "{}", error);
+    assert!(registry.check("missing").await.is_err());
+    assert!(registry.check("unknown").await.is_err());
+}
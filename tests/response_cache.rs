@@ -0,0 +1,260 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, StatusCode},
+    response::Response,
+    routing::post,
+    Router,
+};
+use openrunner::agent::{GatewayConfig, GATEWAY_MANAGER};
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::{ResponseCache, ResponseCacheConfig};
+use serde_json::{json, Value};
+
+/// 上游 stub：统计收到的请求数，每次回答带上序号
+async fn chat_completions(State(calls): State<Arc<AtomicUsize>>) -> Response {
+    let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+    let chunk = json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 1,
+        "model": "stub",
+        "choices": [{ "index": 0, "delta": { "content": format!("answer {}", call) }, "finish_reason": "stop" }]
+    });
+    let usage = json!({
+        "id": "chatcmpl-1",
+        "object": "chat.completion.chunk",
+        "created": 1,
+        "model": "stub",
+        "choices": [],
+        "usage": { "prompt_tokens": 3, "completion_tokens": 2, "total_tokens": 5 }
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .body(Body::from(format!(
+            "data: {}\n\ndata: {}\n\ndata: [DONE]\n\n",
+            chunk, usage
+        )))
+        .unwrap()
+}
+
+async fn listen(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+fn enabled() -> ResponseCacheConfig {
+    ResponseCacheConfig {
+        enabled: true,
+        ..Default::default()
+    }
+}
+
+async fn state() -> AppState {
+    let data_dir = std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()));
+    AppState::with_data_dir(&data_dir).await
+}
+
+/// 注册指向 stub 的全局 provider，启动开启缓存的 OpenRunner；返回地址、模型名和上游请求计数
+async fn serve() -> (String, String, Arc<AtomicUsize>) {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let upstream = listen(
+        Router::new()
+            .route("/v1/chat/completions", post(chat_completions))
+            .with_state(calls.clone()),
+    )
+    .await;
+    let name = format!("cached-{}", &uuid::Uuid::new_v4().simple().to_string()[..8]);
+    GATEWAY_MANAGER.register_provider(
+        name.clone(),
+        GatewayConfig {
+            provider: "openai".to_string(),
+            model: Some("stub".to_string()),
+            api_key: Some("sk-stub".to_string()),
+            base_url: Some(format!("{}/v1", upstream)),
+            fallback_providers: vec![],
+            load_balancing: None,
+            restart_on_failure: false,
+            circuit_breaker: Default::default(),
+            rate_limit: None,
+        },
+    );
+
+    let mut state = state().await;
+    state.cache = ResponseCache::new(state.db.clone(), enabled());
+    let url = listen(create_router_with_state(state)).await;
+    (url, format!("{}/stub", name), calls)
+}
+
+fn chat(model: &str, temperature: f32, stream: bool) -> Value {
+    json!({
+        "model": model,
+        "messages": [{ "role": "user", "content": "hi" }],
+        "temperature": temperature,
+        "stream": stream,
+        "stream_options": { "include_usage": true },
+    })
+}
+
+/// 发送请求，返回缓存状态头和响应体
+async fn send(url: &str, body: &Value, cache_control: Option<&str>) -> (Option<String>, String) {
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/v1/chat/completions", url))
+        .json(body);
+    if let Some(value) = cache_control {
+        request = request.header(header::CACHE_CONTROL, value);
+    }
+    let response = request.send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let cache = response
+        .headers()
+        .get("x-openrunner-cache")
+        .map(|v| v.to_str().unwrap().to_string());
+    (cache, response.text().await.unwrap())
+}
+
+/// SSE 响应中的 data 行
+fn sse_data(body: &str) -> Vec<String> {
+    body.lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .map(str::to_string)
+        .collect()
+}
+
+#[tokio::test]
+async fn repeated_deterministic_requests_are_answered_from_the_cache() {
+    let (url, model, calls) = serve().await;
+    let body = chat(&model, 0.0, false);
+
+    let (cache, first) = send(&url, &body, None).await;
+    assert_eq!(cache.as_deref(), Some("miss"));
+    let (cache, second) = send(&url, &body, None).await;
+    assert_eq!(cache.as_deref(), Some("hit"));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    let first: Value = serde_json::from_str(&first).unwrap();
+    let second: Value = serde_json::from_str(&second).unwrap();
+    assert_eq!(second["choices"], first["choices"]);
+    assert_eq!(second["choices"][0]["message"]["content"], "answer 1");
+    assert_eq!(second["usage"]["total_tokens"], 5);
+    assert_eq!(second["model"], model.as_str());
+    assert_ne!(second["id"], first["id"]);
+
+    // `no-cache` 跳过缓存，新的回答覆盖旧的
+    let (cache, _) = send(&url, &body, Some("no-cache")).await;
+    assert_eq!(cache.as_deref(), Some("miss"));
+    let (_, third) = send(&url, &body, None).await;
+    let third: Value = serde_json::from_str(&third).unwrap();
+    assert_eq!(third["choices"][0]["message"]["content"], "answer 2");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // 非确定性请求不经过缓存
+    let (cache, _) = send(&url, &chat(&model, 0.7, false), None).await;
+    assert_eq!(cache, None);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn no_store_keeps_the_answer_out_of_the_cache() {
+    let (url, model, calls) = serve().await;
+    let body = chat(&model, 0.0, false);
+
+    let (cache, _) = send(&url, &body, Some("no-store")).await;
+    assert_eq!(cache.as_deref(), Some("miss"));
+    let (cache, _) = send(&url, &body, None).await;
+    assert_eq!(cache.as_deref(), Some("miss"));
+    let (cache, _) = send(&url, &body, None).await;
+    assert_eq!(cache.as_deref(), Some("hit"));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn cached_answers_are_replayed_as_chunks() {
+    let (url, model, calls) = serve().await;
+
+    // 流式请求的回答写入缓存后，流式和非流式请求都能命中
+    let (cache, streamed) = send(&url, &chat(&model, 0.0, true), None).await;
+    assert_eq!(cache.as_deref(), Some("miss"));
+    let (cache, replayed) = send(&url, &chat(&model, 0.0, true), None).await;
+    assert_eq!(cache.as_deref(), Some("hit"));
+    let (cache, complete) = send(&url, &chat(&model, 0.0, false), None).await;
+    assert_eq!(cache.as_deref(), Some("hit"));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    let complete: Value = serde_json::from_str(&complete).unwrap();
+    assert_eq!(complete["choices"][0]["message"]["content"], "answer 1");
+
+    for body in [streamed, replayed] {
+        let data = sse_data(&body);
+        assert_eq!(data.last().map(String::as_str), Some("[DONE]"));
+        let chunks: Vec<Value> = data[..data.len() - 1]
+            .iter()
+            .map(|d| serde_json::from_str(d).unwrap())
+            .collect();
+        assert!(chunks
+            .iter()
+            .all(|c| c["object"] == "chat.completion.chunk"));
+        let text: String = chunks
+            .iter()
+            .filter_map(|c| c["choices"][0]["delta"]["content"].as_str())
+            .collect();
+        assert_eq!(text, "answer 1");
+        assert!(chunks
+            .iter()
+            .any(|c| c["choices"][0]["finish_reason"] == "stop"));
+        assert_eq!(chunks.last().unwrap()["usage"]["total_tokens"], 5);
+    }
+}
+
+#[tokio::test]
+async fn entries_expire_and_respect_size_limits() {
+    let db = state().await.db;
+    let response = |n: usize| json!({ "text": "x".repeat(n) });
+
+    let expired = ResponseCache::new(
+        db.clone(),
+        ResponseCacheConfig {
+            ttl: Duration::ZERO,
+            ..enabled()
+        },
+    );
+    assert!(expired.put("expired", &response(10)).await);
+    assert_eq!(expired.get("expired").await, None);
+
+    let cache = ResponseCache::new(
+        db,
+        ResponseCacheConfig {
+            max_entry_bytes: 200,
+            max_total_bytes: 250,
+            ..enabled()
+        },
+    );
+    assert!(!cache.put("too-big", &response(300)).await);
+    assert_eq!(cache.get("too-big").await, None);
+
+    // 超出总大小时淘汰最早写入的
+    for key in ["a", "b", "c"] {
+        assert!(cache.put(key, &response(100)).await);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    assert_eq!(cache.get("a").await, None);
+    assert_eq!(cache.get("b").await, Some(response(100)));
+    assert_eq!(cache.get("c").await, Some(response(100)));
+}
+
+#[test]
+fn keys_ignore_object_key_order() {
+    let a = json!({ "model": "m", "params": { "temperature": 0.0, "seed": 1 } });
+    let b = json!({ "params": { "seed": 1, "temperature": 0.0 }, "model": "m" });
+    assert_eq!(ResponseCache::key(&a), ResponseCache::key(&b));
+    assert_ne!(
+        ResponseCache::key(&a),
+        ResponseCache::key(&json!({ "model": "n" }))
+    );
+}
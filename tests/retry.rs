@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::{header, StatusCode},
+    response::Response,
+    routing::post,
+    Router,
+};
+use futures::StreamExt;
+use openrunner::agent::{Agent, AnthropicAgent, OpenAIAgent};
+use openrunner::run::{RunManager, RunStatus, RunStore};
+use openrunner::types::{AgentConfig, AgentRequest, ProviderAttempt, RetryConfig, StreamEvent};
+use tokio::sync::mpsc;
+
+const OPENAI_STREAM: &str = concat!(
+    "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hello\"},\"finish_reason\":null}]}\n\n",
+    "data: {\"id\":\"chatcmpl-1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+    "data: [DONE]\n\n",
+);
+
+const ANTHROPIC_STREAM: &str = concat!(
+    "event: message_start\n",
+    "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":5,\"output_tokens\":1}}}\n\n",
+    "event: content_block_start\n",
+    "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+    "event: content_block_delta\n",
+    "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"hello\"}}\n\n",
+    "event: content_block_stop\n",
+    "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+    "event: message_delta\n",
+    "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":2}}\n\n",
+    "event: message_stop\n",
+    "data: {\"type\":\"message_stop\"}\n\n",
+);
+
+/// 上游对一次请求的响应
+#[derive(Clone)]
+struct Reply {
+    status: StatusCode,
+    headers: Vec<(&'static str, String)>,
+    /// 成功时为 SSE 流，`None` 表示发出一个 token 后断开连接
+    body: Option<&'static str>,
+}
+
+impl Reply {
+    fn ok(body: &'static str) -> Self {
+        Self {
+            status: StatusCode::OK,
+            headers: vec![],
+            body: Some(body),
+        }
+    }
+
+    fn error(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: Some(r#"{"error":{"message":"upstream error","type":"server_error"}}"#),
+        }
+    }
+
+    fn header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    fn broken() -> Self {
+        Self {
+            status: StatusCode::OK,
+            headers: vec![],
+            body: None,
+        }
+    }
+}
+
+/// 按顺序回放响应，超出后重复最后一个；记录每次请求到达的时间
+#[derive(Clone)]
+struct Script {
+    replies: Arc<Vec<Reply>>,
+    requests: Arc<Mutex<Vec<Instant>>>,
+}
+
+async fn reply(State(script): State<Script>) -> Response {
+    let reply = {
+        let mut requests = script.requests.lock().unwrap();
+        requests.push(Instant::now());
+        script.replies[(requests.len() - 1).min(script.replies.len() - 1)].clone()
+    };
+
+    let mut builder = Response::builder().status(reply.status);
+    for (name, value) in &reply.headers {
+        builder = builder.header(*name, value);
+    }
+    let body = match reply.body {
+        Some(body) if reply.status.is_success() => {
+            builder = builder.header(header::CONTENT_TYPE, "text/event-stream");
+            Body::from(body)
+        }
+        Some(body) => Body::from(body),
+        None => {
+            let parts: Vec<Result<Bytes, std::io::Error>> = vec![
+                Ok(Bytes::from(
+                    &OPENAI_STREAM[..OPENAI_STREAM.find("\n\n").unwrap() + 2],
+                )),
+                Err(std::io::Error::other("connection reset")),
+            ];
+            Body::from_stream(futures::stream::iter(parts).then(|part| async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                part
+            }))
+        }
+    };
+    builder.body(body).unwrap()
+}
+
+/// 启动回放 `replies` 的上游，返回 base url 和请求时间
+async fn serve(replies: Vec<Reply>) -> (String, Arc<Mutex<Vec<Instant>>>) {
+    let script = Script {
+        replies: Arc::new(replies),
+        requests: Arc::default(),
+    };
+    let requests = script.requests.clone();
+    let app = Router::new()
+        .route("/v1/chat/completions", post(reply))
+        .route("/v1/messages", post(reply))
+        .with_state(script);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (format!("http://{}/v1", addr), requests)
+}
+
+fn retry(max_retries: u32, max_delay_ms: u64) -> RetryConfig {
+    RetryConfig {
+        max_retries,
+        base_delay_ms: 1,
+        max_delay_ms,
+    }
+}
+
+fn config(agent_type: &str, base_url: &str, retry: RetryConfig) -> AgentConfig {
+    let prefix = agent_type.to_ascii_uppercase();
+    AgentConfig {
+        agent_type: agent_type.to_string(),
+        env: HashMap::from([
+            (format!("{}_API_KEY", prefix), "sk-test".to_string()),
+            (format!("{}_BASE_URL", prefix), base_url.to_string()),
+        ]),
+        retry,
+        ..Default::default()
+    }
+}
+
+async fn execute(agent: impl Agent) -> (anyhow::Result<()>, Vec<StreamEvent>) {
+    let (tx, mut rx) = mpsc::channel(100);
+    let result = agent.execute(AgentRequest::new("hi"), tx).await;
+    let mut events = Vec::new();
+    while let Some(event) = rx.recv().await {
+        events.push(event);
+    }
+    (result, events)
+}
+
+fn attempts(events: &[StreamEvent]) -> Vec<ProviderAttempt> {
+    events
+        .iter()
+        .filter_map(|e| match e {
+            StreamEvent::Attempt { attempt } => Some(attempt.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn text(events: &[StreamEvent]) -> String {
+    events
+        .iter()
+        .filter_map(|e| match e {
+            StreamEvent::Token { content } => Some(content.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn retries_rate_limits_and_server_errors() {
+    let (base_url, requests) = serve(vec![
+        Reply::error(StatusCode::TOO_MANY_REQUESTS).header("retry-after", "0"),
+        Reply::error(StatusCode::SERVICE_UNAVAILABLE),
+        Reply::ok(OPENAI_STREAM),
+    ])
+    .await;
+
+    let agent = OpenAIAgent::new(config("openai", &base_url, retry(3, 1000)));
+    let (result, events) = execute(agent).await;
+    result.unwrap();
+    assert_eq!(text(&events), "hello");
+    assert_eq!(requests.lock().unwrap().len(), 3);
+
+    let attempts = attempts(&events);
+    let statuses: Vec<_> = attempts.iter().map(|a| a.status).collect();
+    assert_eq!(statuses, vec![Some(429), Some(503), Some(200)]);
+    assert_eq!(attempts[0].provider, "openai");
+    assert_eq!(attempts[0].attempt, 1);
+    // Retry-After: 0 表示立即重试
+    assert_eq!(attempts[0].retry_after_ms, Some(0));
+    assert!(attempts[1].retry_after_ms.is_some());
+    assert_eq!(attempts[2].retry_after_ms, None);
+    assert_eq!(attempts[2].error, None);
+}
+
+#[tokio::test]
+async fn waits_for_the_rate_limit_reset() {
+    let (base_url, requests) = serve(vec![
+        Reply::error(StatusCode::TOO_MANY_REQUESTS)
+            .header("x-ratelimit-remaining-requests", "0")
+            .header("x-ratelimit-reset-requests", "300ms")
+            // 还有剩余的那一组不影响等待时间
+            .header("x-ratelimit-remaining-tokens", "100")
+            .header("x-ratelimit-reset-tokens", "6m0s"),
+        Reply::ok(OPENAI_STREAM),
+    ])
+    .await;
+
+    let agent = OpenAIAgent::new(config("openai", &base_url, retry(3, 1000)));
+    let (result, events) = execute(agent).await;
+    result.unwrap();
+
+    let requests = requests.lock().unwrap();
+    assert!(requests[1] - requests[0] >= Duration::from_millis(300));
+    assert_eq!(attempts(&events)[0].retry_after_ms, Some(300));
+}
+
+#[tokio::test]
+async fn does_not_retry_client_errors() {
+    let (base_url, requests) = serve(vec![Reply::error(StatusCode::BAD_REQUEST)]).await;
+
+    let agent = OpenAIAgent::new(config("openai", &base_url, retry(3, 1000)));
+    let (result, events) = execute(agent).await;
+    assert!(result.unwrap_err().to_string().contains("upstream error"));
+    assert_eq!(requests.lock().unwrap().len(), 1);
+    assert_eq!(attempts(&events)[0].status, Some(400));
+    assert_eq!(attempts(&events)[0].retry_after_ms, None);
+}
+
+#[tokio::test]
+async fn honours_x_should_retry() {
+    let (base_url, requests) = serve(vec![
+        Reply::error(StatusCode::INTERNAL_SERVER_ERROR).header("x-should-retry", "false")
+    ])
+    .await;
+
+    let agent = OpenAIAgent::new(config("openai", &base_url, retry(3, 1000)));
+    assert!(execute(agent).await.0.is_err());
+    assert_eq!(requests.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn gives_up_when_the_provider_asks_to_wait_too_long() {
+    let (base_url, requests) = serve(vec![
+        Reply::error(StatusCode::TOO_MANY_REQUESTS).header("retry-after", "120"),
+        Reply::ok(OPENAI_STREAM),
+    ])
+    .await;
+
+    let agent = OpenAIAgent::new(config("openai", &base_url, retry(3, 1000)));
+    let started = Instant::now();
+    assert!(execute(agent).await.0.is_err());
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(requests.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn stops_after_max_retries() {
+    let (base_url, requests) = serve(vec![Reply::error(StatusCode::BAD_GATEWAY)]).await;
+
+    let agent = OpenAIAgent::new(config("openai", &base_url, retry(2, 1000)));
+    let (result, events) = execute(agent).await;
+    assert!(result.is_err());
+    assert_eq!(requests.lock().unwrap().len(), 3);
+    assert_eq!(attempts(&events).len(), 3);
+}
+
+#[tokio::test]
+async fn does_not_retry_after_output_started() {
+    let (base_url, requests) = serve(vec![Reply::broken(), Reply::ok(OPENAI_STREAM)]).await;
+
+    let agent = OpenAIAgent::new(config("openai", &base_url, retry(3, 1000)));
+    let (result, events) = execute(agent).await;
+    assert!(result.is_err());
+    assert_eq!(text(&events), "hello");
+    assert_eq!(requests.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn retries_connection_failures() {
+    // 绑定后立即释放，得到一个没有监听的端口
+    let addr = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    };
+
+    let base_url = format!("http://{}/v1", addr);
+    let agent = OpenAIAgent::new(config("openai", &base_url, retry(1, 1000)));
+    let (result, events) = execute(agent).await;
+    assert!(result.is_err());
+    let attempts = attempts(&events);
+    assert_eq!(attempts.len(), 2);
+    assert!(attempts
+        .iter()
+        .all(|a| a.status.is_none() && a.error.is_some()));
+}
+
+#[tokio::test]
+async fn anthropic_retries_overloaded_errors() {
+    let (base_url, requests) = serve(vec![
+        Reply::error(StatusCode::from_u16(529).unwrap()),
+        Reply::ok(ANTHROPIC_STREAM),
+    ])
+    .await;
+
+    let agent = AnthropicAgent::new(config("anthropic", &base_url, retry(3, 1000)));
+    let (result, events) = execute(agent).await;
+    result.unwrap();
+    assert_eq!(text(&events), "hello");
+    assert_eq!(requests.lock().unwrap().len(), 2);
+    assert_eq!(attempts(&events)[0].provider, "anthropic");
+}
+
+#[tokio::test]
+async fn attempts_are_recorded_on_the_run() {
+    let (base_url, _) = serve(vec![
+        Reply::error(StatusCode::TOO_MANY_REQUESTS).header("retry-after", "0"),
+        Reply::ok(OPENAI_STREAM),
+    ])
+    .await;
+
+    let manager = RunManager::new(RunStore::new());
+    let run_id = manager.create_run("user", None, "hi");
+    manager
+        .start_run(&run_id, config("openai", &base_url, retry(3, 1000)))
+        .await
+        .unwrap();
+    let run = manager
+        .wait_run(&run_id, Duration::from_secs(10))
+        .await
+        .unwrap();
+
+    assert_eq!(run.status, RunStatus::Completed);
+    assert_eq!(run.output, "hello");
+    let statuses: Vec<_> = run.attempts.iter().map(|a| a.status).collect();
+    assert_eq!(statuses, vec![Some(429), Some(200)]);
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body, extract::State, http::header, response::Response, routing::post, Json, Router,
+};
+use openrunner::agent::{Agent, OpenAIAgent};
+use openrunner::types::{AgentConfig, AgentRequest, SamplingParams};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+const STREAM: &str = concat!(
+    "data: {\"id\":\"c\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"ok\"},\"finish_reason\":\"stop\"}]}\n\n",
+    "data: [DONE]\n\n",
+);
+
+async fn chat_completions(
+    State(requests): State<Arc<Mutex<Vec<Value>>>>,
+    Json(body): Json<Value>,
+) -> Response {
+    requests.lock().unwrap().push(body);
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .body(Body::from(STREAM))
+        .unwrap()
+}
+
+/// 用 OpenAI agent 发送请求，返回上游收到的请求体
+async fn upstream_body(sampling: SamplingParams) -> Value {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let app = Router::new()
+        .route("/v1/chat/completions", post(chat_completions))
+        .with_state(requests.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let agent = OpenAIAgent::new(AgentConfig {
+        agent_type: "openai".to_string(),
+        model: Some("m".to_string()),
+        env: HashMap::from([
+            ("OPENAI_API_KEY".to_string(), "sk-test".to_string()),
+            ("OPENAI_BASE_URL".to_string(), format!("http://{}/v1", addr)),
+        ]),
+        ..Default::default()
+    });
+    let mut request = AgentRequest::new("hi");
+    request.sampling = sampling;
+    let (tx, _rx) = mpsc::channel(100);
+    agent.execute(request, tx).await.unwrap();
+
+    let body = requests.lock().unwrap().remove(0);
+    body
+}
+
+#[test]
+fn unset_fields_fall_back_to_defaults() {
+    let defaults = SamplingParams {
+        temperature: Some(0.7),
+        max_tokens: Some(512),
+        stop: vec!["END".to_string()],
+        response_format: Some(json!({ "type": "json_object" })),
+        ..Default::default()
+    };
+    let request = SamplingParams {
+        temperature: Some(0.0),
+        seed: Some(7),
+        ..Default::default()
+    };
+    assert_eq!(
+        request.or(&defaults),
+        SamplingParams {
+            temperature: Some(0.0),
+            max_tokens: Some(512),
+            stop: vec!["END".to_string()],
+            seed: Some(7),
+            response_format: Some(json!({ "type": "json_object" })),
+            ..Default::default()
+        }
+    );
+
+    let own_stop = SamplingParams {
+        stop: vec!["STOP".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(own_stop.or(&defaults).stop, ["STOP"]);
+    assert_eq!(SamplingParams::default().or(&defaults), defaults);
+}
+
+#[tokio::test]
+async fn openai_requests_carry_every_sampling_field() {
+    let body = upstream_body(SamplingParams {
+        temperature: Some(0.25),
+        top_p: Some(0.5),
+        max_tokens: Some(64),
+        frequency_penalty: Some(0.5),
+        presence_penalty: Some(-0.5),
+        stop: vec!["\n\n".to_string()],
+        seed: Some(42),
+        response_format: Some(json!({ "type": "json_object" })),
+    })
+    .await;
+    assert_eq!(body["temperature"], 0.25);
+    assert_eq!(body["top_p"], 0.5);
+    assert_eq!(body["max_tokens"], 64);
+    assert_eq!(body["frequency_penalty"], 0.5);
+    assert_eq!(body["presence_penalty"], -0.5);
+    assert_eq!(body["stop"], json!(["\n\n"]));
+    assert_eq!(body["seed"], 42);
+    assert_eq!(body["response_format"], json!({ "type": "json_object" }));
+}
+
+#[tokio::test]
+async fn unset_sampling_fields_use_provider_defaults() {
+    let body = upstream_body(SamplingParams::default()).await;
+    for field in [
+        "temperature",
+        "top_p",
+        "max_tokens",
+        "frequency_penalty",
+        "presence_penalty",
+    ] {
+        assert!(body[field].is_null(), "{}: {}", field, body[field]);
+    }
+    for field in ["stop", "seed", "response_format"] {
+        assert!(body.get(field).is_none(), "{}", field);
+    }
+}
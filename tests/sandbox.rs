@@ -0,0 +1,205 @@
+use std::path::PathBuf;
+
+use axum::http::StatusCode;
+use openrunner::agent::SandboxCommand;
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::types::{SandboxBackend, SandboxConfig};
+use serde_json::json;
+
+/// 构建后的程序名和参数
+fn argv(command: SandboxCommand) -> (String, Vec<String>) {
+    let command = command.build().unwrap();
+    let std = command.as_std();
+    (
+        std.get_program().to_string_lossy().to_string(),
+        std.get_args()
+            .map(|a| a.to_string_lossy().to_string())
+            .collect(),
+    )
+}
+
+/// `args` 中是否连续出现 `expected`
+fn contains(args: &[String], expected: &[&str]) -> bool {
+    args.windows(expected.len()).any(|w| w == expected)
+}
+
+fn sandbox(backend: SandboxBackend) -> SandboxConfig {
+    SandboxConfig {
+        backend,
+        ro_binds: vec!["/ro".to_string()],
+        rw_binds: vec!["/rw".to_string()],
+        ..Default::default()
+    }
+}
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn without_a_sandbox_the_program_runs_directly() {
+    let mut command = SandboxCommand::new("claude", None);
+    command.arg("-p").arg("hi").current_dir("/work");
+    assert_eq!(
+        argv(command),
+        (
+            "claude".to_string(),
+            vec!["-p".to_string(), "hi".to_string()]
+        )
+    );
+}
+
+#[test]
+fn bubblewrap_mounts_only_the_working_dir_read_write() {
+    let config = SandboxConfig {
+        network: false,
+        ..sandbox(SandboxBackend::Bubblewrap)
+    };
+    let mut command = SandboxCommand::new("claude", Some(&config));
+    command
+        .arg("-p")
+        .current_dir("/work")
+        .bind("/extra")
+        .env("KEY", "secret");
+    let (program, args) = argv(command);
+
+    assert_eq!(program, "bwrap");
+    assert!(contains(
+        &args,
+        &["--die-with-parent", "--new-session", "--unshare-all"]
+    ));
+    assert!(!args.contains(&"--share-net".to_string()));
+    assert!(contains(&args, &["--ro-bind-try", "/usr", "/usr"]));
+    assert!(contains(&args, &["--ro-bind-try", "/ro", "/ro"]));
+    for path in ["/work", "/extra", "/rw"] {
+        assert!(contains(&args, &["--bind", path, path]), "{:?}", args);
+    }
+    assert!(contains(&args, &["--chdir", "/work"]));
+    assert!(!args.iter().any(|a| a == "/" || a.contains("secret")));
+    assert!(args.ends_with(&["--".to_string(), "claude".to_string(), "-p".to_string()]));
+
+    let config = sandbox(SandboxBackend::Bubblewrap);
+    let (_, args) = argv(SandboxCommand::new("claude", Some(&config)));
+    assert!(args.contains(&"--share-net".to_string()));
+}
+
+#[test]
+fn podman_passes_limits_mounts_and_env_keys() {
+    let mut config = SandboxConfig {
+        network: false,
+        memory_mb: Some(512),
+        cpu_secs: Some(60),
+        max_pids: Some(64),
+        ..sandbox(SandboxBackend::Podman)
+    };
+    assert!(SandboxCommand::new("codex", Some(&config)).build().is_err());
+
+    config.image = Some("example/codex".to_string());
+    let mut command = SandboxCommand::new("codex", Some(&config));
+    command
+        .arg("exec")
+        .current_dir("/work")
+        .env("OPENAI_API_KEY", "sk-secret");
+    let (program, args) = argv(command);
+
+    assert_eq!(program, "podman");
+    assert!(contains(&args, &["run", "--rm", "-i", "--init"]));
+    for expected in [
+        "--network=none",
+        "--memory=512m",
+        "--pids-limit=64",
+        "--ulimit=cpu=60:60",
+        "--volume=/ro:/ro:ro",
+        "--volume=/work:/work:rw",
+        "--volume=/rw:/rw:rw",
+        "--workdir=/work",
+        "--env=OPENAI_API_KEY",
+    ] {
+        assert!(args.contains(&expected.to_string()), "{:?}", args);
+    }
+    // 环境变量的值不出现在参数中
+    assert!(!args.iter().any(|a| a.contains("sk-secret")));
+    assert!(args.ends_with(&[
+        "example/codex".to_string(),
+        "codex".to_string(),
+        "exec".to_string()
+    ]));
+}
+
+#[test]
+fn from_env_reads_agent_specific_settings() {
+    assert!(SandboxConfig::from_env("sandbox_test_none").is_none());
+
+    std::env::set_var("OPENRUNNER_SANDBOX_SANDBOX_TEST_BWRAP_BACKEND", "bwrap");
+    std::env::set_var("OPENRUNNER_SANDBOX_SANDBOX_TEST_BWRAP_NETWORK", "off");
+    std::env::set_var("OPENRUNNER_SANDBOX_SANDBOX_TEST_BWRAP_MEMORY_MB", "1024");
+    std::env::set_var("OPENRUNNER_SANDBOX_SANDBOX_TEST_BWRAP_RO_BINDS", "/a:/b");
+    std::env::set_var(
+        "OPENRUNNER_SANDBOX_SANDBOX_TEST_BWRAP_WORKSPACE_ROOT",
+        "/srv/work",
+    );
+    let config = SandboxConfig::from_env("sandbox_test_bwrap").unwrap();
+    assert_eq!(config.backend, SandboxBackend::Bubblewrap);
+    assert!(!config.network);
+    assert_eq!(config.memory_mb, Some(1024));
+    assert_eq!(config.ro_binds, ["/a", "/b"]);
+    assert_eq!(config.workspace_root.as_deref(), Some("/srv/work"));
+
+    // 只有资源限制时不隔离，但仍返回配置
+    std::env::set_var("OPENRUNNER_SANDBOX_SANDBOX_TEST_LIMITS_PIDS", "32");
+    let config = SandboxConfig::from_env("sandbox_test_limits").unwrap();
+    assert_eq!(config.backend, SandboxBackend::None);
+    assert_eq!(config.max_pids, Some(32));
+
+    std::env::set_var("OPENRUNNER_SANDBOX_SANDBOX_TEST_UNKNOWN_BACKEND", "docker");
+    assert!(SandboxConfig::from_env("sandbox_test_unknown").is_none());
+}
+
+#[test]
+fn working_dirs_must_stay_inside_the_workspace_root() {
+    let root = temp_dir();
+    let project = root.join("project");
+    std::fs::create_dir_all(&project).unwrap();
+    let mut config = SandboxConfig {
+        workspace_root: Some(root.to_string_lossy().to_string()),
+        ..sandbox(SandboxBackend::Bubblewrap)
+    };
+
+    assert!(config.allows_working_dir(&project.to_string_lossy()));
+    assert!(config.allows_working_dir(&root.to_string_lossy()));
+    assert!(!config.allows_working_dir("/"));
+    assert!(!config.allows_working_dir(&format!("{}/..", root.to_string_lossy())));
+    assert!(!config.allows_working_dir(&root.join("missing").to_string_lossy()));
+
+    // 没有 workspace root 时不接受客户端目录；只限制资源时不检查
+    config.workspace_root = None;
+    assert!(!config.allows_working_dir(&project.to_string_lossy()));
+    config.backend = SandboxBackend::None;
+    assert!(config.allows_working_dir("/"));
+}
+
+#[tokio::test]
+async fn runs_reject_a_cwd_outside_the_workspace() {
+    std::env::set_var("OPENRUNNER_SANDBOX_MOCK_BACKEND", "bubblewrap");
+    let state = AppState::with_data_dir(temp_dir()).await;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, create_router_with_state(state))
+            .await
+            .unwrap()
+    });
+
+    let response = reqwest::Client::new()
+        .post(format!("http://{}/api/runs", addr))
+        .json(&json!({
+            "input": { "text": "hi" },
+            "metadata": { "agent_type": "mock", "cwd": "/" },
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::auth::create_token;
+use openrunner::run::RunStatus;
+use openrunner::secrets::{is_secret_ref, is_sensitive_key, references, secret_ref, secret_scope};
+use openrunner::storage::AgentDefault;
+use openrunner::types::AgentConfig;
+use openrunner::{Db, SecretCipher, SecretStore};
+use serde_json::{json, Value};
+
+fn data_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("openrunner-test-{}", uuid::Uuid::new_v4()))
+}
+
+async fn store() -> (Db, SecretStore) {
+    let path = data_dir().join("openrunner.db");
+    let db = Db::new(&path.to_string_lossy()).await.unwrap();
+    let store = SecretStore::new(db.clone(), SecretCipher::new(&[7; 32]));
+    (db, store)
+}
+
+fn env(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn cipher_round_trips_with_a_fresh_nonce() {
+    let cipher = SecretCipher::new(&[1; 32]);
+    let (nonce, ciphertext) = cipher.encrypt("sk-secret-value").unwrap();
+    assert_eq!(nonce.len(), 12);
+    assert!(!ciphertext
+        .windows(b"sk-secret".len())
+        .any(|w| w == b"sk-secret"));
+    assert_eq!(
+        cipher.decrypt(&nonce, &ciphertext).unwrap(),
+        "sk-secret-value"
+    );
+
+    let (other_nonce, _) = cipher.encrypt("sk-secret-value").unwrap();
+    assert_ne!(nonce, other_nonce);
+}
+
+#[test]
+fn decryption_fails_with_the_wrong_key_or_tampered_data() {
+    let cipher = SecretCipher::new(&[1; 32]);
+    let (nonce, mut ciphertext) = cipher.encrypt("sk-secret-value").unwrap();
+
+    assert!(SecretCipher::new(&[2; 32])
+        .decrypt(&nonce, &ciphertext)
+        .is_err());
+    assert!(cipher.decrypt(&nonce[..8], &ciphertext).is_err());
+    ciphertext[0] ^= 1;
+    assert!(cipher.decrypt(&nonce, &ciphertext).is_err());
+}
+
+#[test]
+fn references_are_parsed_from_values() {
+    let value = format!("Bearer {} and {}", secret_ref("a.b"), secret_ref("c"));
+    assert_eq!(references(&value), ["a.b", "c"]);
+    assert_eq!(references("${secret:unterminated"), Vec::<String>::new());
+    assert!(is_secret_ref(&secret_ref("name")));
+    assert!(!is_secret_ref(&value));
+
+    assert!(is_sensitive_key("OPENAI_API_KEY"));
+    assert!(is_sensitive_key("github-token"));
+    assert!(!is_sensitive_key("KEYBOARD_LAYOUT"));
+
+    assert_eq!(secret_scope("session", "a/b c"), "session.a_b_c");
+}
+
+#[tokio::test]
+async fn store_resolves_references_per_user() {
+    let (_, store) = store().await;
+    let info = store
+        .set("alice", "openai", "sk-0123456789abcd")
+        .await
+        .unwrap();
+    assert_eq!(info.last4, "abcd");
+    assert!(store.set("alice", "bad name", "x").await.is_err());
+
+    let resolved = store
+        .resolve_env(
+            "alice",
+            &env(&[
+                ("OPENAI_API_KEY", &secret_ref("openai")),
+                ("HEADER", &format!("Bearer {}", secret_ref("openai"))),
+                ("PLAIN", "value"),
+            ]),
+        )
+        .await
+        .unwrap();
+    assert_eq!(resolved["OPENAI_API_KEY"], "sk-0123456789abcd");
+    assert_eq!(resolved["HEADER"], "Bearer sk-0123456789abcd");
+    assert_eq!(resolved["PLAIN"], "value");
+
+    // 其他用户看不到 alice 的密钥
+    let missing = env(&[("OPENAI_API_KEY", &secret_ref("openai"))]);
+    assert!(store.resolve_env("bob", &missing).await.is_err());
+
+    assert!(store.delete("alice", "openai").await.unwrap());
+    assert!(store.get("alice", "openai").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn externalized_values_are_scoped_to_their_owner() {
+    let (_, store) = store().await;
+    let first = store
+        .externalize_env(
+            "alice",
+            &secret_scope("session", "one"),
+            &env(&[("ANTHROPIC_API_KEY", "sk-first"), ("MODEL", "opus")]),
+        )
+        .await
+        .unwrap();
+    let second = store
+        .externalize_env(
+            "alice",
+            &secret_scope("session", "two"),
+            &env(&[("ANTHROPIC_API_KEY", "sk-second")]),
+        )
+        .await
+        .unwrap();
+    assert_eq!(first["MODEL"], "opus");
+    assert_ne!(first["ANTHROPIC_API_KEY"], second["ANTHROPIC_API_KEY"]);
+
+    // 同一 agent 类型的两个 session 各自保留自己的 key
+    let first = store.resolve_env("alice", &first).await.unwrap();
+    let second = store.resolve_env("alice", &second).await.unwrap();
+    assert_eq!(first["ANTHROPIC_API_KEY"], "sk-first");
+    assert_eq!(second["ANTHROPIC_API_KEY"], "sk-second");
+
+    // 已经是引用的值不再存一次
+    let reference = env(&[("ANTHROPIC_API_KEY", &secret_ref("mine"))]);
+    let unchanged = store
+        .externalize_env("alice", "session.three", &reference)
+        .await
+        .unwrap();
+    assert_eq!(unchanged, reference);
+}
+
+#[tokio::test]
+async fn plaintext_credentials_are_migrated_at_startup() {
+    let dir = data_dir();
+    let db = Db::new(&dir.join("openrunner.db").to_string_lossy())
+        .await
+        .unwrap();
+    let plaintext = env(&[("OPENAI_API_KEY", "sk-plaintext-0001")]);
+    db.upsert_session(
+        "alice",
+        "s1",
+        None,
+        Some("codex".to_string()),
+        None,
+        Some(plaintext.clone()),
+        None,
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    db.set_agent_default(
+        "alice",
+        &AgentDefault {
+            agent_type: "codex".to_string(),
+            model: None,
+            env: plaintext,
+            extra_args: vec![],
+            sampling: Default::default(),
+            mcp_servers: vec![],
+        },
+    )
+    .await
+    .unwrap();
+
+    let state = AppState::with_data_dir(&dir).await;
+    let session = &state.db.list_sessions("alice").await.unwrap()[0];
+    assert_eq!(
+        session.env["OPENAI_API_KEY"],
+        secret_ref("session.s1.OPENAI_API_KEY")
+    );
+    let default = &state.db.get_agent_defaults("alice").await.unwrap()[0];
+    assert_eq!(
+        default.env["OPENAI_API_KEY"],
+        secret_ref("defaults.codex.OPENAI_API_KEY")
+    );
+    let resolved = state
+        .secrets
+        .resolve_env("alice", &session.env)
+        .await
+        .unwrap();
+    assert_eq!(resolved["OPENAI_API_KEY"], "sk-plaintext-0001");
+}
+
+#[tokio::test]
+async fn saved_sessions_keep_separate_credentials() {
+    let state = AppState::with_data_dir(data_dir()).await;
+    let secrets = state.secrets.clone();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, create_router_with_state(state))
+            .await
+            .unwrap()
+    });
+
+    let user_id = uuid::Uuid::new_v4().to_string();
+    let token = create_token(&user_id, "tester", &[]).unwrap();
+    let client = reqwest::Client::new();
+    let session = |id: &str, key: &str| json!({ "id": id, "agent_type": "claude_code", "env": { "ANTHROPIC_API_KEY": key } });
+    let response = client
+        .post(format!("{}/api/sessions", url))
+        .bearer_auth(&token)
+        .json(&json!({ "sessions": [session("a", "sk-session-a"), session("b", "sk-session-b")] }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let body: Value = client
+        .get(format!("{}/api/sessions", url))
+        .bearer_auth(&token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    for session in body["sessions"].as_array().unwrap() {
+        let env: HashMap<String, String> = serde_json::from_value(session["env"].clone()).unwrap();
+        let resolved = secrets.resolve_env(&user_id, &env).await.unwrap();
+        assert_eq!(
+            resolved["ANTHROPIC_API_KEY"],
+            format!("sk-session-{}", session["id"].as_str().unwrap())
+        );
+    }
+}
+
+#[tokio::test]
+async fn runs_with_an_unknown_secret_are_rejected() {
+    let state = AppState::with_data_dir(data_dir()).await;
+    let manager = state.run_manager.clone();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, create_router_with_state(state))
+            .await
+            .unwrap()
+    });
+
+    let user_id = uuid::Uuid::new_v4().to_string();
+    let token = create_token(&user_id, "tester", &[]).unwrap();
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/runs", url))
+        .bearer_auth(&token)
+        .json(&json!({
+            "input": { "text": "hi" },
+            "metadata": { "env": { "GITHUB_TOKEN": secret_ref("missing_token") } }
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let body: Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().contains("missing_token"));
+    // 被拒绝的请求不留下 Run
+    assert!(manager.store().list_by_user(&user_id).is_empty());
+
+    // 直接启动的 Run 解析失败时标记为失败，不停留在 Pending
+    let run_id = manager.create_run(&user_id, None, "hi");
+    let config = AgentConfig {
+        agent_type: "mock".to_string(),
+        env: env(&[("GITHUB_TOKEN", &secret_ref("missing_token"))]),
+        ..Default::default()
+    };
+    assert!(manager.start_run(&run_id, config).await.is_err());
+    let run = manager.get_run(&run_id).unwrap();
+    assert_eq!(run.status, RunStatus::Failed);
+    assert!(run.error.unwrap().contains("missing_token"));
+}
@@ -0,0 +1,155 @@
+use futures::StreamExt;
+use openrunner::agent::{sse_events, SseDecoder, SseEvent};
+
+/// 一次性喂入全部字节
+fn decode_all(input: &[u8]) -> Vec<SseEvent> {
+    let mut decoder = SseDecoder::new();
+    let mut events = decoder.push(input);
+    events.extend(decoder.finish());
+    events
+}
+
+/// 在每个字节位置切成两片喂入，结果必须与一次性喂入相同
+fn assert_split_invariant(input: &[u8]) -> Vec<SseEvent> {
+    let expected = decode_all(input);
+    for at in 0..=input.len() {
+        let mut decoder = SseDecoder::new();
+        let mut events = decoder.push(&input[..at]);
+        events.extend(decoder.push(&input[at..]));
+        events.extend(decoder.finish());
+        assert_eq!(events, expected, "split at byte {}", at);
+    }
+
+    // 逐字节喂入
+    let mut decoder = SseDecoder::new();
+    let mut events = Vec::new();
+    for byte in input {
+        events.extend(decoder.push(std::slice::from_ref(byte)));
+    }
+    events.extend(decoder.finish());
+    assert_eq!(events, expected, "byte by byte");
+
+    expected
+}
+
+fn data(events: &[SseEvent]) -> Vec<&str> {
+    events.iter().map(|e| e.data.as_str()).collect()
+}
+
+#[test]
+fn openai_chunks() {
+    let input = b"data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n\
+data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\n\
+data: [DONE]\n\n";
+    let events = assert_split_invariant(input);
+    assert_eq!(
+        data(&events),
+        vec![
+            "{\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}",
+            "{\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}",
+            "[DONE]",
+        ]
+    );
+}
+
+#[test]
+fn multibyte_utf8_split_across_chunks() {
+    let input = "data: 你好，世界 🌍\n\n".as_bytes();
+    let events = assert_split_invariant(input);
+    assert_eq!(data(&events), vec!["你好，世界 🌍"]);
+}
+
+#[test]
+fn named_events_and_crlf() {
+    let input = b"event: message_start\r\ndata: {\"a\":1}\r\n\r\nevent: ping\r\ndata: {}\r\n\r\n";
+    let events = assert_split_invariant(input);
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].event.as_deref(), Some("message_start"));
+    assert_eq!(events[0].data, "{\"a\":1}");
+    assert_eq!(events[1].event.as_deref(), Some("ping"));
+}
+
+#[test]
+fn bare_cr_line_endings() {
+    let input = b"data: one\r\rdata: two\r\r";
+    let events = assert_split_invariant(input);
+    assert_eq!(data(&events), vec!["one", "two"]);
+}
+
+#[test]
+fn multi_line_data() {
+    let input = b"data: first\ndata: second\ndata:\ndata:third\n\n";
+    let events = assert_split_invariant(input);
+    assert_eq!(data(&events), vec!["first\nsecond\n\nthird"]);
+}
+
+#[test]
+fn comments_and_unknown_fields_are_ignored() {
+    let input = b": keep-alive\n\nfoo: bar\ndata: x\n: inline comment\n\n";
+    let events = assert_split_invariant(input);
+    assert_eq!(data(&events), vec!["x"]);
+}
+
+#[test]
+fn event_without_data_is_not_dispatched() {
+    let input = b"event: ping\n\ndata: x\n\n";
+    let events = assert_split_invariant(input);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event, None);
+}
+
+#[test]
+fn id_and_retry() {
+    let input = b"id: 1\nretry: 3000\ndata: a\n\ndata: b\n\nid\nretry: soon\ndata: c\n\n";
+    let events = assert_split_invariant(input);
+    assert_eq!(events[0].id.as_deref(), Some("1"));
+    assert_eq!(events[0].retry, Some(3000));
+    // id 跨事件保留
+    assert_eq!(events[1].id.as_deref(), Some("1"));
+    // 空 id 重置为空字符串，非法 retry 忽略
+    assert_eq!(events[2].id.as_deref(), Some(""));
+    assert_eq!(events[2].retry, Some(3000));
+}
+
+#[test]
+fn leading_bom_is_stripped() {
+    let input = "\u{feff}data: x\n\n".as_bytes();
+    let events = assert_split_invariant(input);
+    assert_eq!(data(&events), vec!["x"]);
+}
+
+#[test]
+fn trailing_event_without_blank_line() {
+    let input = b"data: a\n\ndata: b";
+    let events = assert_split_invariant(input);
+    assert_eq!(data(&events), vec!["a", "b"]);
+}
+
+#[tokio::test]
+async fn byte_stream_adapter() {
+    let chunks: Vec<Result<Vec<u8>, std::io::Error>> = vec![
+        Ok(b"data: {\"t\":\"".to_vec()),
+        Ok("é".as_bytes()[..1].to_vec()),
+        Ok(["é".as_bytes()[1..].to_vec(), b"\"}\n".to_vec()].concat()),
+        Ok(b"\ndata: [DONE]\n\n".to_vec()),
+    ];
+    let events: Vec<SseEvent> = sse_events(futures::stream::iter(chunks))
+        .map(|e| e.unwrap())
+        .collect()
+        .await;
+    assert_eq!(data(&events), vec!["{\"t\":\"é\"}", "[DONE]"]);
+}
+
+#[tokio::test]
+async fn byte_stream_error_ends_stream() {
+    let chunks: Vec<Result<Vec<u8>, &str>> = vec![
+        Ok(b"data: a\n\n".to_vec()),
+        Err("connection reset"),
+        Ok(b"data: b\n\n".to_vec()),
+    ];
+    let items: Vec<Result<SseEvent, &str>> =
+        sse_events(futures::stream::iter(chunks)).collect().await;
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].as_ref().unwrap().data, "a");
+    assert_eq!(items[1].as_ref().unwrap_err(), &"connection reset");
+}
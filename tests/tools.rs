@@ -0,0 +1,273 @@
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use openrunner::agent::{AgentInfo, MockAgent, ToolBox, ToolCall, ToolResult};
+use openrunner::api::router::{create_router_with_state, AppState};
+use openrunner::auth::create_token;
+use openrunner::types::{AgentConfig, ToolsConfig};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("openrunner-tools-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+async fn toolbox(root: &Path, allow: &[&str]) -> ToolBox {
+    let config = AgentConfig {
+        tools: ToolsConfig {
+            allow: allow.iter().map(|t| t.to_string()).collect(),
+            shell_timeout_secs: 2,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    ToolBox::new(&config, Some(&root.to_string_lossy()))
+        .await
+        .unwrap()
+}
+
+async fn call(toolbox: &ToolBox, name: &str, input: Value) -> ToolResult {
+    let (tx, _rx) = mpsc::channel(10);
+    let call = ToolCall {
+        id: "call-1".to_string(),
+        name: name.to_string(),
+        input,
+    };
+    toolbox.call(&call, &tx).await
+}
+
+fn assert_outside(result: &ToolResult) {
+    assert!(!result.ok, "{}", result.output);
+    assert!(
+        result.output.contains("outside the working directory"),
+        "{}",
+        result.output
+    );
+}
+
+#[tokio::test]
+async fn relative_and_absolute_paths_stay_inside() {
+    let root = temp_dir();
+    let tools = toolbox(&root, &["read_file", "write_file", "list_files"]).await;
+    std::fs::write(root.join("a.txt"), "inside").unwrap();
+
+    for path in ["../a.txt", "sub/../../a.txt", "/etc/passwd"] {
+        assert_outside(&call(&tools, "read_file", json!({ "path": path })).await);
+    }
+    assert_outside(&call(&tools, "list_files", json!({ "path": ".." })).await);
+    assert_outside(
+        &call(
+            &tools,
+            "write_file",
+            json!({ "path": "../x.txt", "content": "x" }),
+        )
+        .await,
+    );
+
+    // 工作目录内的绝对路径和多余的 `..` 都可以
+    let absolute = root.join("a.txt").to_string_lossy().to_string();
+    let result = call(&tools, "read_file", json!({ "path": absolute })).await;
+    assert_eq!(result.output, "inside");
+    let result = call(&tools, "read_file", json!({ "path": "sub/../a.txt" })).await;
+    assert_eq!(result.output, "inside");
+}
+
+#[tokio::test]
+async fn symlinks_cannot_lead_outside() {
+    let root = temp_dir();
+    let outside = temp_dir();
+    std::fs::write(outside.join("secret.txt"), "secret").unwrap();
+    std::fs::write(root.join("a.txt"), "inside").unwrap();
+    symlink(&outside, root.join("out")).unwrap();
+    symlink(outside.join("secret.txt"), root.join("secret.txt")).unwrap();
+    symlink(outside.join("new.txt"), root.join("dangling")).unwrap();
+    symlink(root.join("a.txt"), root.join("alias.txt")).unwrap();
+    let tools = toolbox(&root, &["read_file", "write_file", "list_files"]).await;
+
+    assert_outside(&call(&tools, "read_file", json!({ "path": "out/secret.txt" })).await);
+    assert_outside(&call(&tools, "read_file", json!({ "path": "secret.txt" })).await);
+    assert_outside(&call(&tools, "list_files", json!({ "path": "out" })).await);
+    assert_outside(
+        &call(
+            &tools,
+            "write_file",
+            json!({ "path": "out/new.txt", "content": "x" }),
+        )
+        .await,
+    );
+    // 悬空链接指向工作目录外，写入会在外面创建文件
+    assert_outside(
+        &call(
+            &tools,
+            "write_file",
+            json!({ "path": "dangling", "content": "x" }),
+        )
+        .await,
+    );
+    assert!(!outside.join("new.txt").exists());
+
+    // 指向工作目录内的链接可以使用
+    let result = call(&tools, "read_file", json!({ "path": "alias.txt" })).await;
+    assert_eq!(result.output, "inside");
+
+    // 列目录不跟随链接
+    let result = call(&tools, "list_files", json!({ "recursive": true })).await;
+    assert!(result.ok);
+    assert!(!result.output.contains("secret.txt\n") && !result.output.contains("out/"));
+}
+
+#[tokio::test]
+async fn files_are_written_with_parent_directories() {
+    let root = temp_dir();
+    let tools = toolbox(&root, &["write_file", "list_files"]).await;
+    let result = call(
+        &tools,
+        "write_file",
+        json!({ "path": "a/b/c.txt", "content": "hi" }),
+    )
+    .await;
+    assert_eq!(result.output, "Wrote 2 bytes to a/b/c.txt");
+    assert_eq!(
+        std::fs::read_to_string(root.join("a/b/c.txt")).unwrap(),
+        "hi"
+    );
+
+    let result = call(&tools, "list_files", json!({ "recursive": true })).await;
+    assert_eq!(result.output, "a/\na/b/\na/b/c.txt");
+
+    // 未允许的工具
+    let result = call(&tools, "read_file", json!({ "path": "a/b/c.txt" })).await;
+    assert!(!result.ok);
+    assert_eq!(result.output, "Error: Tool 'read_file' is not available");
+}
+
+#[test]
+fn run_shell_needs_a_sandbox_or_explicit_allow() {
+    assert!(!ToolsConfig::default()
+        .allow
+        .contains(&"run_shell".to_string()));
+    assert!(!ToolsConfig::from_env("tools_test_plain")
+        .allow
+        .contains(&"run_shell".to_string()));
+
+    std::env::set_var("OPENRUNNER_SANDBOX_TOOLS_TEST_BWRAP_BACKEND", "bubblewrap");
+    let allow = ToolsConfig::from_env("tools_test_bwrap").allow;
+    assert_eq!(
+        allow,
+        ["read_file", "write_file", "list_files", "run_shell"]
+    );
+
+    // 只限制资源不算隔离
+    std::env::set_var("OPENRUNNER_SANDBOX_TOOLS_TEST_LIMITS_MEMORY_MB", "256");
+    assert!(!ToolsConfig::from_env("tools_test_limits")
+        .allow
+        .contains(&"run_shell".to_string()));
+
+    // 显式配置优先
+    std::env::set_var("OPENRUNNER_TOOLS_TOOLS_TEST_EXPLICIT_ALLOW", "run_shell");
+    assert_eq!(
+        ToolsConfig::from_env("tools_test_explicit").allow,
+        ["run_shell"]
+    );
+}
+
+#[tokio::test]
+async fn shell_commands_run_with_a_minimal_env() {
+    std::env::set_var("OPENRUNNER_TOOLS_TEST_SECRET", "server-secret");
+    let root = temp_dir();
+    let tools = toolbox(&root, &["run_shell"]).await;
+
+    let result = call(
+        &tools,
+        "run_shell",
+        json!({ "command": "pwd; env | sort | cut -d= -f1 | tr '\\n' ' '; echo oops >&2; exit 3" }),
+    )
+    .await;
+    assert!(result.ok);
+    let root = std::fs::canonicalize(&root).unwrap();
+    assert_eq!(
+        result.output,
+        format!(
+            "exit code: 3\nstdout:\n{}\nHOME LANG PATH PWD \nstderr:\noops\n",
+            root.display()
+        )
+    );
+
+    let result = call(&tools, "run_shell", json!({ "command": "sleep 5" })).await;
+    assert!(!result.ok);
+    assert_eq!(result.output, "Error: Command timed out after 2s");
+}
+
+#[tokio::test]
+async fn file_tools_need_a_project_or_workspace_directory() {
+    let workspace = temp_dir();
+    std::env::set_var("OPENRUNNER_SANDBOX_TOOLS_PROBE_WORKSPACE_ROOT", &workspace);
+    let state = AppState::with_data_dir(&temp_dir()).await;
+    // 记录每个 Run 的 agent 拿到的工具列表
+    let allowed = Arc::new(Mutex::new(Vec::new()));
+    let seen = allowed.clone();
+    state.registry.register(
+        AgentInfo::new("tools_probe", "Records its tools"),
+        move |config| {
+            seen.lock().unwrap().push(config.tools.allow.clone());
+            Ok(Box::new(MockAgent::new(config.clone())))
+        },
+    );
+    let manager = state.run_manager.clone();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, create_router_with_state(state))
+            .await
+            .unwrap()
+    });
+    let client = reqwest::Client::new();
+    let token = create_token(&uuid::Uuid::new_v4().to_string(), "tester", &[]).unwrap();
+    let project: Value = client
+        .post(format!("{}/api/projects", url))
+        .bearer_auth(&token)
+        .json(&json!({ "name": "tools-probe" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let run = |metadata: Value| {
+        let request = client
+            .post(format!("{}/api/runs", url))
+            .bearer_auth(&token)
+            .json(&json!({ "input": { "text": "hi" }, "metadata": metadata }));
+        let manager = manager.clone();
+        let allowed = allowed.clone();
+        async move {
+            let body: Value = request.send().await.unwrap().json().await.unwrap();
+            let run_id = body["run_id"].as_str().unwrap();
+            manager
+                .wait_run(run_id, Duration::from_secs(10))
+                .await
+                .unwrap();
+            allowed.lock().unwrap().pop().unwrap()
+        }
+    };
+    let defaults = ["read_file", "write_file", "list_files"];
+
+    // 客户端指定的任意目录不开放文件工具
+    let tools = run(json!({ "agent_type": "tools_probe", "cwd": "/" })).await;
+    assert!(tools.is_empty(), "{:?}", tools);
+    let tools = run(json!({ "agent_type": "tools_probe", "cwd": temp_dir() })).await;
+    assert!(tools.is_empty(), "{:?}", tools);
+
+    // 项目目录和 workspace root 之内的目录可以使用
+    let tools = run(json!({ "agent_type": "tools_probe", "project_id": project["id"] })).await;
+    assert_eq!(tools, defaults);
+    let inside = workspace.join("repo");
+    std::fs::create_dir_all(&inside).unwrap();
+    let tools = run(json!({ "agent_type": "tools_probe", "cwd": inside })).await;
+    assert_eq!(tools, defaults);
+}